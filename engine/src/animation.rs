@@ -0,0 +1,47 @@
+//! Objects which move by themselves, like platforms sliding back and forth or turning in place.
+//! Where one is follows from the tick alone rather than building up tick by tick, so it's the same every time
+//! (and on every machine) the world gets there. Animated objects are kinematic in the simulation, so they carry
+//! and shove whatever's on them without being pushed back.
+
+use crate::objects::VoxelObject;
+use crate::physics::TIMESTEP;
+use glam::{Quat, Vec3};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Animation {
+  /// World units a second
+  pub velocity: Vec3,
+  /// Radians a second around each world axis, turning about the object's pivot
+  pub spin: Vec3,
+  /// Seconds before it heads back the way it came, so it goes back and forth. None keeps going forever
+  pub period: Option<f32>,
+  /// (pivot, rot) at start_tick, where the motion is measured from
+  pub origin: (Vec3, Quat),
+  pub start_tick: u64,
+}
+impl Animation {
+  /// Starts object moving from where it is at tick
+  pub fn new(object: &VoxelObject, tick: u64, velocity: Vec3, spin: Vec3, period: Option<f32>) -> Self {
+    Self { velocity, spin, period, origin: (object.pos + object.pivot_offset, object.rot), start_tick: tick }
+  }
+
+  // Seconds of motion in at tick, which count back down on every other period when going back and forth
+  fn elapsed(&self, tick: u64) -> f32 {
+    let seconds = tick.saturating_sub(self.start_tick) as f64 * TIMESTEP as f64;
+    match self.period {
+      Some(period) if period > 0.0 => {
+        let period = period as f64;
+        let phase = seconds % (period * 2.0);
+        (if phase > period { period * 2.0 - phase } else { phase }) as f32
+      }
+      _ => seconds as f32,
+    }
+  }
+
+  /// (pivot, rot) at tick
+  pub fn pose(&self, tick: u64) -> (Vec3, Quat) {
+    let elapsed = self.elapsed(tick);
+    let (pivot, rot) = self.origin;
+    (pivot + self.velocity * elapsed, (Quat::from_scaled_axis(self.spin * elapsed) * rot).normalize())
+  }
+}
@@ -1,50 +1,253 @@
 use crate::wgpu_ctx::WgpuCtx;
+use std::f32::consts::PI;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use winit::application::ApplicationHandler;
 use winit::event::{DeviceEvent, ElementState, MouseButton, WindowEvent};
 use winit::event_loop::ActiveEventLoop;
+use winit::dpi::PhysicalPosition;
 use winit::keyboard::{KeyCode, PhysicalKey};
 use winit::window::{CursorGrabMode, Window, WindowId};
-use glam::{Vec2, Vec3};
+use glam::{UVec3, Vec2, Vec3, Vec4};
 use std::cell::OnceCell;
-use crate::objects::GameData;
+use crate::objects::{DagRef, GameData, RayHit, VoxelObject, EMPTY};
+use crate::materials::MaterialRegistry;
+use sdg::prelude::{Index, SparseDirectedGraph};
+use crate::physics::{DummyShape, TIMESTEP};
+use crate::camera::{Camera, Projection};
+use crate::console::{Console, Settings};
+use crate::plugins::Plugins;
+use crate::profiling::{count, zone};
+use crate::editor::{PaintMode, Placement};
+use crate::input::{Action, Input, InputMap, PadButton};
+use crate::gamepad::{Gamepads, PadEvent};
+use crate::loading::{Loader, WorldSource};
+use crate::saves;
+use crate::start_screen::StartScreen;
+use crate::textures::Atlas;
 
+/// How camera movement is applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MovementMode {
+  /// Free flight straight through everything
+  Fly,
+  /// Flight as a capsule which slides along and steps up onto the world
+  Collide,
+  /// The same capsule on foot, pulled down by gravity and jumping off the ground, looking out from its eyes
+  Walk,
+}
+
+/// What the second window opened with --second-window shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecondView {
+  /// Straight down on wherever the main camera is, from high above it
+  Top,
+  /// A camera of its own, flown with the movement keys and turned by dragging while the window has focus
+  Spectator,
+}
+impl SecondView {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "top" => Some(SecondView::Top),
+      "spectator" => Some(SecondView::Spectator),
+      _ => None,
+    }
+  }
+}
+
+// How far above the main camera the top down view looks down from
+const TOP_HEIGHT: f32 = 96.0;
+
+// Another window onto the same world, drawn each time the main one is
+struct SecondWindow<'window> {
+  view: SecondView,
+  window: Arc<Window>,
+  // Shares the main context's device and voxels, see WgpuCtx::share
+  ctx: WgpuCtx<'window>,
+  camera: Camera,
+  focused: bool,
+  // Whether the left button is held down on the window, and where the cursor last was over it
+  dragging: bool,
+  cursor: Option<PhysicalPosition<f64>>,
+}
 
 pub struct App<'window> {
   // Windowing
   window: OnceCell<Arc<Window>>,
   wgpu_ctx: OnceCell<WgpuCtx<'window>>,
+  // Opened alongside the main window if set, see with_second_window
+  second_view: Option<SecondView>,
+  second: Option<SecondWindow<'window>>,
 
   game_data: GameData,
+  console: Console,
+  plugins: Plugins,
+  // Some until a world has been picked, game_data is an empty stand in till then
+  menu: Option<StartScreen>,
+  // Worlds being built or read, whichever finishes is swapped in as it does
+  worlds: Loader<(GameData, bool)>,
+  // Imported into every world as it starts
+  vox_paths: Vec<String>,
 
   // Input
+  input: InputMap,
   keys_pressed: Vec<KeyCode>,
   mouse_delta: Vec2,
   mouse_buttons_pressed: Vec<MouseButton>,
   mouse_captured: bool,
+  // While either is set there's nothing to draw into, the world keeps ticking in about_to_wait instead
+  minimized: bool,
+  occluded: bool,
+  gamepads: Gamepads,
+  pad_pressed: Vec<PadButton>,
+  movement: MovementMode,
+
+  // Editing
+  selected_leaf: Index,
+  // None places and breaks, otherwise left click recolors
+  paint: Option<PaintMode>,
+  brush_radius: u32,
+  // Some while a prefab is following the crosshair, right click places it
+  placing: Option<VoxelObject>,
+  placement: Placement,
+
+  // Debug
+  dummy_size: f32,
+
+  // Tiles materials are textured with, uploaded once there's a window
+  atlas: Atlas,
+  // Run against every world as it starts
+  settings: Settings,
+  // Rebuild pipelines when the shaders on disk change
+  hot_reload: bool,
 
   // Frame Timing
   last_update: Instant,
-  fps_update_timer: f32, // We want to print fps once per second
+  // When the last frame began, for the fps limit
+  last_frame: Instant,
+  fps_update_timer: f32, // We want to print checksums once per second
 }
 
-impl<'window> Default for App<'window> {
-  fn default() -> Self {
-    Self {
+impl<'window> App<'window> {
+  /// Starts loading world if there is one, with the start screen up till it's done
+  pub fn new(world: Option<WorldSource>, plugins: Plugins, vox_paths: Vec<String>, input: InputMap, atlas: Atlas, settings: Settings, hot_reload: bool) -> Self {
+    let mut app = Self {
       window: OnceCell::new(),
       wgpu_ctx: OnceCell::new(),
-      game_data: GameData::default(),
+      second_view: None,
+      second: None,
+      game_data: GameData::from_parts(SparseDirectedGraph::new(), MaterialRegistry::default()),
+      console: Console::spawn(),
+      plugins,
+      menu: Some(StartScreen::new()),
+      worlds: Loader::default(),
+      vox_paths,
+      input,
       keys_pressed: Vec::new(),
       mouse_delta: Vec2::ZERO,
       mouse_buttons_pressed: Vec::new(),
       mouse_captured: false,
+      minimized: false,
+      occluded: false,
+      gamepads: Gamepads::new(),
+      pad_pressed: Vec::new(),
+      movement: MovementMode::Fly,
+      selected_leaf: 1,
+      paint: None,
+      brush_radius: 2,
+      placing: None,
+      placement: Placement::default(),
+      dummy_size: 1.0,
+      atlas,
+      settings,
+      hot_reload,
       last_update: Instant::now(),
+      last_frame: Instant::now(),
       fps_update_timer: 0.0,
+    };
+    if let Some(world) = world { app.load_world(world) }
+    app
+  }
+
+  /// Opens a second window showing view of the same world once the main one is up. Closing it leaves the main one be
+  pub fn with_second_window(mut self, view: SecondView) -> Self {
+    self.second_view = Some(view);
+    self
+  }
+
+  /// Loads the world on a worker, it's swapped in once it's done, see poll_worlds
+  fn load_world(&mut self, world: WorldSource) {
+    self.worlds.start(world.name(), move || world.load());
+  }
+
+  // Swaps in whichever world has finished loading, the start screen shows the one still going if it's up
+  fn poll_worlds(&mut self) {
+    for (name, result) in self.worlds.finished() {
+      match result {
+        Ok((game_data, fresh)) => {
+          self.start_world(game_data, fresh);
+          tracing::info!("Loaded {name}");
+        }
+        Err(err) => {
+          tracing::error!("Failed to load {name}: {err}");
+          if let Some(menu) = &mut self.menu { menu.error = Some(format!("Failed to load {name}: {err}")) }
+        }
+      }
     }
+    if let Some(menu) = &mut self.menu { menu.loading = self.worlds.loads().first().map(|load| (load.name.clone(), load.state)) }
   }
 
+  /// Swaps in the world to play
+  fn start_world(&mut self, mut game_data: GameData, fresh: bool) {
+    self.menu = None;
+    prepare_world(&mut game_data, &mut self.plugins, &self.vox_paths, fresh);
+    self.settings.apply(&mut game_data, &mut self.plugins);
+    game_data.camera.aspect_ratio = self.game_data.camera.aspect_ratio;
+    self.game_data = game_data;
+    if let Some(ctx) = self.wgpu_ctx.get_mut() {
+      ctx.update_voxels(&self.game_data.sdg);
+      ctx.update_materials(&self.game_data.materials);
+      ctx.reset_history();
+    }
+    if let Some(second) = &mut self.second {
+      second.ctx.update_materials(&self.game_data.materials);
+      second.ctx.reset_history();
+    }
+  }
+
+  fn open_second_window(&mut self, event_loop: &ActiveEventLoop, view: SecondView) {
+    let title = match view {
+      SecondView::Top => "Top down",
+      SecondView::Spectator => "Spectator",
+    };
+    let window = Arc::new(event_loop.create_window(Window::default_attributes().with_title(title)).unwrap());
+    let mut ctx = self.wgpu_ctx.get().unwrap().share(Arc::clone(&window));
+    ctx.update_materials(&self.game_data.materials);
+    ctx.update_atlas(&self.atlas);
+    if self.hot_reload { ctx.watch_shaders() }
+    // Starting out where the main camera is, the spectator from then on goes its own way
+    let mut camera = self.game_data.camera.clone();
+    camera.prev_position = None;
+    if view == SecondView::Top { camera.set_projection(Projection::Top) }
+    let size = window.inner_size();
+    camera.aspect_ratio = size.width as f32 / size.height.max(1) as f32;
+    self.second = Some(SecondWindow { view, window, ctx, camera, focused: false, dragging: false, cursor: None });
+  }
 }
+
+/// Imports the .vox files into a world that's about to be played. Fresh worlds also get the mods' worldgen
+/// and, if they have a slot, saved to it
+pub fn prepare_world(game_data: &mut GameData, plugins: &mut Plugins, vox_paths: &[String], fresh: bool) {
+  // Dropped onto the middle of the terrain so they're visible from the spawn, they turn up as they finish
+  for path in vox_paths { game_data.queue_vox(path.clone(), Vec3::new(24.0, 64.0, 24.0)) }
+  if fresh {
+    plugins.setup(game_data);
+    if let Some(slot) = game_data.world.slot.clone() && let Err(err) = saves::save(game_data, &slot) {
+      tracing::error!("Failed to save {slot}: {err}");
+    }
+  }
+}
+
 impl<'window> ApplicationHandler for App<'window> {
   // Create window and wgpu_ctx
   // Requests redraw
@@ -57,13 +260,27 @@ impl<'window> ApplicationHandler for App<'window> {
         );
         self.window.set(new_window.clone()).unwrap();
         new_window.request_redraw();
-        let new_ctx = WgpuCtx::new(new_window);
+        let mut new_ctx = WgpuCtx::new(new_window);
         new_ctx.update_voxels(&self.game_data.sdg);
+        new_ctx.update_materials(&self.game_data.materials);
+        new_ctx.update_atlas(&self.atlas);
+        if self.hot_reload { new_ctx.watch_shaders() }
+        let modes: Vec<_> = new_ctx.present_modes().iter().map(|mode| mode.name()).collect();
+        tracing::info!("Can present with {}, switch with the present command", modes.join(", "));
         self.wgpu_ctx.set(new_ctx).unwrap_or_else(|_| panic!("I'm not gonna let this fail quietly and I'm not implementing debug on WgpuCtx, that's way too much work"));
+        if let Some(view) = self.second_view { self.open_second_window(event_loop, view) }
       }
     }
   }
 
+  fn about_to_wait(&mut self, _event_loop: &ActiveEventLoop) {
+    if !self.hidden() { return }
+    // Redraws stop coming while hidden, so tick from here at roughly the simulation's own rate
+    self.poll_worlds();
+    if self.menu.is_none() { self.tick_world() }
+    std::thread::sleep(Duration::from_secs_f32(TIMESTEP));
+  }
+
   // Cursor is locked, so we need to acquire mouse motion directly
   fn device_event(&mut self, _event_loop: &ActiveEventLoop, _device_id: winit::event::DeviceId, event: winit::event::DeviceEvent) {
     // Don't trigger any device events  unless mouse is locked
@@ -74,25 +291,44 @@ impl<'window> ApplicationHandler for App<'window> {
     }
   }
 
-  fn window_event(&mut self, event_loop: &ActiveEventLoop, _window_id: WindowId, event: WindowEvent) {
+  fn window_event(&mut self, event_loop: &ActiveEventLoop, window_id: WindowId, event: WindowEvent) {
+    if self.second.as_ref().is_some_and(|second| second.window.id() == window_id) { return self.second_window_event(event) }
+    // The overlay only gets input while the cursor is free to click on it
+    let menu_open = self.menu.is_some();
+    if !self.mouse_captured && self.wgpu_ctx.get_mut().is_some_and(|ctx| ctx.overlay_event(&event, menu_open)) { return }
     match event {
       WindowEvent::CloseRequested => event_loop.exit(),
       WindowEvent::Resized(new_size) => {
+        let was_hidden = self.hidden();
+        self.minimized = new_size.width == 0 || new_size.height == 0;
+        if self.minimized { return }
         self.game_data.camera.aspect_ratio = new_size.width as f32 / new_size.height as f32;
         self.wgpu_ctx.get_mut().unwrap().resize(new_size);
+        if was_hidden && !self.hidden() { self.restore() }
+      },
+      WindowEvent::Occluded(occluded) => {
+        let was_hidden = self.hidden();
+        self.occluded = occluded;
+        if was_hidden && !self.hidden() { self.restore() }
       },
       WindowEvent::RedrawRequested => self.redraw(),
       WindowEvent::KeyboardInput { event, .. } => {
         if let PhysicalKey::Code(key_code) = event.physical_key {
           match event.state {
-            ElementState::Pressed => if !self.keys_pressed.contains(&key_code) { self.keys_pressed.push(key_code); },
+            ElementState::Pressed => if !self.keys_pressed.contains(&key_code) {
+              self.keys_pressed.push(key_code);
+              self.input_down(Input::Key(key_code));
+            },
             ElementState::Released => self.keys_pressed.retain(|&k| k != key_code),
           }
         }
       },
       WindowEvent::MouseInput { state, button, .. } => {
         match state {
-          ElementState::Pressed => if !self.mouse_buttons_pressed.contains(&button) { self.mouse_buttons_pressed.push(button) },
+          ElementState::Pressed => if !self.mouse_buttons_pressed.contains(&button) {
+            self.mouse_buttons_pressed.push(button);
+            self.input_down(Input::Mouse(button));
+          },
           ElementState::Released => self.mouse_buttons_pressed.retain(|&b| b != button)
         }
       },
@@ -102,18 +338,109 @@ impl<'window> ApplicationHandler for App<'window> {
 }
 
 impl<'window> App<'window> {
+  // The second window doesn't take actions, only keys to fly the spectator and the mouse to turn it
+  fn second_window_event(&mut self, event: WindowEvent) {
+    let Some(second) = &mut self.second else { return };
+    match event {
+      WindowEvent::CloseRequested => {
+        self.second = None;
+        self.second_view = None;
+      }
+      WindowEvent::Resized(new_size) if new_size.width != 0 && new_size.height != 0 => {
+        second.camera.aspect_ratio = new_size.width as f32 / new_size.height as f32;
+        second.ctx.resize(new_size);
+      }
+      WindowEvent::Focused(focused) => {
+        second.focused = focused;
+        second.dragging = false;
+      }
+      WindowEvent::KeyboardInput { event, .. } => if let PhysicalKey::Code(key_code) = event.physical_key {
+        match event.state {
+          ElementState::Pressed => if !self.keys_pressed.contains(&key_code) { self.keys_pressed.push(key_code) },
+          ElementState::Released => self.keys_pressed.retain(|&k| k != key_code),
+        }
+      },
+      WindowEvent::MouseInput { state, button: MouseButton::Left, .. } => second.dragging = state == ElementState::Pressed,
+      WindowEvent::CursorMoved { position, .. } => {
+        if let Some(last) = second.cursor.replace(position) && second.dragging && second.view == SecondView::Spectator {
+          let delta = Vec2::new((position.x - last.x) as f32, (position.y - last.y) as f32);
+          second.camera.rotate(delta, second.camera.sensitivity);
+        }
+      }
+      WindowEvent::CursorLeft { .. } => second.cursor = None,
+      _ => (),
+    }
+  }
+
+  /// Moves the second window's camera along for the frame, the top down view with the main camera
+  /// and the spectator with the movement keys while it has focus
+  fn move_second_camera(&mut self, dt: f32) {
+    let Some(second) = &mut self.second else { return };
+    let main = &self.game_data.camera;
+    match second.view {
+      SecondView::Top => {
+        second.camera.position = main.position + Vec3::Y * TOP_HEIGHT;
+        second.camera.prev_position = main.prev_position.map(|prev| prev + Vec3::Y * TOP_HEIGHT);
+      }
+      SecondView::Spectator if second.focused => {
+        let held = |action| self.input.held(action, &self.keys_pressed, &self.mouse_buttons_pressed, &self.pad_pressed);
+        let [right, up, forward] = second.camera.basis();
+        let mut displacement = Vec3::ZERO;
+        if held(Action::MoveForward) { displacement += forward }
+        if held(Action::MoveBack) { displacement -= forward }
+        if held(Action::MoveRight) { displacement += right }
+        if held(Action::MoveLeft) { displacement -= right }
+        if held(Action::Jump) { displacement += up }
+        if held(Action::Descend) { displacement -= up }
+        second.camera.position += displacement.normalize_or_zero() * main.speed * dt;
+      }
+      SecondView::Spectator => (),
+    }
+  }
+
   fn redraw(&mut self) {
-    let before = Instant::now();
-    self.tick_world();
+    if self.hidden() { return }
+    // Sleeping before the frame rather than after keeps input as fresh as possible once it's drawn
+    if let Some(fps) = self.game_data.render.fps_limit {
+      let next = self.last_frame + Duration::from_secs_f32(1.0 / fps);
+      if let Some(wait) = next.checked_duration_since(Instant::now()) { std::thread::sleep(wait) }
+    }
+    self.last_frame = Instant::now();
+    self.poll_worlds();
+    // Nothing to simulate until there's a world
+    if self.menu.is_none() { self.tick_world() }
 
-    self.wgpu_ctx.get_mut().unwrap().draw(&self.game_data);
+    let ctx = self.wgpu_ctx.get_mut().unwrap();
+    if let Some(path) = self.game_data.thumbnail.take() { ctx.request_thumbnail(path, saves::THUMBNAIL_WIDTH) }
+    if let Some(path) = self.game_data.gbuffer.take() {
+      let far = self.game_data.render.max_distance;
+      ctx.request_gbuffer(move |gbuffer| match gbuffer.write(&path, far) {
+        Ok(()) => tracing::info!("Saved normals to {} and depth beside them", path.display()),
+        Err(err) => tracing::error!("Failed to save the gbuffer to {}: {err}", path.display()),
+      });
+    }
+    ctx.draw(&self.game_data, self.menu.as_mut());
+    if let Some(second) = &mut self.second { second.ctx.draw_view(&self.game_data, &second.camera) }
+    if let Some(world) = self.menu.as_mut().and_then(|menu| menu.started.take()) { self.load_world(world) }
     self.window.get().unwrap().request_redraw();
     if self.fps_update_timer > 1.0 {
-      println!("FPS: {:.1}", 1.0 / Instant::now().duration_since(before).as_secs_f32());
+      if let Some((tick, checksum)) = self.game_data.last_checksum {
+        tracing::info!("Tick {tick} checksum: {checksum:016x}");
+      }
       self.fps_update_timer = 0.0;
     }
   }
 
+  fn hidden(&self) -> bool { self.minimized || self.occluded }
+
+  /// Picks up drawing where it left off once the window is visible again
+  fn restore(&mut self) {
+    // The last frame drawn is too old to blend with
+    if let Some(ctx) = self.wgpu_ctx.get_mut() { ctx.reset_history() }
+    if let Some(second) = &mut self.second { second.ctx.reset_history() }
+    self.window.get().unwrap().request_redraw();
+  }
+
   fn toggle_mouse_capture(&mut self) {
     let window = self.window.get().unwrap();
     let new_mode = if self.mouse_captured { CursorGrabMode::None } else { CursorGrabMode::Confined };
@@ -129,41 +456,311 @@ impl<'window> App<'window> {
     self.last_update = now;
     if dt > 1.0 { return }
     self.fps_update_timer += dt;
+    zone!("tick_world");
+    // Anything can draw lines while the frame's being worked out, they're shown on it and gone by the next
+    self.game_data.debug_lines.clear();
+    self.console.poll(&mut self.game_data, &mut self.plugins);
+    for event in self.gamepads.events() {
+      match event {
+        PadEvent::Pressed(button) => if !self.pad_pressed.contains(&button) {
+          self.pad_pressed.push(button);
+          self.input_down(Input::Pad(button));
+        },
+        PadEvent::Released(button) => self.pad_pressed.retain(|&b| b != button),
+      }
+    }
     self.handle_inputs(dt);
+    self.game_data.camera.advance(dt);
+    self.move_second_camera(dt);
+    // Everything which changes the world runs in fixed ticks, so it plays out the same at any frame rate.
+    // Frames land between ticks, the renderer draws them part way from the last tick to the latest
+    for _ in 0 .. self.game_data.physics.steps_due(dt) {
+      self.move_camera();
+      self.game_data.step();
+      self.plugins.tick(self.game_data.tick, &mut self.game_data);
+    }
+    if std::mem::take(&mut self.game_data.graph_changed) && let Some(ctx) = self.wgpu_ctx.get_mut() { ctx.update_voxels(&self.game_data.sdg) }
+    if std::mem::take(&mut self.game_data.materials_changed) {
+      if let Some(ctx) = self.wgpu_ctx.get_mut() { ctx.update_materials(&self.game_data.materials) }
+      if let Some(second) = &mut self.second { second.ctx.update_materials(&self.game_data.materials) }
+    }
+    // Only while playing, the crosshair doesn't point at anything while the cursor is free
+    self.game_data.targeted = if self.mouse_captured { self.crosshair() } else { None };
+    self.gather_debug_lines();
+    count!("nodes touched", self.game_data.sdg.take_touched());
+  }
+
+  /// What's under the crosshair. The renderer picks it out of what it drew, so it's exactly what's on screen
+  /// (level of detail and all). Until the first pick makes it back there's only a raycast to go by
+  fn crosshair(&self) -> Option<RayHit> {
+    match self.wgpu_ctx.get().and_then(|ctx| ctx.picked()) {
+      // Objects removed since it was drawn leave it pointing past the end
+      Some(picked) => picked.filter(|hit| self.game_data.objects.contains(hit.object)),
+      None => {
+        let camera = &self.game_data.camera;
+        self.game_data.raycast(camera.position, camera.forward(), 256.0)
+      }
+    }
+  }
+
+  fn gather_debug_lines(&mut self) {
+    // The prefab being placed, green where it fits and red where it'd overlap something
+    if let Some(prefab) = &self.placing && let Some(hit) = self.crosshair() {
+      let preview = self.placement.preview(&self.game_data, &hit, prefab);
+      let color = if preview.valid { Vec3::new(0.2, 1.0, 0.2) } else { Vec3::new(1.0, 0.2, 0.2) };
+      self.game_data.debug_lines.transformed_box(preview.transform, prefab.min_cell.as_vec3(), prefab.max_cell.as_vec3() + 1.0, color);
+    }
+    // The frozen ray in yellow up to what it hits now, where there's a cross and the face's normal in magenta.
+    // Red out to where the crosshair gives up if it misses
+    if let Some((origin, dir)) = self.game_data.debug_flags.ray {
+      let hit = self.game_data.raycast(origin, dir, 256.0);
+      let lines = &mut self.game_data.debug_lines;
+      match hit {
+        Some(hit) => {
+          lines.line(origin, hit.pos, Vec3::new(1.0, 1.0, 0.0));
+          lines.cross(hit.pos, 0.5, Vec3::new(1.0, 1.0, 0.0));
+          let normal = self.game_data.objects[hit.object].rot * hit.normal.as_vec3();
+          lines.line(hit.pos, hit.pos + normal, Vec3::new(1.0, 0.0, 1.0));
+        }
+        None => lines.line(origin, origin + dir * 256.0, Vec3::new(1.0, 0.2, 0.2)),
+      }
+    }
+    let GameData { debug_flags, debug_lines, physics, objects, streaming, .. } = &mut self.game_data;
+    if debug_flags.chunks && let Some(streaming) = streaming { streaming.draw_chunks(debug_lines) }
+    if debug_flags.contacts { physics.draw_contacts(debug_lines) }
+    if debug_flags.bounds {
+      physics.draw_bounds(debug_lines);
+      // The bounds exactly as the renderer sees them, in cyan
+      for object in objects.values() {
+        debug_lines.transformed_box(object.transform(), object.min_cell.as_vec3(), object.max_cell.as_vec3() + 1.0, Vec3::new(0.0, 1.0, 1.0));
+      }
+    }
+  }
+
+  // One-shot actions which shouldn't repeat while a key is held
+  fn input_down(&mut self, input: Input) {
+    // The number keys pick leaves by number, so they aren't rebindable
+    if self.mouse_captured && let Input::Key(key) = input && (KeyCode::Digit1 as Index ..= KeyCode::Digit9 as Index).contains(&(key as Index)) {
+      let leaf = key as Index - KeyCode::Digit0 as Index;
+      if self.game_data.sdg.is_leaf(leaf) { self.selected_leaf = leaf }
+    }
+    let actions: Vec<_> = self.input.actions(input, &self.keys_pressed).collect();
+    for action in actions { self.act(action) }
+  }
+
+  fn act(&mut self, action: Action) {
+    if action == Action::ToggleCapture {
+      self.toggle_mouse_capture();
+      return
+    }
+    if !self.mouse_captured { return }
+    let flags = &mut self.game_data.debug_flags;
+    match action {
+      Action::BreakBlock | Action::PlaceBlock => self.edit_world(action),
+      Action::ToggleContacts => flags.contacts = !flags.contacts,
+      Action::ToggleBounds => flags.bounds = !flags.bounds,
+      Action::ToggleRay => {
+        let camera = &self.game_data.camera;
+        flags.ray = match flags.ray { Some(_) => None, None => Some((camera.position, camera.forward())) };
+      }
+      Action::ToggleChunks => flags.chunks = !flags.chunks,
+      Action::ToggleChecksum => {
+        flags.checksum = !flags.checksum;
+        if !flags.checksum { self.game_data.last_checksum = None }
+      }
+      Action::SpawnBall => self.spawn_dummy(DummyShape::Ball),
+      Action::SpawnCuboid => self.spawn_dummy(DummyShape::Cuboid),
+      Action::TogglePlacing => self.toggle_placing(),
+      Action::TurnPrefab if self.placing.is_some() => self.placement.turns = (self.placement.turns + 1) % 4,
+      Action::CycleSnap if let Some(prefab) = &self.placing => {
+        // Up to the prefab's own size, past that it'd jump around more than it lines up
+        self.placement.snap = (self.placement.snap + 1) % (prefab.dag_ref.height + 1);
+        tracing::info!("Snapping to {} cells", 1 << self.placement.snap);
+      }
+      Action::Scorch => self.scorch(),
+      Action::ToggleMovement => {
+        self.movement = match self.movement {
+          MovementMode::Fly => MovementMode::Collide,
+          MovementMode::Collide => MovementMode::Walk,
+          MovementMode::Walk => MovementMode::Fly,
+        };
+        // Whatever it was doing when last on foot is long over
+        self.game_data.player.vertical_speed = 0.0;
+        tracing::info!("Movement mode: {:?}", self.movement);
+      }
+      Action::CycleProjection => {
+        let camera = &mut self.game_data.camera;
+        camera.set_projection(camera.projection.next());
+        tracing::info!("Projection: {:?}", camera.projection);
+      }
+      Action::ToggleOverlay => if let Some(ctx) = self.wgpu_ctx.get_mut() { ctx.toggle_overlay() },
+      Action::Screenshot => if let Some(ctx) = self.wgpu_ctx.get_mut() {
+        let time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+        ctx.request_screenshot(format!("screenshot_{}.ppm", time.as_secs()).into());
+      }
+      Action::QuickSave => {
+        let slot = self.game_data.world.slot.clone().unwrap_or_else(|| saves::QUICKSAVE_SLOT.to_string());
+        match saves::save(&mut self.game_data, &slot) {
+          Ok(()) => tracing::info!("Saved to {slot}"),
+          Err(err) => tracing::error!("Failed to save {slot}: {err}"),
+        }
+      }
+      // Back to however the world was when it was last saved
+      Action::QuickLoad => {
+        let slot = self.game_data.world.slot.clone().unwrap_or_else(|| saves::QUICKSAVE_SLOT.to_string());
+        self.load_world(WorldSource::Save(slot));
+      }
+      Action::CyclePaint => {
+        self.paint = PaintMode::next(self.paint);
+        tracing::info!("Paint mode: {:?}", self.paint);
+      }
+      Action::BrushSmaller => self.brush_radius = self.brush_radius.saturating_sub(1).max(1),
+      Action::BrushBigger => self.brush_radius = (self.brush_radius + 1).min(8),
+      Action::Undo => {
+        if self.game_data.undo() && let Some(ctx) = self.wgpu_ctx.get_mut() { ctx.update_voxels(&self.game_data.sdg) }
+      }
+      Action::Redo => {
+        if self.game_data.redo() && let Some(ctx) = self.wgpu_ctx.get_mut() { ctx.update_voxels(&self.game_data.sdg) }
+      }
+      Action::DummySmaller => self.dummy_size = (self.dummy_size / 2.0).max(0.125),
+      Action::DummyBigger => self.dummy_size = (self.dummy_size * 2.0).min(16.0),
+      Action::TimeFaster | Action::TimeSlower => {
+        let clock = &mut self.game_data.time_of_day;
+        if action == Action::TimeFaster { clock.faster() } else { clock.slower() }
+        tracing::info!("A day takes {:.0} seconds", 24.0 / clock.speed);
+      }
+      Action::PauseTime => {
+        let clock = &mut self.game_data.time_of_day;
+        clock.paused = !clock.paused;
+        tracing::info!("Time is {}", if clock.paused { "paused" } else { "running" });
+      }
+      _ => ()
+    }
+  }
+
+  /// Breaking removes (or paints, in paint mode) the targeted cell, placing puts the selected leaf
+  /// against the targeted face
+  fn edit_world(&mut self, action: Action) {
+    let Some(hit) = self.crosshair() else { return };
+    if action == Action::BreakBlock && let Some(mode) = self.paint {
+      // A pick from before the grid last grew can point past it
+      let Some(cell) = self.game_data.objects[hit.object].grid_cell(hit.cell) else { return };
+      mode.paint(&mut self.game_data, hit.object, cell, self.brush_radius, self.selected_leaf);
+      if let Some(ctx) = self.wgpu_ctx.get_mut() { ctx.update_voxels(&self.game_data.sdg) }
+      return
+    }
+    if action == Action::PlaceBlock && let Some(prefab) = &self.placing {
+      let preview = self.placement.preview(&self.game_data, &hit, prefab);
+      if !preview.valid {
+        tracing::warn!("That would overlap something");
+        return
+      }
+      let object = VoxelObject { pos: preview.pos, rot: preview.rot, ..prefab.clone() };
+      self.game_data.add_object(object, true);
+      if let Some(ctx) = self.wgpu_ctx.get_mut() { ctx.update_voxels(&self.game_data.sdg) }
+      return
+    }
+    let (cell, leaf) = match action {
+      Action::BreakBlock => (hit.cell, EMPTY),
+      Action::PlaceBlock => (hit.cell + hit.normal, self.selected_leaf),
+      _ => return
+    };
+    // Building off the edge of the grid grows it instead
+    let Some(cell) = self.game_data.grow_to_fit(hit.object, cell) else { return };
+    self.game_data.set_cell(hit.object, cell, leaf);
+    if let Some(ctx) = self.wgpu_ctx.get_mut() { ctx.update_voxels(&self.game_data.sdg) }
+  }
+
+  /// Leaves a scorch mark on whatever the crosshair is on
+  fn scorch(&mut self) {
+    let Some(hit) = self.crosshair() else { return };
+    self.game_data.spawn_decal(&hit, 1.5, Vec4::new(0.05, 0.04, 0.03, 0.85), 30.0);
+  }
+
+  fn spawn_dummy(&mut self, shape: DummyShape) {
+    let camera = &self.game_data.camera;
+    let forward = camera.forward();
+    // Spawn just past the crosshair so we don't start inside the camera
+    let pos = camera.position + forward * (self.dummy_size + 1.0);
+    let vel = forward * camera.speed;
+    self.game_data.physics.spawn_dummy(shape, self.dummy_size, pos, vel);
+    tracing::info!("Spawned {shape:?} (size {}) at {pos:.1}, {} bodies", self.dummy_size, self.game_data.physics.body_count());
+  }
+
+  /// Starts or stops placing a dynamic voxel cube of the selected leaf, which follows the crosshair until
+  /// right click drops it. R turns it and G changes the snap
+  fn toggle_placing(&mut self) {
+    if self.placing.take().is_some() {
+      tracing::info!("Stopped placing");
+      return
+    }
+    let height = 2;
+    let leaf = self.selected_leaf;
+    let head = self.game_data.sdg.build(height, |_| leaf);
+    let size = 1u32 << height;
+    self.placing = Some(VoxelObject::new(&self.game_data.sdg, DagRef::new(head, height), UVec3::ZERO, UVec3::splat(size - 1), Vec3::ZERO));
+    tracing::info!("Placing a crate of leaf {leaf}, right click to drop it, R to turn it, G to change the snap");
   }
 
   fn handle_inputs(&mut self, delta_time: f32) {
-    if self.keys_pressed.contains(&KeyCode::Escape)
-    || (self.mouse_buttons_pressed.contains(&MouseButton::Left) && !self.mouse_captured) {
+    // Clicking into the window captures the mouse too
+    if self.mouse_buttons_pressed.contains(&MouseButton::Left) && !self.mouse_captured {
       self.toggle_mouse_capture()
     }
-    if !self.mouse_captured { return }
-    
-    if self.mouse_delta != Vec2::ZERO {
-      self.game_data.camera.rotate(self.mouse_delta, 0.002);
-      self.mouse_delta = Vec2::ZERO;
+    if !self.mouse_captured {
+      self.game_data.camera.set_zoomed(false);
+      return
     }
+    
+    self.game_data.camera.look(self.mouse_delta);
+    self.mouse_delta = Vec2::ZERO;
+    let (_, look_stick) = self.gamepads.sticks();
+    // Full tilt turns at 3 radians a second, rotate wants it screen-space with +y down
+    if look_stick != Vec2::ZERO { self.game_data.camera.rotate(Vec2::new(look_stick.x, -look_stick.y), 3.0 * delta_time) }
+    let held = |action| self.input.held(action, &self.keys_pressed, &self.mouse_buttons_pressed, &self.pad_pressed);
+    let roll = held(Action::RollRight) as i32 - held(Action::RollLeft) as i32;
+    // Half a turn a second
+    if roll != 0 { self.game_data.camera.turn(Vec3::Z * roll as f32 * PI * delta_time) }
+    self.game_data.camera.set_zoomed(held(Action::Zoom));
+  }
 
+  /// One tick's worth of flying or walking, looking around is left to handle_inputs so it stays smooth
+  fn move_camera(&mut self) {
+    self.game_data.camera.prev_position = Some(self.game_data.camera.position);
+    // Following something leaves the keys alone, the mouse still swings the camera around it
+    if self.game_data.follow.is_some() { return self.game_data.follow_camera() }
+    // The movement keys fly the spectator instead while its window has focus
+    if !self.mouse_captured || self.second.as_ref().is_some_and(|second| second.focused) { return }
+    let (move_stick, _) = self.gamepads.sticks();
     let mut displacement = Vec3::ZERO; // Replace with impulse
-    let camera_speed = self.game_data.camera.speed * delta_time;
+    let camera_speed = self.game_data.camera.speed * TIMESTEP;
     let (right, _, mut forward) = self.game_data.camera.basis().into();
-    forward = forward.with_y(0.0).normalize();
-    for key in &self.keys_pressed {
-      match key {
-        KeyCode::Escape => {}
-        KeyCode::KeyW => { displacement += forward }
-        KeyCode::KeyS => { displacement -= forward }
-        KeyCode::KeyD => { displacement += right }
-        KeyCode::KeyA => { displacement -= right }
-        KeyCode::Space => { displacement += Vec3::Y }
-        KeyCode::ShiftLeft => { displacement -= Vec3::Y }
-        KeyCode::Equal => { self.game_data.camera.speed *= 1.003 }
-        KeyCode::Minus => { self.game_data.camera.speed /= 1.003 }
-        _ => ()
-      }
-    }
-    self.game_data.camera.position += displacement.normalize_or_zero() * camera_speed;
-
+    forward = forward.with_y(0.0).normalize_or_zero();
+    let held = |action| self.input.held(action, &self.keys_pressed, &self.mouse_buttons_pressed, &self.pad_pressed);
+    if held(Action::MoveForward) { displacement += forward }
+    if held(Action::MoveBack) { displacement -= forward }
+    if held(Action::MoveRight) { displacement += right }
+    if held(Action::MoveLeft) { displacement -= right }
+    let walking = self.movement == MovementMode::Walk;
+    let jump = held(Action::Jump);
+    if jump && !walking { displacement += Vec3::Y }
+    if held(Action::Descend) && !walking { displacement -= Vec3::Y }
+    if held(Action::SpeedUp) { self.game_data.camera.speed *= 1.003 }
+    if held(Action::SpeedDown) { self.game_data.camera.speed /= 1.003 }
+    displacement += forward * move_stick.y + right * move_stick.x;
+    // Clamped rather than normalized so a half tilted stick moves at half speed
+    let motion = displacement.clamp_length_max(1.0) * camera_speed;
+    // The player collides with the snapshots, which edits since the last tick left behind
+    if self.movement != MovementMode::Fly { self.game_data.refresh_shapes() }
+    let GameData { camera, player, objects, .. } = &mut self.game_data;
+    match self.movement {
+      MovementMode::Fly => camera.position += motion,
+      MovementMode::Collide => camera.position = player.move_and_slide(objects, camera.position, motion),
+      MovementMode::Walk => {
+        let center = player.walk(objects, player.center(camera.position), motion, jump);
+        camera.position = player.eye(center);
+      }
+    }
   }
 
 }
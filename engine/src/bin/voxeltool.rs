@@ -0,0 +1,80 @@
+//! Turns content into world files (what GameData::save writes) without opening a window,
+//! so it can be prepared ahead of time and started with voxel_game --open <world>
+
+use glam::Vec3;
+use std::path::Path;
+use voxel_game::heightmap::{self, Heightmap, Layering};
+use voxel_game::objects::{self, GameData};
+
+const USAGE: &str = "\
+Usage:
+  voxeltool convert-vox <model.vox> <world>               A world holding just the model, its palette becomes its materials
+  voxeltool convert-heightmap <image.png> <world> [max height]
+                                                          A world holding the image as terrain, grass over stone like
+                                                          the templates. Max height defaults to 32 cells
+  voxeltool inspect <world>                               Describe what's in a world file";
+
+fn main() {
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  let args: Vec<&str> = args.iter().map(String::as_str).collect();
+  let result = match args.as_slice() {
+    ["convert-vox", input, output] => convert_vox(input.as_ref(), output.as_ref()),
+    ["convert-heightmap", input, output] => convert_heightmap(input.as_ref(), output.as_ref(), 32),
+    ["convert-heightmap", input, output, max_height] => match max_height.parse() {
+      Ok(max_height) => convert_heightmap(input.as_ref(), output.as_ref(), max_height),
+      Err(_) => Err(format!("{max_height} isn't a height")),
+    },
+    ["inspect", path] => inspect(path.as_ref()),
+    _ => {
+      eprintln!("{USAGE}");
+      std::process::exit(2);
+    }
+  };
+  if let Err(err) = result {
+    eprintln!("{err}");
+    std::process::exit(1);
+  }
+}
+
+fn convert_vox(input: &Path, output: &Path) -> Result<(), String> {
+  let (mut game_data, _) = GameData::blank();
+  let import = objects::import_vox(&mut game_data.sdg, &mut game_data.materials, input, Vec3::ZERO)
+    .map_err(|err| format!("Failed to import {}: {err}", input.display()))?;
+  println!("Imported {} using {} palette colors", input.display(), import.leaf_colors.len());
+  // Nothing's under it to land on, so it stays put until the engine gets it
+  game_data.add_object(import.object, false);
+  save(&game_data, output)
+}
+
+fn convert_heightmap(input: &Path, output: &Path, max_height: u32) -> Result<(), String> {
+  let (mut game_data, leaves) = GameData::blank();
+  let heightmap = Heightmap::load(input).map_err(|err| format!("Failed to read {}: {err}", input.display()))?;
+  let layering = Layering { bands: vec![(leaves.surface, 2)], base: leaves.solid };
+  let object = heightmap::terrain(&mut game_data.sdg, &heightmap, max_height, &layering, Vec3::ZERO);
+  println!("Built {} as {}x{} columns", input.display(), heightmap.width, heightmap.depth);
+  game_data.add_object(object, false);
+  save(&game_data, output)
+}
+
+fn save(game_data: &GameData, output: &Path) -> Result<(), String> {
+  game_data.save(output).map_err(|err| format!("Failed to write {}: {err}", output.display()))?;
+  let bytes = std::fs::metadata(output).map_or(0, |metadata| metadata.len());
+  println!("Wrote {} ({bytes} bytes)", output.display());
+  Ok(())
+}
+
+fn inspect(path: &Path) -> Result<(), String> {
+  let game_data = GameData::load(path).map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
+  println!("{} objects, {} leaves, {} ticks in", game_data.objects.len(), game_data.sdg.leaves().len(), game_data.tick);
+  for (idx, object) in game_data.objects.iter() {
+    let dynamic = object.physics.is_some_and(|handle| game_data.physics.is_dynamic(handle.body));
+    println!(
+      "  object {idx}: {} cells across, solid from {} to {}, at {:.1}{}",
+      1u32 << object.dag_ref.height, object.signed_cell(object.min_cell.as_ivec3()), object.signed_cell(object.max_cell.as_ivec3()), object.pos, if dynamic { ", dynamic" } else { "" }
+    );
+  }
+  let heads: Vec<_> = game_data.objects.values().map(|object| object.dag_ref.head).collect();
+  println!("{}", game_data.sdg.stats(&heads).map_err(|err| err.to_string())?);
+  println!("Camera at {:.1} looking along {:.2}", game_data.camera.position, game_data.camera.forward());
+  Ok(())
+}
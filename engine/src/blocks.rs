@@ -0,0 +1,78 @@
+//! Named block types, so nothing outside the graph has to remember which leaf is which. Every block is a leaf
+//! (and so a material, materials go by leaf) bound to a name like "stone", along with how it plays.
+
+use crate::materials::{Material, MaterialRegistry, FLAG_TRANSLUCENT, FLAG_WATER};
+use sdg::prelude::{BasicNode3d, Index, SparseDirectedGraph, EMPTY};
+
+/// How a block behaves, as opposed to how it looks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockProperties {
+  /// Whether things placed in the world are kept out of it
+  pub solid: bool,
+  /// Whether light and sight carry on through it
+  pub transparent: bool,
+  /// How many times over GameData::break_speed an impact has to be to break it off
+  pub hardness: f32,
+}
+impl BlockProperties {
+  pub const AIR: Self = Self { solid: false, transparent: true, hardness: 0.0 };
+
+  /// What a block made of material most likely plays like: see through if it's translucent, and not solid if it's water
+  pub fn of(material: &Material) -> Self {
+    Self { solid: material.flags & FLAG_WATER == 0, transparent: material.flags & FLAG_TRANSLUCENT != 0, ..Self::default() }
+  }
+}
+impl Default for BlockProperties {
+  fn default() -> Self { Self { solid: true, transparent: false, hardness: 1.0 } }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+  pub name: String,
+  /// Also the block's material ID, see MaterialRegistry
+  pub leaf: Index,
+  pub properties: BlockProperties,
+}
+
+/// Every named block, in the order they were registered
+#[derive(Default)]
+pub struct BlockRegistry {
+  blocks: Vec<Block>,
+}
+impl BlockRegistry {
+  /// The leaf called name, registering it as a new leaf which renders as material if there isn't one yet.
+  /// A block that's already there keeps its leaf and material but takes on properties
+  pub fn register(&mut self, sdg: &mut SparseDirectedGraph<BasicNode3d>, materials: &mut MaterialRegistry, name: &str, material: Material, properties: BlockProperties) -> Index {
+    if let Some(block) = self.blocks.iter_mut().find(|block| block.name == name) {
+      block.properties = properties;
+      return block.leaf
+    }
+    let leaf = materials.register(sdg, material);
+    self.bind(name, leaf, properties);
+    leaf
+  }
+
+  /// Names a leaf the graph already has, replacing whatever name it went by before
+  pub fn bind(&mut self, name: &str, leaf: Index, properties: BlockProperties) {
+    self.blocks.retain(|block| block.name != name && block.leaf != leaf);
+    self.blocks.push(Block { name: name.to_string(), leaf, properties });
+  }
+
+  pub fn get(&self, name: &str) -> Option<&Block> { self.blocks.iter().find(|block| block.name == name) }
+
+  pub fn leaf(&self, name: &str) -> Option<Index> { self.get(name).map(|block| block.leaf) }
+
+  /// The block leaf is bound to, None for leaves without a name
+  pub fn of_leaf(&self, leaf: Index) -> Option<&Block> { self.blocks.iter().find(|block| block.leaf == leaf) }
+
+  /// How leaf plays, leaves without a name are solid unless they're EMPTY
+  pub fn properties(&self, leaf: Index) -> BlockProperties {
+    match self.of_leaf(leaf) {
+      Some(block) => block.properties,
+      None if leaf == EMPTY => BlockProperties::AIR,
+      None => BlockProperties::default(),
+    }
+  }
+
+  pub fn all(&self) -> &[Block] { &self.blocks }
+}
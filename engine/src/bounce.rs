@@ -0,0 +1,57 @@
+//! Coarse bounce lighting. Every node's cells averaged into one color and how much of the node they fill,
+//! which the lighting pass cone traces against: a cone reads further up the tree the wider it's got,
+//! so a few of them per pixel take in everything nearby. The averages follow the graph, so they're worked out
+//! over again whenever it or the materials change, see WgpuCtx::update_averages
+
+use crate::materials::{MaterialRegistry, FLAG_TRANSLUCENT};
+use glam::Vec3;
+use sdg::prelude::*;
+
+/// What a node looks like from far enough away
+#[derive(Clone, Copy, Default)]
+pub struct Average {
+  /// Over the cells which aren't empty, weighted by how much of each there is
+  pub albedo: Vec3,
+  pub emissive: Vec3,
+  /// Share of the node that blocks light, 0 if it's empty and 1 if it's solid through.
+  /// Translucent cells count for their opacity
+  pub coverage: f32,
+}
+
+/// Averages by graph index, worked out as they're asked for since a node's average needs its children's first
+pub struct NodeAverages<'a> {
+  sdg: &'a SparseDirectedGraph<BasicNode3d>,
+  materials: &'a MaterialRegistry,
+  averages: Vec<Option<Average>>,
+}
+impl<'a> NodeAverages<'a> {
+  pub fn new(sdg: &'a SparseDirectedGraph<BasicNode3d>, materials: &'a MaterialRegistry) -> Self {
+    Self { sdg, materials, averages: vec![None; sdg.nodes.len()] }
+  }
+
+  pub fn get(&mut self, idx: Index) -> Average {
+    if let Some(average) = self.averages[idx as usize] { return average }
+    let average = if idx == EMPTY {
+      Average::default()
+    } else if self.sdg.is_leaf(idx) {
+      let material = self.materials.get(idx);
+      let coverage = if material.flags & FLAG_TRANSLUCENT != 0 { material.opacity } else { 1.0 };
+      Average { albedo: material.albedo, emissive: material.emissive, coverage }
+    } else {
+      let children = *self.sdg.nodes.get(idx as usize).unwrap();
+      let mut sum = Average::default();
+      let mut count = 0;
+      for &child in &children {
+        let child = self.get(child);
+        sum.albedo += child.albedo * child.coverage;
+        sum.emissive += child.emissive * child.coverage;
+        sum.coverage += child.coverage;
+        count += 1;
+      }
+      if sum.coverage == 0.0 { return Average::default() }
+      Average { albedo: sum.albedo / sum.coverage, emissive: sum.emissive / sum.coverage, coverage: sum.coverage / count as f32 }
+    };
+    self.averages[idx as usize] = Some(average);
+    average
+  }
+}
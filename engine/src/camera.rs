@@ -1,58 +1,235 @@
-use glam::{Vec2, Vec3};
+use glam::{EulerRot, Mat4, Quat, Vec2, Vec3};
 use std::f32::consts::PI;
 const QUARTER: f32 = PI / 2.;
+// Seconds the fov takes to get most (1 - 1/e) of the way to a new zoom
+const ZOOM_TIME: f32 = 0.06;
+// Gap kept between a following camera and whatever it got pulled in by, and the closest it gets pulled in to
+const ARM_MARGIN: f32 = 0.2;
+const MIN_ARM: f32 = 0.5;
+
+/// How rays leave the camera
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Projection {
+  Perspective,
+  /// Parallel rays, starting out looking straight down
+  Top,
+  /// Parallel rays, starting out looking level
+  Side,
+}
+impl Projection {
+  pub fn next(self) -> Self {
+    match self {
+      Projection::Perspective => Projection::Top,
+      Projection::Top => Projection::Side,
+      Projection::Side => Projection::Perspective,
+    }
+  }
+}
 
 /// Camera struct for handling camera position, rotation, and movement
+#[derive(Clone)]
 pub struct Camera {
   pub speed: f32, // Temp field for persistent move speed until it gets tethered
   // Position
   pub position: Vec3,
-  yaw: f32,   // Horizontal rotation in radians
-  pitch: f32, // Vertical rotation in radians
+  /// Where the camera was before the last tick moved it, None draws it right at position
+  pub prev_position: Option<Vec3>,
+  // Camera space -> world space, the camera looks down its -z with +y up
+  orientation: Quat,
+
+  // Mouse look
+  /// Radians turned per count of mouse movement
+  pub sensitivity: f32,
+  /// Seconds over which mouse movement is eased in, 0 turns as soon as the mouse moves
+  pub smoothing: f32,
+  /// Extra sensitivity (as a multiple of it) per 1000 counts a second the mouse moves, 0 is none
+  pub acceleration: f32,
+  // Mouse movement not turned through yet, see smoothing
+  pending_look: Vec2,
 
   // Camera properties
   pub aspect_ratio: f32,
+  /// Vertical field of view in radians as drawn, eases towards base_fov or its zoomed fraction in advance
   pub fov: f32,
+  pub base_fov: f32,
+  /// Fraction of base_fov zooming narrows to
+  pub zoom: f32,
+  zoomed: bool,
+  pub projection: Projection,
+  /// Half the height of the view in world units while orthographic, zooming narrows it like it does fov
+  pub ortho_extent: f32,
+  // Pitch to go back to when switching back to perspective
+  perspective_pitch: f32,
 }
 
 impl Default for Camera {
   fn default() -> Self {
     Self {
       speed: 8.0,
-      position: Vec3::new(-5., 10., -5.),
-      yaw: PI/4.,
-      pitch: -0.5,
+      position: Vec3::new(-10., 45., -10.),
+      prev_position: None,
+      orientation: Self::orientation_of(Vec3::new(PI/4., -0.5, 0.)),
+      sensitivity: 0.002,
+      smoothing: 0.0,
+      acceleration: 0.0,
+      pending_look: Vec2::ZERO,
       aspect_ratio: 2.0,
       fov: 1.0,
+      base_fov: 1.0,
+      zoom: 0.3,
+      zoomed: false,
+      projection: Projection::Perspective,
+      ortho_extent: 32.0,
+      perspective_pitch: -0.5,
     }
   }
 }
 
 impl Camera {
-  /// Rotates the camera by the specified yaw and pitch deltas
+  // Yaw 0 looks down +x and turns towards +z, pitch turns up and roll turns clockwise as seen through the camera
+  fn orientation_of(angles: Vec3) -> Quat {
+    Quat::from_euler(EulerRot::YXZ, -(angles.x + QUARTER), angles.y, -angles.z)
+  }
+
+  /// Rotates the camera by the specified yaw and pitch deltas, yaw around the world's up and pitch clamped short
+  /// of straight up or down. Any roll is kept
   pub fn rotate(&mut self, raw_delta: Vec2, sensitivity: f32) {
-    self.yaw += raw_delta.x * sensitivity;
-    self.pitch -= raw_delta.y * sensitivity;
-    self.pitch = self.pitch.clamp(-QUARTER + 0.001, QUARTER - 0.001);
-    self.yaw = self.yaw % (PI * 2.);
-  }
-
-  pub fn forward(&self) -> Vec3 {
-    let (yaw_sin, yaw_cos) = self.yaw.sin_cos();
-    let (pitch_sin, pitch_cos) = self.pitch.sin_cos();
-    Vec3::new(
-      yaw_cos * pitch_cos,
-      pitch_sin,
-      yaw_sin * pitch_cos,
-    ).normalize()
-  }
-  
+    let angles = self.angles();
+    let yaw = (angles.x + raw_delta.x * sensitivity) % (PI * 2.);
+    let pitch = (angles.y - raw_delta.y * sensitivity).clamp(-QUARTER + 0.001, QUARTER - 0.001);
+    self.orientation = Self::orientation_of(Vec3::new(yaw, pitch, angles.z));
+  }
+
+  /// Queues up mouse movement for advance to turn through, smoothed and accelerated as set
+  pub fn look(&mut self, mouse_delta: Vec2) { self.pending_look += mouse_delta }
+
+  /// Zooms in to zoom * base_fov, or back out, over the next few advances
+  pub fn set_zoomed(&mut self, zoomed: bool) { self.zoomed = zoomed }
+
+  /// Moves everything that eases over time dt seconds along
+  pub fn advance(&mut self, dt: f32) {
+    let eased = |time: f32| if time > 0.0 { 1.0 - (-dt / time).exp() } else { 1.0 };
+    let look = self.pending_look * eased(self.smoothing);
+    self.pending_look -= look;
+    if look != Vec2::ZERO && dt > 0.0 {
+      let gain = 1.0 + self.acceleration * look.length() / dt / 1000.0;
+      // Zoomed in views turn slower too, so the same flick covers the same part of the screen
+      self.rotate(look, self.sensitivity * gain * self.fov / self.base_fov);
+    }
+    let fov = if self.zoomed { self.base_fov * self.zoom } else { self.base_fov };
+    self.fov += (fov - self.fov) * eased(ZOOM_TIME);
+  }
+
+  /// Switches projection, pointing the camera the way the new one starts out
+  pub fn set_projection(&mut self, projection: Projection) {
+    let mut angles = self.angles();
+    if self.projection == Projection::Perspective { self.perspective_pitch = angles.y }
+    angles.y = match projection {
+      Projection::Perspective => self.perspective_pitch,
+      Projection::Top => -QUARTER + 0.001,
+      Projection::Side => 0.0,
+    };
+    self.set_angles(angles);
+    self.projection = projection;
+  }
+
+  /// Half the height of the view in world units, None for perspective
+  pub fn ortho_height(&self) -> Option<f32> {
+    (self.projection != Projection::Perspective).then(|| self.ortho_extent * self.fov / self.base_fov)
+  }
+
+  /// Spaceship style rotation around the camera's own axes, (yaw, pitch, roll) in radians.
+  /// Nothing is clamped, so it'll happily fly upside down
+  pub fn turn(&mut self, delta: Vec3) {
+    self.orientation = (self.orientation * Quat::from_euler(EulerRot::YXZ, -delta.x, delta.y, -delta.z)).normalize();
+  }
+
+  /// (yaw, pitch, roll) in radians, see rotate and turn for which way each goes
+  pub fn angles(&self) -> Vec3 {
+    let (yaw, pitch, roll) = self.orientation.to_euler(EulerRot::YXZ);
+    Vec3::new(-yaw - QUARTER, pitch, -roll)
+  }
+
+  /// Points the camera along (yaw, pitch, roll), as returned by angles
+  pub fn set_angles(&mut self, angles: Vec3) { self.orientation = Self::orientation_of(angles) }
+
+  /// Camera space -> world space
+  pub fn orientation(&self) -> Quat { self.orientation }
+
+  pub fn set_orientation(&mut self, orientation: Quat) { self.orientation = orientation.normalize() }
+
+  /// The camera alpha of the way from its last tick's position to its current one, for drawing between ticks
+  pub fn interpolated(&self, alpha: f32) -> Camera {
+    let position = self.prev_position.map_or(self.position, |prev| prev.lerp(self.position, alpha));
+    Camera { position, ..self.clone() }
+  }
+
+  pub fn forward(&self) -> Vec3 { self.orientation * Vec3::NEG_Z }
+
   /// [Right, Up, Forward]
   pub fn basis(&self) -> [Vec3; 3] {
-    let forward = self.forward();
-    let right = forward.cross(Vec3::Y).normalize();
-    let up = right.cross(forward).normalize();
-    [right, up, forward]
+    [self.orientation * Vec3::X, self.orientation * Vec3::Y, self.forward()]
   }
 
+  /// Whether any of the world space box min..max could be on screen, near enough to reach within max_distance
+  pub fn sees(&self, min: Vec3, max: Vec3, max_distance: f32) -> bool {
+    if self.position.clamp(min, max).distance(self.position) > max_distance { return false }
+    let view_proj = self.view_proj();
+    let [x, y, z, w] = [0, 1, 2, 3].map(|row| view_proj.row(row));
+    // Left, right, bottom, top and near, the far plane is either at infinity or past max_distance anyway
+    [w + x, w - x, w + y, w - y, z].iter().all(|plane| {
+      // The box's corner furthest along the plane's normal, if that's behind it the whole box is
+      let corner = Vec3::select(plane.truncate().cmpge(Vec3::ZERO), max, min);
+      plane.truncate().dot(corner) + plane.w >= 0.0
+    })
+  }
+
+  /// Projection matrix matching the rays generated in dda.wgsl, for rasterizing on top of the marched image
+  pub fn view_proj(&self) -> Mat4 {
+    let [_, up, forward] = self.basis();
+    let projection = match self.ortho_height() {
+      // The far plane only has to be past anything a ray could reach
+      Some(height) => Mat4::orthographic_rh(-height * self.aspect_ratio, height * self.aspect_ratio, -height, height, 0.0, 65536.0),
+      None => Mat4::perspective_infinite_rh(self.fov, self.aspect_ratio, 0.01),
+    };
+    projection * Mat4::look_to_rh(self.position, forward, up)
+  }
+
+}
+
+/// Spring arm keeping the camera a way back from an object and looking at it. Looking around swings the camera around
+/// the object, and anything getting in between pulls the camera in in front of it so it never ends up inside the terrain
+#[derive(Clone)]
+pub struct Follow {
+  /// Handle of the object followed
+  pub target: usize,
+  /// How far back the camera sits with nothing in the way
+  pub distance: f32,
+  /// How far above the object's center the arm swings around
+  pub height: f32,
+  /// Seconds the arm takes to get most (1 - 1/e) of the way to where the object went, and back out after being pulled in
+  pub lag: f32,
+  // Where the arm swings around and how long it is right now, None until the first update
+  pivot: Option<Vec3>,
+  length: f32,
+}
+impl Follow {
+  pub fn new(target: usize) -> Self {
+    Self { target, distance: 8.0, height: 1.0, lag: 0.15, pivot: None, length: 0.0 }
+  }
+
+  /// Moves camera dt seconds further along after an object centered at center, to the end of the arm behind it.
+  /// blocked is how far along a ray (origin, direction, max distance) something's in the way, if anything is
+  pub fn update(&mut self, camera: &mut Camera, center: Vec3, dt: f32, blocked: impl Fn(Vec3, Vec3, f32) -> Option<f32>) {
+    // Starts out right where it's headed
+    let eased = if self.lag > 0.0 && self.pivot.is_some() { 1.0 - (-dt / self.lag).exp() } else { 1.0 };
+    let target = center + Vec3::Y * self.height;
+    let pivot = self.pivot.map_or(target, |pivot| pivot.lerp(target, eased));
+    self.pivot = Some(pivot);
+    let back = -camera.forward();
+    let room = blocked(pivot, back, self.distance).map_or(self.distance, |t| (t - ARM_MARGIN).max(MIN_ARM));
+    // Pulled in straight away so nothing's ever seen from the inside, eased back out so it doesn't bounce around
+    self.length = if room < self.length { room } else { self.length + (room - self.length) * eased };
+    camera.position = pivot + back * self.length;
+  }
 }
@@ -0,0 +1,692 @@
+use crate::objects::{DagRef, GameData, EMPTY};
+use crate::camera::Follow;
+use crate::materials::MaterialRegistry;
+use crate::blocks::BlockProperties;
+use crate::wgpu_ctx::{DebugView, NodeFormat, PresentMode, Quality, Tonemap, UpscaleFilter};
+use crate::plugins::Plugins;
+use crate::saves;
+use crate::net::{self, Session};
+use crate::heightmap::Layering;
+use crate::animation::Animation;
+use crate::lights::{Light, CELL_LIGHT_RADIUS};
+use glam::{IVec3, Vec3};
+use sdg::{export, prelude::{Axis, Blend, Brush, Index, Orientation}};
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+
+/// Reads commands from stdin on a background thread so the event loop never blocks on them
+pub struct Console {
+  lines: Receiver<String>,
+}
+impl Console {
+  pub fn spawn() -> Self {
+    let (sender, lines) = mpsc::channel();
+    std::thread::spawn(move || {
+      for line in std::io::stdin().lock().lines() {
+        let Ok(line) = line else { break };
+        if sender.send(line).is_err() { break }
+      }
+    });
+    Self { lines }
+  }
+
+  /// Runs every command typed since the last poll, plugins get the first look at each
+  pub fn poll(&self, game_data: &mut GameData, plugins: &mut Plugins) {
+    while let Ok(line) = self.lines.try_recv() {
+      if let Err(err) = run_line(&line, game_data, plugins) { println!("{err}") }
+    }
+  }
+}
+
+/// Commands kept in a file, one per line with # starting a comment, run against every world as it starts.
+/// Settings (quality, fov, far, aa...) come back to their built in values with each new world, this is how
+/// they get other defaults without recompiling
+#[derive(Default)]
+pub struct Settings {
+  // (line number, command)
+  commands: Vec<(usize, String)>,
+  path: String,
+}
+impl Settings {
+  /// No file is no commands
+  pub fn load(path: &Path) -> Self {
+    let Ok(source) = std::fs::read_to_string(path) else { return Self::default() };
+    let commands = source.lines().enumerate()
+      .map(|(number, line)| (number + 1, line.split('#').next().unwrap().trim().to_string()))
+      .filter(|(_, line)| !line.is_empty())
+      .collect();
+    Self { commands, path: path.display().to_string() }
+  }
+
+  /// A command failing is reported and skipped, the rest still run
+  pub fn apply(&self, game_data: &mut GameData, plugins: &mut Plugins) {
+    for (number, line) in &self.commands {
+      if let Err(err) = run_line(line, game_data, plugins) { println!("{} line {number}: {err}", self.path) }
+    }
+  }
+}
+
+// A plugin's command if one has it, otherwise a built in one
+fn run_line(line: &str, game_data: &mut GameData, plugins: &mut Plugins) -> Result<(), String> {
+  let mut words = line.split_whitespace();
+  let Some(command) = words.next() else { return Ok(()) };
+  let args: Vec<&str> = words.collect();
+  plugins.command(command, &args, game_data).unwrap_or_else(|| run_builtin(line, game_data))
+}
+
+const HELP: &str = "\
+Commands:
+  help                           Show this message
+  dot <path> [depth] [object]    Write an object's graph to a graphviz file, depth defaults to 3
+  mesh <path> [object]           Write an object's surface to a .obj (with a .mtl beside it) or .gltf (with a .bin)
+  tree [depth] [object]          Print an object's graph as an outline, depth defaults to 2
+  stats                          Show how big the graph is and how well the objects' trees are deduplicating
+  look                           Describe the voxel under the crosshair
+  gbuffer [path]                 Write the next frame's normals to a PPM (gbuffer.ppm by default) and its depth
+                                 beside it as a PGM, white up close fading to black at the far plane
+  roll [degrees]                 Show or set how far the camera is tipped clockwise
+  mouse [sensitivity] [smoothing] [acceleration]
+                                 Show or set radians turned per count, seconds movement is eased over (0 is off)
+                                 and extra sensitivity per 1000 counts a second (0 is off)
+  fov [degrees] [zoom]           Show or set the vertical field of view and the fraction of it zooming narrows to
+  ortho [extent]                 Show or set half the height of the orthographic views in world units
+  sun <x> <y> <z>                Point the sunlight along a new direction, towards the sun. Stops the clock
+  time [hours] [speed]           Show or set the time of day, which moves the sun, and in-game hours a second
+  clock [on|off]                 Show or toggle the time of day moving on
+  haze [turbidity]               Show or set how hazy the sky is, 2 is clear
+  far [distance]                 Show or set how far rays march before giving up
+  steps [count]                  Show or set how many steps a ray may take before giving up
+  lod [distance] [falloff]       Show or set how far out rays start skipping the finest levels (0 is off)
+                                 and how many times further each coarser level starts
+  view [lit|steps|nodes]         Show or set what's drawn, steps is a heatmap of how many steps each ray took
+                                 and nodes outlines the nodes rays step into, colored by their depth
+  detail [on|off]                Show or toggle noise on distant faces of large uniform regions
+  outline [width] [threshold]    Show or set outline width in pixels (0 is off) and relative depth threshold
+  quality [low|medium|high|ultra]  Show the last preset or switch every knob below to a new one
+  scale [fraction|auto [fps]]    Show or set the resolution the scene renders at relative to the window,
+                                 auto lowers it as needed to hold fps (60 by default) instead
+  shadows [on|off]               Show or toggle sun shadows
+  ao [rays]                      Show or set ambient occlusion rays per pixel, 0 is off
+  gi [cones]                     Show or set cones per pixel gathering bounced light, 0 is off
+  temporal [on|off]              Show or toggle blending frames over time
+  aa [on|off]                    Show or toggle jittering rays within their pixels so blending over time smooths edges
+  fog [density] [falloff]        Show or set fog per unit of depth (0 is off) and how fast it thins with height
+  fogcolor <r> <g> <b>           Set the fog's linear color
+  exposure [multiplier]          Show or set how bright the final image is
+  tonemap [clip|reinhard|aces]   Show or set how brightness above 1 is brought back into range
+  filter [nearest|bilinear|sharpen] [sharpness]
+                                 Show or set how the scene is stretched to the window, sharpness goes 0 to 1
+  crosshair [on|off]             Show or toggle the cross at the center of the screen
+  nodes [raw|packed|objects]     Show or set how the graph is laid out on the GPU, packed leaves out empty children
+                                 and objects packs each object's tree on its own
+  present [fifo|mailbox|immediate]  Show or set how frames reach the display, fifo is vsync and immediate may tear
+  fpslimit [fps|off]             Show or set the most frames drawn a second
+  stream [radius]                Show or set how many chunks out an endless world is kept loaded around the camera
+  sphere <radius> [block]        Fill a ball around the voxel under the crosshair with block, air (carving it out) by default.
+                                 Blocks go by name or leaf number wherever a command takes one
+  cylinder <radius> <length> [block]  Same with an upright cylinder
+  setblock <x> <y> <z> [block] [object]  Put block (stone by default) in the cell at world position x y z, which can be
+                                 negative, of object (0 by default), growing its grid to reach if it has to
+  rotate <object> <x|y|z> [turns]  Turn an object's cells a quarter turn (or several) around an axis of its grid
+  mirror <object> <x|y|z>        Flip an object's cells across the middle of its grid along an axis
+  animate <object> [off|<x> <y> <z> [spin x y z] [period]]  Show, stop or set an object moving by itself at x y z
+                                 units a second, turning spin degrees a second around each axis, and heading back
+                                 every period seconds (forever onwards if left out)
+  break [speed|off]              Show or set how much speed objects have to lose hitting something to break apart,
+                                 off by default
+  follow [object|off] [distance]  Show what the camera's following, or have it follow an object (the one under the
+                                 crosshair by default) from distance (8) units back, looking around swings around it
+  light <r> <g> <b> [radius]     Place a point light where the camera is, reaching radius (8) units
+  spotlight <r> <g> <b> [radius] [degrees]  Place a spotlight where the camera is, shining the way it's looking in a cone
+                                 degrees (30) out from its middle
+  lights [clear]                 Show how many lights are placed, or remove every one of them
+  blocks                         List the named blocks, their leaves and how they play
+  tile <block> [n|off]           Show or set which tile of the texture atlas a block's material is wrapped in
+  import <slot> [object]         Copy an object out of another save in front of the camera, object 0 by default
+  heightmap <path> [max height] [block:depth ...] [block]  Build terrain out of a grayscale PNG beneath the camera, up to
+                                 max height (32) cells tall, with bands of each block down to a base (2 of grass over stone)
+  compact                        Pack the graph's nodes together, shrinking what's sent to the GPU
+  save [name]                    Save the world to a slot under saves/, the one it came from by default
+  host [port]                    Share the world with games started with --join <address>, port defaults to 7420
+  leave                          Stop hosting or leave the host, keeping the world as it is";
+
+/// Runs one of the commands listed in HELP
+pub fn run_builtin(line: &str, game_data: &mut GameData) -> Result<(), String> {
+  let mut words = line.split_whitespace();
+  let Some(command) = words.next() else { return Ok(()) };
+  match command {
+    "help" => println!("{HELP}"),
+    "dot" => {
+      let path = words.next().ok_or("Usage: dot <path> [depth] [object]")?;
+      let depth = parse_or(words.next(), 3)?;
+      let object = parse_or(words.next(), 0)?;
+      let head = game_data.objects.get(object).ok_or(format!("There's no object {object}"))?.dag_ref.head;
+      let dot = export::to_dot(&game_data.sdg, head, depth, |leaf| leaf_color(&game_data.materials, leaf)).map_err(|err| err.to_string())?;
+      std::fs::write(path, dot).map_err(|err| format!("Failed to write {path}: {err}"))?;
+      println!("Wrote object {object} to {path}");
+    }
+    "mesh" => {
+      let path = Path::new(words.next().ok_or("Usage: mesh <path> [object]")?);
+      let object = parse_or(words.next(), 0)?;
+      let DagRef { head, height } = game_data.objects.get(object).ok_or(format!("There's no object {object}"))?.dag_ref;
+      let mesh = game_data.sdg.greedy_mesh(head, height).map_err(|err| err.to_string())?;
+      let color = |leaf| {
+        let material = game_data.materials.get(leaf);
+        material.albedo.extend(material.opacity).to_array()
+      };
+      // The materials or buffer go in a file of the same name beside it
+      let (side, main, extra) = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("obj") => {
+          let side = path.with_extension("mtl");
+          let name = side.file_name().unwrap().to_string_lossy().into_owned();
+          (side, mesh.to_obj(&name).into_bytes(), mesh.to_mtl(color).into_bytes())
+        }
+        Some("gltf") => {
+          let side = path.with_extension("bin");
+          let (json, bin) = mesh.to_gltf(&side.file_name().unwrap().to_string_lossy(), color);
+          (side, json.into_bytes(), bin)
+        }
+        _ => return Err("The path has to end in .obj or .gltf".into()),
+      };
+      for (path, bytes) in [(path, main), (side.as_path(), extra)] {
+        std::fs::write(path, bytes).map_err(|err| format!("Failed to write {}: {err}", path.display()))?;
+      }
+      println!("Wrote {} quads of object {object} to {}", mesh.quads(), path.display());
+    }
+    "tree" => {
+      let depth = parse_or(words.next(), 2)?;
+      let object = parse_or(words.next(), 0)?;
+      let head = game_data.objects.get(object).ok_or(format!("There's no object {object}"))?.dag_ref.head;
+      print!("{}", export::to_tree(&game_data.sdg, head, depth).map_err(|err| err.to_string())?);
+    }
+    "stats" => {
+      let heads: Vec<_> = game_data.objects.values().map(|object| object.dag_ref.head).collect();
+      println!("{}", game_data.sdg.stats(&heads).map_err(|err| err.to_string())?);
+    }
+    "look" => {
+      let camera = &game_data.camera;
+      let hit = game_data.raycast(camera.position, camera.forward(), 256.0).ok_or("Nothing under the crosshair")?;
+      let block = game_data.blocks.of_leaf(hit.leaf).map_or(String::new(), |block| format!(" ({})", block.name));
+      println!(
+        "Object {} cell {} leaf {}{block} normal {} at {:.3} (uv {:.3}), {:.2} away",
+        hit.object, hit.cell, hit.leaf, hit.normal, hit.pos, hit.uv, hit.t * camera.forward().length()
+      );
+    }
+    "gbuffer" => {
+      let path = PathBuf::from(words.next().unwrap_or("gbuffer.ppm"));
+      if path.extension().is_some_and(|ext| ext == "pgm") { return Err("The depth goes in the .pgm beside path, pick another extension".into()) }
+      game_data.gbuffer = Some(path);
+    }
+    "roll" => {
+      let camera = &mut game_data.camera;
+      let mut angles = camera.angles();
+      angles.z = parse_or(words.next(), angles.z.to_degrees())?.to_radians();
+      camera.set_angles(angles);
+      println!("The camera is rolled {:.1} degrees", angles.z.to_degrees());
+    }
+    "mouse" => {
+      let camera = &mut game_data.camera;
+      camera.sensitivity = parse_or(words.next(), camera.sensitivity)?.max(0.0);
+      camera.smoothing = parse_or(words.next(), camera.smoothing)?.max(0.0);
+      camera.acceleration = parse_or(words.next(), camera.acceleration)?.max(0.0);
+      println!("Mouse sensitivity {}, smoothing {}s, acceleration {}", camera.sensitivity, camera.smoothing, camera.acceleration);
+    }
+    "fov" => {
+      let camera = &mut game_data.camera;
+      let degrees = parse_or(words.next(), camera.base_fov.to_degrees())?;
+      if !(10.0 ..= 170.0).contains(&degrees) { return Err("Fov has to be between 10 and 170 degrees".into()) }
+      camera.base_fov = degrees.to_radians();
+      camera.zoom = parse_or(words.next(), camera.zoom)?.clamp(0.05, 1.0);
+      println!("Fov is {degrees:.1} degrees, zooming to {}x of it", camera.zoom);
+    }
+    "ortho" => {
+      let camera = &mut game_data.camera;
+      let extent = parse_or(words.next(), camera.ortho_extent)?;
+      if extent <= 0.0 { return Err("The extent has to be above 0".into()) }
+      camera.ortho_extent = extent;
+      println!("Orthographic views are {} units tall, currently {:?}", extent * 2.0, camera.projection);
+    }
+    "sun" => {
+      let usage = "Usage: sun <x> <y> <z>";
+      let mut axis = || parse_or(Some(words.next().ok_or(usage)?), 0.0);
+      let dir = Vec3::new(axis()?, axis()?, axis()?);
+      if dir == Vec3::ZERO { return Err("The sun needs a direction".into()) }
+      game_data.sky.sun_dir = dir;
+      // Or it'd be swept back onto the sun's course next tick
+      game_data.time_of_day.paused = true;
+    }
+    "time" => {
+      let (sky, clock) = (&mut game_data.sky, &mut game_data.time_of_day);
+      if let Some(word) = words.next() {
+        clock.hours = parse_or(Some(word), 0.0f32)?.rem_euclid(24.0);
+        sky.set_time_of_day(clock.hours);
+      }
+      clock.speed = parse_or(words.next(), clock.speed)?.max(0.0);
+      let hours = sky.time_of_day();
+      println!("It's {:02}:{:02}, going by at {} in-game hours a second", hours as u32, (hours.fract() * 60.0) as u32, clock.speed);
+    }
+    "clock" => {
+      let clock = &mut game_data.time_of_day;
+      clock.paused = !parse_switch(words.next(), !clock.paused)?;
+      println!("The clock is {}", on_off(!clock.paused));
+    }
+    "haze" => {
+      let sky = &mut game_data.sky;
+      sky.turbidity = parse_or(words.next(), sky.turbidity)?.max(1.0);
+      println!("Sky turbidity is {}", sky.turbidity);
+    }
+    "far" => {
+      let render = &mut game_data.render;
+      render.max_distance = parse_or(words.next(), render.max_distance)?;
+      println!("Rays march up to {} units", render.max_distance);
+    }
+    "steps" => {
+      let render = &mut game_data.render;
+      render.max_steps = parse_or(words.next(), render.max_steps)?;
+      println!("Rays take up to {} steps", render.max_steps);
+    }
+    "lod" => {
+      let render = &mut game_data.render;
+      render.lod_distance = parse_or(words.next(), render.lod_distance)?.max(0.0);
+      let falloff = parse_or(words.next(), render.lod_falloff)?;
+      if falloff <= 1.0 { return Err("The falloff has to be above 1".into()) }
+      render.lod_falloff = falloff;
+      match render.lod_distance {
+        0.0 => println!("Level of detail is off"),
+        distance => println!("Rays coarsen past {distance}, a level more every {falloff}x further"),
+      }
+    }
+    "detail" => {
+      let render = &mut game_data.render;
+      render.detail = parse_switch(words.next(), render.detail)?;
+      println!("Surface detail is {}", on_off(render.detail));
+    }
+    "outline" => {
+      let render = &mut game_data.render;
+      render.outline_width = parse_or(words.next(), render.outline_width)?;
+      render.outline_threshold = parse_or(words.next(), render.outline_threshold)?;
+      match render.outline_width {
+        0 => println!("Outlines are off"),
+        width => println!("Outlines are {width} pixels wide at depth changes over {}", render.outline_threshold),
+      }
+    }
+    "quality" => {
+      let render = &mut game_data.render;
+      if let Some(word) = words.next() {
+        render.apply(Quality::from_name(word).ok_or(format!("{word} isn't low, medium, high or ultra"))?);
+      }
+      println!("Rendering at {} quality", render.quality.name());
+    }
+    "scale" => {
+      let render = &mut game_data.render;
+      match words.next() {
+        Some("auto") => {
+          let fps: f32 = parse_or(words.next(), 60.0)?;
+          if fps <= 0.0 { return Err("Fps has to be above 0".into()) }
+          render.target_fps = Some(fps);
+        }
+        word => {
+          let scale = parse_or(word, render.render_scale)?;
+          if !(0.1 ..= 2.0).contains(&scale) { return Err("Scale has to be between 0.1 and 2".into()) }
+          if word.is_some() { render.target_fps = None }
+          render.render_scale = scale;
+        }
+      }
+      match render.target_fps {
+        Some(fps) => println!("Rendering at up to {}x the window's resolution, holding {fps} fps", render.render_scale),
+        None => println!("Rendering at {}x the window's resolution", render.render_scale),
+      }
+    }
+    "shadows" => {
+      let render = &mut game_data.render;
+      render.shadows = parse_switch(words.next(), render.shadows)?;
+      println!("Shadows are {}", on_off(render.shadows));
+    }
+    "ao" => {
+      let render = &mut game_data.render;
+      render.ao_rays = parse_or(words.next(), render.ao_rays)?;
+      println!("Ambient occlusion traces {} rays per pixel", render.ao_rays);
+    }
+    "gi" => {
+      let render = &mut game_data.render;
+      render.gi_cones = parse_or(words.next(), render.gi_cones)?;
+      println!("Bounce lighting traces {} cones per pixel", render.gi_cones);
+    }
+    "temporal" => {
+      let render = &mut game_data.render;
+      render.temporal = parse_switch(words.next(), render.temporal)?;
+      println!("Temporal blending is {}", on_off(render.temporal));
+    }
+    "aa" => {
+      let render = &mut game_data.render;
+      render.antialias = parse_switch(words.next(), render.antialias)?;
+      println!("Antialiasing is {}", on_off(render.antialias));
+      if render.antialias && !render.temporal { println!("It needs temporal blending on to do anything") }
+    }
+    "fog" => {
+      let fog = &mut game_data.render.fog;
+      fog.density = parse_or(words.next(), fog.density)?.max(0.0);
+      fog.falloff = parse_or(words.next(), fog.falloff)?.max(0.0);
+      println!("Fog density is {}, thinning by {} per unit of height", fog.density, fog.falloff);
+    }
+    "fogcolor" => {
+      let usage = "Usage: fogcolor <r> <g> <b>";
+      let mut channel = || parse_or(Some(words.next().ok_or(usage)?), 0.0);
+      game_data.render.fog.color = Vec3::new(channel()?, channel()?, channel()?);
+    }
+    "exposure" => {
+      let render = &mut game_data.render;
+      render.exposure = parse_or(words.next(), render.exposure)?.max(0.0);
+      println!("Exposure is {}", render.exposure);
+    }
+    "view" => {
+      let render = &mut game_data.render;
+      if let Some(word) = words.next() {
+        render.debug_view = DebugView::from_name(word).ok_or(format!("{word} isn't lit, steps or nodes"))?;
+      }
+      println!("Showing the {} view", render.debug_view.name());
+    }
+    "nodes" => {
+      let render = &mut game_data.render;
+      if let Some(word) = words.next() {
+        render.node_format = NodeFormat::from_name(word).ok_or(format!("{word} isn't raw, packed or objects"))?;
+      }
+      let stats = game_data.sdg.stats(&[]).map_err(|err| err.to_string())?;
+      let bytes = match render.node_format {
+        NodeFormat::Raw => stats.gpu_bytes,
+        NodeFormat::Packed => stats.packed_bytes,
+        NodeFormat::Objects => {
+          let mut heads: Vec<_> = game_data.objects.values().map(|object| object.dag_ref.head).collect();
+          heads.sort();
+          heads.dedup();
+          heads.into_iter().map(|head| game_data.sdg.pack_tree(head).map(|tree| tree.words.len() * std::mem::size_of::<u32>())).sum::<Result<_, _>>()
+            .map_err(|err| err.to_string())?
+        }
+      };
+      println!("Uploading the graph {}, {:.2} MB", render.node_format.name(), bytes as f64 / 1_000_000.0);
+    }
+    "tonemap" => {
+      let render = &mut game_data.render;
+      if let Some(word) = words.next() {
+        render.tonemap = Tonemap::from_name(word).ok_or(format!("{word} isn't clip, reinhard or aces"))?;
+      }
+      println!("Tonemapping with {}", render.tonemap.name());
+    }
+    "filter" => {
+      let render = &mut game_data.render;
+      if let Some(word) = words.next() {
+        render.upscale_filter = UpscaleFilter::from_name(word).ok_or(format!("{word} isn't nearest, bilinear or sharpen"))?;
+      }
+      render.sharpness = parse_or(words.next(), render.sharpness)?.clamp(0.0, 1.0);
+      match render.upscale_filter {
+        UpscaleFilter::Sharpen => println!("Upscaling with sharpen at {}", render.sharpness),
+        filter => println!("Upscaling with {}", filter.name()),
+      }
+    }
+    "crosshair" => {
+      let render = &mut game_data.render;
+      render.crosshair = parse_switch(words.next(), render.crosshair)?;
+      println!("The crosshair is {}", on_off(render.crosshair));
+    }
+    "present" => {
+      let render = &mut game_data.render;
+      if let Some(word) = words.next() {
+        render.present_mode = PresentMode::from_name(word).ok_or(format!("{word} isn't fifo, mailbox or immediate"))?;
+      }
+      println!("Asking for {} presentation", render.present_mode.name());
+    }
+    "fpslimit" => {
+      let render = &mut game_data.render;
+      match words.next() {
+        Some("off") => render.fps_limit = None,
+        word => {
+          let fps = parse_or(word, render.fps_limit.unwrap_or(60.0))?;
+          if fps <= 0.0 { return Err("Fps has to be above 0".into()) }
+          if word.is_some() { render.fps_limit = Some(fps) }
+        }
+      }
+      match render.fps_limit {
+        Some(fps) => println!("Drawing at most {fps} frames a second"),
+        None => println!("Frames aren't limited"),
+      }
+    }
+    "stream" => {
+      let streaming = game_data.streaming.as_mut().ok_or("This world isn't streamed")?;
+      let radius = parse_or(words.next(), streaming.radius)?;
+      if radius < 0.0 { return Err("The radius can't be negative".into()) }
+      streaming.radius = radius;
+      println!("{} chunks of {} cells loaded, up to {radius} chunks out", streaming.loaded(), streaming.chunk_size());
+    }
+    "sphere" | "cylinder" => {
+      let camera = &game_data.camera;
+      let hit = game_data.raycast(camera.position, camera.forward(), 256.0).ok_or("Nothing under the crosshair")?;
+      let center = (hit.cell - game_data.objects[hit.object].origin).as_vec3() + 0.5;
+      let usage = if command == "sphere" { "Usage: sphere <radius> [block]" } else { "Usage: cylinder <radius> <length> [block]" };
+      let mut number = || parse_or(Some(words.next().ok_or(usage)?), 0.0);
+      let brush = match command {
+        "sphere" => Brush::Sphere { center, radius: number()? },
+        _ => Brush::Cylinder { center, radius: number()?, length: number()?, axis: Axis::Y },
+      };
+      let leaf = parse_leaf(words.next(), EMPTY, game_data)?;
+      game_data.apply_brush(hit.object, brush, leaf, Blend::Replace);
+      game_data.graph_changed = true;
+    }
+    "setblock" => {
+      let usage = "Usage: setblock <x> <y> <z> [block] [object]";
+      let mut coord = || parse_or::<i32>(Some(words.next().ok_or(usage)?), 0);
+      let cell = IVec3::new(coord()?, coord()?, coord()?);
+      let leaf = match words.next() { Some(word) => parse_leaf(Some(word), EMPTY, game_data)?, None => parse_leaf(Some("stone"), EMPTY, game_data)? };
+      let object = parse_or(words.next(), 0)?;
+      if !game_data.objects.contains(object) { return Err(format!("There's no object {object}")) }
+      // The middle of the cell, so it lands in the object's cell there even if it's turned a little
+      let local = game_data.set_world_cell(object, cell.as_vec3() + 0.5, leaf).ok_or("That's further than the object's grid can grow")?;
+      game_data.graph_changed = true;
+      println!("Set cell {local} of object {object}");
+    }
+    "rotate" | "mirror" => {
+      let object: usize = words.next().ok_or(format!("Usage: {command} <object> <x|y|z>"))?.parse().map_err(|_| "That isn't an object number")?;
+      if !game_data.objects.contains(object) { return Err(format!("There's no object {object}")) }
+      let axis = match words.next() {
+        Some("x") => 0,
+        Some("y") => 1,
+        Some("z") => 2,
+        _ => return Err("The axis has to be x, y or z".into()),
+      };
+      let orientation = match command {
+        "rotate" => Orientation::rotation(axis, parse_or(words.next(), 1)?),
+        _ => Orientation::mirror(axis),
+      };
+      game_data.reorient(object, orientation);
+      game_data.graph_changed = true;
+    }
+    "animate" => {
+      let object: usize = words.next().ok_or("Usage: animate <object> [off|<x> <y> <z> [spin x y z] [period]]")?
+        .parse().map_err(|_| "That isn't an object number")?;
+      let current = game_data.objects.get(object).ok_or(format!("There's no object {object}"))?;
+      let rest: Vec<&str> = words.collect();
+      let animation = match rest.as_slice() {
+        [] => {
+          match current.animation {
+            Some(animation) => println!(
+              "Object {object} moves {:.2} a second, turning {:.1} degrees a second, {}",
+              animation.velocity, animation.spin * 180.0 / std::f32::consts::PI,
+              animation.period.map_or("forever".into(), |period| format!("back and forth every {period} seconds"))
+            ),
+            None => println!("Object {object} isn't animated"),
+          }
+          return Ok(())
+        }
+        ["off"] => None,
+        words if [3, 6, 7].contains(&words.len()) => {
+          let numbers = words.iter().map(|&word| parse_or(Some(word), 0.0f32)).collect::<Result<Vec<_>, _>>()?;
+          let spin = if numbers.len() > 3 { Vec3::new(numbers[3], numbers[4], numbers[5]) } else { Vec3::ZERO };
+          let period = numbers.get(6).copied();
+          if period.is_some_and(|period| period <= 0.0) { return Err("The period has to be above 0".into()) }
+          Some(Animation::new(current, game_data.tick, Vec3::new(numbers[0], numbers[1], numbers[2]), spin * std::f32::consts::PI / 180.0, period))
+        }
+        _ => return Err("Give a velocity as x y z, optionally followed by a spin as x y z and a period".into()),
+      };
+      game_data.animate(object, animation);
+    }
+    "break" => match words.next() {
+      None => match game_data.break_speed {
+        Some(speed) => println!("Objects break losing {speed} units a second or more in a hit"),
+        None => println!("Objects don't break"),
+      },
+      Some("off") => game_data.break_speed = None,
+      speed => {
+        let speed: f32 = parse_or(speed, 0.0)?;
+        if speed <= 0.0 { return Err("The speed has to be above 0".into()) }
+        game_data.break_speed = Some(speed);
+      }
+    },
+    "follow" => match words.next() {
+      None => match &game_data.follow {
+        Some(follow) => println!("Following object {} from {} units back", follow.target, follow.distance),
+        None => println!("The camera's flying free"),
+      },
+      Some("off") => game_data.follow = None,
+      object => {
+        let target = match object {
+          Some(object) => object.parse().map_err(|_| "That isn't an object number")?,
+          None => game_data.targeted.as_ref().ok_or("Nothing under the crosshair")?.object,
+        };
+        if !game_data.objects.contains(target) { return Err(format!("There's no object {target}")) }
+        let mut follow = Follow::new(target);
+        follow.distance = parse_or(words.next(), follow.distance)?;
+        if follow.distance <= 0.0 { return Err("The distance has to be above 0".into()) }
+        game_data.follow = Some(follow);
+      }
+    },
+    "light" | "spotlight" => {
+      let usage = if command == "light" { "Usage: light <r> <g> <b> [radius]" } else { "Usage: spotlight <r> <g> <b> [radius] [degrees]" };
+      let mut channel = || parse_or(Some(words.next().ok_or(usage)?), 0.0);
+      let color = Vec3::new(channel()?, channel()?, channel()?);
+      let radius = parse_or(words.next(), CELL_LIGHT_RADIUS)?;
+      if radius <= 0.0 { return Err("The radius has to be above 0".into()) }
+      let camera = &game_data.camera;
+      let light = match command {
+        "light" => Light::point(camera.position, color, radius),
+        _ => Light::spot(camera.position, camera.forward(), parse_or(words.next(), 30.0f32)?.clamp(1.0, 89.0).to_radians(), color, radius),
+      };
+      game_data.lights.placed.push(light);
+    }
+    "lights" => {
+      if let Some(word) = words.next() {
+        if word != "clear" { return Err("Usage: lights [clear]".into()) }
+        game_data.lights.placed.clear();
+      }
+      println!("{} lights placed", game_data.lights.placed.len());
+    }
+    "blocks" => {
+      for block in game_data.blocks.all() {
+        let BlockProperties { solid, transparent, hardness } = block.properties;
+        println!("{} (leaf {}): {}, {}, hardness {hardness}", block.name, block.leaf,
+          match solid { true => "solid", false => "not solid" }, match transparent { true => "transparent", false => "opaque" });
+      }
+    }
+    "tile" => {
+      let leaf = parse_leaf(words.next(), EMPTY, game_data)?;
+      if leaf == EMPTY { return Err("Usage: tile <block> [n|off]".into()) }
+      let mut material = game_data.materials.get(leaf);
+      if let Some(word) = words.next() {
+        material.tile = match word { "off" => None, _ => Some(word.parse().map_err(|_| format!("{word} isn't a tile"))?) };
+        game_data.materials.set(leaf, material);
+        game_data.materials_changed = true;
+      }
+      match material.tile {
+        Some(tile) => println!("Leaf {leaf} is wrapped in tile {tile}"),
+        None => println!("Leaf {leaf} is untextured"),
+      }
+    }
+    "import" => {
+      let slot = words.next().ok_or("Usage: import <slot> [object]")?;
+      let object = parse_or(words.next(), 0)?;
+      let other = saves::load(slot).map_err(|err| format!("Failed to load {slot}: {err}"))?;
+      let size = other.objects.get(object).map_or(0, |object| 1u32 << object.dag_ref.height) as f32;
+      // Far enough out that the copy doesn't land on the camera
+      let camera = &game_data.camera;
+      let pos = camera.position + camera.forward() * (size + 1.0);
+      let idx = game_data.import_object(&other, object, pos)?;
+      println!("Copied object {object} of {slot} in as object {idx}");
+    }
+    "heightmap" => {
+      let usage = "Usage: heightmap <path> [max height] [block:depth ...] [block]";
+      let path = words.next().ok_or(usage)?;
+      let max_height = parse_or(words.next(), 32)?;
+      let (mut bands, mut base) = (Vec::new(), None);
+      for word in words {
+        match word.split_once(':') {
+          Some((block, depth)) => bands.push((parse_leaf(Some(block), EMPTY, game_data)?, parse_or(Some(depth), 0)?)),
+          None => base = Some(parse_leaf(Some(word), EMPTY, game_data)?),
+        }
+      }
+      // Grass over stone like the templates unless told otherwise
+      if bands.is_empty() && base.is_none() { bands.push((parse_leaf(Some("grass"), EMPTY, game_data)?, 2)) }
+      let layering = Layering { bands, base: base.map_or_else(|| parse_leaf(Some("stone"), EMPTY, game_data), Ok)? };
+      game_data.queue_heightmap(path.to_string(), max_height, layering);
+      println!("Building {path} in the background");
+    }
+    "compact" => {
+      let (before, after) = game_data.compact();
+      println!("Compacted the graph from {before} slots to {after}, {} nodes are live", game_data.sdg.live_nodes());
+    }
+    "save" => {
+      // Names can have spaces in, so it's the rest of the line
+      let name = line.trim().split_once(' ').map(|(_, name)| name.trim().to_string())
+        .or_else(|| game_data.world.slot.clone())
+        .ok_or("This world hasn't been saved before, give it a name")?;
+      saves::save(game_data, &name).map_err(|err| format!("Failed to save {name}: {err}"))?;
+      println!("Saved to {name}");
+    }
+    "host" => {
+      if game_data.net.is_some() { return Err("This world's already shared, leave first".into()) }
+      // Chunks come and go on their own on each end, which would shuffle the object indices edits go by
+      if game_data.streaming.is_some() { return Err("Streamed worlds can't be shared".into()) }
+      let port = parse_or(words.next(), net::DEFAULT_PORT)?;
+      game_data.net = Some(Session::host(port).map_err(|err| format!("Failed to host on port {port}: {err}"))?);
+      println!("Hosting on port {port}");
+    }
+    "leave" => match game_data.net.take() {
+      Some(net) if net.is_host() => println!("Stopped hosting, {} others were connected", net.peers()),
+      Some(_) => println!("Left the host"),
+      None => return Err("This world isn't shared".into()),
+    }
+    _ => return Err(format!("Unknown command {command}, try help")),
+  }
+  Ok(())
+}
+
+fn parse_or<T: std::str::FromStr>(word: Option<&str>, default: T) -> Result<T, String> {
+  word.map_or(Ok(default), |word| word.parse().map_err(|_| format!("{word} isn't a valid number")))
+}
+
+// A leaf by its block's name or its number
+fn parse_leaf(word: Option<&str>, default: Index, game_data: &GameData) -> Result<Index, String> {
+  let Some(word) = word else { return Ok(default) };
+  let leaf = match word.parse() {
+    Ok(leaf) => leaf,
+    Err(_) => game_data.blocks.leaf(word).ok_or(format!("There's no block called {word}"))?,
+  };
+  if !game_data.sdg.is_leaf(leaf) { return Err(format!("{leaf} isn't a leaf")) }
+  Ok(leaf)
+}
+
+fn parse_switch(word: Option<&str>, current: bool) -> Result<bool, String> {
+  match word {
+    None => Ok(current),
+    Some("on") => Ok(true),
+    Some("off") => Ok(false),
+    Some(word) => Err(format!("{word} isn't on or off")),
+  }
+}
+
+fn on_off(value: bool) -> &'static str { if value { "on" } else { "off" } }
+
+// Leaves are filled with their material's albedo
+fn leaf_color(materials: &MaterialRegistry, leaf: u32) -> String {
+  if leaf == EMPTY { return "white".into() }
+  let [r, g, b] = materials.get(leaf).srgb();
+  format!("#{r:02x}{g:02x}{b:02x}")
+}
@@ -0,0 +1,76 @@
+use glam::{Mat4, Vec3};
+use crate::wgpu_buffers::LineVertex;
+
+/// Runtime toggles for the debug visualizations
+#[derive(Default)]
+pub struct DebugFlags {
+  pub contacts: bool,
+  pub bounds: bool,
+  pub checksum: bool,
+  /// (origin, direction) of a crosshair ray frozen in place, drawn up to whatever it hits now
+  pub ray: Option<(Vec3, Vec3)>,
+  /// Outlines of the streamed chunks, see WorldManager::draw_chunks
+  pub chunks: bool,
+}
+
+/// FNV-1a, chosen over ahash because it has to be stable between runs and machines
+pub struct Checksum(u64);
+impl Default for Checksum {
+  fn default() -> Self { Self(0xcbf29ce484222325) }
+}
+impl Checksum {
+  pub fn write_u32(&mut self, value: u32) {
+    for byte in value.to_le_bytes() {
+      self.0 ^= byte as u64;
+      self.0 = self.0.wrapping_mul(0x100000001b3);
+    }
+  }
+
+  /// Hashes the exact bits, so -0.0 and 0.0 differ. That's what we want for lockstep.
+  pub fn write_f32s(&mut self, values: &[f32]) {
+    for value in values { self.write_u32(value.to_bits()) }
+  }
+
+  pub fn finish(&self) -> u64 { self.0 }
+}
+
+/// World-space line segments drawn on top of the final frame. Anything with the GameData can add to them while a frame's
+/// being worked out (ticks, plugins, console commands), they're cleared at the start of the next
+#[derive(Default)]
+pub struct DebugLines {
+  vertices: Vec<LineVertex>,
+}
+impl DebugLines {
+  pub fn clear(&mut self) { self.vertices.clear() }
+
+  pub fn vertices(&self) -> &[LineVertex] { &self.vertices }
+
+  pub fn line(&mut self, start: Vec3, end: Vec3, color: Vec3) {
+    self.vertices.push(LineVertex::new(start, color));
+    self.vertices.push(LineVertex::new(end, color));
+  }
+
+  /// Axis aligned 3d cross, good for marking points
+  pub fn cross(&mut self, center: Vec3, size: f32, color: Vec3) {
+    for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+      self.line(center - axis * size / 2.0, center + axis * size / 2.0, color);
+    }
+  }
+
+  pub fn aabb(&mut self, min: Vec3, max: Vec3, color: Vec3) {
+    self.transformed_box(Mat4::IDENTITY, min, max, color)
+  }
+
+  /// The 12 edges of the box min..max after being moved through transform
+  pub fn transformed_box(&mut self, transform: Mat4, min: Vec3, max: Vec3, color: Vec3) {
+    let corner = |bits: u32| transform.transform_point3(Vec3::select(
+      glam::BVec3::new(bits & 1 != 0, bits & 2 != 0, bits & 4 != 0), max, min
+    ));
+    for bits in 0 .. 8u32 {
+      for axis in [1, 2, 4] {
+        // Only walk edges in the positive direction so each is drawn once
+        if bits & axis == 0 { self.line(corner(bits), corner(bits | axis), color) }
+      }
+    }
+  }
+}
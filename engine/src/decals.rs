@@ -0,0 +1,52 @@
+use glam::{Vec3, Vec4};
+use crate::physics::TIMESTEP;
+
+/// Most decals alive at once, spawning past this replaces the oldest
+pub const MAX_DECALS: usize = 256;
+// Decals fade out over their last second instead of popping
+const FADE_TICKS: u64 = (1.0 / TIMESTEP) as u64;
+
+/// A square stuck flat against a voxel face, tinting whatever's underneath it
+#[derive(Clone, Copy, Debug)]
+pub struct Decal {
+  /// World space center, on the face it's stuck to
+  pub pos: Vec3,
+  /// Out of the face, the decal only shows on surfaces facing the same way
+  pub normal: Vec3,
+  /// Width of the square in world units
+  pub size: f32,
+  /// rgb replaces the albedo, a is how strongly
+  pub color: Vec4,
+  pub expires_at: u64,
+}
+impl Decal {
+  /// How visible the decal is at tick, fading towards its expiry
+  pub fn opacity(&self, tick: u64) -> f32 {
+    let remaining = self.expires_at.saturating_sub(tick);
+    self.color.w * (remaining as f32 / FADE_TICKS as f32).min(1.0)
+  }
+}
+
+/// Every live decal, oldest first
+#[derive(Default)]
+pub struct Decals {
+  list: Vec<Decal>,
+}
+impl Decals {
+  pub fn iter(&self) -> impl Iterator<Item = &Decal> { self.list.iter() }
+
+  /// Sticks a decal to the face at pos for lifetime seconds (of simulation time)
+  pub fn spawn(&mut self, tick: u64, pos: Vec3, normal: Vec3, size: f32, color: Vec4, lifetime: f32) {
+    if self.list.len() == MAX_DECALS { self.list.remove(0); }
+    self.list.push(Decal {
+      pos,
+      normal: normal.normalize_or_zero(),
+      size,
+      color,
+      expires_at: tick + (lifetime / TIMESTEP).ceil() as u64,
+    });
+  }
+
+  /// Drops everything that's expired by tick
+  pub fn expire(&mut self, tick: u64) { self.list.retain(|decal| decal.expires_at > tick) }
+}
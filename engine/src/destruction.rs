@@ -0,0 +1,91 @@
+//! Objects breaking when they hit something hard. A ball of cells is knocked out of them where they struck,
+//! sized by how hard it was, and what was in it flies off as debris: small dynamic objects of a few cells each,
+//! which crumble away after a while. Switched on by GameData::break_speed.
+
+use crate::objects::{DagRef, GameData, VoxelObject};
+use crate::physics::TIMESTEP;
+use glam::{UVec3, Vec3};
+use sdg::prelude::*;
+use std::collections::BTreeMap;
+
+// Debris comes in pieces of up to 2^DEBRIS_HEIGHT cells across, cut along the grid of what it broke off
+const DEBRIS_HEIGHT: u32 = 1;
+// Pieces a single impact knocks loose, the nearest to it. Anything more it broke off crumbles away on the spot
+const MAX_PIECES: usize = 12;
+// Debris around at once, past which breaking things leaves none
+const MAX_DEBRIS: usize = 64;
+// Seconds debris lies around before it's removed
+const DEBRIS_LIFETIME: f32 = 8.0;
+// Radius of the hole in cells, grows by this much each time over break_speed it hit
+const RADIUS_PER_SPEED: f32 = 1.0;
+const MAX_RADIUS: f32 = 4.0;
+// Units a second debris flies off from where it was struck at, on top of how it was moving
+const KICK: f32 = 2.0;
+
+impl GameData {
+  /// Breaks whatever hit something hard enough during the tick, run by step
+  pub(crate) fn break_on_impact(&mut self) {
+    let Some(break_speed) = self.break_speed.filter(|&speed| speed > 0.0) else { return };
+    for impact in self.physics.impacts(break_speed) {
+      let strength = impact.speed / break_speed;
+      let radius = (1.0 + (strength - 1.0) * RADIUS_PER_SPEED).min(MAX_RADIUS);
+      let struck = [impact.colliders.0, impact.colliders.1]
+        .map(|collider| self.objects.iter().find(|(_, object)| object.physics.is_some_and(|handle| handle.collider == collider)).map(|(idx, _)| idx));
+      // Debris is as broken as it gets, and flying off as fast as it does it'd set off another break wherever it landed
+      if struck.iter().flatten().any(|&idx| self.objects[idx].expires.is_some()) { continue }
+      for idx in struck.into_iter().flatten() { self.shatter(idx, impact.point, radius, strength) }
+    }
+  }
+
+  /// Knocks the cells within radius (in cells) of a world space point out of an object and throws them off as debris.
+  /// Cells whose block is harder than strength hold. Like the engine's own edits it's kept out of the undo history
+  pub fn shatter(&mut self, idx: usize, point: Vec3, radius: f32, strength: f32) {
+    let object = &self.objects[idx];
+    let center = object.inv_transform().transform_point3(point);
+    let brush = Brush::Sphere { center, radius };
+    let Some((min, max)) = brush.bounds(1 << object.dag_ref.height) else { return };
+    // What the brush is about to take (cells whose centers are in it), by which piece of debris it'll end up in
+    let mut pieces: BTreeMap<[u32; 3], Vec<(UVec3, Index)>> = BTreeMap::new();
+    for z in min.z ..= max.z { for y in min.y ..= max.y { for x in min.x ..= max.x {
+      let cell = UVec3::new(x, y, z);
+      if (cell.as_vec3() + 0.5).distance(center) > radius { continue }
+      let leaf = object.leaf_at(&self.sdg, cell);
+      if leaf != EMPTY && self.blocks.properties(leaf).hardness <= strength { pieces.entry((cell >> DEBRIS_HEIGHT).to_array()).or_default().push((cell, leaf)) }
+    }}}
+    if pieces.is_empty() { return }
+    let (transform, rot, body) = (object.transform(), object.rot, object.physics.map(|handle| handle.body));
+    let broken: Vec<_> = pieces.values().flatten().map(|&(cell, _)| (cell, EMPTY)).collect();
+    self.write_cells(idx, &broken);
+
+    let distance = |block: [u32; 3]| ((UVec3::from_array(block) << DEBRIS_HEIGHT).as_vec3() + (1 << DEBRIS_HEIGHT) as f32 / 2.0).distance(center);
+    let mut pieces: Vec<_> = pieces.into_iter().collect();
+    pieces.sort_by(|a, b| distance(a.0).total_cmp(&distance(b.0)));
+    let expires = self.tick + (DEBRIS_LIFETIME / TIMESTEP) as u64;
+    let room = MAX_DEBRIS.saturating_sub(self.objects.values().filter(|object| object.expires.is_some()).count());
+    for (block, cells) in pieces.into_iter().take(MAX_PIECES.min(room)) {
+      let corner = UVec3::from_array(block) << DEBRIS_HEIGHT;
+      let head = self.sdg.build(DEBRIS_HEIGHT, |cell| cells.iter().find(|&&(at, _)| at == corner + cell).map_or(EMPTY, |&(_, leaf)| leaf));
+      let (low, high) = cells.iter().fold((UVec3::MAX, UVec3::ZERO), |(low, high), &(cell, _)| (low.min(cell - corner), high.max(cell - corner)));
+      let mut piece = VoxelObject::new(&self.sdg, DagRef::new(head, DEBRIS_HEIGHT), low, high, Vec3::ZERO);
+      // Turned like what it came off and right where it was in it
+      let middle = transform.transform_point3(corner.as_vec3() + piece.pivot_offset);
+      piece.pos = middle - piece.pivot_offset;
+      piece.rot = rot;
+      piece.expires = Some(expires);
+      let velocity = body.map_or(Vec3::ZERO, |body| self.physics.velocity_at(body, middle)) + (middle - point).normalize_or_zero() * KICK;
+      let piece = self.add_object(piece, true);
+      if let Some(handle) = self.objects[piece].physics { self.physics.set_velocity(handle.body, velocity) }
+    }
+    self.graph_changed = true;
+  }
+
+  /// Removes debris that's been lying around long enough, run by step
+  pub(crate) fn clear_debris(&mut self) {
+    for idx in self.objects.handles() {
+      if self.objects[idx].expires.is_some_and(|tick| tick <= self.tick) {
+        self.remove_object(idx);
+        self.graph_changed = true;
+      }
+    }
+  }
+}
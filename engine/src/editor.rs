@@ -0,0 +1,185 @@
+use glam::{IVec3, Mat4, Quat, UVec3, Vec3};
+use sdg::prelude::*;
+use std::f32::consts::FRAC_PI_2;
+use crate::objects::{DagRef, GameData, RayHit, VoxelObject};
+
+// Actions remembered for undo
+const MAX_UNDO: usize = 64;
+// Most cells a single flood fill will touch, so a click on the ground doesn't repaint the world
+pub const MAX_FILL: usize = 4096;
+
+/// Which cells a paint click recolors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaintMode {
+  /// Just the targeted cell
+  Single,
+  /// Every solid cell within the brush radius
+  Brush,
+  /// The targeted cell and every cell connected to it by faces with the same leaf
+  Fill,
+}
+impl PaintMode {
+  /// Single -> Brush -> Fill -> off
+  pub fn next(mode: Option<Self>) -> Option<Self> {
+    match mode {
+      None => Some(Self::Single),
+      Some(Self::Single) => Some(Self::Brush),
+      Some(Self::Brush) => Some(Self::Fill),
+      Some(Self::Fill) => None,
+    }
+  }
+
+  /// Paints leaf over the solid cells of object a click on cell covers, as a single undoable action
+  pub fn paint(self, game_data: &mut GameData, object: usize, cell: UVec3, radius: u32, leaf: Index) {
+    match self {
+      Self::Single => game_data.set_cell(object, cell, leaf),
+      Self::Brush => {
+        let brush = Brush::Sphere { center: cell.as_vec3() + 0.5, radius: radius as f32 };
+        game_data.apply_brush(object, brush, leaf, Blend::Paint);
+      }
+      Self::Fill => {
+        let DagRef { head, height } = game_data.objects[object].dag_ref;
+        let cells = match game_data.sdg.select_connected(head, height, cell, Connectivity::Faces, MAX_FILL) {
+          Ok(region) => region.cells,
+          Err(err) => { tracing::error!("Failed to fill: {err}"); return }
+        };
+        game_data.set_cells(object, &cells.into_iter().map(|cell| (cell, leaf)).collect::<Vec<_>>());
+      }
+    }
+  }
+}
+
+/// How prefabs line up against the face under the crosshair
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Placement {
+  /// Corners snap to multiples of 1 << snap cells of whatever's being placed against
+  pub snap: u32,
+  /// Quarter turns around the up axis
+  pub turns: u32,
+}
+
+/// Where a prefab would go if it were placed right now
+pub struct PlacementPreview {
+  pub pos: Vec3,
+  pub rot: Quat,
+  /// The prefab's grid space -> world space at pos and rot
+  pub transform: Mat4,
+  /// False if any of its cells would overlap a solid cell of an existing object
+  pub valid: bool,
+}
+
+impl Placement {
+  /// Sits prefab against the face hit is on, centered on the targeted cell (as near as the snap allows)
+  /// and turned to match the object it's going onto
+  pub fn preview(&self, game_data: &GameData, hit: &RayHit, prefab: &VoxelObject) -> PlacementPreview {
+    let target = &game_data.objects[hit.object];
+    let mut extent = (prefab.max_cell - prefab.min_cell + 1).as_ivec3();
+    if self.turns % 2 == 1 { extent = IVec3::new(extent.z, extent.y, extent.x) }
+    let step = IVec3::splat(1 << self.snap);
+    let cell = hit.cell + hit.normal - target.origin;
+    let mut corner = (cell - extent / 2).div_euclid(step) * step;
+    // Along the normal it sits flush against the face instead
+    for axis in 0 .. 3 {
+      if hit.normal[axis] > 0 { corner[axis] = cell[axis] }
+      if hit.normal[axis] < 0 { corner[axis] = cell[axis] - extent[axis] + 1 }
+    }
+    let center = target.transform().transform_point3(corner.as_vec3() + extent.as_vec3() / 2.0);
+
+    let rot = target.rot * Quat::from_rotation_y(self.turns as f32 * FRAC_PI_2);
+    let prefab_center = (prefab.min_cell + prefab.max_cell + 1).as_vec3() / 2.0;
+    let pos = center - prefab.pivot_offset - rot * (prefab_center - prefab.pivot_offset);
+    let transform = Mat4::from_translation(pos + prefab.pivot_offset) * Mat4::from_quat(rot) * Mat4::from_translation(-prefab.pivot_offset);
+    let valid = !Self::overlaps(game_data, prefab, transform);
+    PlacementPreview { pos, rot, transform, valid }
+  }
+
+  // Whether the center of any of prefab's solid cells lands in a solid cell of an existing object
+  fn overlaps(game_data: &GameData, prefab: &VoxelObject, transform: Mat4) -> bool {
+    let (min, max) = (prefab.min_cell, prefab.max_cell);
+    (min.z ..= max.z).flat_map(|z| (min.y ..= max.y).flat_map(move |y| (min.x ..= max.x).map(move |x| UVec3::new(x, y, z))))
+      .filter(|&cell| game_data.blocks.properties(prefab.leaf_at(&game_data.sdg, cell)).solid)
+      .any(|cell| {
+        let world = transform.transform_point3(cell.as_vec3() + 0.5);
+        game_data.objects.values().any(|object| {
+          object.grid_cell(object.local_cell(world)).is_some_and(|cell| game_data.blocks.properties(object.leaf_at(&game_data.sdg, cell)).solid)
+        })
+      })
+  }
+}
+
+/// One undoable action as the object's head on either side of it, the history holds a ref on both
+struct Edit {
+  object: usize,
+  before: Index,
+  after: Index,
+}
+impl Edit {
+  fn release(self, sdg: &mut SparseDirectedGraph<BasicNode3d>) -> Result<(), GraphError> {
+    let before = sdg.drop_root(self.before);
+    before.and(sdg.drop_root(self.after))
+  }
+}
+
+/// Undo and redo stacks for edits made through GameData::set_cells. Undoing carries the change from after back to before
+/// over onto the object (see SparseDirectedGraph::apply_change), so anything written since without recording is kept
+#[derive(Default)]
+pub struct EditHistory {
+  undo: Vec<Edit>,
+  redo: Vec<Edit>,
+}
+impl EditHistory {
+  /// Records object going from before to after, which throws away anything undone.
+  /// Takes over a ref the caller holds on before and takes its own on after.
+  /// The edit is recorded even if giving up the ones thrown away fails, the error is passed on.
+  /// If after isn't a live node nothing is recorded and the ref on before is given back
+  pub fn record(&mut self, sdg: &mut SparseDirectedGraph<BasicNode3d>, object: usize, before: Index, after: Index) -> Result<(), GraphError> {
+    if before == after { return sdg.drop_root(before) }
+    if let Err(err) = sdg.get_root(after) { return sdg.drop_root(before).and(Err(err)) }
+    let mut released = self.redo.drain(..).map(|edit| edit.release(sdg)).fold(Ok(()), Result::and);
+    if self.undo.len() == MAX_UNDO { released = released.and(self.undo.remove(0).release(sdg)) }
+    self.undo.push(Edit { object, before, after });
+    released
+  }
+
+  /// Re-roots object's heads along with it, offset being what VoxelObject::grow_towards returned
+  pub fn shift(&mut self, sdg: &mut SparseDirectedGraph<BasicNode3d>, object: usize, offset: UVec3) -> Result<(), GraphError> {
+    for edit in self.undo.iter_mut().chain(&mut self.redo).filter(|edit| edit.object == object) {
+      // Cells moved up wherever the grid grew towards the negative side
+      let direction = -offset.min(UVec3::ONE).as_ivec3();
+      edit.before = sdg.grow_root(edit.before, direction)?;
+      edit.after = sdg.grow_root(edit.after, direction)?;
+    }
+    Ok(())
+  }
+
+  /// Forgets object's edits, for when it's taken out of GameData::objects
+  pub fn remove_object(&mut self, sdg: &mut SparseDirectedGraph<BasicNode3d>, object: usize) -> Result<(), GraphError> {
+    [&mut self.undo, &mut self.redo].into_iter()
+      .flat_map(|stack| stack.extract_if(.., |edit| edit.object == object).collect::<Vec<_>>())
+      .map(|edit| edit.release(sdg))
+      .fold(Ok(()), Result::and)
+  }
+
+  /// Points every head at wherever it moved, see SparseDirectedGraph::compact
+  pub fn remap(&mut self, moved: impl Fn(Index) -> Index) {
+    for edit in self.undo.iter_mut().chain(&mut self.redo) {
+      (edit.before, edit.after) = (moved(edit.before), moved(edit.after));
+    }
+  }
+
+  /// The latest edit as (object, from, to) to carry back over, it's kept for redo
+  pub fn undo(&mut self) -> Option<(usize, Index, Index)> {
+    let edit = self.undo.pop()?;
+    let change = (edit.object, edit.after, edit.before);
+    self.redo.push(edit);
+    Some(change)
+  }
+
+  /// The latest undone edit as (object, from, to) to carry over again
+  pub fn redo(&mut self) -> Option<(usize, Index, Index)> {
+    let edit = self.redo.pop()?;
+    let change = (edit.object, edit.before, edit.after);
+    self.undo.push(edit);
+    Some(change)
+  }
+}
@@ -0,0 +1,99 @@
+//! Controller input through gilrs, without the gamepad feature there are simply never any controllers
+
+use crate::input::PadButton;
+use glam::Vec2;
+
+// Stick travel ignored around the center, so worn sticks don't drift
+#[cfg(feature = "gamepad")]
+const DEADZONE: f32 = 0.15;
+
+// Only gilrs makes these
+#[cfg_attr(not(feature = "gamepad"), allow(dead_code))]
+pub enum PadEvent {
+  Pressed(PadButton),
+  Released(PadButton),
+}
+
+/// Every connected controller, read as one
+pub struct Gamepads {
+  // None if the platform's controller backend failed to start
+  #[cfg(feature = "gamepad")]
+  gilrs: Option<gilrs::Gilrs>,
+}
+impl Default for Gamepads {
+  fn default() -> Self { Self::new() }
+}
+impl Gamepads {
+  pub fn new() -> Self {
+    Self {
+      #[cfg(feature = "gamepad")]
+      gilrs: gilrs::Gilrs::new().inspect_err(|err| tracing::warn!("No controller support: {err}")).ok(),
+    }
+  }
+
+  /// Button presses and releases since the last call
+  pub fn events(&mut self) -> Vec<PadEvent> {
+    #[allow(unused_mut)]
+    let mut events = Vec::new();
+    #[cfg(feature = "gamepad")]
+    if let Some(gilrs) = &mut self.gilrs {
+      while let Some(event) = gilrs.next_event() {
+        match event.event {
+          gilrs::EventType::ButtonPressed(button, _) => events.extend(pad_button(button).map(PadEvent::Pressed)),
+          gilrs::EventType::ButtonReleased(button, _) => events.extend(pad_button(button).map(PadEvent::Released)),
+          gilrs::EventType::Connected => tracing::info!("Controller connected: {}", gilrs.gamepad(event.id).name()),
+          _ => (),
+        }
+      }
+    }
+    events
+  }
+
+  /// (left, right) stick with +y up, each summed over every controller and clamped to the unit circle
+  pub fn sticks(&self) -> (Vec2, Vec2) {
+    #[allow(unused_mut)]
+    let (mut left, mut right) = (Vec2::ZERO, Vec2::ZERO);
+    #[cfg(feature = "gamepad")]
+    if let Some(gilrs) = &self.gilrs {
+      use gilrs::Axis;
+      for (_, pad) in gilrs.gamepads() {
+        left += deadzone(Vec2::new(pad.value(Axis::LeftStickX), pad.value(Axis::LeftStickY)));
+        right += deadzone(Vec2::new(pad.value(Axis::RightStickX), pad.value(Axis::RightStickY)));
+      }
+    }
+    (left.clamp_length_max(1.0), right.clamp_length_max(1.0))
+  }
+}
+
+/// Rescaled so the edge of the deadzone is zero rather than jumping straight to DEADZONE
+#[cfg(feature = "gamepad")]
+fn deadzone(stick: Vec2) -> Vec2 {
+  let length = stick.length();
+  if length < DEADZONE { return Vec2::ZERO }
+  stick / length * ((length - DEADZONE) / (1.0 - DEADZONE)).min(1.0)
+}
+
+#[cfg(feature = "gamepad")]
+fn pad_button(button: gilrs::Button) -> Option<PadButton> {
+  use gilrs::Button;
+  Some(match button {
+    Button::South => PadButton::South,
+    Button::East => PadButton::East,
+    Button::West => PadButton::West,
+    Button::North => PadButton::North,
+    // gilrs calls the bumpers triggers and the triggers trigger 2s
+    Button::LeftTrigger => PadButton::LeftBumper,
+    Button::RightTrigger => PadButton::RightBumper,
+    Button::LeftTrigger2 => PadButton::LeftTrigger,
+    Button::RightTrigger2 => PadButton::RightTrigger,
+    Button::Select => PadButton::Select,
+    Button::Start => PadButton::Start,
+    Button::LeftThumb => PadButton::LeftStick,
+    Button::RightThumb => PadButton::RightStick,
+    Button::DPadUp => PadButton::DPadUp,
+    Button::DPadDown => PadButton::DPadDown,
+    Button::DPadLeft => PadButton::DPadLeft,
+    Button::DPadRight => PadButton::DPadRight,
+    _ => return None,
+  })
+}
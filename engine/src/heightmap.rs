@@ -0,0 +1,98 @@
+use crate::objects::{DagRef, VoxelObject};
+use glam::{UVec3, Vec3};
+use sdg::prelude::*;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// A grayscale image read as ground heights from 0 to 1, a row of pixels at a time
+pub struct Heightmap {
+  pub width: u32,
+  pub depth: u32,
+  pub heights: Vec<f32>,
+}
+impl Heightmap {
+  /// Reads a PNG of any bit depth. Colored ones are averaged across their channels, alpha is ignored
+  pub fn load(path: &Path) -> Result<Self, String> {
+    let file = File::open(path).map_err(|err| err.to_string())?;
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    // Palettes and bit depths under 8 come out as plain 8 bit samples
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(|err| err.to_string())?;
+    let mut bytes = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut bytes).map_err(|err| err.to_string())?;
+    let samples: Vec<f32> = match info.bit_depth {
+      png::BitDepth::Sixteen => bytes.chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]]) as f32 / 65535.0).collect(),
+      _ => bytes.iter().map(|&byte| byte as f32 / 255.0).collect(),
+    };
+    let channels = info.color_type.samples();
+    // Alpha is always the last channel
+    let colors = match info.color_type {
+      png::ColorType::GrayscaleAlpha | png::ColorType::Rgba => channels - 1,
+      _ => channels,
+    };
+    let pixels = (info.width * info.height) as usize;
+    let heights = samples[.. pixels * channels].chunks_exact(channels)
+      .map(|pixel| pixel[.. colors].iter().sum::<f32>() / colors as f32)
+      .collect();
+    Ok(Self { width: info.width, depth: info.height, heights })
+  }
+}
+
+/// What the ground is made of from the surface down
+#[derive(Debug, Clone)]
+pub struct Layering {
+  /// (leaf, how many cells thick) for each band under the surface in turn
+  pub bands: Vec<(Index, u32)>,
+  /// Everything under the last band
+  pub base: Index,
+}
+impl Layering {
+  // Which band the cell depth cells under the surface is in (1 is the top cell), bands.len() for the base
+  fn band(&self, depth: u32) -> usize {
+    let mut bottom = 0;
+    self.bands.iter().position(|&(_, thickness)| { bottom += thickness; depth <= bottom }).unwrap_or(self.bands.len())
+  }
+
+  fn leaf(&self, band: usize) -> Index { self.bands.get(band).map_or(self.base, |&(leaf, _)| leaf) }
+}
+
+/// A terrain object with its min corner at pos. Every pixel is a column of cells reaching up to its height
+/// times max_height, and always at least one cell so there's no holes in the floor
+pub fn terrain(sdg: &mut SparseDirectedGraph<BasicNode3d>, heightmap: &Heightmap, max_height: u32, layering: &Layering, pos: Vec3) -> VoxelObject {
+  let tops: Vec<u32> = heightmap.heights.iter().map(|height| ((height * max_height as f32).round() as u32).max(1)).collect();
+  let extent = UVec3::new(heightmap.width, tops.iter().copied().max().unwrap_or(1), heightmap.depth);
+  let height = extent.max_element().max(2).next_power_of_two().trailing_zeros();
+
+  // The lowest and highest top over every node's square of columns, level by level up from single columns.
+  // Columns past the edge of the image are empty
+  let side = 1u32 << height;
+  let mut levels = vec![(0 .. side * side).map(|idx| {
+    let (x, z) = (idx % side, idx / side);
+    let top = if x < extent.x && z < extent.z { tops[(z * extent.x + x) as usize] } else { 0 };
+    (top, top)
+  }).collect::<Vec<(u32, u32)>>()];
+  for level in 1 ..= height {
+    let (below, side) = (levels.last().unwrap(), side >> level);
+    let merged = (0 .. side * side).map(|idx| {
+      let corner = (idx / side * 2) * side * 2 + idx % side * 2;
+      [corner, corner + 1, corner + side * 2, corner + side * 2 + 1].map(|idx| below[idx as usize])
+        .into_iter().fold((u32::MAX, 0), |(min, max), (low, high)| (min.min(low), max.max(high)))
+    }).collect();
+    levels.push(merged);
+  }
+
+  // Nodes lying wholly above the ground or wholly within a band are filled in one go, so only the
+  // nodes the surface or a boundary between bands runs through are split
+  let head = sdg.build_regions(height, |corner, level| {
+    let (lowest, highest) = levels[level as usize][((corner.z >> level) * (side >> level) + (corner.x >> level)) as usize];
+    // How far under the surface the node's top and bottom cells get across its columns
+    let shallowest = lowest as i64 - (corner.y + (1 << level) - 1) as i64;
+    let deepest = highest as i64 - corner.y as i64;
+    if deepest <= 0 { return Some(EMPTY) }
+    if shallowest < 1 { return None }
+    let band = layering.band(shallowest as u32);
+    (band == layering.band(deepest as u32)).then(|| layering.leaf(band))
+  });
+  VoxelObject::new(sdg, DagRef::new(head, height), UVec3::ZERO, extent - 1, pos)
+}
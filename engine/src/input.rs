@@ -0,0 +1,364 @@
+//! Which keys and mouse buttons do what, with defaults that can be overridden from a bindings file.
+//!
+//! The file is TOML with a key per action, set to an input or a list of them:
+//!   move_forward = "KeyW"
+//!   place_block = ["MouseRight", "KeyE"]
+//! Keys are named like winit's KeyCode (KeyW, Space, ShiftLeft, F1, ArrowUp...), mouse buttons are
+//! MouseLeft, MouseRight and MouseMiddle and controller buttons are Pad followed by a PadButton. Binding an action replaces all of its default inputs,
+//! an empty list unbinds it. An input can be prefixed with Ctrl+, Shift+ or Alt+ to only count while that's held:
+//!   undo = "Ctrl+KeyZ"
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use serde::Deserialize;
+use winit::event::MouseButton;
+use winit::keyboard::KeyCode;
+
+/// Something that can be bound to an action
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+  Key(KeyCode),
+  Mouse(MouseButton),
+  Pad(PadButton),
+}
+
+/// A key which has to be held down for a binding to count, on either side of the keyboard
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Modifier {
+  Ctrl,
+  Shift,
+  Alt,
+}
+impl Modifier {
+  fn held(self, keys: &[KeyCode]) -> bool {
+    let (left, right) = match self {
+      Self::Ctrl => (KeyCode::ControlLeft, KeyCode::ControlRight),
+      Self::Shift => (KeyCode::ShiftLeft, KeyCode::ShiftRight),
+      Self::Alt => (KeyCode::AltLeft, KeyCode::AltRight),
+    };
+    keys.contains(&left) || keys.contains(&right)
+  }
+}
+
+/// A controller button, by position rather than label so it means the same thing across brands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PadButton {
+  South,
+  East,
+  West,
+  North,
+  LeftBumper,
+  RightBumper,
+  LeftTrigger,
+  RightTrigger,
+  Select,
+  Start,
+  LeftStick,
+  RightStick,
+  DPadUp,
+  DPadDown,
+  DPadLeft,
+  DPadRight,
+}
+impl PadButton {
+  const ALL: [PadButton; 16] = [
+    PadButton::South, PadButton::East, PadButton::West, PadButton::North,
+    PadButton::LeftBumper, PadButton::RightBumper, PadButton::LeftTrigger, PadButton::RightTrigger,
+    PadButton::Select, PadButton::Start, PadButton::LeftStick, PadButton::RightStick,
+    PadButton::DPadUp, PadButton::DPadDown, PadButton::DPadLeft, PadButton::DPadRight,
+  ];
+}
+
+/// What an input means to the game, independent of which input it is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+  // Held
+  MoveForward,
+  MoveBack,
+  MoveLeft,
+  MoveRight,
+  Jump,
+  Descend,
+  SpeedUp,
+  SpeedDown,
+  RollLeft,
+  RollRight,
+  Zoom,
+  // Pressed
+  ToggleCapture,
+  BreakBlock,
+  PlaceBlock,
+  ToggleContacts,
+  ToggleBounds,
+  ToggleChecksum,
+  ToggleRay,
+  ToggleChunks,
+  ToggleOverlay,
+  Screenshot,
+  QuickSave,
+  QuickLoad,
+  SpawnBall,
+  SpawnCuboid,
+  TogglePlacing,
+  TurnPrefab,
+  CycleSnap,
+  Scorch,
+  ToggleMovement,
+  CycleProjection,
+  CyclePaint,
+  BrushSmaller,
+  BrushBigger,
+  DummySmaller,
+  DummyBigger,
+  TimeFaster,
+  TimeSlower,
+  PauseTime,
+  Undo,
+  Redo,
+}
+impl Action {
+  const ALL: [(Action, &'static str); 41] = [
+    (Action::MoveForward, "move_forward"),
+    (Action::MoveBack, "move_back"),
+    (Action::MoveLeft, "move_left"),
+    (Action::MoveRight, "move_right"),
+    (Action::Jump, "jump"),
+    (Action::Descend, "descend"),
+    (Action::SpeedUp, "speed_up"),
+    (Action::SpeedDown, "speed_down"),
+    (Action::RollLeft, "roll_left"),
+    (Action::RollRight, "roll_right"),
+    (Action::Zoom, "zoom"),
+    (Action::ToggleCapture, "toggle_capture"),
+    (Action::BreakBlock, "break_block"),
+    (Action::PlaceBlock, "place_block"),
+    (Action::ToggleContacts, "toggle_contacts"),
+    (Action::ToggleBounds, "toggle_bounds"),
+    (Action::ToggleChecksum, "toggle_checksum"),
+    (Action::ToggleRay, "toggle_ray"),
+    (Action::ToggleChunks, "toggle_chunks"),
+    (Action::ToggleOverlay, "toggle_overlay"),
+    (Action::Screenshot, "screenshot"),
+    (Action::QuickSave, "quick_save"),
+    (Action::QuickLoad, "quick_load"),
+    (Action::SpawnBall, "spawn_ball"),
+    (Action::SpawnCuboid, "spawn_cuboid"),
+    (Action::TogglePlacing, "toggle_placing"),
+    (Action::TurnPrefab, "turn_prefab"),
+    (Action::CycleSnap, "cycle_snap"),
+    (Action::Scorch, "scorch"),
+    (Action::ToggleMovement, "toggle_movement"),
+    (Action::CycleProjection, "cycle_projection"),
+    (Action::CyclePaint, "cycle_paint"),
+    (Action::BrushSmaller, "brush_smaller"),
+    (Action::BrushBigger, "brush_bigger"),
+    (Action::DummySmaller, "dummy_smaller"),
+    (Action::DummyBigger, "dummy_bigger"),
+    (Action::TimeFaster, "time_faster"),
+    (Action::TimeSlower, "time_slower"),
+    (Action::PauseTime, "pause_time"),
+    (Action::Undo, "undo"),
+    (Action::Redo, "redo"),
+  ];
+
+  fn from_name(name: &str) -> Option<Self> {
+    Self::ALL.iter().find(|(_, action_name)| *action_name == name).map(|&(action, _)| action)
+  }
+}
+
+/// Every key a binding can name, looked up by their Debug names
+const KEYS: &[KeyCode] = &[
+  KeyCode::KeyA, KeyCode::KeyB, KeyCode::KeyC, KeyCode::KeyD, KeyCode::KeyE, KeyCode::KeyF, KeyCode::KeyG,
+  KeyCode::KeyH, KeyCode::KeyI, KeyCode::KeyJ, KeyCode::KeyK, KeyCode::KeyL, KeyCode::KeyM, KeyCode::KeyN,
+  KeyCode::KeyO, KeyCode::KeyP, KeyCode::KeyQ, KeyCode::KeyR, KeyCode::KeyS, KeyCode::KeyT, KeyCode::KeyU,
+  KeyCode::KeyV, KeyCode::KeyW, KeyCode::KeyX, KeyCode::KeyY, KeyCode::KeyZ,
+  KeyCode::Digit0, KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3, KeyCode::Digit4,
+  KeyCode::Digit5, KeyCode::Digit6, KeyCode::Digit7, KeyCode::Digit8, KeyCode::Digit9,
+  KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4, KeyCode::F5, KeyCode::F6,
+  KeyCode::F7, KeyCode::F8, KeyCode::F9, KeyCode::F10, KeyCode::F11, KeyCode::F12,
+  KeyCode::ArrowUp, KeyCode::ArrowDown, KeyCode::ArrowLeft, KeyCode::ArrowRight,
+  KeyCode::Space, KeyCode::Enter, KeyCode::Tab, KeyCode::Backspace, KeyCode::Escape,
+  KeyCode::ShiftLeft, KeyCode::ShiftRight, KeyCode::ControlLeft, KeyCode::ControlRight,
+  KeyCode::AltLeft, KeyCode::AltRight, KeyCode::CapsLock,
+  KeyCode::Minus, KeyCode::Equal, KeyCode::BracketLeft, KeyCode::BracketRight, KeyCode::Backslash,
+  KeyCode::Semicolon, KeyCode::Quote, KeyCode::Backquote, KeyCode::Comma, KeyCode::Period, KeyCode::Slash,
+  KeyCode::Insert, KeyCode::Delete, KeyCode::Home, KeyCode::End, KeyCode::PageUp, KeyCode::PageDown,
+  KeyCode::Numpad0, KeyCode::Numpad1, KeyCode::Numpad2, KeyCode::Numpad3, KeyCode::Numpad4,
+  KeyCode::Numpad5, KeyCode::Numpad6, KeyCode::Numpad7, KeyCode::Numpad8, KeyCode::Numpad9,
+  KeyCode::NumpadAdd, KeyCode::NumpadSubtract, KeyCode::NumpadEnter,
+];
+
+// An input's name with the modifier it may be prefixed with
+fn binding_from_name(name: &str) -> Option<(Option<Modifier>, Input)> {
+  let Some((modifier, input)) = name.split_once('+') else { return Some((None, input_from_name(name)?)) };
+  let modifier = match modifier {
+    "Ctrl" => Modifier::Ctrl,
+    "Shift" => Modifier::Shift,
+    "Alt" => Modifier::Alt,
+    _ => return None,
+  };
+  Some((Some(modifier), input_from_name(input)?))
+}
+
+fn input_from_name(name: &str) -> Option<Input> {
+  match name {
+    "MouseLeft" => Some(Input::Mouse(MouseButton::Left)),
+    "MouseRight" => Some(Input::Mouse(MouseButton::Right)),
+    "MouseMiddle" => Some(Input::Mouse(MouseButton::Middle)),
+    _ => match name.strip_prefix("Pad") {
+      Some(button) => PadButton::ALL.into_iter().find(|pad| format!("{pad:?}") == button).map(Input::Pad),
+      None => KEYS.iter().find(|key| format!("{key:?}") == name).map(|&key| Input::Key(key)),
+    },
+  }
+}
+
+// What the file binds an action to
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Binding {
+  One(String),
+  Many(Vec<String>),
+}
+
+/// Maps inputs to actions, an input can trigger several actions and an action can have several inputs.
+/// Inputs bound with a modifier only count while it's held
+pub struct InputMap {
+  bindings: Vec<(Option<Modifier>, Input, Action)>,
+}
+impl Default for InputMap {
+  fn default() -> Self {
+    use Action::*;
+    use Input::*;
+    let plain = [
+      (Key(KeyCode::KeyW), MoveForward),
+      (Key(KeyCode::KeyS), MoveBack),
+      (Key(KeyCode::KeyA), MoveLeft),
+      (Key(KeyCode::KeyD), MoveRight),
+      (Key(KeyCode::Space), Jump),
+      (Key(KeyCode::ShiftLeft), Descend),
+      (Key(KeyCode::Equal), SpeedUp),
+      (Key(KeyCode::Minus), SpeedDown),
+      (Key(KeyCode::KeyQ), RollLeft),
+      (Key(KeyCode::KeyE), RollRight),
+      (Key(KeyCode::KeyF), Zoom),
+      (Key(KeyCode::Escape), ToggleCapture),
+      (Mouse(MouseButton::Left), BreakBlock),
+      (Mouse(MouseButton::Right), PlaceBlock),
+      (Key(KeyCode::F1), ToggleContacts),
+      (Key(KeyCode::F2), ToggleBounds),
+      (Key(KeyCode::F3), ToggleChecksum),
+      (Key(KeyCode::F4), ToggleOverlay),
+      (Key(KeyCode::F6), ToggleRay),
+      (Key(KeyCode::F7), ToggleChunks),
+      (Key(KeyCode::F12), Screenshot),
+      (Key(KeyCode::F5), QuickSave),
+      (Key(KeyCode::F9), QuickLoad),
+      (Key(KeyCode::KeyB), SpawnBall),
+      (Key(KeyCode::KeyN), SpawnCuboid),
+      (Key(KeyCode::KeyV), TogglePlacing),
+      (Key(KeyCode::KeyR), TurnPrefab),
+      (Key(KeyCode::KeyG), CycleSnap),
+      (Key(KeyCode::KeyX), Scorch),
+      (Key(KeyCode::KeyC), ToggleMovement),
+      (Key(KeyCode::KeyO), CycleProjection),
+      (Key(KeyCode::KeyP), CyclePaint),
+      (Key(KeyCode::Minus), BrushSmaller),
+      (Key(KeyCode::Equal), BrushBigger),
+      (Key(KeyCode::BracketLeft), DummySmaller),
+      (Key(KeyCode::BracketRight), DummyBigger),
+      (Key(KeyCode::Period), TimeFaster),
+      (Key(KeyCode::Comma), TimeSlower),
+      (Key(KeyCode::KeyT), PauseTime),
+      (Pad(PadButton::Start), ToggleCapture),
+      (Pad(PadButton::South), Jump),
+      (Pad(PadButton::East), Descend),
+      (Pad(PadButton::RightTrigger), BreakBlock),
+      (Pad(PadButton::LeftTrigger), PlaceBlock),
+      (Pad(PadButton::North), TogglePlacing),
+      (Pad(PadButton::RightBumper), TurnPrefab),
+      (Pad(PadButton::LeftBumper), CyclePaint),
+    ];
+    let mut bindings: Vec<_> = plain.into_iter().map(|(input, action)| (None, input, action)).collect();
+    bindings.extend([
+      (Some(Modifier::Ctrl), Key(KeyCode::KeyZ), Undo),
+      (Some(Modifier::Ctrl), Key(KeyCode::KeyY), Redo),
+    ]);
+    Self { bindings }
+  }
+}
+impl InputMap {
+  /// The defaults with path's bindings on top. A missing file just means the defaults
+  pub fn load(path: &Path) -> Result<Self, String> {
+    match std::fs::read_to_string(path) {
+      Ok(source) => Self::parse(&source),
+      Err(_) => Ok(Self::default()),
+    }
+  }
+
+  // The defaults with a bindings file's source on top
+  fn parse(source: &str) -> Result<Self, String> {
+    let mut map = Self::default();
+    let file: BTreeMap<String, Binding> = toml::from_str(source).map_err(|err| err.to_string())?;
+    for (name, binding) in file {
+      let action = Action::from_name(&name).ok_or(format!("Unknown action {name}"))?;
+      let names = match binding {
+        Binding::One(name) => vec![name],
+        Binding::Many(names) => names,
+      };
+      let inputs = names.iter().map(|name| binding_from_name(name).ok_or(format!("Unknown input {name} for {action:?}")))
+        .collect::<Result<Vec<_>, String>>()?;
+      map.bindings.retain(|&(_, _, bound)| bound != action);
+      map.bindings.extend(inputs.into_iter().map(|(modifier, input)| (modifier, input, action)));
+    }
+    Ok(map)
+  }
+
+  /// Every action input triggers with keys held
+  pub fn actions<'a>(&'a self, input: Input, keys: &'a [KeyCode]) -> impl Iterator<Item = Action> + 'a {
+    self.bindings.iter()
+      .filter(move |&&(modifier, bound, _)| bound == input && modifier.is_none_or(|modifier| modifier.held(keys)))
+      .map(|&(_, _, action)| action)
+  }
+
+  /// Whether any of action's inputs are among the held ones
+  pub fn held(&self, action: Action, keys: &[KeyCode], buttons: &[MouseButton], pad: &[PadButton]) -> bool {
+    self.bindings.iter().any(|&(modifier, input, bound)| bound == action && modifier.is_none_or(|modifier| modifier.held(keys)) && match input {
+      Input::Key(key) => keys.contains(&key),
+      Input::Mouse(button) => buttons.contains(&button),
+      Input::Pad(button) => pad.contains(&button),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn bindings_files_replace_defaults() {
+    let map = InputMap::parse("# comments are fine\nplace_block = [\"MouseRight\", \"KeyE\"]\njump = \"PadNorth\"\nzoom = []\n").unwrap();
+    assert_eq!(map.actions(Input::Key(KeyCode::KeyE), &[]).collect::<Vec<_>>(), [Action::RollRight, Action::PlaceBlock]);
+    assert!(map.held(Action::Jump, &[], &[], &[PadButton::North]));
+    assert!(!map.held(Action::Jump, &[KeyCode::Space], &[], &[PadButton::South]));
+    assert!(!map.held(Action::Zoom, &[KeyCode::KeyF], &[], &[]));
+    assert!(map.held(Action::MoveForward, &[KeyCode::KeyW], &[], &[]));
+    assert!(InputMap::parse("fly = \"KeyW\"").is_err());
+    assert!(InputMap::parse("jump = \"KeyWW\"").is_err());
+    assert!(InputMap::parse("jump = KeyW").is_err());
+  }
+
+  #[test]
+  fn modifiers_have_to_be_held() {
+    let undo = |map: &InputMap, key, keys: &[KeyCode]| map.actions(Input::Key(key), keys).any(|action| action == Action::Undo);
+    let map = InputMap::default();
+    assert!(!undo(&map, KeyCode::KeyZ, &[KeyCode::KeyZ]));
+    assert!(undo(&map, KeyCode::KeyZ, &[KeyCode::ControlRight, KeyCode::KeyZ]));
+    let map = InputMap::parse("undo = [\"Alt+Backspace\", \"F8\"]\nredo = []").unwrap();
+    assert!(!undo(&map, KeyCode::KeyZ, &[KeyCode::ControlLeft, KeyCode::KeyZ]));
+    assert!(!undo(&map, KeyCode::Backspace, &[KeyCode::ControlLeft, KeyCode::Backspace]));
+    assert!(undo(&map, KeyCode::Backspace, &[KeyCode::AltLeft, KeyCode::Backspace]));
+    assert!(undo(&map, KeyCode::F8, &[KeyCode::F8]));
+    assert!(!map.held(Action::Redo, &[KeyCode::ControlLeft, KeyCode::KeyY], &[], &[]));
+    assert!(InputMap::parse("undo = \"Super+KeyZ\"").is_err());
+  }
+}
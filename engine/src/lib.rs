@@ -0,0 +1,33 @@
+pub mod app;
+pub mod wgpu_ctx;
+pub mod camera;
+pub mod wgpu_buffers;
+pub mod physics;
+pub mod objects;
+pub mod debug;
+pub mod worldgen;
+pub mod templates;
+pub mod console;
+pub mod shaders;
+pub mod decals;
+pub mod overlay;
+pub mod editor;
+pub mod materials;
+pub mod lights;
+pub mod sky;
+pub mod plugins;
+pub mod profiling;
+pub mod saves;
+pub mod smoke_test;
+pub mod start_screen;
+pub mod input;
+pub mod gamepad;
+pub mod streaming;
+pub mod heightmap;
+pub mod net;
+pub mod animation;
+pub mod destruction;
+pub mod bounce;
+pub mod textures;
+pub mod blocks;
+pub mod loading;
@@ -0,0 +1,160 @@
+use crate::materials::MaterialRegistry;
+use crate::objects::{DagRef, ObjectManager, VoxelObject};
+use crate::physics::PhysicsManager;
+use glam::{UVec3, Vec3};
+use sdg::prelude::*;
+use std::collections::HashMap;
+
+/// Most lights (glowing cells and placed ones together) lighting the scene in a frame, past this the ones nearest the camera win
+pub const MAX_LIGHTS: usize = 64;
+/// How far a glowing cell's light reaches
+pub const CELL_LIGHT_RADIUS: f32 = 8.0;
+// Uniform emissive nodes are tracked cell by cell, an object stops picking up more past this
+const MAX_CELLS_PER_OBJECT: usize = 4096;
+// Sky visibility rays cast per probe, how far they look for cover, and how many objects get probed each tick
+const PROBE_RAYS: usize = 12;
+const PROBE_DISTANCE: f32 = 24.0;
+const PROBES_PER_TICK: usize = 2;
+
+/// Which way a light shines
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightKind {
+  /// Every way
+  Point,
+  /// In a cone along dir (normalized), angle is its half angle in radians. It softens over the outer quarter
+  Spot { dir: Vec3, angle: f32 },
+}
+
+/// Light reaching out from a point, fading out to nothing at radius, as handed to the lighting pass
+#[derive(Debug, Clone, Copy)]
+pub struct Light {
+  /// World space
+  pub pos: Vec3,
+  pub color: Vec3,
+  pub radius: f32,
+  pub kind: LightKind,
+  /// How far short of pos the shadow rays stop, so whatever the light sits in doesn't shadow it.
+  /// Glowing cells are lit from their center, so their faces are at least half a cell out
+  pub inset: f32,
+}
+impl Light {
+  pub fn point(pos: Vec3, color: Vec3, radius: f32) -> Self { Self { pos, color, radius, kind: LightKind::Point, inset: 0.0 } }
+
+  pub fn spot(pos: Vec3, dir: Vec3, angle: f32, color: Vec3, radius: f32) -> Self {
+    Self { kind: LightKind::Spot { dir: dir.normalize(), angle }, ..Self::point(pos, color, radius) }
+  }
+}
+
+/// Everything lighting the scene besides the sun: lights placed in the world, and every emissive cell in every object,
+/// kept in step with edits so the renderer doesn't have to search the graph
+#[derive(Default)]
+pub struct Lights {
+  /// Torches, lamps and the like, in world space
+  pub placed: Vec<Light>,
+  // Indexed by object handle, cell -> emitted color. Empty for slots without an object
+  objects: Vec<HashMap<UVec3, Vec3>>,
+}
+impl Lights {
+  fn emission(materials: &MaterialRegistry, leaf: Index) -> Option<Vec3> {
+    let emissive = materials.get(leaf).emissive;
+    (leaf != EMPTY && emissive != Vec3::ZERO).then_some(emissive)
+  }
+
+  /// Finds every emissive cell in an object that was just spawned at idx
+  pub fn add_object(&mut self, sdg: &SparseDirectedGraph<BasicNode3d>, materials: &MaterialRegistry, idx: usize, object: &VoxelObject) {
+    if idx >= self.objects.len() { self.objects.resize_with(idx + 1, HashMap::new) }
+    self.rescan(sdg, materials, idx, object);
+  }
+
+  /// Finds every emissive cell in the object at idx over again, for when more of it changed than a list of cells
+  pub fn rescan(&mut self, sdg: &SparseDirectedGraph<BasicNode3d>, materials: &MaterialRegistry, idx: usize, object: &VoxelObject) {
+    let DagRef { head, height } = object.dag_ref;
+    // Everything solid lies within the bounds, so emissive regions never reach past them
+    let cells = match sdg.iter_region_holding(head, height, object.min_cell, object.max_cell, |leaf| Self::emission(materials, leaf).is_some()) {
+      Ok(cells) => cells,
+      Err(err) => { tracing::error!("Failed to find the lights in object {idx}: {err}"); self.objects[idx].clear(); return }
+    };
+    let cells = cells
+      .filter_map(|(corner, leaf, size)| Some((corner, size, Self::emission(materials, leaf)?)))
+      .flat_map(|(corner, size, color)| (0 .. size * size * size).map(move |cell| {
+        (corner + UVec3::new(cell % size, cell / size % size, cell / size / size), color)
+      }));
+    self.objects[idx] = cells.take(MAX_CELLS_PER_OBJECT).collect();
+  }
+
+  /// Follows cells being written into object
+  pub fn set_cells(&mut self, materials: &MaterialRegistry, object: usize, cells: &[(UVec3, Index)]) {
+    let tracked = &mut self.objects[object];
+    for &(cell, leaf) in cells {
+      match Self::emission(materials, leaf) {
+        Some(color) if tracked.len() < MAX_CELLS_PER_OBJECT => { tracked.insert(cell, color); }
+        _ => { tracked.remove(&cell); }
+      }
+    }
+  }
+
+  /// Forgets an object taken out of GameData::objects
+  pub fn remove_object(&mut self, object: usize) { self.objects[object].clear() }
+
+  /// Every cell of object moved by offset, see VoxelObject::grow_towards
+  pub fn shift(&mut self, object: usize, offset: UVec3) {
+    let tracked = std::mem::take(&mut self.objects[object]);
+    self.objects[object] = tracked.into_iter().map(|(cell, color)| (cell + offset, color)).collect();
+  }
+
+  /// Every light, or the MAX_LIGHTS whose reach comes nearest to pos if there are more
+  pub fn nearest(&self, objects: &ObjectManager, pos: Vec3) -> Vec<Light> {
+    let cells = objects.iter().filter_map(|(idx, object)| Some((object, self.objects.get(idx)?))).flat_map(|(object, cells)| {
+      let transform = object.transform();
+      cells.iter().map(move |(cell, &color)| Light { inset: 0.9, ..Light::point(transform.transform_point3(cell.as_vec3() + 0.5), color, CELL_LIGHT_RADIUS) })
+    });
+    let mut lights: Vec<Light> = self.placed.iter().copied().chain(cells).collect();
+    if lights.len() > MAX_LIGHTS {
+      let reach = |light: &Light| light.pos.distance(pos) - light.radius;
+      lights.select_nth_unstable_by(MAX_LIGHTS, |a, b| reach(a).total_cmp(&reach(b)));
+      lights.truncate(MAX_LIGHTS);
+    }
+    lights
+  }
+}
+
+/// Keeps each dynamic object's ambient up to date by casting a few rays at the sky from its center,
+/// so objects under cover darken along with the world around them. Fixed objects keep an ambient of 1,
+/// their faces already get per pixel occlusion from the lighting pass.
+#[derive(Default)]
+pub struct AmbientProbes {
+  // Round robin over the object handles, a few per tick
+  next: usize,
+}
+impl AmbientProbes {
+  pub fn update(&mut self, sdg: &SparseDirectedGraph<BasicNode3d>, objects: &mut ObjectManager, physics: &PhysicsManager) {
+    for _ in 0 .. PROBES_PER_TICK.min(objects.len()) {
+      // On to the next handle with an object behind it, there's at least one
+      self.next = (1 ..= objects.slots()).map(|step| (self.next + step) % objects.slots()).find(|&idx| objects.contains(idx)).unwrap();
+      let Some(handle) = objects[self.next].physics else { continue };
+      if !physics.is_dynamic(handle.body) { continue }
+      let visibility = Self::sky_visibility(sdg, objects, self.next);
+      // Eased in so passing under a ledge doesn't pop
+      let object = &mut objects[self.next];
+      object.ambient += (visibility - object.ambient) * 0.5;
+    }
+  }
+
+  /// Cosine weighted share of rays from object's center up into the sky which don't hit any other object
+  fn sky_visibility(sdg: &SparseDirectedGraph<BasicNode3d>, objects: &ObjectManager, object: usize) -> f32 {
+    let center = objects[object].pos + objects[object].pivot_offset;
+    let (mut open, mut total) = (0.0, 0.0);
+    for i in 0 .. PROBE_RAYS {
+      // Spiralling evenly over the upper hemisphere
+      let height = (i as f32 + 0.5) / PROBE_RAYS as f32;
+      let angle = i as f32 * 2.3999632;
+      let r = (1.0 - height * height).sqrt();
+      let dir = Vec3::new(angle.cos() * r, height, angle.sin() * r);
+      let blocked = objects.iter()
+        .any(|(idx, other)| idx != object && other.raycast(sdg, center, dir, PROBE_DISTANCE).is_some());
+      if !blocked { open += height }
+      total += height;
+    }
+    open / total
+  }
+}
@@ -0,0 +1,257 @@
+//! Content too slow to build on the main thread without the window freezing: whole worlds, .vox imports and
+//! heightmap terrain. Each load runs on a worker thread and comes back over a channel, while the window carries
+//! on drawing and can show how far along they are.
+//!
+//! Like streamed chunks, objects for the world being played are built in a graph of their own and merged into the
+//! world's on the main thread, see GameData::merge_loads. Whole worlds are simply handed over once they're built.
+
+use crate::heightmap::{self, Heightmap, Layering};
+use crate::materials::{Material, MaterialRegistry};
+use crate::objects::{self, DagRef, GameData, VoxelObject};
+use crate::templates::WorldTemplate;
+use crate::{net, saves};
+use glam::Vec3;
+use sdg::prelude::*;
+use std::panic::AssertUnwindSafe;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
+
+const WORKERS: usize = 2;
+
+type Job<T> = Box<dyn FnOnce() -> Result<T, String> + Send>;
+// Jobs out to the workers and word of how they're going back
+type Channels<T> = (mpsc::Sender<(u64, Job<T>)>, mpsc::Receiver<Message<T>>);
+
+enum Message<T> {
+  Started(u64),
+  Finished(u64, Result<T, String>),
+}
+
+/// Where a load has got to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadState {
+  /// Waiting on a worker to be free
+  Queued,
+  Working,
+}
+
+/// A load that hasn't finished yet
+pub struct Load {
+  id: u64,
+  /// What it's loading, a path or a world's name
+  pub name: String,
+  pub state: LoadState,
+}
+
+/// A few worker threads running loads in the order they were started. They're only spawned with the first load,
+/// and stop once the loader's dropped
+pub struct Loader<T> {
+  // None until the workers are spawned
+  channels: Option<Channels<T>>,
+  loads: Vec<Load>,
+  next_id: u64,
+  // Loads taken by finished since the loader was last idle
+  finished: usize,
+}
+impl<T> Default for Loader<T> {
+  fn default() -> Self { Self { channels: None, loads: Vec::new(), next_id: 0, finished: 0 } }
+}
+impl<T: Send + 'static> Loader<T> {
+  /// Runs job on a worker, name is what it's shown and reported as
+  pub fn start(&mut self, name: impl Into<String>, job: impl FnOnce() -> Result<T, String> + Send + 'static) {
+    let (jobs, _) = self.channels.get_or_insert_with(Self::spawn_workers);
+    let id = self.next_id;
+    self.next_id += 1;
+    jobs.send((id, Box::new(job))).unwrap();
+    self.loads.push(Load { id, name: name.into(), state: LoadState::Queued });
+  }
+
+  fn spawn_workers() -> Channels<T> {
+    let (jobs, job_queue) = mpsc::channel::<(u64, Job<T>)>();
+    let (messages, received) = mpsc::channel();
+    let job_queue = Arc::new(Mutex::new(job_queue));
+    for _ in 0 .. WORKERS {
+      let (job_queue, messages) = (job_queue.clone(), messages.clone());
+      std::thread::spawn(move || loop {
+        let Ok((id, job)) = job_queue.lock().unwrap().recv() else { return };
+        if messages.send(Message::Started(id)).is_err() { return }
+        // A load that panics fails on its own, rather than taking the worker down with it
+        let result = std::panic::catch_unwind(AssertUnwindSafe(job)).unwrap_or_else(|_| Err("it panicked".into()));
+        if messages.send(Message::Finished(id, result)).is_err() { return }
+      });
+    }
+    (jobs, received)
+  }
+
+  /// Every load that hasn't finished, oldest first
+  pub fn loads(&self) -> &[Load] { &self.loads }
+
+  pub fn is_busy(&self) -> bool { !self.loads.is_empty() }
+
+  /// (finished, started) since the loader was last idle, for a progress bar
+  pub fn progress(&self) -> (usize, usize) { (self.finished, self.finished + self.loads.len()) }
+
+  /// Takes whatever's finished since last time, each with its name
+  pub fn finished(&mut self) -> Vec<(String, Result<T, String>)> {
+    let Some((_, received)) = &self.channels else { return Vec::new() };
+    let messages: Vec<_> = received.try_iter().collect();
+    messages.into_iter().filter_map(|message| self.receive(message)).collect()
+  }
+
+  /// Blocks until every load has finished, then takes them like finished does
+  pub fn wait(&mut self) -> Vec<(String, Result<T, String>)> {
+    let mut done = Vec::new();
+    while self.is_busy() {
+      let Some((_, received)) = &self.channels else { break };
+      let Ok(message) = received.recv() else { break };
+      done.extend(self.receive(message));
+    }
+    done
+  }
+
+  fn receive(&mut self, message: Message<T>) -> Option<(String, Result<T, String>)> {
+    match message {
+      Message::Started(id) => {
+        if let Some(load) = self.loads.iter_mut().find(|load| load.id == id) { load.state = LoadState::Working }
+        None
+      }
+      Message::Finished(id, result) => {
+        let load = self.loads.remove(self.loads.iter().position(|load| load.id == id)?);
+        self.finished = if self.loads.is_empty() { 0 } else { self.finished + 1 };
+        Some((load.name, result))
+      }
+    }
+  }
+}
+
+/// Where a world to play comes from
+pub enum WorldSource {
+  /// Generated from a template and seed, to be saved to the slot if there is one
+  New { template: &'static WorldTemplate, seed: i32, slot: Option<String> },
+  /// A save slot
+  Save(String),
+  /// A world file written by save or voxeltool, it's in no slot until it's saved to one
+  File(PathBuf),
+  /// Whatever the game at this address is playing
+  Join(String),
+}
+impl WorldSource {
+  /// What it's shown as while loading
+  pub fn name(&self) -> String {
+    match self {
+      WorldSource::New { template, slot, .. } => slot.clone().unwrap_or_else(|| template.name.to_string()),
+      WorldSource::Save(name) => name.clone(),
+      WorldSource::File(path) => path.display().to_string(),
+      WorldSource::Join(address) => address.clone(),
+    }
+  }
+
+  /// Builds or reads the world, along with whether it was freshly generated. Slow enough to want a Loader
+  pub fn load(self) -> Result<(GameData, bool), String> {
+    match self {
+      WorldSource::New { template, seed, slot } => {
+        let mut game_data = GameData::new(template, seed);
+        // Saved under it once the mods have had their turn, see App::start_world
+        game_data.world.slot = slot;
+        Ok((game_data, true))
+      }
+      WorldSource::Save(name) => saves::load(&name).map(|game_data| (game_data, false)).map_err(|err| err.to_string()),
+      WorldSource::File(path) => GameData::load(&path).map(|game_data| (game_data, false)).map_err(|err| err.to_string()),
+      WorldSource::Join(address) => {
+        let (session, mut game_data) = net::Session::join(&address).map_err(|err| err.to_string())?;
+        game_data.net = Some(session);
+        Ok((game_data, false))
+      }
+    }
+  }
+}
+
+// What one of a built graph's leaves becomes in the world's
+enum LeafSource {
+  /// A leaf the world already has
+  Existing(Index),
+  /// A new leaf rendering as this
+  New(Material),
+}
+
+/// An object built on a worker in a graph of its own, waiting to be merged into the world
+pub struct BuiltObject {
+  sdg: SparseDirectedGraph<BasicNode3d>,
+  object: VoxelObject,
+  // By the built graph's leaf
+  leaves: Vec<LeafSource>,
+  dynamic: bool,
+  // Said once it's merged in, after the object's handle
+  summary: String,
+}
+
+impl GameData {
+  /// Imports the .vox file at path on a worker, it turns up as a dynamic object at pos once merge_loads sees it's done
+  pub fn queue_vox(&mut self, path: String, pos: Vec3) {
+    self.loads.start(path.clone(), move || {
+      let (mut sdg, mut materials) = (SparseDirectedGraph::new(), MaterialRegistry::default());
+      sdg.add_leaf();
+      let import = objects::import_vox(&mut sdg, &mut materials, path.as_ref(), pos).map_err(|err| err.to_string())?;
+      // Palette colors are all new leaves, after the empty one
+      let leaves = std::iter::once(LeafSource::Existing(EMPTY))
+        .chain(materials.all().iter().skip(1).map(|&material| LeafSource::New(material)))
+        .collect();
+      let summary = format!("using {} palette colors", import.leaf_colors.len());
+      Ok(BuiltObject { sdg, object: import.object, leaves, dynamic: true, summary })
+    });
+  }
+
+  /// Builds terrain from the heightmap at path on a worker, spread out below the camera with its highest point just
+  /// under it. See heightmap::terrain
+  pub fn queue_heightmap(&mut self, path: String, max_height: u32, layering: Layering) {
+    let camera = self.camera.position;
+    self.loads.start(path.clone(), move || {
+      let heightmap = Heightmap::load(path.as_ref())?;
+      let pos = camera - Vec3::new(heightmap.width as f32 / 2.0, max_height as f32 + 2.0, heightmap.depth as f32 / 2.0);
+      // The layering's leaves, at the same indices as the world's so they map straight back
+      let mut sdg = SparseDirectedGraph::new();
+      let last = layering.bands.iter().map(|&(leaf, _)| leaf).chain([layering.base, EMPTY]).max().unwrap();
+      let leaves = (0 ..= last).map(|_| LeafSource::Existing(sdg.add_leaf())).collect();
+      let object = heightmap::terrain(&mut sdg, &heightmap, max_height, &layering, pos);
+      let summary = format!("{}x{} columns", heightmap.width, heightmap.depth);
+      Ok(BuiltObject { sdg, object, leaves, dynamic: false, summary })
+    });
+  }
+
+  /// Adds whatever objects have finished building to the world
+  pub fn merge_loads(&mut self) {
+    let finished = self.loads.finished();
+    for (name, result) in finished { self.merge_load(name, result) }
+  }
+
+  /// Waits for everything still building and merges it in, for when nothing's drawn in the meantime anyway
+  pub fn finish_loads(&mut self) {
+    let finished = self.loads.wait();
+    for (name, result) in finished { self.merge_load(name, result) }
+  }
+
+  fn merge_load(&mut self, name: String, result: Result<BuiltObject, String>) {
+    let built = match result {
+      Ok(built) => built,
+      Err(err) => return tracing::error!("Failed to load {name}: {err}"),
+    };
+    let leaves: Vec<Index> = built.leaves.into_iter().map(|leaf| match leaf {
+      LeafSource::Existing(leaf) => leaf,
+      LeafSource::New(material) => {
+        self.materials_changed = true;
+        self.materials.register(&mut self.sdg, material)
+      }
+    }).collect();
+    let built_object = built.object;
+    let head = match self.sdg.clone_from(&built.sdg, built_object.dag_ref.head, |leaf| leaves[leaf as usize]) {
+      Ok(head) => head,
+      Err(err) => return tracing::error!("Failed to load {name}: {err}"),
+    };
+    let dag_ref = DagRef::new(head, built_object.dag_ref.height);
+    let mut object = VoxelObject::new(&self.sdg, dag_ref, built_object.min_cell, built_object.max_cell, built_object.pos);
+    object.pivot_offset = built_object.pivot_offset;
+    let idx = self.add_object(object, built.dynamic);
+    self.graph_changed = true;
+    tracing::info!("Loaded {name} as object {idx}, {}", built.summary);
+  }
+}
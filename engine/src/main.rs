@@ -1,16 +1,129 @@
-use crate::app::App;
+use voxel_game::app::{self, App, SecondView};
+use voxel_game::console::Settings;
+use voxel_game::input::InputMap;
+use voxel_game::objects::GameData;
+use voxel_game::plugins::Plugins;
+use voxel_game::textures::Atlas;
+use voxel_game::wgpu_ctx::WgpuCtx;
+use voxel_game::loading::WorldSource;
+use voxel_game::{profiling, smoke_test, templates};
+use std::path::Path;
 use winit::event_loop::{ControlFlow, EventLoop};
 
-mod app;
-mod wgpu_ctx;
-mod camera;
-mod wgpu_buffers;
-mod physics;
-mod objects;
-
 fn main() {
+  profiling::start();
+  // Without any of --world, --load, --open or --join the world is picked on the start screen
+  let mut template = None;
+  let mut seed = templates::DEFAULT_SEED;
+  let mut load = None;
+  let mut open = None;
+  let mut join = None;
+  let mut vox_paths = Vec::new();
+  let mut hot_reload = false;
+  let mut second_view = None;
+  let mut mods_dir = "mods".to_string();
+  let mut keys_path = "keys.toml".to_string();
+  let mut atlas_path = "textures/atlas.png".to_string();
+  let mut settings_path = "settings.cfg".to_string();
+  // Some to render the world headlessly into this directory and exit, instead of opening a window
+  let mut render_dir = None;
+  let mut frames = 8;
+  let mut size = (640, 360);
+  let mut args = std::env::args().skip(1);
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--vox" => vox_paths.push(args.next().expect("--vox needs a path")),
+      "--world" => template = Some(args.next().expect("--world needs a template name")),
+      "--seed" => seed = args.next().and_then(|seed| seed.parse().ok()).expect("--seed needs a whole number"),
+      "--load" => load = Some(args.next().expect("--load needs a save name")),
+      "--open" => open = Some(args.next().expect("--open needs a world file")),
+      "--join" => join = Some(args.next().expect("--join needs the host's address")),
+      "--hot-reload" => hot_reload = true,
+      "--second-window" => second_view = Some(args.next().and_then(|view| SecondView::from_name(&view)).expect("--second-window needs top or spectator")),
+      "--mods" => mods_dir = args.next().expect("--mods needs a directory"),
+      "--keys" => keys_path = args.next().expect("--keys needs a path"),
+      "--atlas" => atlas_path = args.next().expect("--atlas needs a path"),
+      "--settings" => settings_path = args.next().expect("--settings needs a path"),
+      "--render" => render_dir = Some(args.next().expect("--render needs a directory")),
+      "--frames" => frames = args.next().and_then(|frames| frames.parse().ok()).expect("--frames needs a count"),
+      "--size" => size = args.next().and_then(|size| {
+        let (width, height) = size.split_once('x')?;
+        Some((width.parse().ok()?, height.parse().ok()?))
+      }).expect("--size needs a resolution like 1280x720"),
+      "--smoke-test" => match smoke_test::run() {
+        Ok(()) => std::process::exit(0),
+        Err(err) => {
+          tracing::error!("Smoke test failed: {err}");
+          std::process::exit(1);
+        }
+      },
+      _ => tracing::warn!("Ignoring unknown argument {arg}"),
+    }
+  }
+  let world = match (join, load, open, template) {
+    (Some(address), ..) => Some(WorldSource::Join(address)),
+    (None, Some(name), ..) => Some(WorldSource::Save(name)),
+    (None, None, Some(path), _) => Some(WorldSource::File(path.into())),
+    (None, None, None, Some(name)) => {
+      let Some(template) = templates::find(&name) else {
+        let available: String = templates::TEMPLATES.iter().map(|template| format!("\n  {:<14}{}", template.name, template.description)).collect();
+        tracing::error!("Unknown world {name}, available worlds are:{available}");
+        std::process::exit(1);
+      };
+      Some(WorldSource::New { template, seed, slot: None })
+    }
+    (None, None, None, None) => None,
+  };
+  let mut plugins = Plugins::default();
+  plugins.load_dir(mods_dir.as_ref());
+  let atlas = Atlas::load_or_default(atlas_path.as_ref());
+  let settings = Settings::load(settings_path.as_ref());
+  if let Some(dir) = render_dir {
+    let Some(world) = world else {
+      tracing::error!("--render needs a world, pick one with --world, --load or --open");
+      std::process::exit(1);
+    };
+    let name = world.name();
+    let (mut game_data, fresh) = world.load().unwrap_or_else(|err| {
+      tracing::error!("Failed to load {name}: {err}");
+      std::process::exit(1);
+    });
+    app::prepare_world(&mut game_data, &mut plugins, &vox_paths, fresh);
+    // Nothing's drawn till the imports are in anyway
+    game_data.finish_loads();
+    settings.apply(&mut game_data, &mut plugins);
+    if let Err(err) = render(&mut game_data, &atlas, dir.as_ref(), frames, size) {
+      tracing::error!("Rendering failed: {err}");
+      std::process::exit(1);
+    }
+    profiling::finish();
+    return
+  }
+  let input = InputMap::load(keys_path.as_ref()).unwrap_or_else(|err| {
+    tracing::warn!("Using the default keys, {keys_path} is invalid: {err}");
+    InputMap::default()
+  });
   let event_loop = EventLoop::new().unwrap();
   event_loop.set_control_flow(ControlFlow::Poll);
-  let mut app = App::default();
+  let mut app = App::new(world, plugins, vox_paths, input, atlas, settings, hot_reload);
+  if let Some(view) = second_view { app = app.with_second_window(view) }
   event_loop.run_app(&mut app).expect("App crashed");
+  profiling::finish();
+}
+
+/// Renders frames of game_data without a window, writing each to dir as frame_<n>.ppm
+fn render(game_data: &mut GameData, atlas: &Atlas, dir: &Path, frames: u32, (width, height): (u32, u32)) -> Result<(), String> {
+  let mut ctx = WgpuCtx::headless(width, height)?;
+  game_data.camera.aspect_ratio = width as f32 / height as f32;
+  game_data.render.crosshair = false;
+  ctx.update_voxels(&game_data.sdg);
+  ctx.update_materials(&game_data.materials);
+  ctx.update_atlas(atlas);
+  std::fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+  for (idx, image) in ctx.render_frames(game_data, frames)?.iter().enumerate() {
+    let path = dir.join(format!("frame_{idx:03}.ppm"));
+    image.write_ppm(&path).map_err(|err| format!("Failed to write {}: {err}", path.display()))?;
+  }
+  tracing::info!("Wrote {frames} frames to {}", dir.display());
+  Ok(())
 }
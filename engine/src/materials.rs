@@ -0,0 +1,107 @@
+use glam::Vec3;
+use sdg::prelude::{BasicNode3d, Index, SparseDirectedGraph, EMPTY};
+
+/// Skips lighting entirely, the albedo is drawn as is
+#[allow(unused)] // Nothing registers unlit materials yet
+pub const FLAG_UNLIT: u32 = 1;
+/// Rays carry on through, tinted by the albedo and bent by the ior
+pub const FLAG_TRANSLUCENT: u32 = 2;
+/// Translucent with a rippling, reflective surface
+pub const FLAG_WATER: u32 = 4;
+/// Mirrors what's around it like a metal, tinted by the albedo and blurred by the roughness
+pub const FLAG_REFLECTIVE: u32 = 8;
+
+/// How a leaf looks, colors are linear
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Material {
+  pub albedo: Vec3,
+  /// 0 is mirror-like, 1 is fully diffuse. Only reflective materials look any different for it
+  pub roughness: f32,
+  /// Light given off regardless of what reaches the surface
+  pub emissive: Vec3,
+  /// FLAG_ bits
+  pub flags: u32,
+  /// How strongly a translucent material tints what's behind it, 0 is clear
+  pub opacity: f32,
+  /// Index of refraction of a translucent material, 1 doesn't bend rays at all
+  pub ior: f32,
+  /// Tile of the texture atlas wrapped around it (see crate::textures), which multiplies the albedo
+  pub tile: Option<u32>,
+}
+impl Material {
+  /// A plain diffuse material
+  pub fn new(albedo: Vec3) -> Self { Self { albedo, roughness: 1.0, emissive: Vec3::ZERO, flags: 0, opacity: 1.0, ior: 1.0, tile: None } }
+
+  /// Gives off color * strength, and lights up the cells around it
+  pub fn glowing(color: Vec3, strength: f32) -> Self { Self { emissive: color * strength, ..Self::new(color) } }
+
+  /// Something rays pass through, like glass or water
+  pub fn translucent(albedo: Vec3, opacity: f32, ior: f32) -> Self {
+    Self { flags: FLAG_TRANSLUCENT, opacity, ior, ..Self::new(albedo) }
+  }
+
+  /// Translucent like water, rippling and reflecting the sky where it meets the air
+  pub fn water(albedo: Vec3, opacity: f32) -> Self {
+    Self { flags: FLAG_TRANSLUCENT | FLAG_WATER, ..Self::translucent(albedo, opacity, 1.33) }
+  }
+
+  /// A metal, reflecting the scene tinted by albedo. Sharp at 0 roughness, blurring and dulling towards diffuse at 1
+  pub fn reflective(albedo: Vec3, roughness: f32) -> Self {
+    Self { flags: FLAG_REFLECTIVE, roughness: roughness.clamp(0.0, 1.0), ..Self::new(albedo) }
+  }
+
+  /// From an 8 bit sRGB color, like the ones in .vox palettes
+  pub fn from_srgb(color: [u8; 3]) -> Self {
+    let linear = |channel: u8| {
+      let srgb = channel as f32 / 255.0;
+      if srgb <= 0.04045 { srgb / 12.92 } else { ((srgb + 0.055) / 1.055).powf(2.4) }
+    };
+    Self::new(Vec3::new(linear(color[0]), linear(color[1]), linear(color[2])))
+  }
+
+  /// The albedo as an 8 bit sRGB color
+  pub fn srgb(&self) -> [u8; 3] {
+    let srgb = |linear: f32| {
+      let linear = linear.clamp(0.0, 1.0);
+      let srgb = if linear <= 0.0031308 { linear * 12.92 } else { 1.055 * linear.powf(1.0 / 2.4) - 0.055 };
+      (srgb * 255.0).round() as u8
+    };
+    [srgb(self.albedo.x), srgb(self.albedo.y), srgb(self.albedo.z)]
+  }
+}
+impl Default for Material {
+  // Loud enough to notice a leaf somebody forgot to give a material
+  fn default() -> Self { Self::new(Vec3::new(1.0, 0.0, 1.0)) }
+}
+
+/// Every leaf's material, indexed by the leaf itself so the shaders can look them up directly
+#[derive(Default)]
+pub struct MaterialRegistry {
+  // Leaves without a material (like EMPTY) hold the default
+  materials: Vec<Material>,
+}
+impl MaterialRegistry {
+  /// Adds a new leaf to the graph which renders as material
+  pub fn register(&mut self, sdg: &mut SparseDirectedGraph<BasicNode3d>, material: Material) -> Index {
+    let leaf = sdg.add_leaf();
+    self.set(leaf, material);
+    leaf
+  }
+
+  /// A leaf of this graph that already renders as material, or a newly registered one
+  pub fn find_or_register(&mut self, sdg: &mut SparseDirectedGraph<BasicNode3d>, material: Material) -> Index {
+    let existing = sdg.leaves().iter().copied().find(|&leaf| leaf != EMPTY && self.get(leaf) == material);
+    existing.unwrap_or_else(|| self.register(sdg, material))
+  }
+
+  /// Changes what an existing leaf looks like
+  pub fn set(&mut self, leaf: Index, material: Material) {
+    if leaf as usize >= self.materials.len() { self.materials.resize(leaf as usize + 1, Material::default()) }
+    self.materials[leaf as usize] = material;
+  }
+
+  pub fn get(&self, leaf: Index) -> Material { self.materials.get(leaf as usize).copied().unwrap_or_default() }
+
+  /// Indexed by leaf, for uploading
+  pub fn all(&self) -> &[Material] { &self.materials }
+}
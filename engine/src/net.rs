@@ -0,0 +1,416 @@
+//! Worlds shared over TCP. One game hosts and its GameData is the authority, the others join it.
+//! Joining sends the host's whole world across (in world.bin's format, see saves), after that peers only trade edits.
+//!
+//! Edits are applied where they're made straight away and sent to the host, which applies them in the order they
+//! reach it and passes every one on to all of its clients, the sender included. Cells and brushes land the same
+//! however many times they're applied, so replaying the host's order leaves everyone agreeing once it's caught up.
+//! Edits the host drops (see GameData::apply_edit) aren't passed on. Cells and brushes go by signed cell (see
+//! VoxelObject::origin) and grids grow to fit them wherever they land, so growing doesn't move anything on peers.
+//! The host also sends where its dynamic objects are every few ticks, which clients copy over their own simulation.
+//!
+//! Objects are named by index, so adding or removing them after joining isn't shared yet and edits to an object
+//! a peer doesn't have are dropped. Undo, redo and reorienting aren't shared either.
+//!
+//! Every message is its length as a u32 and then the message, little endian throughout like world.bin.
+//! Leaves go by their place in the graph's leaves rather than their index, joining renumbers them but keeps their order.
+
+use crate::objects::GameData;
+use crate::saves::{invalid, Reader, Writer};
+use glam::{IVec3, Quat, Vec3};
+use sdg::prelude::*;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+pub const DEFAULT_PORT: u16 = 7420;
+// How long joining waits on the host to answer and then for each read of its world
+const JOIN_TIMEOUT: Duration = Duration::from_secs(10);
+// Anything claiming to be longer is taken as a broken connection rather than allocated
+const MAX_MESSAGE: usize = 1 << 30;
+
+// What each message starts with
+const WORLD: u32 = 0;
+const CELLS: u32 = 1;
+const BRUSH: u32 = 2;
+const POSES: u32 = 3;
+
+// Ticks between the host sending its dynamic objects' poses, at 60 ticks a second that's 20 times a second
+const POSE_TICKS: u64 = 3;
+
+/// A change to one of the world's objects, made on one peer and replayed on the rest
+#[derive(Debug, Clone)]
+pub enum Edit {
+  /// See GameData::set_cells, the cells are signed
+  Cells { object: usize, cells: Vec<(IVec3, Index)> },
+  /// See GameData::apply_brush, the brush is in signed cells
+  Brush { object: usize, brush: Brush, leaf: Index, blend: Blend },
+}
+impl Edit {
+  fn encode(&self, sdg: &SparseDirectedGraph<BasicNode3d>) -> Vec<u8> {
+    // Leaves which aren't any (yet) go out past the end, so peers drop the edit
+    let leaf_slot = |leaf: Index| sdg.leaves().binary_search(&leaf).map_or(u32::MAX, |slot| slot as u32);
+    let mut out = Writer(Vec::new());
+    match self {
+      Self::Cells { object, cells } => {
+        out.u32(CELLS);
+        out.u32(*object as u32);
+        out.u32(cells.len() as u32);
+        for &(cell, leaf) in cells {
+          out.uvec3(cell.as_uvec3());
+          out.u32(leaf_slot(leaf));
+        }
+      }
+      Self::Brush { object, brush, leaf, blend } => {
+        out.u32(BRUSH);
+        out.u32(*object as u32);
+        match *brush {
+          Brush::Sphere { center, radius } => {
+            out.u32(0);
+            out.vec3(center);
+            out.f32(radius);
+          }
+          Brush::Cylinder { center, radius, length, axis } => {
+            out.u32(1);
+            out.vec3(center);
+            out.f32(radius);
+            out.f32(length);
+            out.u32(axis.index() as u32);
+          }
+          Brush::Box { min, max } => {
+            out.u32(2);
+            out.vec3(min);
+            out.vec3(max);
+          }
+        }
+        out.u32(leaf_slot(*leaf));
+        out.u32(match blend { Blend::Replace => 0, Blend::Paint => 1, Blend::Add => 2 });
+      }
+    }
+    out.0
+  }
+}
+
+// A leaf Edit::encode wrote, by its place in sdg's leaves
+fn read_leaf(input: &mut Reader, sdg: &SparseDirectedGraph<BasicNode3d>) -> io::Result<Index> {
+  let slot = input.u32()?;
+  sdg.leaves().get(slot as usize).copied().ok_or_else(|| invalid(format!("There's no leaf {slot}")))
+}
+
+enum Message {
+  Edit(Edit),
+  /// (object, pivot, rot) of every dynamic object. By pivot rather than pos, which moves whenever the grid grows
+  Poses(Vec<(usize, Vec3, Quat)>),
+}
+impl Message {
+  fn decode(bytes: &[u8], sdg: &SparseDirectedGraph<BasicNode3d>) -> io::Result<Self> {
+    let mut input = Reader::new(bytes);
+    Ok(match input.u32()? {
+      CELLS => {
+        let object = input.u32()? as usize;
+        let cells = (0 .. input.u32()?).map(|_| Ok((input.uvec3()?.as_ivec3(), read_leaf(&mut input, sdg)?))).collect::<io::Result<_>>()?;
+        Self::Edit(Edit::Cells { object, cells })
+      }
+      BRUSH => {
+        let object = input.u32()? as usize;
+        let brush = match input.u32()? {
+          0 => Brush::Sphere { center: input.vec3()?, radius: input.f32()? },
+          1 => {
+            let (center, radius, length) = (input.vec3()?, input.f32()?, input.f32()?);
+            let axis = match input.u32()? {
+              0 => Axis::X,
+              1 => Axis::Y,
+              2 => Axis::Z,
+              axis => return Err(invalid(format!("Unknown cylinder axis {axis}"))),
+            };
+            Brush::Cylinder { center, radius, length, axis }
+          }
+          2 => Brush::Box { min: input.vec3()?, max: input.vec3()? },
+          shape => return Err(invalid(format!("Unknown brush shape {shape}"))),
+        };
+        let leaf = read_leaf(&mut input, sdg)?;
+        let blend = match input.u32()? {
+          0 => Blend::Replace,
+          1 => Blend::Paint,
+          2 => Blend::Add,
+          blend => return Err(invalid(format!("Unknown blend {blend}"))),
+        };
+        Self::Edit(Edit::Brush { object, brush, leaf, blend })
+      }
+      POSES => {
+        let poses = (0 .. input.u32()?).map(|_| {
+          let object = input.u32()? as usize;
+          let pivot = input.vec3()?;
+          Ok((object, pivot, Quat::from_array([input.f32()?, input.f32()?, input.f32()?, input.f32()?])))
+        }).collect::<io::Result<_>>()?;
+        Self::Poses(poses)
+      }
+      tag => return Err(invalid(format!("Unexpected message {tag}"))),
+    })
+  }
+}
+
+// One end of a connection, reading and writing never block
+struct Peer {
+  stream: TcpStream,
+  addr: SocketAddr,
+  // Read but not yet a whole message
+  received: Vec<u8>,
+  // Queued messages, the socket has taken the first sent bytes of them
+  unsent: Vec<u8>,
+  sent: usize,
+}
+impl Peer {
+  fn new(stream: TcpStream) -> io::Result<Self> {
+    stream.set_nonblocking(true)?;
+    // Edits are small and wanted now, not batched up
+    stream.set_nodelay(true)?;
+    Ok(Self { addr: stream.peer_addr()?, stream, received: Vec::new(), unsent: Vec::new(), sent: 0 })
+  }
+
+  fn send(&mut self, message: &[u8]) {
+    self.unsent.extend_from_slice(&(message.len() as u32).to_le_bytes());
+    self.unsent.extend_from_slice(message);
+  }
+
+  /// Writes as much of what's queued as the socket will take, errors once the connection's gone
+  fn flush(&mut self) -> io::Result<()> {
+    while self.sent < self.unsent.len() {
+      match self.stream.write(&self.unsent[self.sent ..]) {
+        Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+        Ok(written) => self.sent += written,
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+        Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+        Err(err) => return Err(err),
+      }
+    }
+    if self.sent == self.unsent.len() {
+      self.unsent.clear();
+      self.sent = 0;
+    }
+    Ok(())
+  }
+
+  /// Every whole message that's arrived since last time, errors once the connection's gone
+  fn receive(&mut self) -> io::Result<Vec<Vec<u8>>> {
+    let mut buffer = [0; 4096];
+    loop {
+      match self.stream.read(&mut buffer) {
+        Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+        Ok(read) => self.received.extend_from_slice(&buffer[.. read]),
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+        Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+        Err(err) => return Err(err),
+      }
+    }
+    let mut messages = Vec::new();
+    let mut at = 0;
+    while let Some(len) = self.received.get(at .. at + 4) {
+      let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+      if len > MAX_MESSAGE { return Err(invalid(format!("A {len} byte message is too long"))) }
+      let Some(message) = self.received.get(at + 4 .. at + 4 + len) else { break };
+      messages.push(message.to_vec());
+      at += 4 + len;
+    }
+    self.received.drain(.. at);
+    Ok(messages)
+  }
+}
+
+enum Role {
+  Host { listener: TcpListener, clients: Vec<Peer> },
+  Client { host: Peer },
+}
+
+/// This game's end of a shared world, see the top of this file
+pub struct Session {
+  role: Role,
+  // Edits made here since the last update, sent by it
+  outgoing: Vec<Edit>,
+}
+impl Session {
+  /// Starts taking joins on port, from anywhere
+  pub fn host(port: u16) -> io::Result<Self> {
+    let listener = TcpListener::bind(("0.0.0.0", port))?;
+    listener.set_nonblocking(true)?;
+    Ok(Self { role: Role::Host { listener, clients: Vec::new() }, outgoing: Vec::new() })
+  }
+
+  /// Connects to the game hosting at address (DEFAULT_PORT unless it names one) and waits for its world
+  pub fn join(address: &str) -> io::Result<(Self, GameData)> {
+    let address = if address.contains(':') { address.to_string() } else { format!("{address}:{DEFAULT_PORT}") };
+    let addr = address.to_socket_addrs()?.next().ok_or_else(|| invalid(format!("{address} didn't resolve to anything")))?;
+    let mut stream = TcpStream::connect_timeout(&addr, JOIN_TIMEOUT)?;
+    stream.set_read_timeout(Some(JOIN_TIMEOUT))?;
+    let mut len = [0; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_MESSAGE { return Err(invalid(format!("A {len} byte world is too big"))) }
+    let mut message = vec![0; len];
+    stream.read_exact(&mut message)?;
+    stream.set_read_timeout(None)?;
+    let mut input = Reader::new(&message);
+    if input.u32()? != WORLD { return Err(invalid("The host didn't start with its world")) }
+    let game_data = GameData::from_bytes(&message[4 ..])?;
+    Ok((Self { role: Role::Client { host: Peer::new(stream)? }, outgoing: Vec::new() }, game_data))
+  }
+
+  pub fn is_host(&self) -> bool { matches!(self.role, Role::Host { .. }) }
+
+  /// Games connected to this one
+  pub fn peers(&self) -> usize {
+    match &self.role {
+      Role::Host { clients, .. } => clients.len(),
+      Role::Client { .. } => 1,
+    }
+  }
+
+  /// Queues an edit already made to this game's world for the others
+  pub fn record(&mut self, edit: Edit) { self.outgoing.push(edit) }
+
+  /// Sends what's been edited here, takes in new clients and applies what's arrived, once a tick.
+  /// Returns false once a client's lost its host, the session's over then
+  pub fn update(&mut self, game_data: &mut GameData) -> bool {
+    let outgoing: Vec<Vec<u8>> = self.outgoing.drain(..).map(|edit| edit.encode(&game_data.sdg)).collect();
+    match &mut self.role {
+      Role::Host { listener, clients } => {
+        for client in clients.iter_mut() { for edit in &outgoing { client.send(edit) } }
+        // Their world includes everything up to here, so they get exactly what's sent from here on
+        while let Ok((stream, _)) = listener.accept() {
+          let Ok(mut client) = Peer::new(stream) else { continue };
+          let mut world = Writer(Vec::new());
+          world.u32(WORLD);
+          world.0.extend_from_slice(&game_data.to_bytes());
+          client.send(&world.0);
+          tracing::info!("{} joined", client.addr);
+          clients.push(client);
+        }
+
+        let mut idx = 0;
+        while idx < clients.len() {
+          let messages = match clients[idx].receive() {
+            Ok(messages) => messages,
+            Err(err) => {
+              tracing::info!("{} left ({err})", clients.remove(idx).addr);
+              continue
+            }
+          };
+          for message in messages {
+            match Message::decode(&message, &game_data.sdg) {
+              // Only what the host's world took goes out, so no client applies an edit the authority doesn't have
+              Ok(Message::Edit(edit)) => if game_data.apply_edit(&edit) {
+                for client in clients.iter_mut() { client.send(&message) }
+              }
+              Ok(Message::Poses(_)) => (),
+              Err(err) => tracing::warn!("Ignoring a message from {}: {err}", clients[idx].addr),
+            }
+          }
+          idx += 1;
+        }
+
+        if game_data.tick.is_multiple_of(POSE_TICKS) {
+          let mut poses = Writer(Vec::new());
+          poses.u32(POSES);
+          let dynamic: Vec<_> = game_data.objects.iter()
+            .filter(|(_, object)| object.physics.is_some_and(|handle| game_data.physics.is_dynamic(handle.body)))
+            .collect();
+          poses.u32(dynamic.len() as u32);
+          for (idx, object) in dynamic {
+            poses.u32(idx as u32);
+            poses.vec3(object.pos + object.pivot_offset);
+            for value in object.rot.to_array() { poses.f32(value) }
+          }
+          for client in clients.iter_mut() { client.send(&poses.0) }
+        }
+        clients.retain_mut(|client| client.flush().inspect_err(|err| tracing::info!("{} left ({err})", client.addr)).is_ok());
+        true
+      }
+      Role::Client { host } => {
+        for edit in &outgoing { host.send(edit) }
+        let messages = match host.flush().and_then(|()| host.receive()) {
+          Ok(messages) => messages,
+          Err(err) => {
+            tracing::warn!("Lost the host ({err}), the world's no longer shared");
+            return false
+          }
+        };
+        for message in messages {
+          match Message::decode(&message, &game_data.sdg) {
+            Ok(Message::Edit(edit)) => { game_data.apply_edit(&edit); }
+            Ok(Message::Poses(poses)) => for (idx, pivot, rot) in poses {
+              let Some(object) = game_data.objects.get_mut(idx) else { continue };
+              object.pos = pivot - object.pivot_offset;
+              object.rot = rot;
+              if let Some(handle) = object.physics { game_data.physics.set_pose(handle.body, pivot, rot) }
+            }
+            Err(err) => tracing::warn!("Ignoring a message from the host: {err}"),
+          }
+        }
+        true
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::materials::Material;
+  use crate::objects::{DagRef, VoxelObject};
+  use glam::UVec3;
+
+  // Sends every edit host's session has queued through the wire format into client
+  fn replay(host: &mut GameData, client: &mut GameData) {
+    let edits: Vec<Edit> = host.net.as_mut().unwrap().outgoing.drain(..).collect();
+    for edit in edits {
+      let Message::Edit(edit) = Message::decode(&edit.encode(&host.sdg), &client.sdg).unwrap() else { panic!("{edit:?} didn't come back an edit") };
+      assert!(client.apply_edit(&edit), "{edit:?}");
+    }
+  }
+
+  #[test]
+  fn peers_grow_along_with_the_host() {
+    let (mut host, leaves) = GameData::blank();
+    let head = host.sdg.get_root(EMPTY).unwrap();
+    let object = host.add_object(VoxelObject::new(&host.sdg, DagRef::new(head, 2), UVec3::ZERO, UVec3::ZERO, Vec3::ZERO), false);
+    let mut client = GameData::from_bytes(&host.to_bytes()).unwrap();
+    host.net = Some(Session::host(0).unwrap());
+
+    host.set_cell(object, UVec3::ONE, leaves.solid);
+    // Past the grid's low corner, so it's re-rooted with everything already there moving up
+    assert_eq!(host.set_world_cell(object, Vec3::new(-2.5, 0.5, 0.5), leaves.surface), Some(IVec3::new(-3, 0, 0)));
+    host.apply_brush(object, Brush::Sphere { center: Vec3::new(5.5, 1.5, 1.5), radius: 1.0 }, leaves.solid, Blend::Add);
+    replay(&mut host, &mut client);
+
+    let (ours, theirs) = (&host.objects[object], &client.objects[object]);
+    assert_eq!((theirs.dag_ref.height, theirs.origin, theirs.pivot_offset), (ours.dag_ref.height, ours.origin, ours.pivot_offset));
+    // The graphs were built apart, so their heads only match cell for cell
+    let side = 1 << ours.dag_ref.height;
+    for cell in (0 .. side * side * side).map(|idx| UVec3::new(idx % side, idx / side % side, idx / side / side)) {
+      assert_eq!(theirs.leaf_at(&client.sdg, cell), ours.leaf_at(&host.sdg, cell), "{cell}");
+    }
+    assert_ne!(ours.leaf_at(&host.sdg, UVec3::new(1, 0, 0)), EMPTY);
+  }
+
+  #[test]
+  fn leaves_registered_late_keep_their_material() {
+    let (mut host, leaves) = GameData::blank();
+    let head = host.sdg.get_root(EMPTY).unwrap();
+    let object = host.add_object(VoxelObject::new(&host.sdg, DagRef::new(head, 2), UVec3::ZERO, UVec3::ZERO, Vec3::ZERO), false);
+    host.set_cell(object, UVec3::ZERO, leaves.solid);
+    // Like a .vox import's, registered once there are nodes so it comes after them
+    let imported = Material::new(Vec3::new(0.9, 0.1, 0.6));
+    let leaf = host.materials.register(&mut host.sdg, imported);
+    let mut client = GameData::from_bytes(&host.to_bytes()).unwrap();
+    assert!(!client.sdg.is_leaf(leaf), "Joining should have renumbered leaf {leaf}");
+    host.net = Some(Session::host(0).unwrap());
+
+    host.set_cell(object, UVec3::ONE, leaf);
+    host.apply_brush(object, Brush::Box { min: Vec3::new(2.0, 0.0, 0.0), max: Vec3::new(4.0, 1.0, 1.0) }, leaf, Blend::Replace);
+    replay(&mut host, &mut client);
+
+    let theirs = &client.objects[object];
+    for cell in [UVec3::ONE, UVec3::new(2, 0, 0), UVec3::new(3, 0, 0)] {
+      assert_eq!(client.materials.get(theirs.leaf_at(&client.sdg, cell)), imported, "{cell}");
+    }
+  }
+}
@@ -1,31 +1,142 @@
-use crate::camera::Camera;
-use glam::{Vec3, UVec3, Quat};
+use crate::camera::{Camera, Follow};
+use crate::physics::{CharacterController, PhysicsHandle, PhysicsManager, TIMESTEP};
+use crate::debug::{Checksum, DebugFlags, DebugLines};
+use crate::worldgen::TerrainLeaves;
+use crate::templates::{self, WorldTemplate};
+use crate::physics::DagSnapshot;
+use crate::decals::Decals;
+use crate::editor::EditHistory;
+use crate::lights::{AmbientProbes, Lights};
+use crate::sky::{Sky, TimeOfDay};
+use crate::saves::WorldInfo;
+use crate::streaming::WorldManager;
+use crate::loading::{BuiltObject, Loader};
+use crate::net::{Edit, Session};
+use crate::animation::Animation;
+use crate::materials::{Material, MaterialRegistry};
+use crate::blocks::{BlockProperties, BlockRegistry};
+use crate::profiling::zone;
+use crate::wgpu_ctx::RenderSettings;
+use glam::{BVec3, IVec3, Mat4, Vec2, Vec3, Vec4, UVec3, Quat};
 use sdg::prelude::*;
+use std::collections::HashMap;
 use lilypads::Pond;
-use fastnoise_lite::FastNoiseLite;
-use fastnoise_lite::NoiseType;
+use std::io;
+use std::sync::Arc;
+use std::path::{Path as FilePath, PathBuf};
 
-// struct ObjectManager {
-//   objects: Pond<VoxelObject>
-// }
-// impl ObjectManager {
-// }
+/// Every VoxelObject in the world. They live in a Pond, so the handle an object's spawned with (its slot) stays
+/// the same for as long as it's around, whatever else comes and goes, and is only reused once it's despawned.
+/// Iterating goes in handle order, which is the order the renderer fills its objects buffer in
+pub struct ObjectManager {
+  pond: Pond<VoxelObject>,
+  // Live objects, the Pond only knows its slots
+  count: usize,
+}
+impl Default for ObjectManager {
+  fn default() -> Self { Self { pond: Pond::new(), count: 0 } }
+}
+impl ObjectManager {
+  /// Puts object into the simulation and returns its handle. Its head should hold a ref, which is kept until it's despawned
+  pub fn spawn(&mut self, physics: &mut PhysicsManager, mut object: VoxelObject, dynamic: bool) -> usize {
+    object.physics = Some(physics.add_voxel_object(&object, dynamic));
+    self.count += 1;
+    self.pond.alloc(object)
+  }
+
+  /// spawn into a particular free slot, for putting objects back where they were saved
+  pub fn spawn_at(&mut self, handle: usize, physics: &mut PhysicsManager, mut object: VoxelObject, dynamic: bool) {
+    assert!(!self.contains(handle), "Object {handle} is already taken");
+    object.physics = Some(physics.add_voxel_object(&object, dynamic));
+    self.count += 1;
+    self.pond.write(handle, object);
+  }
+
+  /// Takes the object at handle out of the simulation and drops its head's ref, so whatever of its graph nothing
+  /// else uses is freed (the head it comes back with may be one of those). None if there's nothing there
+  pub fn despawn(&mut self, sdg: &mut SparseDirectedGraph<BasicNode3d>, physics: &mut PhysicsManager, handle: usize) -> Option<VoxelObject> {
+    let object = self.pond.free(handle)?;
+    self.count -= 1;
+    if let Some(body) = object.physics { physics.remove_voxel_object(body) }
+    if let Err(err) = sdg.drop_root(object.dag_ref.head) { tracing::error!("Failed to free object {handle}'s graph: {err}") }
+    Some(object)
+  }
+
+  pub fn get(&self, handle: usize) -> Option<&VoxelObject> { self.pond.get(handle) }
+
+  pub fn get_mut(&mut self, handle: usize) -> Option<&mut VoxelObject> { self.pond.get_mut(handle) }
+
+  pub fn contains(&self, handle: usize) -> bool { self.pond.get(handle).is_some() }
+
+  /// How many objects there are, handles can go higher
+  pub fn len(&self) -> usize { self.count }
+
+  pub fn is_empty(&self) -> bool { self.count == 0 }
+
+  /// One past the highest handle there's been room for, for anything kept per handle
+  pub fn slots(&self) -> usize { self.pond.len() }
+
+  /// (handle, object) for every object, by handle
+  pub fn iter(&self) -> impl Iterator<Item = (usize, &VoxelObject)> {
+    (0 .. self.pond.len()).filter_map(|handle| Some((handle, self.pond.get(handle)?)))
+  }
+
+  /// Every object, by handle
+  pub fn values(&self) -> impl Iterator<Item = &VoxelObject> { self.iter().map(|(_, object)| object) }
+
+  /// Every handle in use, copied out so the objects can be changed while going through them
+  pub fn handles(&self) -> Vec<usize> { self.iter().map(|(handle, _)| handle).collect() }
+}
+impl std::ops::Index<usize> for ObjectManager {
+  type Output = VoxelObject;
+  fn index(&self, handle: usize) -> &VoxelObject { self.pond.get(handle).unwrap_or_else(|| panic!("There's no object {handle}")) }
+}
+impl std::ops::IndexMut<usize> for ObjectManager {
+  fn index_mut(&mut self, handle: usize) -> &mut VoxelObject { self.pond.get_mut(handle).unwrap_or_else(|| panic!("There's no object {handle}")) }
+}
+// The Pond leaves dropping what's in it to us
+impl Drop for ObjectManager {
+  fn drop(&mut self) {
+    for handle in 0 .. self.pond.len() { self.pond.free(handle); }
+  }
+}
 
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct DagRef {
-  head: u32,
-  height: u32,
+  pub head: u32,
+  pub height: u32,
 }
-impl DagRef { fn new(head: u32, height: u32) -> Self { Self { head, height} } }
+impl DagRef { pub fn new(head: u32, height: u32) -> Self { Self { head, height} } }
+
+pub use sdg::prelude::EMPTY;
+
+/// Tallest an object's grid may grow to by editing past its edge
+pub const MAX_HEIGHT: u32 = 16;
 
-// Impement parry3d::shape
+/// Where a ray struck a voxel. cell is signed (see VoxelObject::origin), normal is in the object's grid space
+#[derive(Debug, Clone, Copy)]
+pub struct RayHit {
+  pub object: usize,
+  pub cell: IVec3,
+  /// Points out of the face that was hit, zero if the ray started inside a solid cell
+  pub normal: IVec3,
+  pub t: f32,
+  pub leaf: Index,
+  /// World space point where the ray struck
+  pub pos: Vec3,
+  pub uv: Vec2,
+}
 
+#[derive(Clone)]
 pub struct VoxelObject {
   pub dag_ref: DagRef,
   // An aabb in local grid_space
   pub min_cell: UVec3,
   pub max_cell: UVec3,
+  /// The signed cell grid cell 0 holds. Grids only go up from 0, so growing one towards negative cells moves this down
+  /// instead, and cells keep the same signed coordinates however often the grid is re-rooted
+  pub origin: IVec3,
 
   // The worldspace position of the min corner of the grid (NOT THE OBJECT)
   pub pos: Vec3,
@@ -33,59 +144,738 @@ pub struct VoxelObject {
   // (0,0,0) representing the bottom left back corner (pos)
   pub pivot_offset: Vec3,
   pub rot: Quat,
+  /// (pivot position, rot) before the last tick moved the object, None draws it right where it is
+  pub prev_pose: Option<(Vec3, Quat)>,
+
+  // What physics samples, a copy of the tree under dag_ref as of the last refresh_snapshot
+  pub snapshot: Arc<DagSnapshot>,
+  /// Whether dag_ref (or the grid) changed since snapshot was taken. Edits only set this,
+  /// the copy is taken again once per tick rather than once per edit, see GameData::refresh_shapes
+  pub snapshot_stale: bool,
+  /// Whether anything's edited the object since it was made, streaming keeps edited chunks loaded rather than lose the edits
+  pub edited: bool,
+  pub physics: Option<PhysicsHandle>,
+  /// Share of the sky visible from around the object, scales its ambient light. See AmbientProbes
+  pub ambient: f32,
+  /// Moves the object by itself every tick, see GameData::animate
+  pub animation: Option<Animation>,
+  /// Tick it's removed at, for debris (see destruction) which shouldn't pile up forever
+  pub expires: Option<u64>,
 }
 impl VoxelObject {
-  pub fn is_point_solid(pos: Vec3) -> bool { todo!() } 
-
-  pub fn floor(sdg: &mut SparseDirectedGraph<BasicNode3d>, pos: Vec3) -> Self {
-    let mut head = sdg.get_root(0);
-    let height = 4;
-    let size = 2u32.pow(height);
-    for x in 0 .. size {
-      for z in 0 .. size {
-        let path = Zorder3d::path_from(UVec3::new(x, 0, z), height);
-        head = sdg.set_node(head, &path, 1);
-      }
-    }
-    for y in 1 ..= 2 {
-      for x in 3 ..= 4 {
-        for z in 3 ..= 4 {
-          let path = Zorder3d::path_from(UVec3::new(x, y, z), height);
-          head = sdg.set_node(head, &path, 1);
-        }
-      }
-    } 
- 
+  /// An unrotated object pivoting around the center of its grid
+  pub fn new(sdg: &SparseDirectedGraph<BasicNode3d>, dag_ref: DagRef, min_cell: UVec3, max_cell: UVec3, pos: Vec3) -> Self {
     Self {
-      dag_ref: DagRef::new(head, height),
-      min_cell: UVec3::ZERO,
-      max_cell: UVec3::splat(size - 1).with_y(3),
+      dag_ref,
+      min_cell,
+      max_cell,
+      origin: IVec3::ZERO,
       pos,
-      pivot_offset: Vec3::splat(size as f32) / 2.0,
+      pivot_offset: Vec3::splat((1u32 << dag_ref.height) as f32) / 2.0,
       rot: Quat::IDENTITY,
+      prev_pose: None,
+      snapshot: Arc::new(DagSnapshot::new(sdg, dag_ref.head, dag_ref.height)),
+      snapshot_stale: false,
+      edited: false,
+      physics: None,
+      ambient: 1.0,
+      animation: None,
+      expires: None,
     }
   }
+
+  /// World space -> local grid space
+  pub fn inv_transform(&self) -> Mat4 { self.inv_transform_at(self.pos, self.rot) }
+
+  /// inv_transform if the object were at pos and rot
+  pub fn inv_transform_at(&self, pos: Vec3, rot: Quat) -> Mat4 {
+    // I don't really understand the matrix math yet, but it works.
+    Mat4::from_translation(self.pivot_offset) *
+    Mat4::from_quat(rot.inverse()) * 
+    Mat4::from_translation(-pos - self.pivot_offset)
+  }
+
+  /// (pos, rot) alpha of the way from the last tick's pose to the current one, for drawing between ticks
+  pub fn render_pose(&self, alpha: f32) -> (Vec3, Quat) {
+    let Some((prev_pivot, prev_rot)) = self.prev_pose else { return (self.pos, self.rot) };
+    // By pivot rather than pos, which jumps when the grid grows
+    (prev_pivot.lerp(self.pos + self.pivot_offset, alpha) - self.pivot_offset, prev_rot.slerp(self.rot, alpha))
+  }
+
+  /// inv_transform at render_pose
+  pub fn render_inv_transform(&self, alpha: f32) -> Mat4 {
+    let (pos, rot) = self.render_pose(alpha);
+    self.inv_transform_at(pos, rot)
+  }
+
+  /// Local grid space -> world space
+  pub fn transform(&self) -> Mat4 { self.inv_transform().inverse() }
+
+  /// World space (min, max) around every cell within the bounds, at render_pose
+  pub fn render_aabb(&self, alpha: f32) -> (Vec3, Vec3) {
+    let transform = self.render_inv_transform(alpha).inverse();
+    let (low, high) = (self.min_cell.as_vec3(), (self.max_cell + 1).as_vec3());
+    (0..8).map(|corner| transform.transform_point3(Vec3::select(BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0), high, low)))
+      .fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), point| (min.min(point), max.max(point)))
+  }
+
+  /// Marches a world space ray through the object, the hit is in grid space. t is measured in units of dir.
+  pub fn raycast(&self, sdg: &SparseDirectedGraph<BasicNode3d>, origin: Vec3, dir: Vec3, max_t: f32) -> Option<Hit> {
+    let inv_transform = self.inv_transform();
+    let (origin, dir) = (inv_transform.transform_point3(origin), inv_transform.transform_vector3(dir));
+    sdg.raycast_within(self.dag_ref.head, self.dag_ref.height, origin, dir, max_t, self.min_cell, self.max_cell)
+      .unwrap_or_else(|err| { tracing::error!("Failed to raycast an object: {err}"); None })
+  }
+
+  /// Writes each leaf into its cell as one batch, growing the bounds to fit anything solid.
+  /// If the graph turns the batch down nothing is written
+  pub fn set_cells(&mut self, sdg: &mut SparseDirectedGraph<BasicNode3d>, cells: &[(UVec3, Index)]) -> Result<(), GraphError> {
+    let mut batch = sdg.edit(self.dag_ref.head, self.dag_ref.height);
+    for &(cell, leaf) in cells { batch.set_cell(cell, leaf); }
+    self.dag_ref.head = batch.commit()?;
+    for &(cell, leaf) in cells {
+      if leaf != EMPTY {
+        self.min_cell = self.min_cell.min(cell);
+        self.max_cell = self.max_cell.max(cell);
+      }
+    }
+    self.changed();
+    Ok(())
+  }
+
+  // Every edit ends here
+  fn changed(&mut self) { self.snapshot_stale = true; self.edited = true }
+
+  /// Takes snapshot again if an edit left it behind, returning whether it had to
+  pub fn refresh_snapshot(&mut self, sdg: &SparseDirectedGraph<BasicNode3d>) -> bool {
+    if !self.snapshot_stale { return false }
+    self.snapshot = Arc::new(DagSnapshot::new(sdg, self.dag_ref.head, self.dag_ref.height));
+    self.snapshot_stale = false;
+    true
+  }
+
+  /// Reads the leaf at cell, which is EMPTY for cells outside the grid
+  pub fn leaf_at(&self, sdg: &SparseDirectedGraph<BasicNode3d>, cell: UVec3) -> Index {
+    let DagRef { head, height } = self.dag_ref;
+    // A MortonPath only reaches MAX_DEPTH levels down, past that it's a plain walk
+    if height > MortonPath::MAX_DEPTH {
+      return if cell.max_element().checked_shr(height).unwrap_or(0) == 0 { sdg.sample(head, height, cell).map_or(EMPTY, |(leaf, _)| leaf) } else { EMPTY }
+    }
+    MortonPath::new(cell, height).and_then(|path| sdg.descend(head, &path)).unwrap_or(EMPTY)
+  }
+
+  /// The signed cell a world space point is in, which may be outside the grid
+  pub fn local_cell(&self, world: Vec3) -> IVec3 { self.signed_cell(self.inv_transform().transform_point3(world).floor().as_ivec3()) }
+
+  /// A grid cell's signed coordinates, see origin. Takes cells outside the grid too
+  pub fn signed_cell(&self, cell: IVec3) -> IVec3 { cell + self.origin }
+
+  /// Where a signed cell sits in the grid, None if the grid doesn't reach it (yet)
+  pub fn grid_cell(&self, cell: IVec3) -> Option<UVec3> {
+    let cell = cell - self.origin;
+    (cell.min_element() >= 0 && cell.max_element() < 1 << self.dag_ref.height).then(|| cell.as_uvec3())
+  }
+
+  /// Whether a signed cell fits inside the object's grid at all
+  pub fn in_grid(&self, cell: IVec3) -> bool { self.grid_cell(cell).is_some() }
+
+  /// Makes the grid one level taller with the old root as one of the new root's children.
+  /// The old grid grows away from the signed cell along each axis, so it ends up closer to (or inside) the new grid.
+  /// Returns how far every existing cell moved within the grid, the object stays put in world space
+  /// and signed cells stay where they were.
+  pub fn grow_towards(&mut self, sdg: &mut SparseDirectedGraph<BasicNode3d>, cell: IVec3) -> Result<UVec3, GraphError> {
+    let size = 1u32 << self.dag_ref.height;
+    // Anything below the grid on an axis means the old root takes the high half of it
+    let local = cell - self.origin;
+    let offset = UVec3::from(local.cmplt(IVec3::ZERO)) * size;
+    self.dag_ref = DagRef::new(sdg.grow_root(self.dag_ref.head, local)?, self.dag_ref.height + 1);
+    self.min_cell += offset;
+    self.max_cell += offset;
+    self.origin -= offset.as_ivec3();
+    // Shifting the pivot along with the cells keeps the transform (and any physics body) where it was
+    self.pos -= offset.as_vec3();
+    self.pivot_offset += offset.as_vec3();
+    self.changed();
+    Ok(offset)
+  }
+
+  /// Lays the object's cells out in orientation within its grid, see SparseDirectedGraph::transformed.
+  /// Returns the old head, whose ref the caller gets. On an error nothing has changed
+  pub fn reorient(&mut self, sdg: &mut SparseDirectedGraph<BasicNode3d>, orientation: Orientation) -> Result<Index, GraphError> {
+    let old = self.dag_ref.head;
+    let size = 1 << self.dag_ref.height;
+    self.dag_ref.head = sdg.transformed(old, orientation)?;
+    let (min, max) = (orientation.apply(self.min_cell, size), orientation.apply(self.max_cell, size));
+    (self.min_cell, self.max_cell) = (min.min(max), min.max(max));
+    self.changed();
+    Ok(old)
+  }
+
+  /// Writes leaf into the cells under brush which blend lets take it, growing the bounds to fit
+  pub fn apply_brush(&mut self, sdg: &mut SparseDirectedGraph<BasicNode3d>, brush: Brush, leaf: Index, blend: Blend) -> Result<(), GraphError> {
+    self.dag_ref.head = sdg.apply_brush(self.dag_ref.head, self.dag_ref.height, brush, leaf, blend)?;
+    if leaf != EMPTY && blend != Blend::Paint && let Some((min, max)) = brush.bounds(1 << self.dag_ref.height) {
+      self.min_cell = self.min_cell.min(min);
+      self.max_cell = self.max_cell.max(max);
+    }
+    self.changed();
+    Ok(())
+  }
+
+  /// Carries the change from one head to another over onto the object, see SparseDirectedGraph::apply_change
+  pub fn apply_change(&mut self, sdg: &mut SparseDirectedGraph<BasicNode3d>, from: Index, to: Index) -> Result<(), GraphError> {
+    self.dag_ref.head = sdg.apply_change(self.dag_ref.head, from, to)?;
+    self.changed();
+    Ok(())
+  }
+}
+
+
+// brush moved by offset, for taking it between grid and signed cells (see VoxelObject::origin)
+fn shift_brush(brush: Brush, offset: Vec3) -> Brush {
+  match brush {
+    Brush::Sphere { center, radius } => Brush::Sphere { center: center + offset, radius },
+    Brush::Cylinder { center, radius, length, axis } => Brush::Cylinder { center: center + offset, radius, length, axis },
+    Brush::Box { min, max } => Brush::Box { min: min + offset, max: max + offset },
+  }
+}
+
+/// A .vox model loaded into the graph, along with the color each new leaf stands for
+pub struct VoxImport {
+  pub object: VoxelObject,
+  pub leaf_colors: Vec<(Index, [u8; 4])>,
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> io::Result<u32> {
+  bytes.get(at .. at + 4)
+    .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+    .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated .vox file"))
 }
 
+/// Loads the first model of a MagicaVoxel .vox file into its own DAG head.
+/// Every palette index used by the model gets a fresh leaf, registered with its palette color.
+pub fn import_vox(sdg: &mut SparseDirectedGraph<BasicNode3d>, materials: &mut MaterialRegistry, path: &FilePath, pos: Vec3) -> io::Result<VoxImport> {
+  let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+  let bytes = std::fs::read(path)?;
+  if bytes.get(0 .. 4) != Some(b"VOX ") { return Err(invalid("Missing VOX header")) }
+
+  // Chunks are [id, content size, children size, content, children], all of interest live under MAIN
+  let mut size = None;
+  let mut voxels: &[u8] = &[];
+  let mut palette = None;
+  let mut cursor = 8 + 12; // Skip the header and MAIN's chunk header
+  while cursor + 12 <= bytes.len() {
+    let id = &bytes[cursor .. cursor + 4];
+    let content_size = read_u32(&bytes, cursor + 4)? as usize;
+    let content = bytes.get(cursor + 12 .. cursor + 12 + content_size).ok_or_else(|| invalid("Chunk overruns file"))?;
+    match id {
+      b"SIZE" if size.is_none() => size = Some(UVec3::new(read_u32(content, 0)?, read_u32(content, 4)?, read_u32(content, 8)?)),
+      b"XYZI" if voxels.is_empty() => {
+        let count = read_u32(content, 0)? as usize;
+        voxels = content.get(4 .. 4 + count * 4).ok_or_else(|| invalid("XYZI chunk is truncated"))?;
+      }
+      b"RGBA" => palette = Some(content),
+      _ => ()
+    }
+    cursor += 12 + content_size + read_u32(&bytes, cursor + 8)? as usize;
+  }
+  let size = size.ok_or_else(|| invalid("No SIZE chunk"))?;
+
+  if size.min_element() == 0 { return Err(invalid("SIZE has nothing along one of its axes")) }
+  if size.max_element() > 1 << MAX_HEIGHT { return Err(invalid(&format!("SIZE {size} is more than {} cells across", 1 << MAX_HEIGHT))) }
 
+  // MagicaVoxel is z-up, swap to y-up and flip the new z so the model isn't mirrored
+  let extent = UVec3::new(size.x, size.z, size.y);
+  let height = extent.max_element().max(2).next_power_of_two().trailing_zeros();
+  let cells: Vec<(UVec3, usize)> = voxels.chunks_exact(4)
+    .map(|voxel| (UVec3::new(voxel[0] as u32, voxel[2] as u32, size.y.wrapping_sub(1 + voxel[1] as u32)), voxel[3] as usize))
+    .collect();
+  // Checked before any leaves are registered, so a bad file doesn't leave materials behind
+  if cells.iter().any(|(cell, _)| cell.cmpge(extent).any()) { return Err(invalid("Voxel lies outside the model's SIZE")) }
+
+  let mut leaves = [EMPTY; 256];
+  let mut leaf_colors = Vec::new();
+  for &(_, color_idx) in &cells {
+    if leaves[color_idx] != EMPTY { continue }
+    // Palette index i lives at rgba[i - 1], files without a palette use the default one which we don't ship
+    let color: [u8; 4] = palette
+      .and_then(|rgba| rgba.get((color_idx + 255) % 256 * 4 ..)?.get(.. 4))
+      .map_or([255; 4], |c| c.try_into().unwrap());
+    leaves[color_idx] = materials.register(sdg, Material::from_srgb([color[0], color[1], color[2]]));
+    leaf_colors.push((leaves[color_idx], color));
+  }
+  // Written sparsely, a grid as big as SIZE allows would be far too many cells to hold at once
+  let empty = sdg.get_root(EMPTY).map_err(|err| invalid(&err.to_string()))?;
+  let mut batch = sdg.edit(empty, height);
+  for (cell, color_idx) in cells { batch.set_cell(cell, leaves[color_idx]); }
+  let head = batch.commit().map_err(|err| {
+    // Nothing uses the leaves yet, so they can go again
+    let _ = sdg.drop_root(empty);
+    for &(leaf, _) in &leaf_colors { let _ = sdg.remove_leaf(leaf); }
+    invalid(&err.to_string())
+  })?;
+
+  let mut object = VoxelObject::new(sdg, DagRef::new(head, height), UVec3::ZERO, extent - 1, pos);
+  object.pivot_offset = extent.as_vec3() / 2.0;
+  Ok(VoxImport {
+    object,
+    leaf_colors,
+  })
+}
+
+impl GameData {
+  /// A cheap hash of the simulation state (object transforms, world roots, physics bodies),
+  /// two runs or peers agree on it for as long as they stay in lockstep
+  pub fn checksum(&self) -> u64 {
+    let mut checksum = Checksum::default();
+    for object in self.objects.values() {
+      checksum.write_u32(object.dag_ref.head);
+      checksum.write_f32s(&object.pos.to_array());
+      checksum.write_f32s(&object.rot.to_array());
+    }
+    self.physics.hash_bodies(&mut checksum);
+    checksum.finish()
+  }
+
+  /// Adds object to the world and the simulation, returning its handle (see ObjectManager)
+  pub fn add_object(&mut self, object: VoxelObject, dynamic: bool) -> usize {
+    let idx = self.objects.spawn(&mut self.physics, object, dynamic);
+    self.lights.add_object(&self.sdg, &self.materials, idx, &self.objects[idx]);
+    idx
+  }
+
+  /// add_object under a particular handle, which has to be free
+  pub fn add_object_at(&mut self, idx: usize, object: VoxelObject, dynamic: bool) {
+    self.objects.spawn_at(idx, &mut self.physics, object, dynamic);
+    self.lights.add_object(&self.sdg, &self.materials, idx, &self.objects[idx]);
+  }
+
+  /// Takes an object out of the world and the simulation and frees whatever of its graph nothing else uses.
+  /// Every other object keeps its handle
+  pub fn remove_object(&mut self, idx: usize) {
+    if self.objects.despawn(&mut self.sdg, &mut self.physics, idx).is_none() { return }
+    self.lights.remove_object(idx);
+    if let Err(err) = self.history.remove_object(&mut self.sdg, idx) { tracing::error!("Failed to forget object {idx}'s edits: {err}") }
+    if let Some(streaming) = &mut self.streaming { streaming.remove_object(idx) }
+    if self.targeted.is_some_and(|hit| hit.object == idx) { self.targeted = None }
+  }
+
+  /// Starts an object moving by itself (or stops it, leaving it where it's got to). Its body turns kinematic
+  /// while it's moving, and fixed after, even if it was dynamic before
+  pub fn animate(&mut self, idx: usize, animation: Option<Animation>) {
+    let object = &mut self.objects[idx];
+    object.animation = animation;
+    if let Some(handle) = object.physics { self.physics.set_kinematic(handle.body, animation.is_some()) }
+  }
+
+  /// Copies one of other's objects into this world as a dynamic object centered at pos, matching up leaves by material.
+  /// Returns its index
+  pub fn import_object(&mut self, other: &GameData, object: usize, pos: Vec3) -> Result<usize, String> {
+    let source = other.objects.get(object).ok_or(format!("There's no object {object}"))?;
+    let registered = self.sdg.leaves().len();
+    let mut leaves = HashMap::new();
+    for &leaf in other.sdg.leaves() {
+      let ours = if leaf == EMPTY { EMPTY } else { self.materials.find_or_register(&mut self.sdg, other.materials.get(leaf)) };
+      leaves.insert(leaf, ours);
+    }
+    self.materials_changed |= self.sdg.leaves().len() != registered;
+    let head = self.sdg.clone_from(&other.sdg, source.dag_ref.head, |leaf| leaves[&leaf]).map_err(|err| err.to_string())?;
+    let height = source.dag_ref.height;
+    let mut copy = VoxelObject::new(&self.sdg, DagRef::new(head, height), source.min_cell, source.max_cell, Vec3::ZERO);
+    copy.pos = pos - copy.pivot_offset;
+    copy.rot = source.rot;
+    self.graph_changed = true;
+    Ok(self.add_object(copy, true))
+  }
+
+  /// Packs the graph's nodes back together after edits have left it full of holes, see SparseDirectedGraph::compact.
+  /// Returns how many slots it took up before and after
+  pub fn compact(&mut self) -> (usize, usize) {
+    let before = self.sdg.nodes.len();
+    let remap = self.sdg.compact();
+    let moved = |head| remap.get(&head).copied().unwrap_or(head);
+    for idx in self.objects.handles() {
+      let object = &mut self.objects[idx];
+      object.dag_ref.head = moved(object.dag_ref.head);
+    }
+    self.history.remap(moved);
+    self.graph_changed = true;
+    (before, self.sdg.nodes.len())
+  }
+
+  /// Edits one of the objects as a single undoable action, keeping its collider up to date
+  pub fn set_cells(&mut self, object_idx: usize, cells: &[(UVec3, Index)]) {
+    // Held across the write, which would otherwise free it
+    let before = match self.sdg.get_root(self.objects[object_idx].dag_ref.head) {
+      Ok(before) => before,
+      Err(err) => { tracing::error!("Failed to edit object {object_idx}: {err}"); return }
+    };
+    self.write_cells(object_idx, cells);
+    if let Err(err) = self.history.record(&mut self.sdg, object_idx, before, self.objects[object_idx].dag_ref.head) { tracing::error!("Failed to record edit: {err}") }
+    if let Some(net) = &mut self.net {
+      // Signed, so they land in the same place on peers whose grid has grown differently
+      let object = &self.objects[object_idx];
+      let cells = cells.iter().map(|&(cell, leaf)| (object.signed_cell(cell.as_ivec3()), leaf)).collect();
+      net.record(Edit::Cells { object: object_idx, cells })
+    }
+  }
+
+  pub fn set_cell(&mut self, object: usize, cell: UVec3, leaf: Index) { self.set_cells(object, &[(cell, leaf)]) }
+
+  /// Re-roots the object until the signed cell fits in its grid, returning where in the grid it is.
+  /// None if that would take the grid past MAX_HEIGHT.
+  pub fn grow_to_fit(&mut self, object_idx: usize, cell: IVec3) -> Option<UVec3> {
+    let object = &mut self.objects[object_idx];
+    if let Some(cell) = object.grid_cell(cell) { return Some(cell) }
+    while !object.in_grid(cell) && object.dag_ref.height < MAX_HEIGHT {
+      let offset = match object.grow_towards(&mut self.sdg, cell) {
+        Ok(offset) => offset,
+        Err(err) => { tracing::error!("Failed to grow object {object_idx}: {err}"); break }
+      };
+      if let Err(err) = self.history.shift(&mut self.sdg, object_idx, offset) { tracing::error!("Failed to shift object {object_idx}'s edits: {err}") }
+      self.lights.shift(object_idx, offset);
+    }
+    object.grid_cell(cell)
+  }
+
+  /// Writes leaf into the cell of object at a world space point, wherever it is, as a single undoable action.
+  /// A grid that doesn't reach the point grows towards it first, see grow_to_fit.
+  /// Returns the signed cell it ended up in, None if the grid can't grow that far
+  pub fn set_world_cell(&mut self, object: usize, world: Vec3, leaf: Index) -> Option<IVec3> {
+    let cell = self.objects[object].local_cell(world);
+    let grid = self.grow_to_fit(object, cell)?;
+    self.set_cell(object, grid, leaf);
+    Some(cell)
+  }
+
+  /// Reverts the latest edit, returning false if there's nothing left to undo
+  pub fn undo(&mut self) -> bool {
+    let Some((object, from, to)) = self.history.undo() else { return false };
+    self.apply_change(object, from, to);
+    true
+  }
+
+  /// Makes the latest undone edit again, returning false if there's nothing left to redo
+  pub fn redo(&mut self) -> bool {
+    let Some((object, from, to)) = self.history.redo() else { return false };
+    self.apply_change(object, from, to);
+    true
+  }
+
+  /// Writes leaf into the cells of an object under brush (in its grid space) as a single undoable action
+  pub fn apply_brush(&mut self, object_idx: usize, brush: Brush, leaf: Index, blend: Blend) {
+    let before = match self.sdg.get_root(self.objects[object_idx].dag_ref.head) {
+      Ok(before) => before,
+      Err(err) => { tracing::error!("Failed to edit object {object_idx}: {err}"); return }
+    };
+    self.write_brush(object_idx, brush, leaf, blend);
+    if let Err(err) = self.history.record(&mut self.sdg, object_idx, before, self.objects[object_idx].dag_ref.head) { tracing::error!("Failed to record edit: {err}") }
+    if let Some(net) = &mut self.net {
+      let brush = shift_brush(brush, self.objects[object_idx].origin.as_vec3());
+      net.record(Edit::Brush { object: object_idx, brush, leaf, blend })
+    }
+  }
+
+  /// apply_brush without recording anything to undo
+  pub(crate) fn write_brush(&mut self, object_idx: usize, brush: Brush, leaf: Index, blend: Blend) {
+    let object = &mut self.objects[object_idx];
+    if let Err(err) = object.apply_brush(&mut self.sdg, brush, leaf, blend) { tracing::error!("Failed to apply brush to object {object_idx}: {err}") }
+    self.lights.rescan(&self.sdg, &self.materials, object_idx, object);
+  }
+
+  /// Makes an edit which came from another game sharing this world, it's kept out of the undo history.
+  /// Edits to objects this world doesn't have or with leaves it doesn't know are dropped, returning false
+  pub fn apply_edit(&mut self, edit: &Edit) -> bool {
+    match edit {
+      Edit::Cells { object, cells } if self.objects.contains(*object) && cells.iter().all(|&(_, leaf)| self.sdg.is_leaf(leaf)) => {
+        // Growing the same way the sender's grid did, cells the grid can't grow to are dropped like set_world_cell does
+        let cells: Vec<_> = cells.iter().filter_map(|&(cell, leaf)| Some((self.grow_to_fit(*object, cell)?, leaf))).collect();
+        self.write_cells(*object, &cells);
+      }
+      &Edit::Brush { object, brush, leaf, blend } if self.objects.contains(object) && self.sdg.is_leaf(leaf) => {
+        let brush = shift_brush(brush, -self.objects[object].origin.as_vec3());
+        self.write_brush(object, brush, leaf, blend);
+      }
+      _ => return false,
+    }
+    self.graph_changed = true;
+    true
+  }
+
+  /// Turns or mirrors an object's cells within its grid as a single undoable action, the grid itself stays put
+  pub fn reorient(&mut self, object_idx: usize, orientation: Orientation) {
+    let object = &mut self.objects[object_idx];
+    let before = match object.reorient(&mut self.sdg, orientation) {
+      Ok(before) => before,
+      Err(err) => { tracing::error!("Failed to reorient object {object_idx}: {err}"); return }
+    };
+    self.lights.rescan(&self.sdg, &self.materials, object_idx, object);
+    let after = object.dag_ref.head;
+    if let Err(err) = self.history.record(&mut self.sdg, object_idx, before, after) { tracing::error!("Failed to record edit: {err}") }
+  }
+
+  fn apply_change(&mut self, object_idx: usize, from: Index, to: Index) {
+    let object = &mut self.objects[object_idx];
+    if let Err(err) = object.apply_change(&mut self.sdg, from, to) { tracing::error!("Failed to carry edit over onto object {object_idx}: {err}") }
+    self.lights.rescan(&self.sdg, &self.materials, object_idx, object);
+  }
+
+  /// set_cells without recording anything to undo, for generating the world
+  pub fn write_cells(&mut self, object: usize, cells: &[(UVec3, Index)]) {
+    zone!("write cells");
+    self.lights.set_cells(&self.materials, object, cells);
+    let (idx, object) = (object, &mut self.objects[object]);
+    if let Err(err) = object.set_cells(&mut self.sdg, cells) { tracing::error!("Failed to write cells into object {idx}: {err}") }
+  }
+
+  /// Runs however many fixed ticks dt covers
+  pub fn step_physics(&mut self, dt: f32) {
+    for _ in 0 .. self.physics.steps_due(dt) { self.step() }
+  }
+
+  /// Hands physics a fresh copy of every object edited since the last call. Copying a tree costs as much as
+  /// the tree, so edits only mark their object and this runs once a tick (and before the player moves)
+  pub fn refresh_shapes(&mut self) {
+    zone!("refresh shapes");
+    for idx in self.objects.handles() {
+      let object = &mut self.objects[idx];
+      if object.refresh_snapshot(&self.sdg) && let Some(handle) = object.physics { self.physics.refresh_shape(handle, object) }
+    }
+  }
+
+  /// Advances the world by one fixed tick, pulling objects back out of the simulation after it
+  pub fn step(&mut self) {
+    zone!("step");
+    self.refresh_shapes();
+    // Animated objects' bodies are steered to where they'll be after this tick, the objects follow them out below
+    for object in self.objects.values() {
+      let (Some(animation), Some(handle)) = (object.animation, object.physics) else { continue };
+      let (pivot, rot) = animation.pose(self.tick + 1);
+      self.physics.move_kinematic(handle.body, pivot, rot);
+    }
+    self.physics.step();
+    for idx in self.objects.handles() {
+      let object = &mut self.objects[idx];
+      let Some(handle) = object.physics else { continue };
+      object.prev_pose = Some((object.pos + object.pivot_offset, object.rot));
+      let (pivot, rot) = self.physics.pose(handle.body);
+      object.pos = pivot - object.pivot_offset;
+      object.rot = rot;
+    }
+    self.break_on_impact();
+    self.tick += 1;
+    self.clear_debris();
+    if let Some(mut streaming) = self.streaming.take() {
+      streaming.update(self);
+      self.streaming = Some(streaming);
+    }
+    self.merge_loads();
+    if let Some(mut net) = self.net.take() && net.update(self) { self.net = Some(net) }
+    self.probes.update(&self.sdg, &mut self.objects, &self.physics);
+    self.decals.expire(self.tick);
+    self.time_of_day.advance(&mut self.sky, TIMESTEP);
+    if self.debug_flags.checksum { self.last_checksum = Some((self.tick, self.checksum())) }
+  }
+
+  /// Sticks a decal onto the face hit was on, size is in world units and lifetime in seconds
+  pub fn spawn_decal(&mut self, hit: &RayHit, size: f32, color: Vec4, lifetime: f32) {
+    let normal = self.objects[hit.object].rot * hit.normal.as_vec3();
+    self.decals.spawn(self.tick, hit.pos, normal, size, color, lifetime);
+  }
+
+  /// Finds the closest voxel hit across every object
+  pub fn raycast(&self, origin: Vec3, dir: Vec3, max_t: f32) -> Option<RayHit> {
+    self.objects.iter()
+      .filter_map(|(object, obj)| {
+        let Hit { cell, normal, t, leaf, pos, uv } = obj.raycast(&self.sdg, origin, dir, max_t)?;
+        Some(RayHit { object, cell: obj.signed_cell(cell.as_ivec3()), normal, t, leaf, pos: obj.transform().transform_point3(pos), uv })
+      })
+      .min_by(|a, b| a.t.total_cmp(&b.t))
+  }
+
+  /// Moves the camera a tick along after the object it's following, stopping once that object's gone
+  pub fn follow_camera(&mut self) {
+    let Some(follow) = &mut self.follow else { return };
+    let Some(target) = self.objects.get(follow.target) else {
+      self.follow = None;
+      return
+    };
+    let (center, followed) = (target.pos + target.pivot_offset, follow.target);
+    // The arm starts out inside the object it's following, which would always be in the way
+    let blocked = |origin, dir, max_t| self.objects.iter()
+      .filter(|&(idx, _)| idx != followed)
+      .filter_map(|(_, obj)| obj.raycast(&self.sdg, origin, dir, max_t).map(|hit| hit.t))
+      .min_by(f32::total_cmp);
+    follow.update(&mut self.camera, center, TIMESTEP, blocked);
+  }
+}
 
 // Remove these things?
 // We may want to extract this all into the app facilitator instead
 pub struct GameData {
   pub camera: Camera,
   pub sdg: SparseDirectedGraph<BasicNode3d>,
-  pub objects: Vec<VoxelObject>,
+  pub objects: ObjectManager,
+  pub physics: PhysicsManager,
+  pub player: CharacterController,
+  pub debug_flags: DebugFlags,
+  pub debug_lines: DebugLines,
+  pub decals: Decals,
+  pub sky: Sky,
+  /// Moves sky's sun as the world ticks, unless it's paused
+  pub time_of_day: TimeOfDay,
+  pub render: RenderSettings,
+  /// What each leaf looks like, the renderer has to be told when this changes
+  pub materials: MaterialRegistry,
+  /// Names for leaves and how they play, so nothing has to go by leaf index
+  pub blocks: BlockRegistry,
+  /// Lights placed in the world, and where the emissive leaves are, which follows every edit
+  pub lights: Lights,
+  pub probes: AmbientProbes,
+  pub history: EditHistory,
+  /// Where the world came from and which save slot it belongs to
+  pub world: WorldInfo,
+  /// Some for worlds streamed in around the camera rather than built up front
+  pub streaming: Option<WorldManager>,
+  /// Objects being built on other threads, merged in as they finish. See loading
+  pub loads: Loader<BuiltObject>,
+  /// Some while this world's shared with other games, see net
+  pub net: Option<Session>,
+  /// Set when the graph changes somewhere the app doesn't upload it itself (like streaming), it uploads and clears it
+  pub graph_changed: bool,
+  /// Like graph_changed, for materials registered after the world started
+  pub materials_changed: bool,
+  /// The voxel under the crosshair, kept up to date by the app and outlined by the renderer
+  pub targeted: Option<RayHit>,
+  /// Set to have the renderer write a thumbnail of the next frame there, see saves::save
+  pub thumbnail: Option<PathBuf>,
+  /// Set to have the renderer write the next frame's normals there and its depth beside them, see WgpuCtx::request_gbuffer
+  pub gbuffer: Option<PathBuf>,
+  /// Number of simulation ticks so far
+  pub tick: u64,
+  /// (tick, checksum) from the last tick, only tracked while debug_flags.checksum is set
+  pub last_checksum: Option<(u64, u64)>,
+  /// How much velocity (in units a second) objects have to lose hitting something to break, None leaves them whole.
+  /// See destruction
+  pub break_speed: Option<f32>,
+  /// Some while the camera's following an object around instead of being flown, see follow_camera
+  pub follow: Option<Follow>,
 }
 impl Default for GameData {
-  fn default() -> Self {
+  fn default() -> Self { Self::new(templates::find(templates::DEFAULT).unwrap(), templates::DEFAULT_SEED) }
+}
+impl GameData {
+  /// Builds template's world from seed
+  pub fn new(template: &WorldTemplate, seed: i32) -> Self {
+    let (mut game_data, leaves) = Self::blank();
+    let objects = (template.build)(&mut game_data.sdg, leaves, seed);
+    game_data.world = WorldInfo { template: template.name.to_string(), seed, slot: None };
+    // Templates are the level itself, so they stay put
+    for object in objects { game_data.add_object(object, false); }
+    game_data.streaming = template.stream.map(|config| WorldManager::new(config(seed), leaves));
+    game_data
+  }
+
+  /// A world with nothing in it yet but the usual blocks, returned with the leaves terrain is built from
+  pub fn blank() -> (Self, TerrainLeaves) {
     let mut sdg = SparseDirectedGraph::new();
-    let _empty = sdg.add_leaf();
-    let _full = sdg.add_leaf();
-    let floor = VoxelObject::floor(&mut sdg, Vec3::ZERO);
+    let mut materials = MaterialRegistry::default();
+    let mut blocks = BlockRegistry::default();
+    let solid = BlockProperties::default();
+    let empty = sdg.add_leaf();
+    blocks.bind("air", empty, BlockProperties::AIR);
+    let leaves = TerrainLeaves {
+      empty,
+      solid: blocks.register(&mut sdg, &mut materials, "stone", Material::new(Vec3::new(0.4, 0.38, 0.36)), solid),
+      surface: blocks.register(&mut sdg, &mut materials, "grass", Material::new(Vec3::new(0.22, 0.45, 0.12)), solid),
+    };
+    // Not used by any template, registered early so they land on leaves 3 to 6 for the number keys
+    let glass = BlockProperties { transparent: true, hardness: 0.5, ..solid };
+    blocks.register(&mut sdg, &mut materials, "glass", Material::translucent(Vec3::new(0.85, 0.95, 1.0), 0.3, 1.5), glass);
+    let water = BlockProperties { solid: false, ..BlockProperties::AIR };
+    blocks.register(&mut sdg, &mut materials, "water", Material::water(Vec3::new(0.2, 0.45, 0.6), 0.6), water);
+    blocks.register(&mut sdg, &mut materials, "lamp", Material::glowing(Vec3::new(1.0, 0.75, 0.4), 2.0), solid);
+    blocks.register(&mut sdg, &mut materials, "mirror", Material::reflective(Vec3::new(0.9, 0.88, 0.85), 0.1), solid);
+    let mut game_data = Self::from_parts(sdg, materials);
+    game_data.blocks = blocks;
+    (game_data, leaves)
+  }
+
+  /// A world without any objects, everything else at its defaults
+  pub fn from_parts(sdg: SparseDirectedGraph<BasicNode3d>, materials: MaterialRegistry) -> Self {
     Self {
       camera: Camera::default(),
       sdg,
-      objects: Vec::from([floor]),
+      objects: ObjectManager::default(),
+      physics: PhysicsManager::default(),
+      player: CharacterController::default(),
+      debug_flags: DebugFlags::default(),
+      debug_lines: DebugLines::default(),
+      decals: Decals::default(),
+      sky: Sky::default(),
+      time_of_day: TimeOfDay::new(&Sky::default()),
+      render: RenderSettings::default(),
+      materials,
+      blocks: BlockRegistry::default(),
+      lights: Lights::default(),
+      probes: AmbientProbes::default(),
+      history: EditHistory::default(),
+      world: WorldInfo::default(),
+      streaming: None,
+      loads: Loader::default(),
+      net: None,
+      graph_changed: false,
+      materials_changed: false,
+      targeted: None,
+      thumbnail: None,
+      gbuffer: None,
+      tick: 0,
+      last_checksum: None,
+      follow: None,
+      break_speed: None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // A .vox file holding one model of the given SIZE, voxels being [x, y, z, color index] in MagicaVoxel's z-up axes
+  fn vox_file(name: &str, size: [u32; 3], voxels: &[[u8; 4]]) -> PathBuf {
+    let chunk = |id: &[u8], content: Vec<u8>| [id, &(content.len() as u32).to_le_bytes(), &0u32.to_le_bytes(), &content].concat();
+    let size = chunk(b"SIZE", size.iter().flat_map(|axis| axis.to_le_bytes()).collect());
+    let xyzi = chunk(b"XYZI", [(voxels.len() as u32).to_le_bytes().as_slice(), voxels.concat().as_slice()].concat());
+    let main = [b"MAIN".as_slice(), &0u32.to_le_bytes(), &((size.len() + xyzi.len()) as u32).to_le_bytes(), &size, &xyzi].concat();
+    let path = std::env::temp_dir().join(format!("voxel_game_test_{}_{name}.vox", std::process::id()));
+    std::fs::write(&path, [b"VOX ".as_slice(), &150u32.to_le_bytes(), &main].concat()).unwrap();
+    path
+  }
+
+  #[test]
+  fn vox_imports_check_their_size() {
+    let mut sdg = SparseDirectedGraph::new();
+    sdg.add_leaf();
+    let mut materials = MaterialRegistry::default();
+    let mut import = |name, size, voxels: &[[u8; 4]]| {
+      let path = vox_file(name, size, voxels);
+      let import = import_vox(&mut sdg, &mut materials, &path, Vec3::ZERO);
+      std::fs::remove_file(path).unwrap();
+      let leaves = sdg.leaves().len();
+      (import.map(|import| (import.object.dag_ref.height, import.object.max_cell, import.leaf_colors.len())), leaves)
+    };
+
+    assert_eq!(import("fine", [3, 1, 2], &[[0, 0, 0, 1], [2, 0, 1, 5], [1, 0, 1, 1]]).0.unwrap(), (2, UVec3::new(2, 1, 0), 2));
+    // As big as a grid gets, but only holding a voxel so it's no bigger in the graph
+    let (huge, leaves) = import("huge", [1, 1 << MAX_HEIGHT, 1], &[[0, 9, 0, 3]]);
+    assert_eq!(huge.unwrap(), (MAX_HEIGHT, UVec3::new(0, 0, (1 << MAX_HEIGHT) - 1), 1));
+
+    for (name, size, voxels) in [("flat", [4, 0, 4], &[][..]), ("too_big", [1, 1, (1 << MAX_HEIGHT) + 1], &[]), ("outside", [2, 2, 2], &[[0, 0, 0, 1], [0, 2, 0, 2]])] {
+      let (err, after) = import(name, size, voxels);
+      let err = err.unwrap_err();
+      assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{name}: {err}");
+      // Refused files don't leave leaves behind
+      assert_eq!(after, leaves, "{name}");
     }
   }
 }
@@ -0,0 +1,158 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Instant;
+use winit::event::WindowEvent;
+use winit::window::Window;
+use crate::objects::GameData;
+use crate::profiling::zone;
+use crate::loading::LoadState;
+use crate::start_screen::StartScreen;
+use crate::wgpu_ctx::RenderStats;
+
+// Frames kept for the frame time graph
+const HISTORY: usize = 240;
+// Frame time at the top of the graph, in seconds
+const GRAPH_MAX: f32 = 1.0 / 30.0;
+
+/// egui readouts drawn on top of the finished frame
+pub struct Overlay {
+  pub visible: bool,
+  window: Arc<Window>,
+  ctx: egui::Context,
+  state: egui_winit::State,
+  renderer: egui_wgpu::Renderer,
+  frame_times: VecDeque<f32>,
+  last_frame: Instant,
+}
+impl Overlay {
+  pub fn new(window: Arc<Window>, device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+    let ctx = egui::Context::default();
+    let state = egui_winit::State::new(
+      ctx.clone(),
+      egui::ViewportId::ROOT,
+      &window,
+      Some(window.scale_factor() as f32),
+      None,
+      Some(device.limits().max_texture_dimension_2d as usize),
+    );
+    Self {
+      visible: false,
+      window,
+      ctx,
+      state,
+      renderer: egui_wgpu::Renderer::new(device, format, None, 1, false),
+      frame_times: VecDeque::with_capacity(HISTORY),
+      last_frame: Instant::now(),
+    }
+  }
+
+  /// Hands the event to egui while it's showing (or the start screen is), returns whether egui wants it for itself
+  pub fn on_window_event(&mut self, event: &WindowEvent, menu_open: bool) -> bool {
+    (self.visible || menu_open) && self.state.on_window_event(&self.window, event).consumed
+  }
+
+  /// Records the frame time and draws the start screen and, if visible, the overlay onto view.
+  /// A graph too big for the GPU is warned about either way, what's on screen is out of date until it shrinks
+  #[allow(clippy::too_many_arguments)]
+  pub fn draw(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, game_data: &GameData, stats: RenderStats, mut menu: Option<&mut StartScreen>) {
+    zone!("overlay");
+    let now = Instant::now();
+    if self.frame_times.len() == HISTORY { self.frame_times.pop_front(); }
+    self.frame_times.push_back(now.duration_since(self.last_frame).as_secs_f32());
+    self.last_frame = now;
+    if !self.visible && menu.is_none() && stats.stale_graph.is_none() { return }
+
+    let input = self.state.take_egui_input(&self.window);
+    let ctx = self.ctx.clone();
+    let output = ctx.run(input, |ctx| {
+      if let Some(menu) = menu.as_deref_mut() { menu.ui(ctx) }
+      if self.visible { Self::ui(ctx, &self.frame_times, game_data, stats) }
+      if let Some(bytes) = stats.stale_graph { Self::stale_warning(ctx, bytes) }
+    });
+    self.state.handle_platform_output(&self.window, output.platform_output);
+    let jobs = self.ctx.tessellate(output.shapes, output.pixels_per_point);
+    let size = self.window.inner_size();
+    let screen = egui_wgpu::ScreenDescriptor { size_in_pixels: [size.width, size.height], pixels_per_point: output.pixels_per_point };
+
+    for (id, delta) in &output.textures_delta.set { self.renderer.update_texture(device, queue, *id, delta) }
+    self.renderer.update_buffers(device, queue, encoder, &jobs, &screen);
+    let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+      label: Some("Overlay Pass"),
+      color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+        view,
+        resolve_target: None,
+        ops: wgpu::Operations { load: wgpu::LoadOp::Load, store: wgpu::StoreOp::Store },
+      })],
+      depth_stencil_attachment: None,
+      timestamp_writes: None,
+      occlusion_query_set: None,
+    }).forget_lifetime();
+    self.renderer.render(&mut pass, &jobs, &screen);
+    drop(pass);
+    for id in &output.textures_delta.free { self.renderer.free_texture(id) }
+  }
+
+  fn ui(ctx: &egui::Context, frame_times: &VecDeque<f32>, game_data: &GameData, stats: RenderStats) {
+    egui::Window::new("Debug").default_pos([8.0, 8.0]).resizable(false).show(ctx, |ui| {
+      let average = frame_times.iter().sum::<f32>() / frame_times.len().max(1) as f32;
+      ui.label(format!("FPS: {:.1} ({:.2} ms)", 1.0 / average, average * 1000.0));
+      Self::frame_graph(ui, frame_times);
+      ui.separator();
+
+      let camera = &game_data.camera;
+      ui.label(format!("Camera: {:.2}", camera.position));
+      // Cells are counted in the first object, which is the level for every template
+      if let Some(world) = game_data.objects.values().next() {
+        ui.label(format!("Cell: {}", world.local_cell(camera.position)));
+      }
+      ui.label(format!("SDG nodes: {} of {} slots", game_data.sdg.live_nodes(), game_data.sdg.nodes.len()));
+      if game_data.loads.is_busy() {
+        let (finished, started) = game_data.loads.progress();
+        ui.add(egui::ProgressBar::new(finished as f32 / started as f32).text(format!("Loading {finished} of {started}")));
+        for load in game_data.loads.loads() {
+          let state = match load.state {
+            LoadState::Queued => "queued",
+            LoadState::Working => "working",
+          };
+          ui.label(format!("{} ({state})", load.name));
+        }
+      }
+      ui.separator();
+
+      match stats.gpu {
+        Some(perf) => {
+          for (pass, ms) in perf.passes() { ui.label(format!("{pass}: {ms:.3} ms")); }
+          ui.label(format!("GPU total: {:.3} ms", perf.total_ms()));
+        }
+        None => { ui.label("GPU timings unsupported"); }
+      }
+      ui.label(format!("Render scale: {:.2}x", stats.render_scale));
+      let textures = stats.textures;
+      ui.label(format!("Textures: {} live, {} pooled, {:.1} MiB", textures.live, textures.pooled, textures.bytes as f32 / (1 << 20) as f32));
+    });
+  }
+
+  fn stale_warning(ctx: &egui::Context, bytes: u64) {
+    egui::Area::new(egui::Id::new("stale graph")).anchor(egui::Align2::CENTER_TOP, [0.0, 8.0]).show(ctx, |ui| {
+      egui::Frame::popup(ui.style()).show(ui, |ui| {
+        ui.colored_label(egui::Color32::LIGHT_RED, format!(
+          "The graph is {:.1} MiB, too big for the GPU. Edits since won't show until it shrinks, try the compact command", bytes as f32 / (1 << 20) as f32,
+        ));
+      });
+    });
+  }
+
+  // Frame times left to right, oldest first, with a line at 60fps
+  fn frame_graph(ui: &mut egui::Ui, frame_times: &VecDeque<f32>) {
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(HISTORY as f32, 60.0), egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 0.0, egui::Color32::from_black_alpha(120));
+    let height = |seconds: f32| rect.bottom() - (seconds / GRAPH_MAX).min(1.0) * rect.height();
+    let target = height(1.0 / 60.0);
+    painter.hline(rect.x_range(), target, egui::Stroke::new(1.0, egui::Color32::DARK_GREEN));
+    let points = frame_times.iter().enumerate()
+      .map(|(idx, &seconds)| egui::pos2(rect.left() + idx as f32, height(seconds)))
+      .collect();
+    painter.add(egui::Shape::line(points, egui::Stroke::new(1.0, egui::Color32::LIGHT_GREEN)));
+  }
+}
@@ -0,0 +1,182 @@
+use crate::objects::ObjectManager;
+use super::TIMESTEP;
+use glam::Vec3;
+
+// How close counts as touching, keeps grounded from flickering while resting exactly on a surface
+const SKIN: f32 = 0.01;
+// Depenetration passes per substep, corners need a couple
+const MAX_PUSHES: usize = 4;
+// Surfaces whose normal points at least this far up can be stood on
+const GROUND_NORMAL: f32 = 0.7;
+// Same pull the physics world has
+const GRAVITY: f32 = 9.81;
+// Fastest it'll fall, keeps a long drop from outrunning the substeps
+const TERMINAL_SPEED: f32 = 50.0;
+
+/// Collide-and-slide movement for an upright capsule against the voxels of every object.
+/// Moves are swept in substeps shorter than the radius, so nothing thinner than a cell can be tunnelled through.
+pub struct CharacterController {
+  pub radius: f32,
+  /// Total height including both caps
+  pub height: f32,
+  /// Ledges up to this tall are climbed while walking into them
+  pub step_height: f32,
+  /// How far above the capsule's center the eyes (and so the camera) are while walking
+  pub eye_height: f32,
+  /// Upward speed a jump starts with, enough to clear about a cell
+  pub jump_speed: f32,
+  /// Speed up (or down while negative) from jumping and falling
+  pub vertical_speed: f32,
+  /// Whether the last move ended standing on something
+  pub grounded: bool,
+}
+impl Default for CharacterController {
+  fn default() -> Self {
+    Self { radius: 0.3, height: 1.7, step_height: 1.05, eye_height: 0.7, jump_speed: 5.0, vertical_speed: 0.0, grounded: false }
+  }
+}
+
+struct Contact {
+  // World space, pointing out of the voxels
+  normal: Vec3,
+  // Negative while within SKIN but not yet overlapping
+  depth: f32,
+}
+
+#[derive(Default)]
+struct Slide {
+  grounded: bool,
+  hit_wall: bool,
+}
+
+impl CharacterController {
+  /// Moves the capsule centered at pos by motion, sliding along anything in the way. Returns the new center.
+  pub fn move_and_slide(&mut self, objects: &ObjectManager, mut pos: Vec3, motion: Vec3) -> Vec3 {
+    let was_grounded = self.grounded || self.touching_ground(objects, pos);
+    let substeps = (motion.length() / (self.radius * 0.5)).ceil().max(1.0);
+    let mut step = motion / substeps;
+    self.grounded = false;
+    for _ in 0 .. substeps as u32 {
+      let before = pos;
+      let intended = step;
+      let slide = self.slide(objects, &mut pos, &mut step);
+      self.grounded |= slide.grounded;
+      // Walked into a wall while standing, it might just be a ledge
+      if slide.hit_wall && was_grounded && let Some(stepped) = self.step_up(objects, before, intended) {
+        let flat = |p: Vec3| (p - before).with_y(0.0).length();
+        if flat(stepped) > flat(pos) + SKIN {
+          pos = stepped;
+          step = intended;
+          self.grounded = true;
+        }
+      }
+    }
+    self.grounded |= self.touching_ground(objects, pos);
+    pos
+  }
+
+  /// One TIMESTEP of walking from the capsule centered at pos: motion is flattened onto the ground, gravity does the rest
+  /// and jump leaps up if it's standing on something. Returns the new center.
+  pub fn walk(&mut self, objects: &ObjectManager, pos: Vec3, motion: Vec3, jump: bool) -> Vec3 {
+    if jump && self.grounded { self.vertical_speed = self.jump_speed }
+    self.vertical_speed = (self.vertical_speed - GRAVITY * TIMESTEP).max(-TERMINAL_SPEED);
+    let rise = self.vertical_speed * TIMESTEP;
+    let new = self.move_and_slide(objects, pos, motion.with_y(0.0) + Vec3::Y * rise);
+    // Landed, or hit its head on the way up
+    let bumped = rise > 0.0 && new.y - pos.y < rise - SKIN;
+    if (self.grounded && self.vertical_speed <= 0.0) || bumped { self.vertical_speed = 0.0 }
+    new
+  }
+
+  /// Where the camera goes for a capsule centered at pos, and back
+  pub fn eye(&self, pos: Vec3) -> Vec3 { pos + Vec3::Y * self.eye_height }
+  pub fn center(&self, eye: Vec3) -> Vec3 { eye - Vec3::Y * self.eye_height }
+
+  /// Moves by step then pushes back out of whatever it ran into. The blocked part of step is removed,
+  /// so the remaining substeps slide along the surface instead of pushing into it again.
+  fn slide(&self, objects: &ObjectManager, pos: &mut Vec3, step: &mut Vec3) -> Slide {
+    *pos += *step;
+    let mut slide = Slide::default();
+    for _ in 0 .. MAX_PUSHES {
+      let Some(contact) = self.deepest_contact(objects, *pos, |_| true) else { break };
+      if contact.depth <= 0.0 { break }
+      *pos += contact.normal * contact.depth;
+      *step -= contact.normal * step.dot(contact.normal).min(0.0);
+      if contact.normal.y > GROUND_NORMAL { slide.grounded = true }
+      else if contact.normal.y.abs() < 1.0 - GROUND_NORMAL { slide.hit_wall = true }
+    }
+    slide
+  }
+
+  /// Tries to make step from on top of a ledge: lift, move, then settle back down onto something solid
+  fn step_up(&self, objects: &ObjectManager, from: Vec3, step: Vec3) -> Option<Vec3> {
+    let mut pos = from + Vec3::Y * self.step_height;
+    if self.deepest_contact(objects, pos, |_| true).is_some_and(|contact| contact.depth > 0.0) { return None }
+    self.slide(objects, &mut pos, &mut step.with_y(0.0));
+    let substeps = (self.step_height / (self.radius * 0.5)).ceil();
+    let mut down = Vec3::NEG_Y * self.step_height / substeps;
+    for _ in 0 .. substeps as u32 {
+      if self.slide(objects, &mut pos, &mut down).grounded { return Some(pos) }
+    }
+    None
+  }
+
+  fn touching_ground(&self, objects: &ObjectManager, pos: Vec3) -> bool {
+    self.deepest_contact(objects, pos - Vec3::Y * SKIN, |normal| normal.y > GROUND_NORMAL)
+      .is_some_and(|contact| contact.depth > -SKIN)
+  }
+
+  /// The deepest contact between the capsule centered at pos and any solid region, ignoring normals filter rejects
+  fn deepest_contact(&self, objects: &ObjectManager, pos: Vec3, filter: impl Fn(Vec3) -> bool) -> Option<Contact> {
+    let half = Vec3::Y * (self.height / 2.0 - self.radius).max(0.0);
+    let mut deepest: Option<Contact> = None;
+    for object in objects.values() {
+      // Work in grid space where every region is an axis aligned box
+      let to_grid = object.inv_transform();
+      let (a, b) = (to_grid.transform_point3(pos - half), to_grid.transform_point3(pos + half));
+      let reach = self.radius + SKIN;
+      let (min, max) = ((a.min(b) - reach).floor().as_ivec3(), (a.max(b) + reach).ceil().as_ivec3());
+      object.snapshot.for_each_solid_in(min, max, &mut |corner, size| {
+        let (box_min, box_max) = (corner.as_vec3(), corner.as_vec3() + size as f32);
+        let (on_segment, on_box) = segment_box_closest(a, b, box_min, box_max);
+        let offset = on_segment - on_box;
+        let dist = offset.length();
+        let (normal, depth) = if dist > 1e-5 {
+          (offset / dist, self.radius - dist)
+        } else {
+          // The axis itself is inside the box, leave through the nearest face
+          let (normal, exit) = nearest_face(on_segment, box_min, box_max);
+          (normal, self.radius + exit)
+        };
+        let normal = object.rot * normal;
+        if depth > -SKIN && filter(normal) && deepest.as_ref().is_none_or(|contact| depth > contact.depth) {
+          deepest = Some(Contact { normal, depth });
+        }
+      });
+    }
+    deepest
+  }
+}
+
+/// Closest points between segment ab and a box, found by alternately projecting onto each (both are convex)
+fn segment_box_closest(a: Vec3, b: Vec3, min: Vec3, max: Vec3) -> (Vec3, Vec3) {
+  let dir = b - a;
+  let len_sq = dir.length_squared();
+  let project = |point: Vec3| if len_sq > 0.0 { ((point - a).dot(dir) / len_sq).clamp(0.0, 1.0) } else { 0.0 };
+  let mut t = project((min + max) / 2.0);
+  for _ in 0 .. 4 { t = project((a + dir * t).clamp(min, max)) }
+  let on_segment = a + dir * t;
+  (on_segment, on_segment.clamp(min, max))
+}
+
+/// Outward normal of the face closest to a point inside the box, and how far away it is
+fn nearest_face(point: Vec3, min: Vec3, max: Vec3) -> (Vec3, f32) {
+  let mut best = (Vec3::Y, max.y - point.y);
+  for axis in 0 .. 3 {
+    let mut normal = Vec3::ZERO;
+    normal[axis] = 1.0;
+    if max[axis] - point[axis] < best.1 { best = (normal, max[axis] - point[axis]) }
+    if point[axis] - min[axis] < best.1 { best = (-normal, point[axis] - min[axis]) }
+  }
+  best
+}
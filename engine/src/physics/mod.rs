@@ -1,8 +1,46 @@
 use rapier3d::prelude::*;
-use nalgebra::Vector3;
+use nalgebra::{Isometry3, Translation3, UnitQuaternion, Vector3};
+use glam::{Quat, Vec3};
+use crate::debug::{Checksum, DebugLines};
+use crate::objects::VoxelObject;
+use crate::profiling::zone;
 
 mod voxel_obj_shape;
+mod voxel_dispatcher;
+mod character;
+pub use voxel_obj_shape::DagSnapshot;
+pub use character::CharacterController;
+use voxel_dispatcher::VoxelDispatcher;
+use rapier3d::parry::query::{DefaultQueryDispatcher, QueryDispatcher};
 
+/// Physics always advances by this much, so the simulation doesn't depend on the framerate
+pub const TIMESTEP: f32 = 1.0 / 60.0;
+// Past this many steps in one frame we drop time rather than fall further and further behind
+const MAX_STEPS: u32 = 5;
+
+/// Primitive shapes the debug spawner can throw into the world
+#[derive(Debug, Clone, Copy)]
+pub enum DummyShape {
+  Ball,
+  Cuboid,
+}
+
+/// What a VoxelObject is called inside the simulation
+#[derive(Debug, Clone, Copy)]
+pub struct PhysicsHandle {
+  pub body: RigidBodyHandle,
+  pub collider: ColliderHandle,
+}
+
+/// Two colliders which struck each other hard in the last step, see PhysicsManager::impacts
+#[derive(Debug, Clone, Copy)]
+pub struct Impact {
+  pub colliders: (ColliderHandle, ColliderHandle),
+  /// World space point where they pushed on each other hardest
+  pub point: Vec3,
+  /// How much velocity the lighter of the moving bodies lost to it
+  pub speed: f32,
+}
 
 pub struct PhysicsManager {
   pipeline: PhysicsPipeline,
@@ -16,9 +54,42 @@ pub struct PhysicsManager {
   impluse_joints: ImpulseJointSet,
   multibody_joints: MultibodyJointSet,
   ccd_solver: CCDSolver,
+  accumulator: f32,
+}
+impl Default for PhysicsManager {
+  fn default() -> Self {
+    Self {
+      pipeline: PhysicsPipeline::new(),
+      gravity: Vector3::new(0.0, -9.81, 0.0),
+      int_params: IntegrationParameters { dt: TIMESTEP, ..Default::default() },
+      islands: IslandManager::new(),
+      broad_phase: BroadPhaseBvh::new(),
+      narrow_phase: NarrowPhase::with_query_dispatcher(VoxelDispatcher.chain(DefaultQueryDispatcher)),
+      rigid_bodes: RigidBodySet::new(),
+      colliders: ColliderSet::new(),
+      impluse_joints: ImpulseJointSet::new(),
+      multibody_joints: MultibodyJointSet::new(),
+      ccd_solver: CCDSolver::new(),
+      accumulator: 0.0,
+    }
+  }
 }
 impl PhysicsManager {
+  /// Banks dt and returns how many fixed steps are ready to run
+  pub fn steps_due(&mut self, dt: f32) -> u32 {
+    self.accumulator += dt;
+    let steps = (self.accumulator / TIMESTEP) as u32;
+    self.accumulator -= steps as f32 * TIMESTEP;
+    if steps > MAX_STEPS { self.accumulator = 0.0 }
+    steps.min(MAX_STEPS)
+  }
+
+  /// How far the banked time reaches into the next step, from 0 to 1
+  pub fn interpolation(&self) -> f32 { self.accumulator / TIMESTEP }
+
+  /// Advances the simulation by one TIMESTEP
   pub fn step(&mut self) {
+    zone!("physics step");
     self.pipeline.step(
       &self.gravity,
       &self.int_params,
@@ -35,7 +106,159 @@ impl PhysicsManager {
     )
   }
 
+  /// Spawns a dynamic primitive for poking at collisions. `size` is the diameter/edge length.
+  pub fn spawn_dummy(&mut self, shape: DummyShape, size: f32, pos: Vec3, vel: Vec3) -> RigidBodyHandle {
+    let body = RigidBodyBuilder::dynamic()
+      .translation(pos.into())
+      .linvel(vel.into())
+      .build();
+    let collider = match shape {
+      DummyShape::Ball => ColliderBuilder::ball(size / 2.0),
+      DummyShape::Cuboid => ColliderBuilder::cuboid(size / 2.0, size / 2.0, size / 2.0),
+    }.build();
+    let handle = self.rigid_bodes.insert(body);
+    self.colliders.insert_with_parent(collider, handle, &mut self.rigid_bodes);
+    handle
+  }
+
+  pub fn body_count(&self) -> usize { self.rigid_bodes.len() }
+
+  /// Puts object into the simulation with its current transform, fixed objects never move
+  pub fn add_voxel_object(&mut self, object: &VoxelObject, dynamic: bool) -> PhysicsHandle {
+    let builder = if dynamic { RigidBodyBuilder::dynamic() } else { RigidBodyBuilder::fixed() };
+    // Rapier places shapes around their origin, which for us is the pivot
+    let body = builder.position(Isometry3::from_parts(
+      Translation3::from(Vector3::from(object.pos + object.pivot_offset)),
+      UnitQuaternion::from(object.rot),
+    )).build();
+    let body = self.rigid_bodes.insert(body);
+    let collider = ColliderBuilder::new(SharedShape::new(object.clone())).build();
+    let collider = self.colliders.insert_with_parent(collider, body, &mut self.rigid_bodes);
+    PhysicsHandle { body, collider }
+  }
+
+  /// Takes an object back out of the simulation, collider and all
+  pub fn remove_voxel_object(&mut self, handle: PhysicsHandle) {
+    self.rigid_bodes.remove(handle.body, &mut self.islands, &mut self.colliders, &mut self.impluse_joints, &mut self.multibody_joints, true);
+  }
+
+  /// Swaps in object's current voxels after an edit
+  pub fn refresh_shape(&mut self, handle: PhysicsHandle, object: &VoxelObject) {
+    if let Some(collider) = self.colliders.get_mut(handle.collider) {
+      collider.set_shape(SharedShape::new(object.clone()));
+    }
+    if let Some(body) = self.rigid_bodes.get_mut(handle.body) { body.wake_up(true) }
+  }
+
+  /// Whether the body is moved by the simulation, rather than fixed in place
+  pub fn is_dynamic(&self, body: RigidBodyHandle) -> bool { self.rigid_bodes[body].is_dynamic() }
+
+  /// The world space position and rotation of a body's origin
+  pub fn pose(&self, body: RigidBodyHandle) -> (Vec3, Quat) {
+    let pos = self.rigid_bodes[body].position();
+    (pos.translation.vector.into(), pos.rotation.into())
+  }
+
+  /// Moves a body straight to a pose given like pose's, keeping its velocities
+  pub fn set_pose(&mut self, body: RigidBodyHandle, pos: Vec3, rot: Quat) {
+    let position = Isometry3::from_parts(Translation3::from(Vector3::from(pos)), UnitQuaternion::from(rot));
+    self.rigid_bodes[body].set_position(position, true);
+  }
+
+  /// Hands a body over to whoever's moving it with move_kinematic (or back to staying put), rather than the simulation
+  pub fn set_kinematic(&mut self, body: RigidBodyHandle, kinematic: bool) {
+    let kind = if kinematic { RigidBodyType::KinematicPositionBased } else { RigidBodyType::Fixed };
+    self.rigid_bodes[body].set_body_type(kind, true);
+  }
+
+  /// Where a kinematic body (see set_kinematic) should be after the next step, given like pose's.
+  /// Unlike set_pose it moves there over the step, so what it touches on the way is pushed along
+  pub fn move_kinematic(&mut self, body: RigidBodyHandle, pos: Vec3, rot: Quat) {
+    let position = Isometry3::from_parts(Translation3::from(Vector3::from(pos)), UnitQuaternion::from(rot));
+    self.rigid_bodes[body].set_next_kinematic_position(position);
+  }
+
+  /// Velocity of body's material at a world space point, spin included
+  pub fn velocity_at(&self, body: RigidBodyHandle, point: Vec3) -> Vec3 {
+    self.rigid_bodes[body].velocity_at_point(&point.into()).into()
+  }
+
+  pub fn set_velocity(&mut self, body: RigidBodyHandle, linvel: Vec3) {
+    self.rigid_bodes[body].set_linvel(linvel.into(), true);
+  }
+
+  /// Every touching pair whose contact pushed hard enough in the last step to change the lighter dynamic body's
+  /// velocity by min_speed. Resting contact only ever makes up for a step of gravity, so it never counts
+  pub fn impacts(&self, min_speed: f32) -> Vec<Impact> {
+    let mut impacts = Vec::new();
+    for pair in self.narrow_phase.contact_pairs() {
+      if !pair.has_any_active_contact { continue }
+      let (Some(a), Some(b)) = (self.colliders.get(pair.collider1), self.colliders.get(pair.collider2)) else { continue };
+      // Fixed and kinematic bodies don't give, so it's whatever's moving that takes the blow
+      let lightest = [a.parent(), b.parent()].into_iter().flatten()
+        .map(|body| &self.rigid_bodes[body])
+        .filter(|body| body.is_dynamic())
+        .map(|body| body.mass())
+        .reduce(f32::min);
+      let Some(mass) = lightest.filter(|&mass| mass > 0.0) else { continue };
+      let mut total = 0.0;
+      let mut hardest = (0.0, Vec3::ZERO);
+      for manifold in &pair.manifolds {
+        // Points against voxels are relative to the region of them they touched
+        let frame = manifold.subshape_pos1.map_or(*a.position(), |sub_pos| a.position() * sub_pos);
+        for point in &manifold.points {
+          total += point.data.impulse;
+          if point.data.impulse > hardest.0 { hardest = (point.data.impulse, Vec3::from(frame * point.local_p1)) }
+        }
+      }
+      if total / mass >= min_speed {
+        impacts.push(Impact { colliders: (pair.collider1, pair.collider2), point: hardest.1, speed: total / mass });
+      }
+    }
+    impacts
+  }
+
+  /// Feeds every body's pose and velocity into the checksum, in handle order
+  pub fn hash_bodies(&self, checksum: &mut Checksum) {
+    for (_, body) in self.rigid_bodes.iter() {
+      let pos = body.position();
+      checksum.write_f32s(pos.translation.vector.as_slice());
+      checksum.write_f32s(pos.rotation.coords.as_slice());
+      checksum.write_f32s(body.linvel().as_slice());
+      checksum.write_f32s(body.angvel().as_slice());
+    }
+  }
+
+  /// Collider AABBs in yellow, with broad-phase pairs joined by magenta lines
+  pub fn draw_bounds(&self, lines: &mut DebugLines) {
+    for (_, collider) in self.colliders.iter() {
+      let aabb = collider.compute_aabb();
+      lines.aabb(aabb.mins.into(), aabb.maxs.into(), Vec3::new(1.0, 1.0, 0.0));
+    }
+    // Every pair the broad-phase hands over ends up in the narrow phase, touching or not
+    for pair in self.narrow_phase.contact_pairs() {
+      let (Some(a), Some(b)) = (self.colliders.get(pair.collider1), self.colliders.get(pair.collider2)) else { continue };
+      lines.line(a.compute_aabb().center().into(), b.compute_aabb().center().into(), Vec3::new(1.0, 0.0, 1.0));
+    }
+  }
+
+  /// Contact points as crosses, normals in green and penetration depth in red
+  pub fn draw_contacts(&self, lines: &mut DebugLines) {
+    for pair in self.narrow_phase.contact_pairs() {
+      if !pair.has_any_active_contact { continue }
+      for manifold in &pair.manifolds {
+        let normal = Vec3::from(manifold.data.normal);
+        for contact in &manifold.data.solver_contacts {
+          let point = Vec3::from(contact.point);
+          lines.cross(point, 0.1, Vec3::ONE);
+          lines.line(point, point + normal * 0.5, Vec3::Y);
+          if contact.dist < 0.0 {
+            lines.line(point, point - normal * contact.dist, Vec3::X);
+          }
+        }
+      }
+    }
+  }
+
 }
 
-// https://docs.rs/parry3d/0.23.0/parry3d/query/trait.QueryDispatcher.html
-// We need to write a custom parry3d::query::QueryDispatcher to handle our custom VoxelObject shape implementation
@@ -0,0 +1,317 @@
+// https://docs.rs/parry3d/0.23.0/parry3d/query/trait.QueryDispatcher.html
+// Parry has no idea what a VoxelObject is, so contacts against one are built here and everything else falls through
+// to the default dispatcher. Each solid uniform region is treated as its own cuboid with its own manifold,
+// the same way parry handles compound shapes. Against another VoxelObject that's each of its regions against
+// each of ours, then manifolds facing the same way are merged (see merge_manifolds).
+use crate::objects::VoxelObject;
+use glam::Vec3;
+use rapier3d::parry::math::{Isometry, Real, Vector};
+use rapier3d::parry::query::{
+  ClosestPoints, Contact, ContactManifold, ContactManifoldsWorkspace, DefaultQueryDispatcher,
+  NonlinearRigidMotion, PersistentQueryDispatcher, QueryDispatcher, ShapeCastHit, ShapeCastOptions, TrackedContact, Unsupported,
+};
+use rapier3d::parry::query::details::NormalConstraints;
+use rapier3d::parry::shape::{Cuboid, Shape};
+use rapier3d::parry::bounding_volume::BoundingVolume;
+
+pub struct VoxelDispatcher;
+
+// Manifolds whose normals are within about 2.5 degrees of each other are merged
+const SAME_NORMAL: Real = 0.999;
+// What a merged manifold is cut back down to, as many as the solver takes in one go
+const MAX_POINTS: usize = 4;
+// Points closer together than this (squared) are the same point
+const SAME_POINT: Real = 1e-6;
+// How far a point can have moved since the last step and still be the same contact, for warmstarting
+const CARRIED: Real = 0.05;
+
+impl VoxelDispatcher {
+  /// Appends a manifold for every solid region of voxels near other. pos12 places other in the voxels' local space.
+  /// When flipped, other is the first shape of the pair and the manifolds are built in that order.
+  fn voxel_manifolds<M: Default + Clone, C: Default + Copy>(
+    pos12: &Isometry<Real>,
+    voxels: &VoxelObject,
+    other: &dyn Shape,
+    prediction: Real,
+    manifolds: &mut Vec<ContactManifold<M, C>>,
+    flipped: bool,
+  ) {
+    // Regions are found in grid space, which is local space shifted by the pivot
+    let aabb = other.compute_aabb(pos12).loosened(prediction);
+    let min = (Vec3::from(aabb.mins) + voxels.pivot_offset).floor().as_ivec3();
+    let max = (Vec3::from(aabb.maxs) + voxels.pivot_offset).ceil().as_ivec3();
+    let bounds = (voxels.min_cell.as_ivec3(), voxels.max_cell.as_ivec3() + 1);
+    let (min, max) = (min.max(bounds.0), max.min(bounds.1));
+    if min.cmpge(max).any() { return }
+
+    let mut region_id = 0;
+    voxels.snapshot.for_each_solid_in(min, max, &mut |corner, size| {
+      let half = size as f32 / 2.0;
+      let center = corner.as_vec3() + half - voxels.pivot_offset;
+      let sub_pos = Isometry::translation(center.x, center.y, center.z);
+      let cuboid = Cuboid::new(Vector::repeat(half));
+      region_id += 1;
+
+      if let Some(other_voxels) = other.as_shape::<VoxelObject>() {
+        // Voxels against voxels, treat our region as a convex shape against the other object
+        let start = manifolds.len();
+        Self::voxel_manifolds(&pos12.inv_mul(&sub_pos), other_voxels, &cuboid, prediction, manifolds, !flipped);
+        for manifold in &mut manifolds[start ..] {
+          if flipped { manifold.subshape2 = region_id; manifold.subshape_pos2 = Some(sub_pos) }
+          else { manifold.subshape1 = region_id; manifold.subshape_pos1 = Some(sub_pos) }
+        }
+        return
+      }
+
+      let mut manifold = if flipped {
+        ContactManifold::with_data(0, region_id, M::default())
+      } else {
+        ContactManifold::with_data(region_id, 0, M::default())
+      };
+      let result = if flipped {
+        manifold.subshape_pos2 = Some(sub_pos);
+        DefaultQueryDispatcher.contact_manifold_convex_convex(&pos12.inv_mul(&sub_pos), other, &cuboid, None, None, prediction, &mut manifold)
+      } else {
+        manifold.subshape_pos1 = Some(sub_pos);
+        DefaultQueryDispatcher.contact_manifold_convex_convex(&sub_pos.inv_mul(pos12), &cuboid, other, None, None, prediction, &mut manifold)
+      };
+      if result.is_ok() && !manifold.points.is_empty() { manifolds.push(manifold) }
+    });
+  }
+}
+
+impl<M: Default + Clone, C: Default + Copy> PersistentQueryDispatcher<M, C> for VoxelDispatcher {
+  fn contact_manifolds(
+    &self,
+    pos12: &Isometry<Real>,
+    g1: &dyn Shape,
+    g2: &dyn Shape,
+    prediction: Real,
+    manifolds: &mut Vec<ContactManifold<M, C>>,
+    _workspace: &mut Option<ContactManifoldsWorkspace>,
+  ) -> Result<(), Unsupported> {
+    // Last step's manifolds, what the solver learnt about them is carried over to this step's
+    let previous = std::mem::take(manifolds);
+    if let Some(voxels) = g1.as_shape::<VoxelObject>() {
+      Self::voxel_manifolds(pos12, voxels, g2, prediction, manifolds, false);
+    } else if let Some(voxels) = g2.as_shape::<VoxelObject>() {
+      Self::voxel_manifolds(&pos12.inverse(), voxels, g1, prediction, manifolds, true);
+    } else { return Err(Unsupported) }
+    if g1.as_shape::<VoxelObject>().is_some() && g2.as_shape::<VoxelObject>().is_some() { merge_manifolds(manifolds) }
+    carry_over(&previous, manifolds);
+    Ok(())
+  }
+
+  fn contact_manifold_convex_convex(
+    &self,
+    _pos12: &Isometry<Real>,
+    _g1: &dyn Shape,
+    _g2: &dyn Shape,
+    _normal_constraints1: Option<&dyn NormalConstraints>,
+    _normal_constraints2: Option<&dyn NormalConstraints>,
+    _prediction: Real,
+    _manifold: &mut ContactManifold<M, C>,
+  ) -> Result<(), Unsupported> { Err(Unsupported) }
+}
+
+// Where two objects rest on each other every pair of regions along the way touches, which is hundreds of
+// manifolds all facing the same way and a solver that crawls. Those are merged into one per direction, in the
+// colliders' own frames, keeping the deepest point and the ones spreading furthest from it like a box's corners
+fn merge_manifolds<M: Default + Clone, C: Default + Copy>(manifolds: &mut Vec<ContactManifold<M, C>>) {
+  let mut merged: Vec<ContactManifold<M, C>> = Vec::new();
+  for mut manifold in manifolds.drain(..) {
+    let sub_pos1 = manifold.subshape_pos1.unwrap_or_else(Isometry::identity);
+    let sub_pos2 = manifold.subshape_pos2.unwrap_or_else(Isometry::identity);
+    for point in &mut manifold.points {
+      point.local_p1 = sub_pos1 * point.local_p1;
+      point.local_p2 = sub_pos2 * point.local_p2;
+    }
+    let normal = sub_pos1 * manifold.local_n1;
+    match merged.iter_mut().find(|other| other.local_n1.dot(&normal) >= SAME_NORMAL) {
+      Some(other) => other.points.append(&mut manifold.points),
+      None => {
+        let id = merged.len() as u32;
+        let mut fresh = ContactManifold::with_data(id, id, M::default());
+        fresh.local_n1 = normal;
+        fresh.local_n2 = sub_pos2 * manifold.local_n2;
+        fresh.points = manifold.points;
+        merged.push(fresh);
+      }
+    }
+  }
+  for manifold in &mut merged { reduce_points(&mut manifold.points) }
+  *manifolds = merged;
+}
+
+// Cuts points down to MAX_POINTS: the deepest, the one furthest from it, the one making the widest triangle
+// with those and the one adding the most to it. Points all in a line have no triangle, so the third is the other
+// end of the line instead, and picks landing on a point already kept are dropped rather than handed over twice
+fn reduce_points<C: Copy>(points: &mut Vec<TrackedContact<C>>) {
+  if points.len() <= MAX_POINTS { return }
+  let at = |idx: usize| Vec3::from(points[idx].local_p1);
+  let widest = |score: &dyn Fn(Vec3) -> f32| (0 .. points.len()).max_by(|&a, &b| score(at(a)).total_cmp(&score(at(b)))).unwrap();
+  let a = (0 .. points.len()).min_by(|&a, &b| points[a].dist.total_cmp(&points[b].dist)).unwrap();
+  let b = widest(&|p| p.distance_squared(at(a)));
+  let triangle = |p: Vec3| (at(b) - at(a)).cross(p - at(a)).length_squared();
+  let mut c = widest(&triangle);
+  if triangle(at(c)) <= SAME_POINT { c = widest(&|p| p.distance_squared(at(b))) }
+  // Inside the triangle the three areas add up to its own, and they only grow the further out of it p is
+  let area = |p: Vec3, q: Vec3, r: Vec3| (q - p).cross(r - p).length();
+  let d = widest(&|p| area(at(a), at(b), p) + area(at(b), at(c), p) + area(at(c), at(a), p));
+  let mut kept: Vec<TrackedContact<C>> = Vec::with_capacity(MAX_POINTS);
+  for idx in [a, b, c, d] {
+    if kept.iter().all(|point| Vec3::from(point.local_p1).distance_squared(at(idx)) > SAME_POINT) { kept.push(points[idx]) }
+  }
+  *points = kept;
+}
+
+// Hands what the solver worked out last step (the impulses it warmstarts from) on to this step's manifolds.
+// A manifold carries on from last step's on the same regions facing the same way, and each of its points from
+// whichever of that one's points hasn't moved further than CARRIED
+fn carry_over<M: Clone, C: Default + Copy>(previous: &[ContactManifold<M, C>], manifolds: &mut [ContactManifold<M, C>]) {
+  let normal = |manifold: &ContactManifold<M, C>| manifold.subshape_pos1.unwrap_or_else(Isometry::identity) * manifold.local_n1;
+  for manifold in manifolds {
+    let Some(old) = previous.iter().find(|old| {
+      old.subshape_pos1 == manifold.subshape_pos1 && old.subshape_pos2 == manifold.subshape_pos2
+        && normal(old).dot(&normal(manifold)) >= SAME_NORMAL
+    }) else { continue };
+    manifold.data = old.data.clone();
+    manifold.match_contacts_using_positions(&old.points, CARRIED);
+  }
+}
+
+// Only contact manifolds are needed to simulate, the rest can be filled in as something needs them
+impl QueryDispatcher for VoxelDispatcher {
+  fn intersection_test(&self, _pos12: &Isometry<Real>, _g1: &dyn Shape, _g2: &dyn Shape) -> Result<bool, Unsupported> {
+    Err(Unsupported)
+  }
+
+  fn distance(&self, _pos12: &Isometry<Real>, _g1: &dyn Shape, _g2: &dyn Shape) -> Result<Real, Unsupported> {
+    Err(Unsupported)
+  }
+
+  fn contact(&self, _pos12: &Isometry<Real>, _g1: &dyn Shape, _g2: &dyn Shape, _prediction: Real) -> Result<Option<Contact>, Unsupported> {
+    Err(Unsupported)
+  }
+
+  fn closest_points(&self, _pos12: &Isometry<Real>, _g1: &dyn Shape, _g2: &dyn Shape, _max_dist: Real) -> Result<ClosestPoints, Unsupported> {
+    Err(Unsupported)
+  }
+
+  fn cast_shapes(
+    &self,
+    _pos12: &Isometry<Real>,
+    _local_vel12: &Vector<Real>,
+    _g1: &dyn Shape,
+    _g2: &dyn Shape,
+    _options: ShapeCastOptions,
+  ) -> Result<Option<ShapeCastHit>, Unsupported> { Err(Unsupported) }
+
+  fn cast_shapes_nonlinear(
+    &self,
+    _motion1: &NonlinearRigidMotion,
+    _g1: &dyn Shape,
+    _motion2: &NonlinearRigidMotion,
+    _g2: &dyn Shape,
+    _start_time: Real,
+    _end_time: Real,
+    _stop_at_penetration: bool,
+  ) -> Result<Option<ShapeCastHit>, Unsupported> { Err(Unsupported) }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rapier3d::parry::math::Point;
+  use rapier3d::parry::shape::PackedFeatureId;
+
+  // A contact at p, dist deep, tagged with data so it can be told apart after
+  fn point(p: [f32; 3], dist: f32, data: u32) -> TrackedContact<u32> {
+    let p = Point::from(p);
+    let mut point = TrackedContact::new(p, p, PackedFeatureId::UNKNOWN, PackedFeatureId::UNKNOWN, dist);
+    point.data = data;
+    point
+  }
+
+  fn manifold(normal: Vector<Real>, points: Vec<TrackedContact<u32>>) -> ContactManifold<(), u32> {
+    let mut manifold = ContactManifold::with_data(0, 0, ());
+    (manifold.local_n1, manifold.local_n2, manifold.points) = (normal, -normal, points);
+    manifold
+  }
+
+  fn kept(points: &[TrackedContact<u32>]) -> Vec<u32> {
+    let mut kept: Vec<u32> = points.iter().map(|point| point.data).collect();
+    kept.sort_unstable();
+    kept
+  }
+
+  #[test]
+  fn reductions_keep_the_corners() {
+    // A 4x4 grid of points on a face, one next to the middle deepest
+    let mut points: Vec<_> = (0 .. 16).map(|idx| {
+      let (x, z) = ((idx % 4) as f32, (idx / 4) as f32);
+      point([x, 0.0, z], if idx == 5 { -0.2 } else { -0.1 }, idx)
+    }).collect();
+    reduce_points(&mut points);
+    assert_eq!(kept(&points), [3, 5, 12, 15]);
+    // Already few enough
+    let mut points = vec![point([0.0; 3], 0.0, 0), point([1.0, 0.0, 0.0], 0.0, 1)];
+    reduce_points(&mut points);
+    assert_eq!(points.len(), 2);
+  }
+
+  #[test]
+  fn reductions_never_repeat_a_point() {
+    // All in the same place
+    let mut points: Vec<_> = (0 .. 6).map(|idx| point([1.0, 0.0, 1.0], -0.1, idx)).collect();
+    reduce_points(&mut points);
+    assert_eq!(points.len(), 1);
+    // Along a line, the deepest in the middle. Both ends are kept along with it and nothing twice
+    let mut points: Vec<_> = (0 .. 6).map(|idx| point([idx as f32, 0.0, 0.0], if idx == 2 { -0.3 } else { -0.1 }, idx)).collect();
+    reduce_points(&mut points);
+    assert_eq!(kept(&points), [0, 2, 5]);
+    // Two points doubled up many times over
+    let mut points: Vec<_> = (0 .. 8).map(|idx| point([(idx % 2) as f32, 0.0, 0.0], -0.1, idx % 2)).collect();
+    reduce_points(&mut points);
+    assert_eq!(kept(&points), [0, 1]);
+  }
+
+  #[test]
+  fn merges_go_by_normal() {
+    let corners = |y: f32, data: u32| (0 .. 6).map(|idx| point([(idx % 3) as f32, y, (idx / 3) as f32], -0.1, data + idx)).collect::<Vec<_>>();
+    let mut manifolds = vec![
+      manifold(Vector::y(), corners(0.0, 0)),
+      manifold(Vector::x(), vec![point([2.0, 0.5, 0.5], -0.1, 100)]),
+      // Close enough to the first to be merged into it
+      manifold(Vector::new(0.01, 1.0, 0.0).normalize(), corners(0.0, 10)),
+    ];
+    merge_manifolds(&mut manifolds);
+    assert_eq!(manifolds.len(), 2);
+    assert_eq!(manifolds[0].local_n1, Vector::y());
+    // Twelve points, six of them doubled, cut down to the four corners
+    assert_eq!(manifolds[0].points.len(), 4);
+    assert_eq!(kept(&manifolds[1].points), [100]);
+    assert_eq!((manifolds[1].subshape1, manifolds[1].subshape2), (1, 1));
+  }
+
+  #[test]
+  fn contacts_carry_over_between_steps() {
+    let mut previous = vec![manifold(Vector::y(), vec![point([0.0; 3], -0.1, 7), point([1.0, 0.0, 0.0], -0.1, 8)])];
+    previous[0].subshape_pos1 = Some(Isometry::translation(0.5, 0.5, 0.5));
+    let mut manifolds = vec![
+      manifold(Vector::y(), vec![point([0.01, 0.0, 0.0], -0.1, 0), point([3.0, 0.0, 0.0], -0.1, 0)]),
+      manifold(Vector::x(), vec![point([0.0; 3], -0.1, 0)]),
+    ];
+    manifolds[0].subshape_pos1 = Some(Isometry::translation(0.5, 0.5, 0.5));
+    manifolds[1].subshape_pos1 = Some(Isometry::translation(0.5, 0.5, 0.5));
+    carry_over(&previous, &mut manifolds);
+    // The point that barely moved keeps its data, the new one and the other face's start afresh
+    assert_eq!(manifolds[0].points.iter().map(|point| point.data).collect::<Vec<_>>(), [7, 0]);
+    assert_eq!(manifolds[1].points[0].data, 0);
+    // A different region isn't the same contact, wherever its points are
+    manifolds[0].subshape_pos1 = Some(Isometry::translation(1.5, 0.5, 0.5));
+    manifolds[0].points[0].data = 0;
+    carry_over(&previous, &mut manifolds);
+    assert_eq!(manifolds[0].points[0].data, 0);
+  }
+}
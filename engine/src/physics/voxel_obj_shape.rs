@@ -1,19 +1,94 @@
-use crate::objects::VoxelObject;
-use nalgebra::Vector3;
-use rapier3d::geometry::{Shape, PointQuery, RayCast};
+use crate::objects::{VoxelObject, EMPTY};
+use nalgebra::{Point3, Vector3};
+use rapier3d::geometry::{Shape, PointQuery, RayCast, ShapeType, TypedShape};
 use rapier3d::parry::shape::FeatureId;
-use rapier3d::parry::bounding_volume::Aabb;
-use rapier3d::parry::query::{Ray, RayIntersection};
-use glam::{BVec3, IVec3, Vec3};
+use rapier3d::parry::bounding_volume::{Aabb, BoundingSphere};
+use rapier3d::parry::mass_properties::MassProperties;
+use rapier3d::parry::query::{PointProjection, Ray, RayIntersection};
+use rapier3d::parry::math::Isometry;
+use sdg::prelude::*;
+use std::collections::HashMap;
+use glam::{BVec3, IVec3, UVec3, Vec3};
+
+// Child entries with this bit set are leaf values rather than snapshot indices
+const LEAF: u32 = 1 << 31;
+
+/// A frozen, self-contained copy of the nodes reachable from one head.
+/// Lets physics sample an object from inside parry's queries, which have no way to reach the graph.
+#[derive(Debug, Default)]
+pub struct DagSnapshot {
+  nodes: Vec<[u32; 8]>,
+  root: u32,
+  height: u32,
+}
+impl DagSnapshot {
+  pub fn new(sdg: &SparseDirectedGraph<BasicNode3d>, head: Index, height: u32) -> Self {
+    let mut snapshot = Self { nodes: Vec::new(), root: 0, height };
+    let mut remap = HashMap::new();
+    let mut queue = Vec::new();
+    snapshot.root = snapshot.entry(sdg, &mut remap, &mut queue, head);
+    while let Some(idx) = queue.pop() {
+      let node = *sdg.nodes.get(idx as usize).unwrap();
+      let mut children = [0; 8];
+      for (slot, child) in children.iter_mut().zip(node) { *slot = snapshot.entry(sdg, &mut remap, &mut queue, child) }
+      snapshot.nodes[remap[&idx] as usize] = children;
+    }
+    snapshot
+  }
+
+  // Leaves are stored inline, everything else gets a slot filled in once it's popped off the queue
+  fn entry(&mut self, sdg: &SparseDirectedGraph<BasicNode3d>, remap: &mut HashMap<Index, u32>, queue: &mut Vec<Index>, idx: Index) -> u32 {
+    if sdg.is_leaf(idx) { return LEAF | idx }
+    *remap.entry(idx).or_insert_with(|| {
+      self.nodes.push([0; 8]);
+      queue.push(idx);
+      self.nodes.len() as u32 - 1
+    })
+  }
+
+  /// Leaf value at cell and the height of the uniform node containing it, cells outside the grid are empty
+  fn sample(&self, cell: IVec3) -> Sample {
+    if cell.min_element() < 0 || cell.max_element() >= 1 << self.height {
+      return Sample { value: EMPTY, height: 0 }
+    }
+    let cell = cell.as_uvec3();
+    let mut entry = self.root;
+    for height in (0 .. self.height).rev() {
+      if entry & LEAF != 0 { return Sample { value: entry & !LEAF, height: height + 1 } }
+      entry = self.nodes[entry as usize][Zorder3d::new(cell >> height & 1) as usize];
+    }
+    Sample { value: entry & !LEAF, height: 0 }
+  }
+
+  /// Calls f with the min corner and size of every solid uniform region
+  fn for_each_solid(&self, f: &mut impl FnMut(UVec3, u32)) {
+    self.for_each_solid_in(IVec3::ZERO, IVec3::splat(1 << self.height), f)
+  }
+
+  /// Calls f with the min corner and size of every solid uniform region overlapping [min, max)
+  pub(super) fn for_each_solid_in(&self, min: IVec3, max: IVec3, f: &mut impl FnMut(UVec3, u32)) {
+    self.visit_solid(self.root, UVec3::ZERO, self.height, min, max, f)
+  }
+
+  fn visit_solid(&self, entry: u32, corner: UVec3, height: u32, min: IVec3, max: IVec3, f: &mut impl FnMut(UVec3, u32)) {
+    let size = 1 << height;
+    if corner.as_ivec3().cmpge(max).any() || (corner.as_ivec3() + size).cmple(min).any() { return }
+    if entry & LEAF != 0 {
+      if entry & !LEAF != EMPTY { f(corner, size as u32) }
+      return
+    }
+    for child in Zorder3d::all() {
+      let child_corner = corner + child.to_coord() * (size as u32 / 2);
+      self.visit_solid(self.nodes[entry as usize][child as usize], child_corner, height - 1, min, max, f);
+    }
+  }
+}
 
-// This needs access to the actual dag to sample
-// Returns [value, height]
 struct Sample {
   value: u32,
   height: u32,
 }
-impl Sample { fn is_solid(&self) -> bool { self.value != 0} }
-fn sample_cell(cell: IVec3) -> Sample { todo!() }
+impl Sample { fn is_solid(&self) -> bool { self.value != EMPTY } }
 
 struct Position {
   cell: IVec3,
@@ -53,19 +128,56 @@ impl LargeRay {
   }
 }
 
+impl VoxelObject {
+  fn sample_cell(&self, cell: IVec3) -> Sample { self.snapshot.sample(cell) }
+
+  /// Bounds in grid space, max is exclusive
+  fn grid_aabb(&self) -> Aabb {
+    Aabb::new(self.min_cell.as_vec3().into(), (self.max_cell.as_vec3() + 1.0).into())
+  }
+
+  /// Closest point to `point` on any cell matching `solid` within the grid, searched in growing shells.
+  /// Cells around the grid count as empty so searches for empty space always terminate.
+  fn closest_cell_point(&self, point: Vec3, solid: bool) -> Option<Vec3> {
+    let center = point.floor().as_ivec3();
+    let (min, max) = (self.min_cell.as_ivec3() - 1, self.max_cell.as_ivec3() + 1);
+    // No cell outside the padded bounds can match, so there's no point searching further than they reach
+    let max_radius = (center - min).abs().max((center - max).abs()).max_element();
+    let mut best: Option<(f32, Vec3)> = None;
+    for radius in 0 ..= max_radius {
+      // Every cell in this shell is at least radius - 1 away
+      if best.is_some_and(|(dist, _)| dist <= (radius - 1) as f32) { break }
+      let shell_min = (center - radius).max(min);
+      let shell_max = (center + radius).min(max);
+      for x in shell_min.x ..= shell_max.x {
+        for y in shell_min.y ..= shell_max.y {
+          for z in shell_min.z ..= shell_max.z {
+            let cell = IVec3::new(x, y, z);
+            if (cell - center).abs().max_element() != radius { continue }
+            if self.sample_cell(cell).is_solid() != solid { continue }
+            let closest = point.clamp(cell.as_vec3(), cell.as_vec3() + 1.0);
+            let dist = closest.distance(point);
+            if best.is_none_or(|(best_dist, _)| dist < best_dist) { best = Some((dist, closest)) }
+          }
+        }
+      }
+    }
+    best.map(|(_, closest)| closest)
+  }
+}
+
 impl RayCast for VoxelObject {
   // I imagine I don't care, but if these conversions to glam really cause problems I can learn
   /// If solid is false, the shape is hollow
   fn cast_local_ray_and_get_normal(&self, ext_ray: &Ray, max_toi: f32, solid: bool) -> Option<RayIntersection> {
     // We need to set our origin to be the negative corner of the shape but rapier thinks our origin is at the pivot
     let translation = Vector3::new(self.pivot_offset.x, self.pivot_offset.y, self.pivot_offset.z);
-    let shifted_ray = Ray::new(ext_ray.origin - translation, ext_ray.dir);
+    let shifted_ray = Ray::new(ext_ray.origin + translation, ext_ray.dir);
     let mut ray = LargeRay::new(shifted_ray);
     // We need to project the shape onto the local aabb
-    let aabb = Aabb::new(self.min_cell.as_vec3().into(), self.max_cell.as_vec3().into());
-    ray.step(aabb.cast_local_ray(&shifted_ray, max_toi, true)?);
+    ray.step(self.grid_aabb().cast_local_ray(&shifted_ray, max_toi, true)?);
     
-    let mut sample = sample_cell(ray.pos.cell);
+    let mut sample = self.sample_cell(ray.pos.cell);
     // if we're inside the shape and it's not hollow, return an immediate intersection
     if sample.is_solid() && solid { return Some(RayIntersection::new(
       0.,
@@ -95,7 +207,7 @@ impl RayCast for VoxelObject {
       if ray.pos.cell.clamp(self.min_cell.as_ivec3(), self.max_cell.as_ivec3()).cmpne(ray.pos.cell).any() {
         return None
       }
-      sample = sample_cell(ray.pos.cell);
+      sample = self.sample_cell(ray.pos.cell);
       // Terminate if we found what we're looking for
       if searching_for_solid == sample.is_solid() { break }
     }
@@ -123,10 +235,59 @@ impl RayCast for VoxelObject {
   }
 
 }
-// https://docs.rs/parry3d/0.23.0/parry3d/shape/trait.Shape.html
-//
-// This means we must implement PointQuery
-// This is kinda tricky, we need to identify the closest point by using some kind of spiraling neighbor search
+
 // https://docs.rs/parry3d/0.23.0/parry3d/query/point/trait.PointQuery.html
-//
+// We find the closest point with an expanding neighborhood search around the query point.
+// Inside solid we search for the nearest empty cell instead, since the surface is the boundary between the two.
+impl PointQuery for VoxelObject {
+  fn project_local_point(&self, pt: &Point3<f32>, solid: bool) -> PointProjection {
+    let point = Vec3::from(*pt) + self.pivot_offset;
+    let inside = self.sample_cell(point.floor().as_ivec3()).is_solid();
+    if inside && solid { return PointProjection::new(true, *pt) }
+    // A shape with no solid cells has no surface, the best we can do is report the point itself
+    let Some(closest) = self.closest_cell_point(point, !inside) else { return PointProjection::new(false, *pt) };
+    PointProjection::new(inside, (closest - self.pivot_offset).into())
+  }
+
+  fn project_local_point_and_get_feature(&self, pt: &Point3<f32>) -> (PointProjection, FeatureId) {
+    (self.project_local_point(pt, false), FeatureId::Unknown)
+  }
+}
+
+// https://docs.rs/parry3d/0.23.0/parry3d/shape/trait.Shape.html
+impl Shape for VoxelObject {
+  fn compute_local_aabb(&self) -> Aabb {
+    let pivot = Vector3::new(self.pivot_offset.x, self.pivot_offset.y, self.pivot_offset.z);
+    let grid = self.grid_aabb();
+    Aabb::new(grid.mins - pivot, grid.maxs - pivot)
+  }
+
+  fn compute_local_bounding_sphere(&self) -> BoundingSphere {
+    let aabb = self.compute_local_aabb();
+    BoundingSphere::new(aabb.center(), aabb.half_extents().norm())
+  }
+
+  fn clone_dyn(&self) -> Box<dyn Shape> { Box::new(self.clone()) }
+
+  fn scale_dyn(&self, _scale: &Vector3<f32>, _num_subdivisions: u32) -> Option<Box<dyn Shape>> { None }
 
+  /// Sums up every solid region as its own cuboid, so hollow or lopsided objects balance correctly
+  fn mass_properties(&self, density: f32) -> MassProperties {
+    let mut total = MassProperties::default();
+    self.snapshot.for_each_solid(&mut |corner, size| {
+      let half = Vec3::splat(size as f32 / 2.0);
+      let center = corner.as_vec3() + half - self.pivot_offset;
+      total += MassProperties::from_cuboid(density, half.into())
+        .transform_by(&Isometry::translation(center.x, center.y, center.z));
+    });
+    total
+  }
+
+  fn shape_type(&self) -> ShapeType { ShapeType::Custom }
+
+  fn as_typed_shape(&self) -> TypedShape<'_> { TypedShape::Custom(self) }
+
+  fn ccd_thickness(&self) -> f32 { 0.5 }
+
+  fn ccd_angular_thickness(&self) -> f32 { std::f32::consts::FRAC_PI_4 }
+}
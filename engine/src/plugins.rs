@@ -0,0 +1,218 @@
+//! Hooks for extending the engine without touching it, plus script mods loaded from a directory at startup.
+//!
+//! A script mod is a `.mod` file of one directive per line, `#` starts a comment:
+//!   material <name> <r> <g> <b> [glow <strength>] [glass <opacity> <ior>] [water <opacity>] [metal <roughness>] [tile <n>]
+//!       Registers a leaf (linear color) as a block called name, replacing any block that went by it before.
+//!       tile picks a tile of the texture atlas
+//!   fill <object> <x0> <y0> <z0> <x1> <y1> <z1> <block>
+//!       Worldgen pass run after the template, fills the inclusive box of cells with block (any mod's, or a built in one).
+//!       Cells are signed like the console's (see VoxelObject::origin), the grid grows to take in any outside it
+//!   alias <name> <command>[; <command>...]
+//!       A console command which runs built in commands
+//!   every <ticks> <command>[; <command>...]
+//!       Runs built in commands every so many simulation ticks
+
+use crate::console;
+use crate::blocks::BlockProperties;
+use crate::materials::Material;
+use crate::objects::{GameData, MAX_HEIGHT};
+use glam::{IVec3, Vec3};
+use sdg::prelude::{Blend, Brush};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Something that hooks into the engine, every hook defaults to doing nothing
+pub trait Plugin {
+  fn name(&self) -> &str;
+
+  /// Runs once before generate, the place to register materials
+  fn register_materials(&mut self, _game_data: &mut GameData) -> Result<(), String> { Ok(()) }
+
+  /// Runs once after the world template has been built
+  fn generate(&mut self, _game_data: &mut GameData) -> Result<(), String> { Ok(()) }
+
+  /// Gets the first look at every console command, None passes it on (eventually to the built in ones)
+  fn command(&mut self, _name: &str, _args: &[&str], _game_data: &mut GameData) -> Option<Result<(), String>> { None }
+
+  /// Runs after every simulation tick
+  fn tick(&mut self, _tick: u64, _game_data: &mut GameData) {}
+}
+
+/// Every loaded plugin, hooks run in the order they were added
+#[derive(Default)]
+pub struct Plugins {
+  list: Vec<Box<dyn Plugin>>,
+}
+impl Plugins {
+  pub fn add(&mut self, plugin: Box<dyn Plugin>) { self.list.push(plugin) }
+
+  /// Adds a ScriptMod for every .mod file in dir, in name order. A missing dir just means no mods
+  pub fn load_dir(&mut self, dir: &Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else { return };
+    let mut paths: Vec<_> = entries.filter_map(|entry| Some(entry.ok()?.path()))
+      .filter(|path| path.extension().is_some_and(|ext| ext == "mod"))
+      .collect();
+    paths.sort();
+    for path in paths {
+      match ScriptMod::load(&path) {
+        Ok(script) => {
+          tracing::info!("Loaded mod {}", script.name);
+          self.add(Box::new(script));
+        }
+        Err(err) => tracing::warn!("Skipping mod {}: {err}", path.display()),
+      }
+    }
+  }
+
+  /// Runs every plugin's one-off hooks against a freshly built world
+  pub fn setup(&mut self, game_data: &mut GameData) {
+    for plugin in &mut self.list {
+      if let Err(err) = plugin.register_materials(game_data) { tracing::error!("{}: {err}", plugin.name()) }
+    }
+    for plugin in &mut self.list {
+      if let Err(err) = plugin.generate(game_data) { tracing::error!("{}: {err}", plugin.name()) }
+    }
+  }
+
+  /// The first plugin to claim the command gets it
+  pub fn command(&mut self, name: &str, args: &[&str], game_data: &mut GameData) -> Option<Result<(), String>> {
+    self.list.iter_mut().find_map(|plugin| plugin.command(name, args, game_data))
+  }
+
+  pub fn tick(&mut self, tick: u64, game_data: &mut GameData) {
+    for plugin in &mut self.list { plugin.tick(tick, game_data) }
+  }
+}
+
+/// A plugin read from a .mod file, see the top of this file for the format
+pub struct ScriptMod {
+  name: String,
+  // (name, material), registered in order
+  materials: Vec<(String, Material)>,
+  // (object, min, max, block name), in signed cells
+  fills: Vec<(usize, IVec3, IVec3, String)>,
+  aliases: HashMap<String, Vec<String>>,
+  // (period in ticks, commands)
+  timers: Vec<(u64, Vec<String>)>,
+}
+impl ScriptMod {
+  pub fn load(path: &Path) -> Result<Self, String> {
+    let source = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let name = path.file_stem().map_or("mod".into(), |stem| stem.to_string_lossy().into_owned());
+    Self::parse(name, &source)
+  }
+
+  pub fn parse(name: String, source: &str) -> Result<Self, String> {
+    let mut script = Self { name, materials: Vec::new(), fills: Vec::new(), aliases: HashMap::new(), timers: Vec::new() };
+    for (number, line) in source.lines().enumerate() {
+      let line = line.split('#').next().unwrap().trim();
+      if line.is_empty() { continue }
+      script.parse_line(line).map_err(|err| format!("line {}: {err}", number + 1))?;
+    }
+    Ok(script)
+  }
+
+  fn parse_line(&mut self, line: &str) -> Result<(), String> {
+    let (directive, rest) = line.split_once(' ').unwrap_or((line, ""));
+    let mut words = rest.split_whitespace();
+    match directive {
+      "material" => {
+        let name = words.next().ok_or("material needs a name")?.to_string();
+        let mut material = Material::new(Vec3::new(number(words.next())?, number(words.next())?, number(words.next())?));
+        // The other options start the material over, so it's put on at the end
+        let mut tile = None;
+        while let Some(word) = words.next() {
+          material = match word {
+            "glow" => Material::glowing(material.albedo, number(words.next())?),
+            "glass" => Material::translucent(material.albedo, number(words.next())?, number(words.next())?),
+            "water" => Material::water(material.albedo, number(words.next())?),
+            "metal" => Material::reflective(material.albedo, number(words.next())?),
+            "tile" => {
+              tile = Some(number(words.next())?);
+              material
+            }
+            _ => return Err(format!("{word} isn't glow, glass, water, metal or tile")),
+          };
+        }
+        self.materials.push((name, Material { tile, ..material }));
+      }
+      "fill" => {
+        let object = number(words.next())?;
+        let min = IVec3::new(number(words.next())?, number(words.next())?, number(words.next())?);
+        let max = IVec3::new(number(words.next())?, number(words.next())?, number(words.next())?);
+        let block = words.next().ok_or("fill needs a block")?.to_string();
+        // Grids start out at 0 and grow no wider than this, whichever way they grow
+        let limit = 1 << MAX_HEIGHT;
+        if min.min(max).min_element() <= -limit || min.max(max).max_element() >= limit { return Err(format!("fill reaches {limit} cells from 0, which no grid does")) }
+        self.fills.push((object, min.min(max), min.max(max), block));
+      }
+      "alias" => {
+        let (name, commands) = rest.split_once(' ').ok_or("alias needs a name and commands")?;
+        self.aliases.insert(name.to_string(), commands_of(commands));
+      }
+      "every" => {
+        let (ticks, commands) = rest.split_once(' ').ok_or("every needs a tick count and commands")?;
+        let ticks: u64 = number(Some(ticks))?;
+        if ticks == 0 { return Err("every needs at least 1 tick".into()) }
+        self.timers.push((ticks, commands_of(commands)));
+      }
+      _ => return Err(format!("Unknown directive {directive}")),
+    }
+    Ok(())
+  }
+
+  fn run(&self, commands: &[String], game_data: &mut GameData) {
+    for command in commands {
+      if let Err(err) = console::run_builtin(command, game_data) { tracing::warn!("{}: {err}", self.name) }
+    }
+  }
+}
+impl Plugin for ScriptMod {
+  fn name(&self) -> &str { &self.name }
+
+  fn register_materials(&mut self, game_data: &mut GameData) -> Result<(), String> {
+    for (name, material) in &self.materials {
+      let leaf = game_data.materials.register(&mut game_data.sdg, *material);
+      game_data.blocks.bind(name, leaf, BlockProperties::of(material));
+    }
+    Ok(())
+  }
+
+  fn generate(&mut self, game_data: &mut GameData) -> Result<(), String> {
+    for (object, min, max, block) in &self.fills {
+      let leaf = game_data.blocks.leaf(block).ok_or(format!("No block called {block}"))?;
+      if !game_data.objects.contains(*object) { return Err(format!("There's no object {object}")) }
+      if game_data.grow_to_fit(*object, *min).is_none() || game_data.grow_to_fit(*object, *max).is_none() {
+        return Err(format!("Object {object}'s grid can't grow to take in {min} to {max}"))
+      }
+      // Growing towards max can move min within the grid, so both are looked up after
+      let grid = &game_data.objects[*object];
+      let (low, high) = (grid.grid_cell(*min).unwrap(), grid.grid_cell(*max).unwrap());
+      // As a brush it costs about as much as the box's surface, the inside is filled a node at a time
+      let brush = Brush::Box { min: low.as_vec3(), max: high.as_vec3() + 1.0 };
+      game_data.write_brush(*object, brush, leaf, Blend::Replace);
+    }
+    Ok(())
+  }
+
+  fn command(&mut self, name: &str, _args: &[&str], game_data: &mut GameData) -> Option<Result<(), String>> {
+    let commands = self.aliases.get(name)?;
+    self.run(commands, game_data);
+    Some(Ok(()))
+  }
+
+  fn tick(&mut self, tick: u64, game_data: &mut GameData) {
+    for (period, commands) in &self.timers {
+      if tick.is_multiple_of(*period) { self.run(commands, game_data) }
+    }
+  }
+}
+
+fn number<T: std::str::FromStr>(word: Option<&str>) -> Result<T, String> {
+  let word = word.ok_or("Expected a number")?;
+  word.parse().map_err(|_| format!("{word} isn't a valid number"))
+}
+
+fn commands_of(list: &str) -> Vec<String> {
+  list.split(';').map(str::trim).filter(|command| !command.is_empty()).map(String::from).collect()
+}
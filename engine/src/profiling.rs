@@ -0,0 +1,135 @@
+//! Instrumentation through tracing. Zones are tracing spans, which cost next to nothing until something's listening,
+//! and the tracy and puffin features each add a listener that hands them on to their profiler.
+//! Status messages are tracing events too, info and up are printed to stderr.
+//! The graph's spans (edit batches and the like) come through here too, it uses tracing directly
+
+/// Times the rest of the enclosing block as a zone called name
+macro_rules! zone {
+  ($name:literal) => {
+    let _zone = tracing::info_span!($name).entered();
+  };
+}
+pub(crate) use zone;
+
+/// Records value as this frame's count of name, as a tracing event and a Tracy plot
+macro_rules! count {
+  ($name:literal, $value:expr) => {
+    let value = $value as u64;
+    tracing::trace!(counter = $name, value);
+    #[cfg(feature = "tracy")]
+    if let Some(client) = tracy_client::Client::running() { client.plot(tracy_client::plot_name!($name), value as f64) }
+  };
+}
+pub(crate) use count;
+
+/// Starts printing info, warn and error events to stderr, and listening for zones with whichever profilers are enabled.
+/// Tracy streams to its GUI once that connects, puffin keeps the last few minutes of frames for finish to write out
+pub fn start() {
+  use tracing_subscriber::layer::{Layer, SubscriberExt};
+  let log = tracing_subscriber::fmt::layer().with_writer(std::io::stderr).with_target(false).without_time();
+  let registry = tracing_subscriber::registry().with(log.with_filter(tracing_subscriber::filter::LevelFilter::INFO));
+  #[cfg(feature = "tracy")]
+  let registry = registry.with(tracing_tracy::TracyLayer::default());
+  #[cfg(feature = "puffin")]
+  let registry = registry.with(puffin_export::PuffinLayer::start());
+  if let Err(err) = tracing::subscriber::set_global_default(registry) { eprintln!("Failed to start logging: {err}") }
+}
+
+/// Writes out whatever needs writing once the game's done, which is the puffin profile if that's enabled
+pub fn finish() {
+  #[cfg(feature = "puffin")]
+  puffin_export::write(std::path::Path::new("profile.puffin"));
+}
+
+/// Marks the end of a frame in the profilers' timelines
+pub fn frame_mark() {
+  #[cfg(feature = "tracy")]
+  if let Some(client) = tracy_client::Client::running() { client.frame_mark() }
+  #[cfg(feature = "puffin")]
+  puffin::GlobalProfiler::lock().new_frame();
+}
+
+/// Hands a frame's timed passes to Tracy as GPU zones, with raw (name, begin, end) timestamps
+#[cfg(feature = "tracy")]
+pub fn gpu_zones(passes: &[(&str, u64, u64)], period: f32) {
+  use std::sync::OnceLock;
+  use tracy_client::{Client, GpuContext, GpuContextType};
+  static CONTEXT: OnceLock<Option<GpuContext>> = OnceLock::new();
+
+  let (Some(client), Some(&(_, first, _))) = (Client::running(), passes.first()) else { return };
+  // Syncing against a stamp a few frames old leaves the gpu timeline slightly behind the cpu one
+  let context = CONTEXT.get_or_init(|| client.new_gpu_context(Some("wgpu"), GpuContextType::Invalid, first as i64, period).ok());
+  let Some(context) = context else { return };
+  for &(name, begin, end) in passes {
+    let Ok(mut span) = context.span_alloc(name, "draw", file!(), line!()) else { return };
+    span.end_zone();
+    span.upload_timestamp_start(begin as i64);
+    span.upload_timestamp_end(end as i64);
+  }
+}
+
+// Puffin has no tracing integration of its own, so spans are turned into its scopes here
+#[cfg(feature = "puffin")]
+mod puffin_export {
+  use puffin::{GlobalFrameView, GlobalProfiler, ScopeDetails, ScopeId, ThreadProfiler};
+  use std::cell::RefCell;
+  use std::collections::HashMap;
+  use std::path::Path;
+  use std::sync::{Mutex, OnceLock};
+  use tracing::callsite::Identifier;
+  use tracing::span::Id;
+  use tracing::Subscriber;
+  use tracing_subscriber::layer::{Context, Layer};
+  use tracing_subscriber::registry::LookupSpan;
+
+  // Collects every frame from start on, for write
+  static FRAMES: OnceLock<GlobalFrameView> = OnceLock::new();
+
+  thread_local! {
+    // Where each span entered on this thread began in its puffin stream, innermost last
+    static OPEN: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+  }
+
+  /// Opens a puffin scope for every span entered, each span's callsite registered as a scope the first time it's seen
+  pub struct PuffinLayer {
+    scopes: Mutex<HashMap<Identifier, ScopeId>>,
+  }
+  impl PuffinLayer {
+    pub fn start() -> Self {
+      puffin::set_scopes_on(true);
+      FRAMES.get_or_init(GlobalFrameView::default);
+      Self { scopes: Mutex::new(HashMap::new()) }
+    }
+  }
+  impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for PuffinLayer {
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+      let Some(metadata) = ctx.metadata(id) else { return };
+      let scope = *self.scopes.lock().unwrap().entry(metadata.callsite()).or_insert_with(|| {
+        let details = ScopeDetails::from_scope_name(metadata.name())
+          .with_file(metadata.file().unwrap_or_default())
+          .with_line_nr(metadata.line().unwrap_or_default());
+        GlobalProfiler::lock().register_user_scopes(&[details])[0]
+      });
+      let start = ThreadProfiler::call(|profiler| profiler.begin_scope(scope, ""));
+      OPEN.with_borrow_mut(|open| open.push(start));
+    }
+
+    fn on_exit(&self, _id: &Id, _ctx: Context<'_, S>) {
+      let Some(start) = OPEN.with_borrow_mut(Vec::pop) else { return };
+      ThreadProfiler::call(|profiler| profiler.end_scope(start));
+    }
+  }
+
+  /// Writes the frames collected so far to path, for puffin_viewer to open
+  pub fn write(path: &Path) {
+    let Some(frames) = FRAMES.get() else { return };
+    // The frame still being recorded only reaches the view once it's ended
+    GlobalProfiler::lock().new_frame();
+    let result = std::fs::File::create(path).map_err(|err| err.to_string())
+      .and_then(|mut file| frames.lock().write(&mut file).map_err(|err| err.to_string()));
+    match result {
+      Ok(()) => tracing::info!("Wrote the profile to {}", path.display()),
+      Err(err) => tracing::error!("Failed to write the profile to {}: {err}", path.display()),
+    }
+  }
+}
@@ -0,0 +1,539 @@
+//! Worlds saved to disk, one directory per save slot under SAVES_DIR holding:
+//!   world.bin      Everything needed to pick the world back up, see save and load
+//!   meta.txt       `key = value` lines describing the save, so listing doesn't have to read world.bin
+//!   thumbnail.ppm  A small screenshot from when it was last saved, written a frame later by the renderer
+
+use crate::materials::{Material, MaterialRegistry};
+use crate::blocks::BlockProperties;
+use crate::objects::{DagRef, GameData, VoxelObject, MAX_HEIGHT};
+use crate::animation::Animation;
+use crate::sky::TimeOfDay;
+use crate::lights::{Light, LightKind};
+use glam::{Quat, UVec3, Vec3};
+use sdg::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const SAVES_DIR: &str = "saves";
+const WORLD_FILE: &str = "world.bin";
+const META_FILE: &str = "meta.txt";
+pub const THUMBNAIL_FILE: &str = "thumbnail.ppm";
+/// Widest a thumbnail gets, in pixels
+pub const THUMBNAIL_WIDTH: u32 = 160;
+/// Where quick saves go for worlds that haven't been given a slot
+pub const QUICKSAVE_SLOT: &str = "quicksave";
+
+const MAGIC: &[u8; 4] = b"VXW1";
+// Bumped whenever world.bin's layout changes, with a Change saying what it added so older saves are still read right.
+// Newer saves are refused rather than misread
+const VERSION: u32 = Change::Origins as u32;
+// How nodes are written, recorded in the header so a change to them can't be mistaken for the old layout
+const NODE_CHILDREN: u32 = 8;
+const INDEX_BYTES: u32 = 4;
+
+/// What world.bin has gained over the versions, each numbered by the version that brought it in.
+/// Files from before a change are read as if what it added was never used, see Header::has
+#[derive(Debug, Clone, Copy)]
+enum Change {
+  /// The camera's full orientation, version 1 only had yaw and pitch
+  CameraOrientation = 2,
+  Animations = 3,
+  /// Objects kept their handles, before that they went 0 up
+  Handles = 4,
+  TimeOfDay = 5,
+  Lights = 6,
+  Tiles = 7,
+  Blocks = 8,
+  /// The node layout in the header
+  NodeFormat = 9,
+  /// Each object's signed root offset, see VoxelObject::origin. Objects before it all started at 0
+  Origins = 10,
+}
+
+/// The start of a world file, read before anything else so the rest is read the way it was written
+struct Header {
+  version: u32,
+}
+impl Header {
+  fn read(input: &mut Reader) -> io::Result<Self> {
+    if input.take(4)? != MAGIC { return Err(invalid("Not a world file")) }
+    let header = Self { version: input.u32()? };
+    if !(1 ..= VERSION).contains(&header.version) {
+      return Err(invalid(format!("World file version {} isn't supported, expected up to {VERSION}", header.version)))
+    }
+    if header.has(Change::NodeFormat) {
+      let (children, index_bytes) = (input.u32()?, input.u32()?);
+      if (children, index_bytes) != (NODE_CHILDREN, INDEX_BYTES) {
+        return Err(invalid(format!("Nodes were written with {children} children of {index_bytes} bytes, expected {NODE_CHILDREN} of {INDEX_BYTES}")))
+      }
+    }
+    Ok(header)
+  }
+
+  fn write(out: &mut Writer) {
+    out.0.extend_from_slice(MAGIC);
+    out.u32(VERSION);
+    out.u32(NODE_CHILDREN);
+    out.u32(INDEX_BYTES);
+  }
+
+  fn has(&self, change: Change) -> bool { self.version >= change as u32 }
+}
+// Handles are slots, so they only go as high as the most objects there have been at once
+const MAX_HANDLE: usize = 1 << 20;
+
+/// Where a world came from
+#[derive(Debug, Clone, Default)]
+pub struct WorldInfo {
+  /// Name of the template it was generated from
+  pub template: String,
+  pub seed: i32,
+  /// The save it was loaded from or last saved to, None for worlds that have never been saved
+  pub slot: Option<String>,
+}
+
+/// A save as listed on the start screen
+#[derive(Debug, Clone)]
+pub struct SaveInfo {
+  pub name: String,
+  pub dir: PathBuf,
+  pub template: String,
+  pub seed: i32,
+  /// Seconds since the unix epoch
+  pub saved_at: u64,
+  /// Size of everything in the save's directory
+  pub bytes: u64,
+}
+impl SaveInfo {
+  pub fn thumbnail(&self) -> PathBuf { self.dir.join(THUMBNAIL_FILE) }
+}
+
+fn slot_dir(name: &str) -> PathBuf { Path::new(SAVES_DIR).join(name) }
+
+pub fn invalid(msg: impl Into<String>) -> io::Error { io::Error::new(io::ErrorKind::InvalidData, msg.into()) }
+
+/// Save names double as directory names, so they're kept to letters, digits, spaces, - and _
+pub fn check_name(name: &str) -> Result<(), String> {
+  if name.trim().is_empty() { return Err("Saves need a name".into()) }
+  if name.len() > 64 { return Err("That name is too long".into()) }
+  if !name.chars().all(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_')) {
+    return Err("Names can only use letters, digits, spaces, - and _".into())
+  }
+  Ok(())
+}
+
+pub fn exists(name: &str) -> bool { slot_dir(name).exists() }
+
+/// Every readable save, most recently saved first. A missing SAVES_DIR just means there aren't any
+pub fn list() -> Vec<SaveInfo> {
+  let Ok(entries) = std::fs::read_dir(SAVES_DIR) else { return Vec::new() };
+  let mut saves: Vec<_> = entries.filter_map(|entry| info(&entry.ok()?.path())).collect();
+  saves.sort_by(|a, b| b.saved_at.cmp(&a.saved_at).then_with(|| a.name.cmp(&b.name)));
+  saves
+}
+
+fn info(dir: &Path) -> Option<SaveInfo> {
+  let meta = std::fs::read_to_string(dir.join(META_FILE)).ok()?;
+  let fields: HashMap<_, _> = meta.lines().filter_map(|line| line.split_once('=')).map(|(key, value)| (key.trim(), value.trim())).collect();
+  let bytes = std::fs::read_dir(dir).ok()?
+    .filter_map(|entry| entry.ok()?.metadata().ok())
+    .map(|metadata| metadata.len())
+    .sum();
+  Some(SaveInfo {
+    name: dir.file_name()?.to_string_lossy().into_owned(),
+    dir: dir.to_path_buf(),
+    template: fields.get("template").unwrap_or(&"unknown").to_string(),
+    seed: fields.get("seed")?.parse().ok()?,
+    saved_at: fields.get("saved_at")?.parse().ok()?,
+    bytes,
+  })
+}
+
+/// Writes the world into slot name, which becomes its slot. Asks for a thumbnail of the next frame too
+pub fn save(game_data: &mut GameData, name: &str) -> io::Result<()> {
+  check_name(name).map_err(invalid)?;
+  let dir = slot_dir(name);
+  std::fs::create_dir_all(&dir)?;
+  game_data.save(&dir.join(WORLD_FILE))?;
+  let saved_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+  let meta = format!("template = {}\nseed = {}\nsaved_at = {saved_at}\n", game_data.world.template, game_data.world.seed);
+  std::fs::write(dir.join(META_FILE), meta)?;
+  game_data.world.slot = Some(name.to_string());
+  game_data.thumbnail = Some(dir.join(THUMBNAIL_FILE));
+  Ok(())
+}
+
+/// Reads slot name back into a world, dynamic objects pick up where they were but at rest
+pub fn load(name: &str) -> io::Result<GameData> {
+  let dir = slot_dir(name);
+  let info = info(&dir).ok_or_else(|| invalid(format!("{name} is missing its {META_FILE}")))?;
+  let mut game_data = GameData::load(&dir.join(WORLD_FILE))?;
+  game_data.world = WorldInfo { template: info.template, seed: info.seed, slot: Some(name.to_string()) };
+  Ok(game_data)
+}
+
+/// Copies slot name into a new slot, returning the new one's name
+pub fn duplicate(name: &str) -> io::Result<String> {
+  let copy = (1 ..).map(|n| if n == 1 { format!("{name} copy") } else { format!("{name} copy {n}") })
+    .find(|copy| !exists(copy))
+    .unwrap();
+  let (from, to) = (slot_dir(name), slot_dir(&copy));
+  std::fs::create_dir_all(&to)?;
+  for entry in std::fs::read_dir(from)? {
+    let entry = entry?;
+    std::fs::copy(entry.path(), to.join(entry.file_name()))?;
+  }
+  Ok(copy)
+}
+
+pub fn delete(name: &str) -> io::Result<()> { std::fs::remove_dir_all(slot_dir(name)) }
+
+impl GameData {
+  /// Writes the whole world to path: the graph its objects use, every object, the materials, camera and sky.
+  /// Slots are a directory of these plus their meta.txt, this is just the world on its own
+  pub fn save(&self, path: &Path) -> io::Result<()> { std::fs::write(path, self.to_bytes()) }
+
+  /// Reads a world written by save. It comes back without a WorldInfo, that lives in a slot's meta.txt
+  pub fn load(path: &Path) -> io::Result<Self> { Self::from_bytes(&std::fs::read(path)?) }
+
+  /// What save writes, for sending the world somewhere other than a file
+  pub fn to_bytes(&self) -> Vec<u8> { encode(self) }
+
+  pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> { decode(bytes) }
+}
+
+// world.bin is little endian throughout:
+//   magic, version, then how many children a node has and how many bytes an index takes (from version 9)
+//   leaf count, then each leaf's index and material, ending in its atlas tile or u32::MAX (from version 7)
+//   node count, then each node's index and children, children always come before their parents
+//   object count, then each object's handle (from version 4, before that they went 0 up), head, height, bounds,
+//   transform, whether it's dynamic and its animation (from version 3, a 0 for none or a 1 followed by it)
+//   camera, sky, tick, then the time of day (from version 5, before that it ran from wherever the sun was)
+//   light count, then each placed light (from version 6)
+//   block count, then each block's name, leaf and properties (from version 8, before that only air had a name)
+// Indices are whatever they were in the saved graph, load maps them onto fresh ones.
+
+fn encode(game_data: &GameData) -> Vec<u8> {
+  let mut out = Writer(Vec::new());
+  Header::write(&mut out);
+
+  let leaves = game_data.sdg.leaves();
+  out.u32(leaves.len() as u32);
+  for &leaf in leaves {
+    let material = game_data.materials.get(leaf);
+    out.u32(leaf);
+    out.vec3(material.albedo);
+    out.f32(material.roughness);
+    out.vec3(material.emissive);
+    out.u32(material.flags);
+    out.f32(material.opacity);
+    out.f32(material.ior);
+    out.u32(material.tile.unwrap_or(u32::MAX));
+  }
+
+  // Objects often share subtrees, each node is only written the first time it comes up
+  let mut written = HashSet::new();
+  let nodes: Vec<Index> = game_data.objects.values()
+    .flat_map(|object| game_data.sdg.tree_nodes(object.dag_ref.head))
+    .filter(|&idx| written.insert(idx))
+    .collect();
+  out.u32(nodes.len() as u32);
+  for idx in nodes {
+    out.u32(idx);
+    for child in game_data.sdg.nodes.get(idx as usize).unwrap() { out.u32(*child) }
+  }
+
+  out.u32(game_data.objects.len() as u32);
+  for (idx, object) in game_data.objects.iter() {
+    out.u32(idx as u32);
+    out.u32(object.dag_ref.head);
+    out.u32(object.dag_ref.height);
+    out.uvec3(object.min_cell);
+    out.uvec3(object.max_cell);
+    out.vec3(object.pos);
+    out.vec3(object.pivot_offset);
+    for value in object.rot.to_array() { out.f32(value) }
+    // Cast to and from u32 bit for bit
+    out.uvec3(object.origin.as_uvec3());
+    let dynamic = object.physics.is_some_and(|handle| game_data.physics.is_dynamic(handle.body));
+    out.u32(dynamic as u32);
+    out.u32(object.animation.is_some() as u32);
+    if let Some(animation) = &object.animation {
+      out.vec3(animation.velocity);
+      out.vec3(animation.spin);
+      // 0 for None, which can't go back and forth anyway
+      out.f32(animation.period.unwrap_or(0.0));
+      out.vec3(animation.origin.0);
+      for value in animation.origin.1.to_array() { out.f32(value) }
+      out.0.extend_from_slice(&animation.start_tick.to_le_bytes());
+    }
+  }
+
+  let camera = &game_data.camera;
+  out.vec3(camera.position);
+  for value in camera.orientation().to_array() { out.f32(value) }
+  out.f32(camera.speed);
+  out.vec3(game_data.sky.sun_dir);
+  out.f32(game_data.sky.turbidity);
+  out.0.extend_from_slice(&game_data.tick.to_le_bytes());
+  let clock = &game_data.time_of_day;
+  out.f32(clock.hours);
+  out.f32(clock.speed);
+  out.u32(clock.paused as u32);
+  out.u32(game_data.lights.placed.len() as u32);
+  for light in &game_data.lights.placed {
+    out.vec3(light.pos);
+    out.vec3(light.color);
+    out.f32(light.radius);
+    out.f32(light.inset);
+    match light.kind {
+      LightKind::Point => out.u32(0),
+      LightKind::Spot { dir, angle } => {
+        out.u32(1);
+        out.vec3(dir);
+        out.f32(angle);
+      }
+    }
+  }
+  out.u32(game_data.blocks.all().len() as u32);
+  for block in game_data.blocks.all() {
+    out.str(&block.name);
+    out.u32(block.leaf);
+    out.u32(block.properties.solid as u32);
+    out.u32(block.properties.transparent as u32);
+    out.f32(block.properties.hardness);
+  }
+  out.0
+}
+
+fn decode(bytes: &[u8]) -> io::Result<GameData> {
+  let mut input = Reader::new(bytes);
+  let header = Header::read(&mut input)?;
+
+  let mut sdg = SparseDirectedGraph::new();
+  let mut materials = MaterialRegistry::default();
+  // Saved index -> index in the new graph
+  let mut remap = HashMap::new();
+  for _ in 0 .. input.u32()? {
+    let leaf = input.u32()?;
+    let material = Material {
+      albedo: input.vec3()?,
+      roughness: input.f32()?,
+      emissive: input.vec3()?,
+      flags: input.u32()?,
+      opacity: input.f32()?,
+      ior: input.f32()?,
+      tile: if header.has(Change::Tiles) { Some(input.u32()?).filter(|&tile| tile != u32::MAX) } else { None },
+    };
+    remap.insert(leaf, materials.register(&mut sdg, material));
+  }
+
+  for _ in 0 .. input.u32()? {
+    let idx = input.u32()?;
+    let mut node = BasicNode3d::default();
+    for child in &mut node {
+      let saved = input.u32()?;
+      *child = *remap.get(&saved).ok_or_else(|| invalid(format!("Node {idx} points at {saved} before it's defined")))?;
+    }
+    remap.insert(idx, sdg.insert_node(node));
+  }
+
+  let mut objects = Vec::new();
+  let mut taken = HashSet::new();
+  for saved in 0 .. input.u32()? {
+    let idx = if header.has(Change::Handles) { input.u32()? } else { saved } as usize;
+    if idx >= MAX_HANDLE || !taken.insert(idx) { return Err(invalid(format!("Object {idx} can't be there"))) }
+    let head = input.u32()?;
+    let head = *remap.get(&head).ok_or_else(|| invalid(format!("Object head {head} was never defined")))?;
+    let height = input.u32()?;
+    let (min_cell, max_cell) = (input.uvec3()?, input.uvec3()?);
+    if height > MAX_HEIGHT { return Err(invalid(format!("Object {idx} is {height} levels tall, past the limit of {MAX_HEIGHT}"))) }
+    if min_cell.cmpgt(max_cell).any() || max_cell.max_element() >= 1 << height {
+      return Err(invalid(format!("Object {idx}'s bounds {min_cell} to {max_cell} don't fit its grid")))
+    }
+    let pos = input.vec3()?;
+    let dag_ref = DagRef::new(sdg.get_root(head).map_err(|err| invalid(err.to_string()))?, height);
+    let mut object = VoxelObject::new(&sdg, dag_ref, min_cell, max_cell, pos);
+    object.pivot_offset = input.vec3()?;
+    object.rot = Quat::from_array([input.f32()?, input.f32()?, input.f32()?, input.f32()?]);
+    if header.has(Change::Origins) { object.origin = input.uvec3()?.as_ivec3() }
+    let dynamic = input.u32()? != 0;
+    let animation = if header.has(Change::Animations) && input.u32()? != 0 {
+      Some(Animation {
+        velocity: input.vec3()?,
+        spin: input.vec3()?,
+        period: Some(input.f32()?).filter(|&period| period > 0.0),
+        origin: (input.vec3()?, Quat::from_array([input.f32()?, input.f32()?, input.f32()?, input.f32()?])),
+        start_tick: u64::from_le_bytes(input.take(8)?.try_into().unwrap()),
+      })
+    } else { None };
+    objects.push((idx, object, dynamic, animation));
+  }
+
+  let mut game_data = GameData::from_parts(sdg, materials);
+  // Back under the handles they were saved with, which edits shared over the network refer to them by
+  for (idx, object, dynamic, animation) in objects {
+    game_data.add_object_at(idx, object, dynamic);
+    if animation.is_some() { game_data.animate(idx, animation) }
+  }
+  let camera = &mut game_data.camera;
+  camera.position = input.vec3()?;
+  if header.has(Change::CameraOrientation) {
+    camera.set_orientation(Quat::from_array([input.f32()?, input.f32()?, input.f32()?, input.f32()?]))
+  } else {
+    camera.set_angles(Vec3::new(input.f32()?, input.f32()?, 0.0))
+  }
+  camera.speed = input.f32()?;
+  game_data.sky.sun_dir = input.vec3()?;
+  game_data.sky.turbidity = input.f32()?;
+  game_data.tick = u64::from_le_bytes(input.take(8)?.try_into().unwrap());
+  game_data.time_of_day = if header.has(Change::TimeOfDay) {
+    TimeOfDay { hours: input.f32()?, speed: input.f32()?, paused: input.u32()? != 0 }
+  } else {
+    TimeOfDay::new(&game_data.sky)
+  };
+  if header.has(Change::Lights) {
+    for _ in 0 .. input.u32()? {
+      let (pos, color, radius, inset) = (input.vec3()?, input.vec3()?, input.f32()?, input.f32()?);
+      let kind = match input.u32()? {
+        0 => LightKind::Point,
+        1 => LightKind::Spot { dir: input.vec3()?, angle: input.f32()? },
+        kind => return Err(invalid(format!("Unknown light kind {kind}"))),
+      };
+      game_data.lights.placed.push(Light { pos, color, radius, kind, inset });
+    }
+  }
+  if header.has(Change::Blocks) {
+    for _ in 0 .. input.u32()? {
+      let name = input.string()?;
+      let leaf = input.u32()?;
+      let leaf = *remap.get(&leaf).filter(|&&leaf| game_data.sdg.is_leaf(leaf)).ok_or_else(|| invalid(format!("Block {name} is bound to {leaf}, which isn't a leaf")))?;
+      let properties = BlockProperties { solid: input.u32()? != 0, transparent: input.u32()? != 0, hardness: input.f32()? };
+      game_data.blocks.bind(&name, leaf, properties);
+    }
+  } else {
+    game_data.blocks.bind("air", EMPTY, BlockProperties::AIR)
+  }
+  Ok(game_data)
+}
+
+/// Little endian values appended to a buffer, the other half of Reader
+pub struct Writer(pub Vec<u8>);
+impl Writer {
+  pub fn u32(&mut self, value: u32) { self.0.extend_from_slice(&value.to_le_bytes()) }
+  pub fn f32(&mut self, value: f32) { self.0.extend_from_slice(&value.to_le_bytes()) }
+  pub fn uvec3(&mut self, value: UVec3) { for value in value.to_array() { self.u32(value) } }
+  pub fn vec3(&mut self, value: Vec3) { for value in value.to_array() { self.f32(value) } }
+  /// Its length in bytes, then the UTF-8
+  pub fn str(&mut self, value: &str) {
+    self.u32(value.len() as u32);
+    self.0.extend_from_slice(value.as_bytes());
+  }
+}
+
+/// Reads back what a Writer wrote, erroring rather than panicking when the bytes run out
+pub struct Reader<'a> {
+  bytes: &'a [u8],
+  at: usize,
+}
+impl<'a> Reader<'a> {
+  pub fn new(bytes: &'a [u8]) -> Self { Self { bytes, at: 0 } }
+
+  pub fn take(&mut self, count: usize) -> io::Result<&[u8]> {
+    let taken = self.bytes.get(self.at .. self.at + count)
+      .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated world file"))?;
+    self.at += count;
+    Ok(taken)
+  }
+  pub fn u32(&mut self) -> io::Result<u32> { Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap())) }
+  pub fn f32(&mut self) -> io::Result<f32> { Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap())) }
+  pub fn uvec3(&mut self) -> io::Result<UVec3> { Ok(UVec3::new(self.u32()?, self.u32()?, self.u32()?)) }
+  pub fn vec3(&mut self) -> io::Result<Vec3> { Ok(Vec3::new(self.f32()?, self.f32()?, self.f32()?)) }
+  pub fn string(&mut self) -> io::Result<String> {
+    let len = self.u32()? as usize;
+    String::from_utf8(self.take(len)?.to_vec()).map_err(|_| invalid("A name isn't valid UTF-8"))
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use glam::IVec3;
+
+  // A world file which gets as far as its one object, a single leaf height levels tall
+  fn one_object(height: u32, min: UVec3, max: UVec3) -> Vec<u8> {
+    let mut out = Writer(Vec::new());
+    Header::write(&mut out);
+    out.u32(1);
+    out.u32(0);
+    for value in [1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 0.0] { out.f32(value) }
+    out.u32(0);
+    for value in [1.0, 1.0] { out.f32(value) }
+    out.u32(u32::MAX);
+    out.u32(0);
+    out.u32(1);
+    for value in [0, 0, height] { out.u32(value) }
+    out.uvec3(min);
+    out.uvec3(max);
+    out.0
+  }
+
+  #[test]
+  fn bad_objects_are_refused() {
+    let error = |bytes: Vec<u8>| decode(&bytes).err().map(|err| (err.kind(), err.to_string()));
+    let (kind, msg) = error(one_object(MAX_HEIGHT + 1, UVec3::ZERO, UVec3::ZERO)).unwrap();
+    assert_eq!(kind, io::ErrorKind::InvalidData);
+    assert!(msg.contains("levels tall"), "{msg}");
+    assert!(error(one_object(u32::MAX, UVec3::ZERO, UVec3::ZERO)).unwrap().1.contains("levels tall"));
+    assert!(error(one_object(2, UVec3::ZERO, UVec3::splat(4))).unwrap().1.contains("don't fit"));
+    assert!(error(one_object(2, UVec3::splat(3), UVec3::ZERO)).unwrap().1.contains("don't fit"));
+    // Fine bounds get past the check, and only run out of file further on
+    assert_eq!(error(one_object(2, UVec3::ZERO, UVec3::splat(3))).unwrap().0, io::ErrorKind::UnexpectedEof);
+  }
+
+  // A world from version 1, before anything in Change: two materials, one node and two objects (the second dynamic),
+  // then the camera with only yaw and pitch. Written by hand to the layout described above encode
+  const WORLD_V1: &[u8] = include_bytes!("../fixtures/world_v1.bin");
+
+  // The leaves of every cell of a height 1 object
+  fn dense_object(game_data: &GameData, idx: usize) -> Vec<Index> {
+    let head = game_data.objects[idx].dag_ref.head;
+    (0 .. 8).map(|cell| game_data.sdg.sample(head, 1, UVec3::new(cell & 1, cell >> 1 & 1, cell >> 2)).unwrap().0).collect()
+  }
+
+  #[test]
+  fn version_1_worlds_still_load() {
+    let game_data = decode(WORLD_V1).unwrap();
+    let stone = game_data.sdg.leaves()[1];
+    assert_eq!(game_data.materials.get(stone).roughness, 0.8);
+    assert_eq!(game_data.materials.get(stone).tile, None);
+
+    // Handles went 0 up, and nothing had an origin or animation
+    assert_eq!(game_data.objects.len(), 2);
+    let (corners, single) = (&game_data.objects[0], &game_data.objects[1]);
+    assert_eq!((corners.dag_ref.height, corners.max_cell, corners.pos, corners.origin), (1, UVec3::ONE, Vec3::new(2.0, 0.0, -3.0), IVec3::ZERO));
+    assert_eq!(dense_object(&game_data, 0), [stone, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, stone]);
+    assert_eq!((single.dag_ref.head, single.dag_ref.height), (stone, 0));
+    assert!(corners.animation.is_none() && single.animation.is_none());
+    assert!(game_data.physics.is_dynamic(single.physics.unwrap().body));
+
+    let camera = &game_data.camera;
+    assert_eq!((camera.position, camera.speed, game_data.tick), (Vec3::new(1.0, 2.0, 3.0), 10.0, 42));
+    // Yaw and pitch of 0, which look down +x
+    assert!(camera.forward().abs_diff_eq(Vec3::X, 1e-6), "{}", camera.forward());
+    assert_eq!((game_data.sky.sun_dir, game_data.sky.turbidity), (Vec3::Y, 2.5));
+    assert!(game_data.lights.placed.is_empty());
+    assert_eq!(game_data.blocks.leaf("air"), Some(EMPTY));
+
+    // Saved again it comes out in the current layout, which reads back the same
+    let again = decode(&encode(&game_data)).unwrap();
+    assert_eq!(again.objects.len(), 2);
+    assert_eq!(dense_object(&again, 0), dense_object(&game_data, 0));
+    assert_eq!((again.objects[1].dag_ref.head, again.tick), (stone, 42));
+    assert!(again.camera.forward().abs_diff_eq(Vec3::X, 1e-6));
+    assert_eq!(again.blocks.all().len(), game_data.blocks.all().len());
+  }
+}
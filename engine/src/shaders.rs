@@ -0,0 +1,170 @@
+use std::time::{Duration, Instant, SystemTime};
+
+// Every shader lives here so the tests below see exactly what the pipelines are built from.
+// Anything marching rays gets traversal.wgsl appended, keep FILES in sync.
+pub const DDA: &str = concat!(include_str!("shaders/dda.wgsl"), include_str!("shaders/traversal.wgsl"));
+pub const BEAM: &str = concat!(include_str!("shaders/beam.wgsl"), include_str!("shaders/traversal.wgsl"));
+pub const LIGHTING: &str = concat!(include_str!("shaders/lighting.wgsl"), include_str!("shaders/traversal.wgsl"));
+pub const TEMPORAL: &str = include_str!("shaders/temporal.wgsl");
+pub const UPSCALE: &str = include_str!("shaders/upscale.wgsl");
+pub const LINES: &str = include_str!("shaders/lines.wgsl");
+
+/// (label, source) for every shader module the engine creates.
+/// Anything that assembles a shader at runtime should add each variant it can produce here.
+#[cfg(test)]
+pub fn all() -> Vec<(&'static str, String)> {
+  vec![
+    ("dda", DDA.into()),
+    ("beam", BEAM.into()),
+    ("lighting", LIGHTING.into()),
+    ("temporal", TEMPORAL.into()),
+    ("upscale", UPSCALE.into()),
+    ("lines", LINES.into()),
+  ]
+}
+
+pub fn create(device: &wgpu::Device, label: &str, source: &str) -> wgpu::ShaderModule {
+  device.create_shader_module(wgpu::ShaderModuleDescriptor {
+    label: Some(label),
+    source: wgpu::ShaderSource::Wgsl(source.into()),
+  })
+}
+
+/// Runs build with validation errors captured instead of panicking, for rebuilding pipelines from untrusted source
+pub fn try_build<T>(device: &wgpu::Device, build: impl FnOnce() -> T) -> Result<T, wgpu::Error> {
+  device.push_error_scope(wgpu::ErrorFilter::Validation);
+  let built = build();
+  match pollster::block_on(device.pop_error_scope()) {
+    Some(err) => Err(err),
+    None => Ok(built),
+  }
+}
+
+// (label, files it's concatenated from) for the watcher to reassemble
+const FILES: [(&str, &[&str]); 6] = [
+  ("dda", &["dda", "traversal"]),
+  ("beam", &["beam", "traversal"]),
+  ("lighting", &["lighting", "traversal"]),
+  ("temporal", &["temporal"]),
+  ("upscale", &["upscale"]),
+  ("lines", &["lines"]),
+];
+
+// Where the shaders are on disk, hot reloading only makes sense from a checkout
+const SHADER_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders");
+// How often the watcher touches the filesystem
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Polls the shader directory for edits so pipelines can be rebuilt without recompiling
+pub struct ShaderWatcher {
+  labels: Vec<&'static str>,
+  // (file, last modified) for every file the watched shaders are built from, file.wgsl is on disk
+  files: Vec<(&'static str, Option<SystemTime>)>,
+  last_check: Instant,
+}
+impl ShaderWatcher {
+  pub fn new(labels: &[&'static str]) -> Self {
+    let mut files: Vec<_> = labels.iter().flat_map(|&label| Self::files_of(label)).copied().collect();
+    files.sort();
+    files.dedup();
+    let files = files.into_iter().map(|file| (file, Self::modified(file))).collect();
+    Self { labels: labels.to_vec(), files, last_check: Instant::now() }
+  }
+
+  fn files_of(label: &str) -> &'static [&'static str] {
+    FILES.iter().find(|(name, _)| *name == label).unwrap_or_else(|| panic!("No files listed for shader {label}")).1
+  }
+
+  fn path(file: &str) -> String { format!("{SHADER_DIR}/{file}.wgsl") }
+
+  fn modified(file: &str) -> Option<SystemTime> {
+    std::fs::metadata(Self::path(file)).and_then(|meta| meta.modified()).ok()
+  }
+
+  /// (label, new source) for every shader with a file saved since the last call
+  pub fn changed(&mut self) -> Vec<(&'static str, String)> {
+    if self.last_check.elapsed() < WATCH_INTERVAL { return Vec::new() }
+    self.last_check = Instant::now();
+    let mut saved = Vec::new();
+    for (file, last_modified) in &mut self.files {
+      let modified = Self::modified(file);
+      if modified == *last_modified { continue }
+      *last_modified = modified;
+      saved.push(*file);
+    }
+    let mut changed = Vec::new();
+    for &label in &self.labels {
+      let files = Self::files_of(label);
+      if !files.iter().any(|file| saved.contains(file)) { continue }
+      match files.iter().map(|file| std::fs::read_to_string(Self::path(file))).collect::<Result<String, _>>() {
+        Ok(source) => changed.push((label, source)),
+        Err(err) => tracing::warn!("Couldn't read the files of {label}: {err}"),
+      }
+    }
+    changed
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use naga::valid::{Capabilities, ValidationFlags, Validator};
+
+  #[test]
+  fn shaders_validate() {
+    let mut failures = Vec::new();
+    for (label, source) in super::all() {
+      let module = match naga::front::wgsl::parse_str(&source) {
+        Ok(module) => module,
+        Err(err) => { failures.push(err.emit_to_string_with_path(&source, label)); continue }
+      };
+      if let Err(err) = Validator::new(ValidationFlags::all(), Capabilities::default()).validate(&module) {
+        failures.push(err.emit_to_string_with_path(&source, label));
+      }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+  }
+
+  // The watcher reassembles shaders from FILES, so it has to agree with the consts
+  #[test]
+  fn files_match_consts() {
+    for (label, source) in super::all() {
+      let assembled: String = super::ShaderWatcher::files_of(label).iter()
+        .map(|file| std::fs::read_to_string(super::ShaderWatcher::path(file)).unwrap())
+        .collect();
+      assert!(assembled == source, "FILES for {label} don't match what it's built from");
+    }
+  }
+
+  // ObjData is uploaded as is, so it has to lay out just like the shaders' VoxelObject
+  #[test]
+  fn object_layout_matches() {
+    let (_, source) = super::all().into_iter().find(|(label, _)| *label == "dda").unwrap();
+    let module = naga::front::wgsl::parse_str(&source).unwrap();
+    let span = module.types.iter().find_map(|(_, ty)| match ty.inner {
+      naga::TypeInner::Struct { span, .. } if ty.name.as_deref() == Some("VoxelObject") => Some(span),
+      _ => None,
+    }).expect("dda has no VoxelObject");
+    assert_eq!(std::mem::size_of::<crate::wgpu_buffers::ObjData>(), 224);
+    assert_eq!(span as usize, std::mem::size_of::<crate::wgpu_buffers::ObjData>());
+  }
+
+  // Catches renamed entry points, which naga is happy with but pipeline creation isn't
+  #[test]
+  fn entry_points_exist() {
+    let expected = [
+      ("dda", &["main"][..]),
+      ("beam", &["main"]),
+      ("lighting", &["main"]),
+      ("temporal", &["main"]),
+      ("upscale", &["vs_main", "fs_main"]),
+      ("lines", &["vs_main", "fs_main"]),
+    ];
+    for (label, source) in super::all() {
+      let module = naga::front::wgsl::parse_str(&source).unwrap();
+      let (_, names) = expected.iter().find(|(name, _)| *name == label).unwrap_or_else(|| panic!("No expected entry points for {label}"));
+      for name in *names {
+        assert!(module.entry_points.iter().any(|entry| entry.name == *name), "{label} is missing entry point {name}");
+      }
+    }
+  }
+}
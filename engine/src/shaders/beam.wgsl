@@ -0,0 +1,131 @@
+// A coarse march ahead of the DDA pass, one ray down the middle of each BEAM_TILE x BEAM_TILE tile of pixels.
+// It treats the ray as a beam as wide as the tile's rays have spread, and finds how far it gets before anything
+// solid could be inside it. Every ray in the tile has nothing to find before there, so the DDA pass starts them there.
+const WG_SIZE = 8;
+// Matches BEAM_TILE in dda.wgsl and wgpu_ctx.rs
+const BEAM_TILE = 8u;
+// Blocks are never finer than this many levels above the cells, even near the camera where the beam's thin
+const MIN_HEIGHT = 2u;
+
+// How far along their rays (in the DDA's t) the tile's pixels can start
+@group(0) @binding(0)
+var beam_tex: texture_storage_2d<r32float, write>;
+
+// Matches dda.wgsl
+struct Camera {
+  pos: vec3<f32>,
+  rot: mat3x3<f32>,
+  aspect_ratio: f32,
+  tan_fov: f32,
+  obj_count: u32,
+  max_distance: f32,
+  max_steps: u32,
+  detail: u32,
+  time: f32,
+  ortho_height: f32,
+  debug_view: u32,
+  lod_distance: f32,
+  lod_falloff: f32,
+  all_obj_count: u32,
+  resolution: vec2<u32>,
+  jitter: vec2<f32>,
+  prev_view_proj: mat4x4<f32>,
+}
+@group(0) @binding(1)
+var<uniform> cam: Camera;
+
+@group(0) @binding(2)
+var<storage, read> voxels: array<u32>;
+
+@group(0) @binding(3)
+var<storage, read> objects: array<VoxelObject>;
+
+// Unused, but traversal.wgsl needs it declared
+@group(0) @binding(4)
+var<storage, read> materials: Materials;
+
+@compute @workgroup_size(WG_SIZE, WG_SIZE)
+fn main(@builtin(global_invocation_id) gid: vec3<u32>) {
+  let tiles = textureDimensions(beam_tex);
+  if gid.x >= tiles.x || gid.y >= tiles.y { return; }
+  // The first and last pixels of the tile, the ones on the bottom and right edges may be cut short
+  let first = vec2<f32>(gid.xy * BEAM_TILE);
+  let last = min(first + f32(BEAM_TILE - 1u), vec2<f32>(cam.resolution) - 1.0);
+  // Same uv as dda.wgsl (jitter shifts every pixel alike), for the tile's middle and how far its furthest pixel is from there
+  let scale = 2.0 * vec2(cam.aspect_ratio, 1.0);
+  let center = ((first + last) * 0.5 + 0.5 + cam.jitter) / vec2<f32>(cam.resolution) * scale - scale * 0.5;
+  let spread = length((last - first) * 0.5 / vec2<f32>(cam.resolution) * scale);
+
+  // Every ray's cam_dir has a z of 1, so at any t they're all on the same plane and the tile's rays are within
+  // radius + rate * t of this one
+  var origin = cam.pos;
+  var cam_dir = vec3(center * cam.tan_fov, 1.0);
+  var radius = 0.0;
+  var rate = spread * cam.tan_fov;
+  if cam.ortho_height > 0.0 {
+    origin += cam.rot * vec3(center * cam.ortho_height, 0.0);
+    cam_dir = vec3(0.0, 0.0, 1.0);
+    radius = spread * cam.ortho_height;
+    rate = 0.0;
+  }
+  let world_dir = cam.rot * cam_dir;
+
+  var start = cam.max_distance;
+  var steps = 0u;
+  for (var idx = 0u; idx < cam.obj_count; idx++) {
+    start = beam_object(idx, origin, world_dir, radius, rate, start, &steps);
+  }
+  textureStore(beam_tex, vec2<i32>(gid.xy), vec4(start, 0.0, 0.0, 0.0));
+}
+
+// How far the beam gets through obj before something solid might be within radius + rate * t of it, max_t if it
+// never does. Steps through a grid offset half a block from the object's, as all the points within half a block
+// of a point in one of its cells lie in the 8 blocks around that cell's middle, which is the corner they share.
+// Blocks are sized to stay at least twice as wide as the beam, and only stop it if there's anything under them
+fn beam_object(obj: u32, origin: vec3<f32>, world_dir: vec3<f32>, radius: f32, rate: f32, max_t: f32, steps: ptr<function, u32>) -> f32 {
+  let pos = (objects[obj].inv_transform * vec4(origin, 1.0)).xyz;
+  let dir = (objects[obj].inv_transform * vec4(world_dir, 0.0)).xyz;
+  let inv_dir = 1.0 / dir;
+  let min_cell = vec3<i32>(objects[obj].min_cell);
+  let max_cell = min_cell + vec3<i32>(objects[obj].extent);
+  // Widened by as far as the beam ever spreads, so rays at its edge reaching the object first aren't missed
+  let widest = radius + rate * max_t;
+  let t1 = (vec3<f32>(min_cell) - widest - pos) * inv_dir;
+  let t2 = (vec3<f32>(max_cell) + widest - pos) * inv_dir;
+  let near = min(t1, t2);
+  let far = max(t1, t2);
+  let t_enter = max(max(max(near.x, near.y), near.z), 0.0);
+  let t_exit = min(min(min(far.x, far.y), far.z), max_t);
+  if t_enter >= t_exit { return max_t; }
+  // Crossing a cell of the offset grid takes at most its diagonal, so the beam grows by up to growth of a block
+  // in one. Past 1 it could outgrow any block, which only happens with tiles covering much of the screen
+  let growth = 2.0 * sqrt(3.0) * rate / length(dir);
+  if growth >= 1.0 { return t_enter; }
+
+  var t = t_enter;
+  // Where the last cell without anything around it was entered, a cell short of what it found in case it's right there
+  var clear = t_enter;
+  while t < t_exit {
+    if *steps >= cam.max_steps { return clear; }
+    *steps += 1u;
+    let wanted = 2.0 * (radius + rate * t) / (1.0 - growth);
+    let height = max(u32(ceil(log2(max(wanted, 1.0)))), MIN_HEIGHT);
+    // Wider than the whole object, so whatever it holds is in reach
+    if height > objects[obj].height { return clear; }
+    let size = f32(1u << height);
+    // The lower of the 2 blocks along each axis within half a block of the ray
+    let low = vec3<i32>(floor((pos + dir * t) / size - 0.5));
+    for (var i = 0; i < 8; i++) {
+      let block = (low + vec3(i & 1, (i >> 1) & 1, i >> 2)) * i32(1u << height);
+      // Nothing's solid past the object's cells, and vox_read would wrap around outside its grid
+      if any(block + i32(1u << height) <= min_cell) || any(block >= max_cell) { continue; }
+      if vox_read(obj, block, height)[0] != 0u { return clear; }
+    }
+    clear = t;
+    // On to the next cell of the offset grid, whose walls are in the middle of the blocks
+    let wall = (vec3<f32>(low) + select(vec3(0.5), vec3(1.5), dir > vec3(0.0))) * size;
+    let t_wall = (wall - pos) * inv_dir;
+    t = max(min(min(t_wall.x, t_wall.y), t_wall.z), t) + 0.001;
+  }
+  return max_t;
+}
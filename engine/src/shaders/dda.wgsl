@@ -1,36 +1,105 @@
 const WG_SIZE = 8;
-const SENTINEL = -314159.0;
-const OBJECTS = 1;
+// Written as the depth of rays which ran out of distance or steps before finding anything
+const FAR_MISS = -1.0;
+// Detail fades in between these distances, in world units
+const DETAIL_NEAR = 24.0;
+const DETAIL_FAR = 48.0;
+// How far into a cell the detail can push a hit, in cells
+const DETAIL_DEPTH = 0.4;
+// Translucent surfaces a ray can pass through before whatever it hits next counts as opaque
+const MAX_LAYERS = 4u;
+// Size and speed of the ripples on water, and how far they tilt its surface
+const WAVE_SCALE = 0.6;
+const WAVE_SPEED = 0.8;
+const WAVE_STRENGTH = 0.25;
+// Matches DebugView in wgpu_ctx.rs
+const DEBUG_STEPS = 1u;
+const DEBUG_NODES = 2u;
+// Pixels across the nodes view's lines
+const NODE_EDGE_PIXELS = 1.0;
 
-// [OctNorm1, OctNorm2, Z, bitcasted BlockType] 
+// [OctNorm1, OctNorm2, Z (FAR_MISS if the march gave up), leaf]
+// On a miss the normal is the direction the ray left in instead, for the sky
 @group(0) @binding(0)
-var output_tex: texture_storage_2d<rgba16float, write>;
+var output_tex: texture_storage_2d<rgba32float, write>;
 
 struct Camera {
   pos: vec3<f32>,
   rot: mat3x3<f32>,
   aspect_ratio: f32,
   tan_fov: f32,
+  // Objects on screen, which come first in objects and are all camera rays need to march
+  obj_count: u32,
+  max_distance: f32,
+  max_steps: u32,
+  // Nonzero to displace distant hits on large uniform nodes, see detail_offset
+  detail: u32,
+  // Seconds of simulation, animates the water
+  time: f32,
+  // Half the view's height in world units when orthographic, 0 for perspective
+  ortho_height: f32,
+  // One of the DEBUG_ consts
+  debug_view: u32,
+  // See Lod in traversal.wgsl
+  lod_distance: f32,
+  lod_falloff: f32,
+  // Every object, on screen or not, for rays that can head off screen
+  all_obj_count: u32,
+  // Pixels across the output, for the beam pass
+  resolution: vec2<u32>,
+  // Pixels every ray is offset by this frame, for the temporal pass to antialias with
+  jitter: vec2<f32>,
+  // Only read by the temporal pass
+  prev_view_proj: mat4x4<f32>,
 }
 @group(0) @binding(1)
 var<uniform> cam: Camera;
 
-struct VoxelNode { children: array<u32, 8> }
 @group(0) @binding(2)
-var<storage, read> voxels: array<VoxelNode>;
+var<storage, read> voxels: array<u32>;
 
-// I only need linear transform, just store that 3x3
-struct VoxelObject {
+@group(0) @binding(3)
+var<storage, read> objects: array<VoxelObject>;
+
+// [bitcasted world hit position, object + 1 << 16 | u << 8 | v], zeroed on a miss
+@group(0) @binding(4)
+var hit_tex: texture_storage_2d<rgba32uint, write>;
+
+@group(0) @binding(5)
+var<storage, read> materials: Materials;
+
+// RGB: how much of the light from the hit makes it back through translucent cells on the way
+@group(0) @binding(6)
+// A: how much of the pixel is water reflecting instead, see water_tex
+var tint_tex: texture_storage_2d<rgba8unorm, write>;
+
+// Where the first water surface was entered, for the lighting pass to trace a reflection from.
+// [bitcasted world position, pack2x16unorm(oct encoded reflected direction)], only meaningful when tint's A isn't 0
+@group(0) @binding(7)
+var water_tex: texture_storage_2d<rgba32uint, write>;
+
+// How far along their rays every pixel in each BEAM_TILE x BEAM_TILE tile can start, see beam.wgsl
+@group(0) @binding(8)
+var beam_tex: texture_2d<f32>;
+// Matches beam.wgsl and wgpu_ctx.rs
+const BEAM_TILE = 8u;
+
+// The first surface under the center pixel, read back for the crosshair to pick with. Matches PickData
+struct Pick {
+  // Signed, see VoxelObject::origin
+  cell: vec3<i32>,
+  // Slot in objects + 1, 0 if nothing was hit
+  object: u32,
+  // Out of the face that was hit, in the object's grid
+  normal: vec3<i32>,
+  leaf: u32,
   pos: vec3<f32>,
-  min_cell: vec3<u32>,
-  extent: vec3<u32>,
-  transform: mat4x4<f32>,
-  inv_transform: mat4x4<f32>,
-  head: u32,
-  height: u32,
+  // Distance from the camera
+  t: f32,
+  uv: vec2<f32>,
 }
-@group(0) @binding(3)
-var<storage, read> objects: array<VoxelObject, OBJECTS>;
+@group(0) @binding(9)
+var<storage, read_write> pick: Pick;
 
 @compute @workgroup_size(WG_SIZE, WG_SIZE)
 fn main(@builtin(global_invocation_id) gid: vec3<u32>) {
@@ -38,129 +107,212 @@ fn main(@builtin(global_invocation_id) gid: vec3<u32>) {
   // We do a little padding so we can fit into the workgroups correctly
   if gid.x >= resolution.x || gid.y >= resolution.y { return; }
   // Transform from <0,1> to <-1, 1>, then scale by aspect_ratio for proper dimensioning
-  let uv = ((vec2<f32>(gid.xy) + 0.5) / vec2<f32>(resolution.xy) - 0.5) * 2 * vec2(cam.aspect_ratio, 1.0);
+  let uv = ((vec2<f32>(gid.xy) + 0.5 + cam.jitter) / vec2<f32>(resolution.xy) - 0.5) * 2 * vec2(cam.aspect_ratio, 1.0);
 
-  let cam_dir = vec3(uv * vec2(cam.tan_fov), 1.0);
+  var origin = cam.pos;
+  var cam_dir = vec3(uv * vec2(cam.tan_fov), 1.0);
+  if cam.ortho_height > 0.0 {
+    // Parallel rays, spread across the plane through the camera instead
+    origin += cam.rot * vec3(uv * cam.ortho_height, 0.0);
+    cam_dir = vec3(0.0, 0.0, 1.0);
+  }
   let world_dir = cam.rot * cam_dir;
-  let ray = march_objects(world_dir);
+  let start = textureLoad(beam_tex, gid.xy / BEAM_TILE, 0).r;
+  if cam.debug_view == DEBUG_NODES {
+    // A pixel's footprint grows with t while perspective, t counting from where the march starts
+    let pixel = 2.0 * NODE_EDGE_PIXELS / f32(resolution.y);
+    let width = select(vec2(pixel * cam.tan_fov * start, pixel * cam.tan_fov), vec2(pixel * cam.ortho_height, 0.0), cam.ortho_height > 0.0);
+    node_trace = NodeTrace(true, width, vec3(0.0), 1e30);
+  }
+  let through = march_translucent(origin, world_dir, start);
+  var ray = through.ray;
+  if cam.detail != 0 && ray.voxel[0] != 0 { ray.t += detail_offset(through.origin + through.dir * ray.t, through.dir, through.travelled + ray.t, ray.voxel[1]); }
 
   let oct_normal = oct_encode(ray.global_normal);
-  let result = vec4(oct_normal.x, oct_normal.y, (ray.t * cam_dir).z, f32(ray.voxel[0]));
+  // Depth is the length of the whole bent path, which is what the fog and sorting care about
+  var result = vec4(oct_normal.x, oct_normal.y, (through.travelled + ray.t) * cam_dir.z, f32(ray.voxel[0]));
+  if ray.voxel[0] == 0 {
+    let exit_dir = oct_encode(normalize(through.dir));
+    result = vec4(exit_dir.x, exit_dir.y, select(0.0, FAR_MISS, ray.far), 0.0);
+  }
 
   textureStore(output_tex, vec2<i32>(gid.xy), result);
-}
+  if cam.debug_view == DEBUG_STEPS {
+    // Handed to the lighting pass in place of the tint, which passes it straight through
+    textureStore(tint_tex, vec2<i32>(gid.xy), vec4(heat(through.steps), 0.0));
+  } else if cam.debug_view == DEBUG_NODES {
+    // The nearest edge passed, otherwise dimly shaded so there's something to see the lines against
+    var color = vec3(0.05);
+    if ray.voxel[0] != 0 { color = vec3(0.1 + 0.2 * max(dot(ray.global_normal, normalize(vec3(0.3, 1.0, 0.5))), 0.0)); }
+    if node_trace.t < 1e30 { color = node_trace.color; }
+    textureStore(tint_tex, vec2<i32>(gid.xy), vec4(color, 0.0));
+  } else {
+    textureStore(tint_tex, vec2<i32>(gid.xy), vec4(through.tint, through.reflectance));
+  }
+  let reflected = pack2x16unorm(oct_encode(through.reflect_dir));
+  textureStore(water_tex, vec2<i32>(gid.xy), vec4(bitcast<vec3<u32>>(through.reflect_origin), reflected));
 
-struct Position {
-  cell: vec3<i32>,
-  offset: vec3<f32>,
+  var hit = vec4(0u);
+  if ray.voxel[0] != 0 {
+    let world_pos = through.origin + through.dir * ray.t;
+    let uv = vec2<u32>(clamp(face_uv(ray.pos.offset, ray.local_normal), vec2(0.0), vec2(1.0)) * 255.0);
+    hit = vec4(bitcast<vec3<u32>>(world_pos), (ray.obj + 1) << 16 | uv.x << 8 | uv.y);
+  }
+  textureStore(hit_tex, vec2<i32>(gid.xy), hit);
+
+  if all(gid.xy == resolution / 2u) {
+    let first = through.first;
+    pick = Pick(vec3(0), 0u, vec3(0), 0u, vec3(0.0), 0.0, vec2(0.0));
+    if first.hit {
+      let normal = vec3<i32>(select(vec3(0.0), -sign(first.dir), first.local_normal));
+      let t = start + first.t;
+      let uv = clamp(face_uv(first.pos.offset, first.local_normal), vec2(0.0), vec2(1.0));
+      pick = Pick(first.pos.cell + objects[first.obj].origin, first.obj + 1, normal, first.voxel[0], origin + world_dir * t, t * length(world_dir), uv);
+    }
+  }
 }
-struct Ray {
-  pos: Position,
+
+struct Translucent {
+  // The last segment, from origin along dir
+  ray: Ray,
+  // The first segment, ending on whatever the ray hit first even if it carried on through it
+  first: Ray,
+  origin: vec3<f32>,
   dir: vec3<f32>,
-  inv_dir: vec3<f32>,
-  local_normal: vec3<bool>,
-  global_normal: vec3<f32>,
-  voxel: vec2<u32>,
-  t: f32,
-  alive: bool,
-}
-fn move_ray(ray: ptr<function, Ray>, timestep: f32) {
-  let delta = (*ray).dir * timestep;
-  (*ray).pos.cell += vec3<i32>(floor(delta));
-  (*ray).pos.offset += fract(delta) + sign((*ray).dir) * 0.0001;
-  (*ray).pos.cell += vec3<i32>(floor( (*ray).pos.offset ));
-  (*ray).pos.offset = fract( (*ray).pos.offset );
-  (*ray).t += timestep;
+  // Ray t spent on the segments before it
+  travelled: f32,
+  tint: vec3<f32>,
+  // Share of the light coming off the first water surface reflected from reflect_origin along reflect_dir
+  reflectance: f32,
+  reflect_origin: vec3<f32>,
+  reflect_dir: vec3<f32>,
+  // DDA steps over every segment
+  steps: u32,
 }
 
-fn march_objects(world_dir: vec3<f32>) -> Ray {
-  var rays: array<Ray, OBJECTS>;
-  let ONE = 1.0; let INF = ONE / 0.0;
-  var best_ray = Ray(); best_ray.t = INF;
-  var ray_obj_idx = 0u;
-
-  for (var idx = 0u; idx < OBJECTS; idx += 1) {
-    var ray = new_ray(world_dir, idx);
-    if !ray.alive { continue; }
-    ray_obj_idx = idx;
-    ray.voxel = vox_read(objects[idx].head, objects[idx].height, ray.pos.cell);
-    while ray.voxel[0] == 0 {
-      dda_step(&ray);
-      // If we've stepped outside of the object bounds
-      // We bitcast pos.cell to u32s to avoid < 0 branching via underflow
-      if !all(bitcast<vec3<u32>>(ray.pos.cell) - objects[idx].min_cell < objects[idx].extent) { break; }
-      ray.voxel = vox_read(objects[idx].head, objects[idx].height, ray.pos.cell); // Sample current position
+// Marches from the camera, carrying on through translucent cells until something opaque (or nothing) is hit.
+// Each translucent leaf the ray enters tints what's behind it by its albedo, and bends the ray at the
+// boundaries when the index of refraction changes. Dir keeps its length so every segment's t means the same.
+// Water gets rippling normals on its top and bottom, and the first water surface reflects some light too.
+// The march begins start along the ray, which the beam pass found nothing could be hit before.
+fn march_translucent(origin: vec3<f32>, world_dir: vec3<f32>, start: f32) -> Translucent {
+  var through = Translucent(Ray(), Ray(), origin + world_dir * start, world_dir, start, vec3(1.0), 0.0, vec3(0.0), vec3(0.0), 0u);
+  let dir_length = length(world_dir);
+  var medium = AIR;
+  var medium_ior = 1.0;
+  for (var layer = 0u; ; layer++) {
+    let max_t = cam.max_distance - through.travelled;
+    let lod = Lod(cam.lod_distance, cam.lod_falloff, through.travelled, 1.0);
+    // Bent rays can leave the view
+    let obj_count = select(cam.all_obj_count, cam.obj_count, layer == 0u);
+    through.ray = march_objects(through.origin, through.dir, obj_count, max_t, cam.max_steps, medium, lod);
+    through.steps += through.ray.steps;
+    if layer == 0u {
+      through.first = through.ray;
+      // Later segments' t start over, and the first is what the view is about anyway
+      node_trace.on = false;
+    }
+    if !through.ray.hit || layer == MAX_LAYERS { break; }
+    let leaf = through.ray.voxel[0];
+    let material = leaf_material(leaf);
+    // EMPTY here means the ray just left medium
+    if leaf != 0 && (material.flags & FLAG_TRANSLUCENT) == 0 { break; }
+    var ior = 1.0;
+    if leaf != 0 {
+      ior = material.ior;
+      through.tint *= mix(vec3(1.0), material.albedo, material.opacity);
     }
-    if ray.t < best_ray.t && ray.voxel[0] != 0 { best_ray = ray; } 
-  }
 
-  let linear = mat3x3<f32>(objects[ray_obj_idx].transform[0].xyz,
-                           objects[ray_obj_idx].transform[1].xyz,
-                           objects[ray_obj_idx].transform[2].xyz);
-  let local_float_normal = vec3<f32>(best_ray.local_normal) * sign(best_ray.inv_dir) * vec3(1.0, -1.0, 1.0);
-  best_ray.global_normal = normalize(linear * local_float_normal);
-  return best_ray;
+    through.origin += through.dir * through.ray.t;
+    through.travelled += through.ray.t;
+    var normal = faceForward(through.ray.global_normal, through.dir, through.ray.global_normal);
+    let entering_water = (material.flags & FLAG_WATER) != 0;
+    // Either side of the boundary being water ripples it
+    let water = entering_water || (leaf_material(medium.y).flags & FLAG_WATER) != 0;
+    if water && abs(normal.y) > 0.5 { normal = ripple(through.origin, normal); }
+    if entering_water && through.reflectance == 0.0 {
+      through.reflectance = fresnel(through.dir / dir_length, normal, medium_ior, ior);
+      through.reflect_origin = through.origin;
+      through.reflect_dir = reflect(through.dir / dir_length, normal);
+      through.tint *= 1.0 - through.reflectance;
+    }
+    let bent = refract(through.dir / dir_length, normal, medium_ior / ior);
+    // Total internal reflection comes back as zero, carrying straight on is close enough
+    if any(bent != vec3(0.0)) { through.dir = bent * dir_length; }
+    // Nudged past the boundary so the next march starts inside the new medium
+    through.origin += through.dir * 0.001;
+    medium = vec2(through.ray.obj, leaf);
+    medium_ior = ior;
+  }
+  return through;
 }
 
-fn new_ray(world_dir: vec3<f32>, obj: u32) -> Ray {
-  var ray = Ray();
-  let pos_f32 = (objects[obj].inv_transform * vec4(cam.pos, 1.0)).xyz;
-  ray.pos = Position( vec3<i32>(floor(pos_f32)), fract(pos_f32));
-  ray.dir = (objects[obj].inv_transform * vec4(world_dir, 0.0)).xyz;
-  ray.inv_dir = 1.0 / ray.dir;
-  let intersection = aabb_intersect(pos_f32, ray.inv_dir, objects[obj].min_cell, objects[obj].extent);
-  ray.alive = intersection.t != SENTINEL;
-  ray.local_normal = intersection.normal;
-  move_ray(&ray, max(0.0, intersection.t));
-  return ray;
+// Black through blue, green, yellow and red to white, on a log scale so both cheap and runaway rays stand out.
+// White is max_steps, which only rays passing through translucent cells can go past.
+fn heat(steps: u32) -> vec3<f32> {
+  let x = clamp(log2(f32(steps) + 1.0) / log2(f32(cam.max_steps) + 1.0), 0.0, 1.0) * 5.0;
+  let ramp = array(vec3(0.0), vec3(0.0, 0.1, 0.8), vec3(0.0, 0.75, 0.2), vec3(1.0, 0.9, 0.0), vec3(1.0, 0.1, 0.0), vec3(1.0));
+  let i = min(u32(x), 4u);
+  return mix(ramp[i], ramp[i + 1], x - f32(i));
 }
 
-fn dda_step(ray: ptr<function, Ray>) {
-  // Sparse marching
-  let neg_wall = (*ray).pos.cell & vec3(~0i << (*ray).voxel[1] );
-  let pos_wall = neg_wall + (1i << (*ray).voxel[1] );
-  let next_wall = select(pos_wall, neg_wall, (*ray).dir < vec3(0.0));
-  // Next position
-  let t_wall = ( vec3<f32>( next_wall - (*ray).pos.cell ) - (*ray).pos.offset ) * (*ray).inv_dir;
-  let t_step = min(min(t_wall.x, t_wall.y), t_wall.z);
-  move_ray(ray, t_step);
-  (*ray).local_normal = t_wall == vec3(t_step);
+// Tilts a horizontal water normal by the slope of some drifting noise
+fn ripple(pos: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+  let p = vec3(pos.xz * WAVE_SCALE, cam.time * WAVE_SPEED);
+  let step = 0.1;
+  let height = value_noise(p);
+  let slope = vec2(value_noise(p + vec3(step, 0.0, 0.0)) - height, value_noise(p + vec3(0.0, step, 0.0)) - height) / step;
+  return normalize(normal - vec3(slope.x, 0.0, slope.y) * WAVE_STRENGTH * sign(normal.y));
 }
 
-fn vox_read(head: u32, height: u32, cell: vec3<i32>) -> vec2<u32> {
-  var cur_idx = head;
-  var cur_height = height;
-  while cur_height != 0 {
-    cur_height -= 1;
-    let child = cell >> vec3<u32>(cur_height) & vec3<i32>(1);
-    let next_idx = voxels[cur_idx].children[child.z << 2 | child.y << 1 | child.x];
-    if next_idx == cur_idx { return vec2(cur_idx, cur_height + 1); }
-    cur_idx = next_idx;
-  }
-  return vec2<u32>(cur_idx, cur_height);
+// Schlick's approximation of how much light reflects off a boundary between two iors, dir and normal facing each other
+fn fresnel(dir: vec3<f32>, normal: vec3<f32>, from_ior: f32, to_ior: f32) -> f32 {
+  let r0 = pow((from_ior - to_ior) / (from_ior + to_ior), 2.0);
+  let cos_theta = clamp(-dot(dir, normal), 0.0, 1.0);
+  return r0 + (1.0 - r0) * pow(1.0 - cos_theta, 5.0);
 }
 
+// How much further along the ray a hit lands once surface detail is added. Only nodes merged
+// from many cells qualify, so small features keep their shape, and only far away where nobody can
+// see it doesn't match the collision. The push is along the ray, which stays inside a node this big.
+// t is along the whole path from the camera, dir is the last segment's.
+fn detail_offset(hit: vec3<f32>, dir: vec3<f32>, t: f32, node_height: u32) -> f32 {
+  let dir_length = length(dir);
+  let distance = t * dir_length;
+  if node_height == 0 || distance < DETAIL_NEAR { return 0.0; }
+  let fade = smoothstep(DETAIL_NEAR, DETAIL_FAR, distance);
+  // Two octaves of value noise, the second an octave finer and half as strong
+  let noise = value_noise(hit * 0.5) * 0.67 + value_noise(hit) * 0.33;
+  return noise * DETAIL_DEPTH * fade / dir_length;
+}
 
-struct Intersection {
-  t: f32,
-  normal: vec3<bool>
+// Smoothly interpolated hashes of the surrounding lattice points, in [0, 1]
+fn value_noise(pos: vec3<f32>) -> f32 {
+  let cell = vec3<i32>(floor(pos));
+  let f = fract(pos);
+  let w = f * f * (3.0 - 2.0 * f);
+  var corners: array<f32, 8>;
+  for (var i = 0; i < 8; i++) {
+    corners[i] = lattice_hash(cell + vec3(i & 1, (i >> 1) & 1, i >> 2));
+  }
+  let x = mix(vec4(corners[0], corners[2], corners[4], corners[6]), vec4(corners[1], corners[3], corners[5], corners[7]), w.x);
+  let y = mix(x.xz, x.yw, w.y);
+  return mix(y.x, y.y, w.z);
 }
 
-fn aabb_intersect(ray_origin: vec3<f32>, inv_dir: vec3<f32>, min_cell: vec3<u32>, extent: vec3<u32>) -> Intersection {
-  var intersection = Intersection(SENTINEL, vec3(false));
-  let t1 = (vec3<f32>(min_cell) - ray_origin) * inv_dir;
-  let t2 = t1 + vec3<f32>(extent) * inv_dir;
-  let min_t = min(t1, t2);
-  let max_t = max(t1, t2);
-  let t_entry = max(max(min_t.x, min_t.y), min_t.z);
-  let t_exit = min(min(max_t.x, max_t.y), max_t.z);
-  // Entry must be before exit and exit must be forward
-  if t_exit < t_entry | t_exit < 0.0 { return intersection; }
-  intersection.t = t_entry;
-  intersection.normal = vec3(t_entry) == min_t;
-  return intersection;
+fn lattice_hash(cell: vec3<i32>) -> f32 {
+  var h = bitcast<u32>(cell.x) * 0x8da6b343u ^ bitcast<u32>(cell.y) * 0xd8163841u ^ bitcast<u32>(cell.z) * 0xcb1ab31fu;
+  h = (h ^ (h >> 16u)) * 0x7feb352du;
+  h = (h ^ (h >> 15u)) * 0x846ca68bu;
+  return f32(h ^ (h >> 16u)) / 4294967296.0;
 }
 
+// Position across the face that was hit, matching Hit::uv on the cpu
+fn face_uv(offset: vec3<f32>, normal: vec3<bool>) -> vec2<f32> {
+  if normal.x { return offset.zy; }
+  if normal.z { return offset.xy; }
+  return offset.xz;
+}
 
 fn oct_wrap(n: vec2<f32>) -> vec2<f32> {
   // Fold the lower hemisphere
@@ -1,74 +1,399 @@
 @group(0) @binding(0)
-var input_tex: texture_2d<f32>;      // RGBA: R,G=oct normal, B=Z, Block ID
+var input_tex: texture_2d<f32>;      // RGBA: R,G=oct normal, B=Z, A=leaf
 
 @group(0) @binding(1)
 var output_tex: texture_storage_2d<rgba16float, write>;
 
+@group(0) @binding(2)
+var hit_tex: texture_2d<u32>;        // RGB: world pos bits, A: object and face uv
+
+struct Decal {
+  pos: vec3<f32>,
+  size: f32,
+  normal: vec3<f32>,
+  opacity: f32,
+  color: vec3<f32>,
+}
+
+struct Decals {
+  count: u32,
+  list: array<Decal>,
+}
+
+@group(0) @binding(3)
+var<storage, read> decals: Decals;
+
+@group(0) @binding(4)
+var<storage, read> voxels: array<u32>;
+
+@group(0) @binding(5)
+var<storage, read> objects: array<VoxelObject>;
+
+struct Light {
+  // Towards the sun
+  sun_dir: vec3<f32>,
+  obj_count: u32,
+  max_distance: f32,
+  max_steps: u32,
+  frame: u32,
+  // Nonzero to trace shadow rays towards the sun
+  shadows: u32,
+  // Occlusion rays per pixel, 0 skips AO
+  ao_rays: u32,
+  // Sky haziness, see sky.rs
+  turbidity: f32,
+  // One of the DEBUG_ consts in dda.wgsl
+  debug_view: u32,
+  // See Lod in traversal.wgsl
+  lod_distance: f32,
+  lod_falloff: f32,
+  // Cones per pixel gathering bounce light, 0 skips it
+  gi_cones: u32,
+  // Where camera rays start from, and the way they all go while orthographic (zero while perspective)
+  camera: vec3<f32>,
+  ortho_dir: vec3<f32>,
+  // Follow the time of day, see sky.rs
+  sun_color: vec3<f32>,
+  // Light from the sky, reaching faces in shadow or facing away from the sun
+  ambient: f32,
+}
+@group(0) @binding(6)
+var<uniform> light: Light;
+
+@group(0) @binding(7)
+var<storage, read> materials: Materials;
+
+// RGB: what translucent cells in front of the hit let through, A: how much water reflects instead
+@group(0) @binding(8)
+var tint_tex: texture_2d<f32>;
+
+struct PointLight {
+  pos: vec3<f32>,
+  radius: f32,
+  color: vec3<f32>,
+  // One of the LIGHT_ consts
+  kind: u32,
+  dir: vec3<f32>,
+  // Cosines of a spotlight's half angle, and of where it starts to soften
+  cos_outer: f32,
+  cos_inner: f32,
+  // How far short of pos shadow rays stop
+  inset: f32,
+}
+const LIGHT_POINT = 0u;
+const LIGHT_SPOT = 1u;
+// Emissive cells and placed lights near the camera, see lights.rs
+struct PointLights {
+  count: u32,
+  list: array<PointLight>,
+}
+@group(0) @binding(9)
+var<storage, read> point_lights: PointLights;
+
+// RGB: world pos bits of the first water surface, A: its reflected direction, see dda.wgsl
+@group(0) @binding(10)
+var water_tex: texture_2d<u32>;
+
+// What each node in voxels looks like from afar, at the same address: RGB albedo and A coverage as unorms,
+// then emissive with a shared exponent in A. Only kept up to date while gi_cones isn't 0, see bounce.rs
+@group(0) @binding(11)
+var<storage, read> averages: array<vec2<u32>>;
+
+// A layer for each tile materials can be textured with, see textures.rs
+@group(0) @binding(12)
+var atlas: texture_2d_array<f32>;
+
+// Rays for a pixel coarsen no further than its camera ray could have by the time it hit,
+// so shadows and occlusion roughly follow the blocks drawn
+var<private> pixel_lod: Lod;
+
+// How far occlusion rays look, anything further away doesn't darken
+const AO_RADIUS = 2.0;
+const AO_STEPS = 16u;
+// Steps a light's shadow ray takes at least, more for lights further away
+const LIGHT_STEPS = 32u;
+// Reflections off water only need to be roughly right, the ripples hide the rest
+const REFLECTION_STEPS = 48u;
+// Reflective materials are flat and can show a sharp image, so their bounce gets longer
+const SPECULAR_STEPS = 96u;
+// Bounce light comes from up to this far away, in this many steps along each cone
+const GI_RADIUS = 32.0;
+const GI_STEPS = 12u;
+// Tangent of the cones' half angle, wide enough that a few of them cover the hemisphere between them
+const GI_APERTURE = 0.6;
+
 @compute @workgroup_size(8, 8)
 fn main(@builtin(global_invocation_id) id : vec3<u32>) {
   let size = textureDimensions(output_tex);
   if (id.x >= size.x || id.y >= size.y) { return; }
 
-  let uv = vec2<f32>(id.xy) / vec2<f32>(size);
-
-  // ---- Read center pixel ----
   let center = textureLoad(input_tex, id.xy, 0);
+  let tint = textureLoad(tint_tex, id.xy, 0);
+  // The DDA pass already colored the pixel in
+  if light.debug_view != 0 {
+    textureStore(output_tex, id.xy, vec4(tint.rgb, 1.0));
+    return;
+  }
+  pixel_lod = Lod(light.lod_distance, light.lod_falloff, max(center.b, 0.0), 0.0);
+  let reflection = water_reflection(id.xy, tint.a);
 
-  let voxel_hit = u32(center.a);
-  if voxel_hit == 0 {
-    textureStore(output_tex, id.xy, vec4<f32>(0.5, 0.5, 0.5, 1.0));
+  let leaf = u32(center.a);
+  if leaf == 0 {
+    // Rays that gave up at the far plane still see the sky here, the temporal pass fogs them over
+    let color = sky(oct_decode(center.rg));
+    textureStore(output_tex, id.xy, vec4(color * tint.rgb + reflection, 1.0));
     return;
   }
 
   let normal_center = oct_decode(center.rg);
-  let depth_center = center.b;
+  let hit = textureLoad(hit_tex, id.xy, 0);
+  let world_pos = bitcast<vec3<f32>>(hit.xyz);
+  let material = leaf_material(leaf);
+  let albedo = apply_decals(textured(material, (hit.a >> 16) - 1, world_pos, normal_center), world_pos, normal_center);
+  if (material.flags & FLAG_UNLIT) != 0 {
+    textureStore(output_tex, id.xy, vec4((albedo + material.emissive) * tint.rgb + reflection, 1.0));
+    return;
+  }
+  let ao = traced_ao(world_pos, normal_center, id.xy);
+  let diffuse = max(dot(normal_center, light.sun_dir), 0.0) * sunlight(world_pos, normal_center);
+
+  // Ambient light comes from the sky, less of it reaches objects under cover
+  let ambient = light.ambient * objects[(hit.a >> 16) - 1].ambient;
+  let lit = mix(vec3(ambient), light.sun_color, diffuse) * ao + gather_point_lights(world_pos, normal_center) + gather_bounce(world_pos, normal_center, id.xy);
+  var color = albedo * lit;
+  if (material.flags & FLAG_REFLECTIVE) != 0 {
+    // Rough metals are mostly their own lit color, smooth ones mostly what they mirror
+    color = mix(specular(world_pos, normal_center, albedo, material.roughness, id.xy), color, material.roughness);
+  }
+  textureStore(output_tex, id.xy, vec4((color + material.emissive) * tint.rgb + reflection, 1.0));
+}
 
-  // ---- Kernel settings ----
-  let radius: i32 = 1;
-  let invRes = 1.0 / vec2<f32>(size);  // pixel → UV
+// What a reflective surface at pos mirrors, tinted like a metal by albedo (its reflectance head on).
+// Rougher surfaces scatter the bounce further from the mirror direction per pixel and frame,
+// which the temporal pass averages into a blur
+fn specular(pos: vec3<f32>, normal: vec3<f32>, albedo: vec3<f32>, roughness: f32, pixel: vec2<u32>) -> vec3<f32> {
+  let view = select(normalize(pos - light.camera), light.ortho_dir, any(light.ortho_dir != vec3(0.0)));
+  let jitter = vec3(hash(pixel, light.frame), hash(pixel, light.frame + 1u), hash(pixel, light.frame + 2u)) * 2.0 - 1.0;
+  var dir = normalize(reflect(view, normal) + jitter * roughness);
+  // Scattered into the surface, fold it back out
+  if dot(dir, normal) < 0.0 { dir = reflect(dir, normal); }
+  let fresnel = albedo + (1.0 - albedo) * pow(1.0 - max(dot(-view, normal), 0.0), 5.0);
+  return bounce(pos + normal * 0.01, dir, SPECULAR_STEPS) * fresnel;
+}
 
-  var occ = 0.0;
-  var count = 0.0;
+// Light off the water surface in front of pixel, already scaled by how much of it reflects.
+// One coarse ray with flat sun and sky lighting, no shadows, AO or decals.
+fn water_reflection(pixel: vec2<u32>, reflectance: f32) -> vec3<f32> {
+  if reflectance == 0.0 { return vec3(0.0); }
+  let water = textureLoad(water_tex, pixel, 0);
+  let origin = bitcast<vec3<f32>>(water.xyz);
+  let dir = oct_decode(unpack2x16unorm(water.w));
+  return bounce(origin + dir * 0.01, dir, REFLECTION_STEPS) * reflectance;
+}
 
-  // ---- Adjacent-pixel SSAO ----
-  for (var dx = -radius; dx <= radius; dx = dx + 1) {
-    for (var dy = -radius; dy <= radius; dy = dy + 1) {
-      if (dx == 0 && dy == 0) { continue; }
+// Light coming back along a secondary ray from origin, the sky if it gets away.
+// Whatever it hits gets flat sun and sky lighting, no shadows, AO, decals or reflections of its own
+fn bounce(origin: vec3<f32>, dir: vec3<f32>, max_steps: u32) -> vec3<f32> {
+  let ray = march_objects(origin, dir, light.obj_count, light.max_distance, max_steps, AIR, pixel_lod);
+  if ray.voxel[0] == 0 { return sky(dir); }
+  let material = leaf_material(ray.voxel[0]);
+  let diffuse = max(dot(ray.global_normal, light.sun_dir), 0.0);
+  return material.albedo * mix(vec3(light.ambient), light.sun_color, diffuse) + material.emissive;
+}
+
+// 0 once the sun is well below the horizon, 1 once it's well above
+fn daylight() -> f32 { return smoothstep(-0.1, 0.2, light.sun_dir.y); }
 
-      let pos = vec2<i32>(i32(id.x) + dx, i32(id.y) + dy);
+// A cheap analytic sky: a horizon to zenith gradient that darkens at night, reddens around sunset,
+// and gets hazier (paler, with a wider glow around the sun) as turbidity goes up
+fn sky(dir: vec3<f32>) -> vec3<f32> {
+  let day = daylight();
+  let haze = clamp((light.turbidity - 1.0) / 9.0, 0.0, 1.0);
+  let zenith = mix(vec3(0.01, 0.015, 0.04), mix(vec3(0.22, 0.42, 0.85), vec3(0.55, 0.62, 0.75), haze), day);
+  let horizon = mix(vec3(0.02, 0.025, 0.05), vec3(0.75, 0.8, 0.88), day);
+  let up = max(dir.y, 0.0);
+  var color = mix(horizon, zenith, pow(up, 0.4 + haze));
 
-      // clamp to screen
-      if (pos.x < 0 || pos.y < 0 || pos.x >= i32(size.x) || pos.y >= i32(size.y)) {
-        continue;
-      }
+  let towards_sun = max(dot(dir, light.sun_dir), 0.0);
+  // Sunsets only happen while the sun is near the horizon, and mostly on that side of the sky
+  let sunset = 1.0 - smoothstep(0.0, 0.35, abs(light.sun_dir.y));
+  color += vec3(1.0, 0.4, 0.12) * sunset * pow(towards_sun, 4.0) * (1.0 - up);
+  color += vec3(1.0, 0.9, 0.7) * pow(towards_sun, mix(96.0, 8.0, haze)) * 0.4 * day;
+  color += vec3(8.0) * smoothstep(0.9995, 0.9998, towards_sun) * step(0.0, dir.y);
+  // Below the horizon fades to a dim ground color
+  return mix(color, horizon * 0.3, clamp(-dir.y * 4.0, 0.0, 1.0));
+}
+
+// Fraction of the hemisphere around normal that's open within AO_RADIUS, closer hits darken more.
+// The rays are rotated per pixel and per frame, which trades banding for noise the temporal pass averages out.
+fn traced_ao(pos: vec3<f32>, normal: vec3<f32>, pixel: vec2<u32>) -> f32 {
+  let tangent = normalize(cross(normal, select(vec3(0.0, 1.0, 0.0), vec3(1.0, 0.0, 0.0), abs(normal.y) > 0.9)));
+  let bitangent = cross(normal, tangent);
+  let origin = pos + normal * 0.01;
+  if light.ao_rays == 0 { return 1.0; }
+  let rotation = hash(pixel, light.frame) * 6.2831853;
+  var occlusion = 0.0;
+  for (var i = 0u; i < light.ao_rays; i++) {
+    // Cosine weighted, spread evenly in angle and staggered in height
+    let height = (f32(i) + 0.5) / f32(light.ao_rays);
+    let angle = rotation + f32(i) * 2.3999632;
+    let r = sqrt(height);
+    let dir = tangent * cos(angle) * r + bitangent * sin(angle) * r + normal * sqrt(1.0 - height);
+    let ray = march_objects(origin, dir, light.obj_count, AO_RADIUS, AO_STEPS, AIR, pixel_lod);
+    if ray.voxel[0] != 0 { occlusion += 1.0 - ray.t / AO_RADIUS; }
+  }
+  return 1.0 - occlusion / f32(light.ao_rays);
+}
+
+// Light bounced onto pos off everything around it, gathered by cones spread over the hemisphere around normal
+// and rotated per pixel and frame like the AO rays
+fn gather_bounce(pos: vec3<f32>, normal: vec3<f32>, pixel: vec2<u32>) -> vec3<f32> {
+  if light.gi_cones == 0 { return vec3(0.0); }
+  let tangent = normalize(cross(normal, select(vec3(0.0, 1.0, 0.0), vec3(1.0, 0.0, 0.0), abs(normal.y) > 0.9)));
+  let bitangent = cross(normal, tangent);
+  let rotation = hash(pixel, light.frame + 3u) * 6.2831853;
+  // Just inside the face, so the cones can tell which nodes hold the surface itself
+  let surface = pos - normal * 0.01;
+  var gathered = vec3(0.0);
+  for (var i = 0u; i < light.gi_cones; i++) {
+    let height = (f32(i) + 0.5) / f32(light.gi_cones);
+    let angle = rotation + f32(i) * 2.3999632;
+    let r = sqrt(height);
+    let dir = tangent * cos(angle) * r + bitangent * sin(angle) * r + normal * sqrt(1.0 - height);
+    gathered += cone_trace(pos + normal * 0.01, dir, surface);
+  }
+  return gathered / f32(light.gi_cones);
+}
 
-      let sample = textureLoad(input_tex, vec2<u32>(pos), 0);
-      let depth_s = sample.b;
+// Light coming back along a cone from origin. It widens as it goes, reading the averages of bigger and bigger nodes
+// and blending them front to back until it's blocked, nothing past GI_RADIUS adds anything
+fn cone_trace(origin: vec3<f32>, dir: vec3<f32>, surface: vec3<f32>) -> vec3<f32> {
+  var color = vec3(0.0);
+  var occlusion = 0.0;
+  var t = 1.0;
+  for (var i = 0u; i < GI_STEPS && t < GI_RADIUS && occlusion < 0.95; i++) {
+    let width = max(2.0 * t * GI_APERTURE, 1.0);
+    let sample = sample_averages(origin + dir * t, u32(log2(width)), surface);
+    // Whatever it hits is lit by the sky and by the sun on the side facing back along the cone, without shadows
+    let lit = light.ambient + max(dot(-dir, light.sun_dir), 0.0) * light.sun_color;
+    color += (1.0 - occlusion) * sample.coverage * (sample.albedo * lit + sample.emissive);
+    occlusion += (1.0 - occlusion) * sample.coverage;
+    t += width * 0.5;
+  }
+  return color;
+}
 
-      // Only consider if sample pixel is closer (occluding)
-      let depthDiff = depth_center - depth_s;
-      if (depthDiff > 0.0) {
-        // Decode neighbor normal for angular falloff
-        let normal_s = oct_decode(sample.gb);
+struct Average {
+  albedo: vec3<f32>,
+  emissive: vec3<f32>,
+  coverage: f32,
+}
 
-        // AO weight = how aligned normals are
-        let ndot = clamp(dot(normal_center, normal_s), 0.0, 1.0);
+// The average of the node 2^height cells across around pos, from whichever object has one there.
+// Nodes which hold surface are skipped, or every face would light itself
+fn sample_averages(pos: vec3<f32>, height: u32, surface: vec3<f32>) -> Average {
+  for (var obj = 0u; obj < light.obj_count; obj++) {
+    let cell = vec3<i32>(floor((objects[obj].inv_transform * vec4(pos, 1.0)).xyz));
+    let min_cell = vec3<i32>(objects[obj].min_cell);
+    if any(cell < min_cell) || any(cell >= min_cell + vec3<i32>(objects[obj].extent)) { continue; }
+    let level = min(height, objects[obj].height);
+    let own = vec3<i32>(floor((objects[obj].inv_transform * vec4(surface, 1.0)).xyz));
+    if all(cell >> vec3(level) == own >> vec3(level)) { continue; }
+    let node = node_at(obj, cell, level);
+    if node.y == 0u {
+      if node.x == 0u { continue; }
+      let material = leaf_material(node.x);
+      let coverage = select(1.0, material.opacity, (material.flags & FLAG_TRANSLUCENT) != 0);
+      return Average(material.albedo, material.emissive, coverage);
+    }
+    let average = averages[node.x];
+    let albedo = unpack4x8unorm(average.x);
+    if albedo.a == 0.0 { continue; }
+    let emissive = unpack4x8unorm(average.y);
+    return Average(albedo.rgb, emissive.rgb * exp2(round(emissive.a * 255.0) - 128.0), albedo.a);
+  }
+  return Average(vec3(0.0), vec3(0.0), 0.0);
+}
 
-        // scaled with depth difference
-        let w = clamp(depthDiff * 4.0, 0.0, 1.0) * (1.0 - ndot * 0.7);
+fn hash(pixel: vec2<u32>, frame: u32) -> f32 {
+  var h = pixel.x * 0x8da6b343u ^ pixel.y * 0xd8163841u ^ frame * 0xcb1ab31fu;
+  h = (h ^ (h >> 16u)) * 0x7feb352du;
+  h = (h ^ (h >> 15u)) * 0x846ca68bu;
+  return f32(h ^ (h >> 16u)) / 4294967296.0;
+}
 
-        occ = occ + w;
-      }
+// 1 if nothing is between pos and the sun, 0 otherwise
+fn sunlight(pos: vec3<f32>, normal: vec3<f32>) -> f32 {
+  if dot(normal, light.sun_dir) <= 0.0 { return 0.0; }
+  if light.shadows == 0 { return 1.0; }
+  // Start just off the face so we don't hit the voxel we're on
+  let shadow_ray = march_objects(pos + normal * 0.01, light.sun_dir, light.obj_count, light.max_distance, light.max_steps, AIR, pixel_lod);
+  // Giving up counts as lit, a dark band at the far plane looks worse than the odd missing shadow
+  return select(1.0, 0.0, shadow_ray.voxel[0] != 0);
+}
 
-      count = count + 1.0;
+// Light reaching pos from the lights it's within reach (and inside the cone) of, each with its own shadow ray
+fn gather_point_lights(pos: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+  let origin = pos + normal * 0.01;
+  var total = vec3(0.0);
+  for (var i = 0u; i < point_lights.count; i++) {
+    let point = point_lights.list[i];
+    let offset = point.pos - origin;
+    let distance = length(offset);
+    if distance >= point.radius { continue; }
+    let dir = offset / distance;
+    let facing = dot(normal, dir);
+    if facing <= 0.0 { continue; }
+    var cone = 1.0;
+    if point.kind == LIGHT_SPOT {
+      cone = smoothstep(point.cos_outer, point.cos_inner, dot(-dir, point.dir));
+      if cone == 0.0 { continue; }
     }
+    // Stop short of whatever the light sits in, like a glowing cell's own faces
+    let steps = max(LIGHT_STEPS, u32(distance * 4.0));
+    let blocker = march_objects(origin, dir, light.obj_count, distance - point.inset, steps, AIR, pixel_lod);
+    if blocker.voxel[0] != 0 { continue; }
+    let falloff = 1.0 - distance / point.radius;
+    total += point.color * facing * cone * falloff * falloff;
   }
+  return total;
+}
+
+// The albedo times the material's tile, projected along each axis in the object's own grid (a tile to a cell)
+// and blended by how far the normal faces down it. Cell faces only ever see the one projection
+fn textured(material: Material, obj: u32, pos: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+  if material.tile == NO_TILE || material.tile >= textureNumLayers(atlas) { return material.albedo; }
+  let local = (objects[obj].inv_transform * vec4(pos, 1.0)).xyz;
+  let weights = pow(abs((objects[obj].inv_transform * vec4(normal, 0.0)).xyz), vec3(4.0));
+  let color = atlas_sample(material.tile, local.zy) * weights.x
+    + atlas_sample(material.tile, local.xz) * weights.y
+    + atlas_sample(material.tile, local.xy) * weights.z;
+  return material.albedo * color / (weights.x + weights.y + weights.z);
+}
 
-  let ao = 1.0 - occ / max(count, 1.0);
-  
-  let color = vec4(0.7, 0.3, .3, 1.0);
+fn atlas_sample(tile: u32, uv: vec2<f32>) -> vec3<f32> {
+  let size = textureDimensions(atlas);
+  let texel = min(vec2<u32>(fract(uv) * vec2<f32>(size)), size - 1u);
+  return textureLoad(atlas, texel, tile, 0).rgb;
+}
 
-  textureStore(output_tex, id.xy, color * ao);
+// Paints every decal covering pos over the albedo, in the order they were spawned
+fn apply_decals(albedo: vec3<f32>, pos: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+  var color = albedo;
+  for (var i = 0u; i < decals.count; i++) {
+    let decal = decals.list[i];
+    // Only faces pointing the same way, so decals don't smear down the sides of blocks
+    if dot(decal.normal, normal) < 0.9 { continue; }
+    let offset = pos - decal.pos;
+    if abs(dot(offset, decal.normal)) > 0.01 { continue; }
+    // Square in the plane of the face
+    let tangent = normalize(cross(decal.normal, select(vec3(0.0, 1.0, 0.0), vec3(1.0, 0.0, 0.0), abs(decal.normal.y) > 0.9)));
+    let bitangent = cross(decal.normal, tangent);
+    let local = abs(vec2(dot(offset, tangent), dot(offset, bitangent))) / decal.size;
+    let edge = 1.0 - smoothstep(0.4, 0.5, max(local.x, local.y));
+    color = mix(color, decal.color, decal.opacity * edge);
+  }
+  return color;
 }
 
 fn oct_decode(f: vec2<f32>) -> vec3<f32> {
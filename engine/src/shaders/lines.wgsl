@@ -0,0 +1,20 @@
+struct View {
+  view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> view: View;
+
+struct VertexOut {
+  @builtin(position) clip_pos: vec4<f32>,
+  @location(0) color: vec3<f32>,
+}
+
+@vertex
+fn vs_main(@location(0) pos: vec3<f32>, @location(1) color: vec3<f32>) -> VertexOut {
+  return VertexOut(view.view_proj * vec4(pos, 1.0), color);
+}
+
+@fragment
+fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
+  return vec4(in.color, 1.0);
+}
@@ -0,0 +1,230 @@
+// Blends each lit pixel with where the same point was last frame, so per frame noise averages out
+
+@group(0) @binding(0)
+var lighting_tex: texture_2d<f32>;
+
+// RGB: world pos bits, A: object + 1 << 16 | face uv, zero on a miss
+@group(0) @binding(1)
+var hit_tex: texture_2d<u32>;
+
+// Last frame's blend, A is how many frames it holds
+@group(0) @binding(2)
+var history_tex: texture_2d<f32>;
+
+@group(0) @binding(3)
+var history_sampler: sampler;
+
+// Becomes next frame's history_tex
+@group(0) @binding(4)
+var next_history: texture_storage_2d<rgba16float, write>;
+
+@group(0) @binding(5)
+var output_tex: texture_storage_2d<rgba16float, write>;
+
+// Matches dda.wgsl, only prev_view_proj is read here
+struct Camera {
+  pos: vec3<f32>,
+  rot: mat3x3<f32>,
+  aspect_ratio: f32,
+  tan_fov: f32,
+  obj_count: u32,
+  max_distance: f32,
+  max_steps: u32,
+  detail: u32,
+  time: f32,
+  ortho_height: f32,
+  debug_view: u32,
+  lod_distance: f32,
+  lod_falloff: f32,
+  all_obj_count: u32,
+  resolution: vec2<u32>,
+  jitter: vec2<f32>,
+  prev_view_proj: mat4x4<f32>,
+}
+@group(0) @binding(6)
+var<uniform> cam: Camera;
+
+struct Motion {
+  // Nonzero when history_tex holds nothing worth keeping
+  reset: u32,
+  // Per object, takes a world position this frame to where it was last frame
+  objects: array<mat4x4<f32>>,
+}
+@group(0) @binding(7)
+var<storage, read> motion: Motion;
+
+// The DDA's output, RG: oct normal, B: depth, A: leaf (0 on a miss)
+@group(0) @binding(8)
+var gbuffer_tex: texture_2d<f32>;
+
+// Applied to the output after blending, the history never sees any of it
+struct Post {
+  fog_color: vec3<f32>,
+  // Fog per unit of depth, 0 is off
+  fog_density: f32,
+  // How quickly fog thins out with height above y = 0, 0 is the same everywhere
+  fog_falloff: f32,
+  exposure: f32,
+  // Pixels outlines reach, 0 is off
+  outline_width: u32,
+  outline_threshold: f32,
+  // One of the TONEMAP_ consts
+  tonemap: u32,
+  // Object + 1 whose target_cell gets outlined, 0 for none
+  target_object: u32,
+  target_cell: vec3<u32>,
+  // World space -> the target object's grid space
+  target_transform: mat4x4<f32>,
+  // Nonzero when the lit color is a debug view, which is shown as is
+  debug_view: u32,
+}
+@group(0) @binding(9)
+var<uniform> post: Post;
+
+// The most frames a pixel averages over, more is smoother but slower to catch up with lighting changes
+const MAX_HISTORY = 16.0;
+// How dark outlines get, and how far apart neighbouring normals have to be (as a dot product) to draw one
+const OUTLINE_STRENGTH = 0.85;
+const OUTLINE_NORMAL = 0.7;
+// Width of the lines around the targeted cell's faces, as a fraction of the cell, and how dark they are
+const TARGET_WIDTH = 0.06;
+const TARGET_STRENGTH = 0.8;
+// Matches Tonemap in wgpu_ctx.rs
+const TONEMAP_CLIP = 0u;
+const TONEMAP_REINHARD = 1u;
+const TONEMAP_ACES = 2u;
+
+@compute @workgroup_size(8, 8)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+  let size = textureDimensions(output_tex);
+  if (id.x >= size.x || id.y >= size.y) { return; }
+
+  let current = textureLoad(lighting_tex, id.xy, 0).rgb;
+  if post.debug_view != 0 {
+    textureStore(next_history, id.xy, vec4(current, 1.0));
+    textureStore(output_tex, id.xy, vec4(current, 1.0));
+    return;
+  }
+  let hit = textureLoad(hit_tex, id.xy, 0);
+  let obj = hit.a >> 16;
+  var color = current;
+  var frames = 1.0;
+  // Misses have nothing to reproject, the sky doesn't need averaging anyway.
+  // Unless the camera's jittered, then the sky's edges want smoothing as much as anything's
+  let jittered = any(cam.jitter != vec2(0.0));
+  if (obj != 0 || jittered) && motion.reset == 0 {
+    var clip: vec4<f32>;
+    if obj != 0 {
+      let world_pos = bitcast<vec3<f32>>(hit.xyz);
+      let prev_pos = motion.objects[obj - 1] * vec4(world_pos, 1.0);
+      clip = cam.prev_view_proj * prev_pos;
+    } else {
+      // Infinitely far off along the way the ray left, so only turning the camera moves it
+      clip = cam.prev_view_proj * vec4(oct_decode(textureLoad(gbuffer_tex, id.xy, 0).rg), 0.0);
+    }
+    // Rows count up from the bottom of the screen, same as clip space. The jitter put the point off the pixel's middle,
+    // history is read where the middle was instead or resampling it every frame would blur it more and more
+    let prev_uv = clip.xy / clip.w * 0.5 + 0.5 - cam.jitter / vec2<f32>(size);
+    if clip.w > 0.0 && all(prev_uv >= vec2(0.0)) && all(prev_uv <= vec2(1.0)) {
+      let history = textureSampleLevel(history_tex, history_sampler, prev_uv, 0.0);
+      // Faster moving pixels keep less history so they don't smear
+      let velocity = length(prev_uv * vec2<f32>(size) - (vec2<f32>(id.xy) + 0.5));
+      frames = min(history.a, MAX_HISTORY / (1.0 + velocity)) + 1.0;
+      let bounds = neighbourhood(id.xy, size);
+      color = mix(clamp(history.rgb, bounds[0], bounds[1]), current, 1.0 / frames);
+    }
+  }
+
+  textureStore(next_history, id.xy, vec4(color, frames));
+  // Outlines and fog stay out of the history, they're sharp every frame anyway
+  if post.outline_width != 0 { color *= 1.0 - OUTLINE_STRENGTH * outline(id.xy, size); }
+  if post.target_object != 0 && obj == post.target_object { color *= 1.0 - TARGET_STRENGTH * target_edge(id.xy, hit); }
+  color = fog(color, id.xy, hit);
+  textureStore(output_tex, id.xy, vec4(tonemap(color * post.exposure), 1.0));
+}
+
+// [min, max] of the lit colors around pixel. History outside that range was probably
+// something else last frame (a disocclusion, a moved light) so it gets pulled into it.
+fn neighbourhood(pixel: vec2<u32>, size: vec2<u32>) -> array<vec3<f32>, 2> {
+  var low = vec3(1e9);
+  var high = vec3(-1e9);
+  for (var y = -1; y <= 1; y++) {
+    for (var x = -1; x <= 1; x++) {
+      let coord = clamp(vec2<i32>(pixel) + vec2(x, y), vec2(0), vec2<i32>(size) - 1);
+      let color = textureLoad(lighting_tex, coord, 0).rgb;
+      low = min(low, color);
+      high = max(high, color);
+    }
+  }
+  return array(low, high);
+}
+
+// 1 where pixel sits on a depth or normal discontinuity within outline_width pixels, 0 otherwise
+fn outline(pixel: vec2<u32>, size: vec2<u32>) -> f32 {
+  let center = surface(vec2<i32>(pixel));
+  let reach = i32(post.outline_width);
+  let offsets = array(vec2(reach, 0), vec2(-reach, 0), vec2(0, reach), vec2(0, -reach));
+  for (var i = 0; i < 4; i++) {
+    let coord = clamp(vec2<i32>(pixel) + offsets[i], vec2(0), vec2<i32>(size) - 1);
+    let other = surface(coord);
+    // Relative, so distant geometry doesn't outline every face
+    let depth_edge = abs(other.w - center.w) / min(other.w, center.w) > post.outline_threshold;
+    if depth_edge || dot(other.xyz, center.xyz) < OUTLINE_NORMAL { return 1.0; }
+  }
+  return 0.0;
+}
+
+// 1 where pixel lands near the edge of a face of the targeted cell, 0 anywhere else
+fn target_edge(pixel: vec2<u32>, hit: vec4<u32>) -> f32 {
+  let world_pos = bitcast<vec3<f32>>(hit.xyz);
+  let normal = surface(vec2<i32>(pixel)).xyz;
+  let local = post.target_transform * vec4(world_pos, 1.0);
+  let local_normal = post.target_transform * vec4(normal, 0.0);
+  // Hits sit right on the face, nudged back inside so they land in the cell they belong to
+  let cell = vec3<i32>(floor(local.xyz - local_normal.xyz * 0.01));
+  if any(cell != vec3<i32>(post.target_cell)) { return 0.0; }
+  let uv = vec2(f32((hit.a >> 8) & 0xff), f32(hit.a & 0xff)) / 255.0;
+  let edge = min(min(uv.x, uv.y), min(1.0 - uv.x, 1.0 - uv.y));
+  return select(0.0, 1.0, edge < TARGET_WIDTH);
+}
+
+// Maps hdr color into [0, 1], so bright sky and emissive cells roll off instead of clipping
+fn tonemap(color: vec3<f32>) -> vec3<f32> {
+  switch post.tonemap {
+    case TONEMAP_REINHARD: { return color / (1.0 + color); }
+    // Narkowicz's fit of the ACES curve
+    case TONEMAP_ACES: { return clamp(color * (2.51 * color + 0.03) / (color * (2.43 * color + 0.59) + 0.14), vec3(0.0), vec3(1.0)); }
+    default: { return clamp(color, vec3(0.0), vec3(1.0)); }
+  }
+}
+
+// Fades color towards fog_color with depth, so the world thins out instead of ending at the far plane.
+// Rays that gave up at the far plane are all fog, and the sky behind everything gets none.
+fn fog(color: vec3<f32>, pixel: vec2<u32>, hit: vec4<u32>) -> vec3<f32> {
+  if post.fog_density == 0.0 { return color; }
+  let depth = textureLoad(gbuffer_tex, pixel, 0).b;
+  if depth < 0.0 { return post.fog_color; }
+  if hit.a >> 16 == 0 { return color; }
+  let height = max(bitcast<vec3<f32>>(hit.xyz).y, 0.0);
+  let thickness = post.fog_density * exp(-post.fog_falloff * height);
+  return mix(post.fog_color, color, exp(-thickness * depth));
+}
+
+// [normal, depth] from the gbuffer, misses are all infinitely far away and facing the same way
+fn surface(coord: vec2<i32>) -> vec4<f32> {
+  let texel = textureLoad(gbuffer_tex, coord, 0);
+  if texel.a == 0.0 { return vec4(0.0, 0.0, 1.0, 1e20); }
+  return vec4(oct_decode(texel.rg), texel.b);
+}
+
+// Matches lighting.wgsl
+fn oct_decode(f: vec2<f32>) -> vec3<f32> {
+  let p = f * 2.0 - 1.0;
+  var n = vec3<f32>(p.x, p.y, 1.0 - abs(p.x) - abs(p.y));
+  if (n.z < 0.0) {
+    let old = n;
+    n.x = (1.0 - abs(old.y)) * select(-1.0, 1.0, old.x >= 0.0);
+    n.y = (1.0 - abs(old.x)) * select(-1.0, 1.0, old.y >= 0.0);
+  }
+  return normalize(n);
+}
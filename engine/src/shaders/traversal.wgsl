@@ -0,0 +1,350 @@
+// Marching rays through every object's DAG, shared by every shader which needs to see the scene.
+// Appended to the end of each of them, which must declare these bindings themselves:
+//   var<storage, read> voxels: array<u32>;
+//   var<storage, read> objects: array<VoxelObject>;
+//   var<storage, read> materials: Materials;
+
+const SENTINEL = -314159.0;
+// [object, leaf] a ray is travelling through, cells of that leaf in that object count as empty.
+// Every other cell is a hit, including EMPTY ones, which is how leaving a medium shows up
+const AIR = vec2<u32>(0u, 0u);
+
+// Objects' heads and packed entries with this set are leaves, see packed.rs
+const PACKED_LEAF = 0x80000000u;
+// In place of a node's bounds when the ray isn't in a gap, see vox_read
+const NO_GAP = 0xffffffffu;
+
+// I only need linear transform, just store that 3x3
+struct VoxelObject {
+  pos: vec3<f32>,
+  min_cell: vec3<u32>,
+  extent: vec3<u32>,
+  transform: mat4x4<f32>,
+  inv_transform: mat4x4<f32>,
+  head: u32,
+  height: u32,
+  // Sky visibility around the object, scales its ambient light
+  ambient: f32,
+  // Whether voxels holds the packed layout (and head is an entry in it) rather than raw nodes, see NodeFormat
+  packed: u32,
+  // Where in voxels the packed entries count from
+  base: u32,
+  // The signed cell at the grid's cell 0, see VoxelObject::origin
+  origin: vec3<i32>,
+}
+
+struct Material {
+  albedo: vec3<f32>,
+  roughness: f32,
+  emissive: vec3<f32>,
+  flags: u32,
+  opacity: f32,
+  ior: f32,
+  // Layer of the texture atlas, see textures.rs
+  tile: u32,
+}
+// Indexed by leaf, see materials.rs
+struct Materials {
+  count: u32,
+  list: array<Material>,
+}
+const FLAG_UNLIT = 1u;
+const FLAG_TRANSLUCENT = 2u;
+const FLAG_WATER = 4u;
+const FLAG_REFLECTIVE = 8u;
+const NO_TILE = 0xffffffffu;
+
+fn leaf_material(leaf: u32) -> Material {
+  // Magenta for leaves nobody registered, matching Material::default
+  if leaf >= materials.count { return Material(vec3(1.0, 0.0, 1.0), 1.0, vec3(0.0), 0u, 1.0, 1.0, NO_TILE); }
+  return materials.list[leaf];
+}
+
+struct Position {
+  cell: vec3<i32>,
+  offset: vec3<f32>,
+}
+struct Ray {
+  pos: Position,
+  dir: vec3<f32>,
+  inv_dir: vec3<f32>,
+  local_normal: vec3<bool>,
+  global_normal: vec3<f32>,
+  // What vox_read found at the ray's cell
+  voxel: vec3<u32>,
+  t: f32,
+  alive: bool,
+  obj: u32,
+  far: bool,
+  // Unlike voxel[0] != 0 this also catches EMPTY cells ending a medium
+  hit: bool,
+  // DDA steps march_objects took across every object to find this
+  steps: u32,
+}
+fn move_ray(ray: ptr<function, Ray>, timestep: f32) {
+  let delta = (*ray).dir * timestep;
+  (*ray).pos.cell += vec3<i32>(floor(delta));
+  (*ray).pos.offset += fract(delta) + sign((*ray).dir) * 0.0001;
+  (*ray).pos.cell += vec3<i32>(floor( (*ray).pos.offset ));
+  (*ray).pos.offset = fract( (*ray).pos.offset );
+  (*ray).t += timestep;
+}
+
+// Distance based level of detail, see RenderSettings::lod_distance. Past distance from the camera (0 is off) the march
+// stops descending a level above the cells, and another level sooner every falloff times further out.
+struct Lod {
+  distance: f32,
+  falloff: f32,
+  // How far from the camera the ray starts, and how much further each unit of t takes it
+  start: f32,
+  rate: f32,
+}
+
+// Levels above the cells a march using lod stops at, t along the ray
+fn lod_height(lod: Lod, t: f32) -> u32 {
+  let distance = lod.start + t * lod.rate;
+  if lod.distance <= 0.0 || distance < lod.distance { return 0u; }
+  return u32(log2(distance / lod.distance) / log2(lod.falloff)) + 1u;
+}
+
+// Edges of the nodes camera rays step into, for the nodes debug view. Only the DDA pass turns it on
+struct NodeTrace {
+  on: bool,
+  // How close to an edge counts as on it, at t = 0 and then for each unit of t
+  width: vec2<f32>,
+  // Of the nearest edge passed, and at what t
+  color: vec3<f32>,
+  t: f32,
+}
+var<private> node_trace: NodeTrace;
+
+// Notes it if the ray stepped into its node (depth levels below the object's head) near one of the node's edges
+fn trace_node(ray: Ray, depth: u32) {
+  if !node_trace.on || ray.t >= node_trace.t { return; }
+  let size = exp2(f32(ray.voxel[1]));
+  // Where in the node the ray is
+  let p = vec3<f32>(ray.pos.cell - (ray.pos.cell & vec3(~0i << ray.voxel[1]))) + ray.pos.offset;
+  let d = min(p, vec3(size) - p);
+  // Entering through a face puts one of them at about 0, it's an edge when the next smallest is close to 0 too
+  let edge = min(min(max(d.x, d.y), max(d.y, d.z)), max(d.x, d.z));
+  if edge > node_trace.width.x + node_trace.width.y * ray.t { return; }
+  node_trace.t = ray.t;
+  // Around the color wheel, a sixth of the way a level
+  node_trace.color = 0.5 + 0.5 * cos(6.2832 * (f32(depth) / 6.0 + vec3(0.0, 0.33, 0.67)));
+}
+
+// Finds the closest hit across every object, giving up past max_t or after max_steps dda steps in total.
+// far is set when one of those limits cut the march short, rather than the ray escaping everything.
+// medium is where the ray starts out, AIR unless it's inside a translucent leaf.
+fn march_objects(origin: vec3<f32>, world_dir: vec3<f32>, obj_count: u32, max_t: f32, max_steps: u32, medium: vec2<u32>, lod: Lod) -> Ray {
+  let ONE = 1.0; let INF = ONE / 0.0;
+  var best_ray = Ray(); best_ray.t = INF;
+  var steps = 0u;
+  var far = false;
+
+  for (var idx = 0u; idx < obj_count; idx += 1) {
+    var ray = new_ray(origin, world_dir, idx);
+    if !ray.alive || ray.t >= best_ray.t { continue; }
+    let empty = select(AIR.y, medium.y, idx == medium.x);
+    // Starting out at full detail, rays leaving a face can't start inside a coarser block behind it
+    var level = 0u;
+    ray.voxel = vox_read(idx, ray.pos.cell, level);
+    while ray.voxel[0] == empty {
+      if steps >= max_steps || ray.t > max_t { far = true; break; }
+      steps += 1;
+      let prev_cell = ray.pos.cell;
+      dda_step(&ray);
+      // If we've stepped outside of the object bounds
+      // We bitcast pos.cell to u32s to avoid < 0 branching via underflow
+      if !all(bitcast<vec3<u32>>(ray.pos.cell) - objects[idx].min_cell < objects[idx].extent) { break; }
+      // Only coarsen on stepping into a new block of the coarser level, so the ray never finds itself already inside one
+      let wanted = lod_height(lod, ray.t);
+      if wanted > level && any(ray.pos.cell >> vec3(wanted) != prev_cell >> vec3(wanted)) { level = wanted; }
+      ray.voxel = vox_read(idx, ray.pos.cell, level); // Sample current position
+      trace_node(ray, objects[idx].height - ray.voxel[1]);
+    }
+    if ray.voxel[0] == empty { continue; }
+    ray.hit = true;
+    if ray.t > max_t { far = true; } else if ray.t < best_ray.t { best_ray = ray; }
+  }
+  best_ray.far = far && !best_ray.hit;
+  best_ray.steps = steps;
+  if obj_count == 0 { return best_ray; }
+
+  let linear = mat3x3<f32>(objects[best_ray.obj].transform[0].xyz,
+                           objects[best_ray.obj].transform[1].xyz,
+                           objects[best_ray.obj].transform[2].xyz);
+  let local_float_normal = vec3<f32>(best_ray.local_normal) * sign(best_ray.inv_dir) * vec3(1.0, -1.0, 1.0);
+  best_ray.global_normal = normalize(linear * local_float_normal);
+  return best_ray;
+}
+
+fn new_ray(origin: vec3<f32>, world_dir: vec3<f32>, obj: u32) -> Ray {
+  var ray = Ray();
+  ray.obj = obj;
+  let pos_f32 = (objects[obj].inv_transform * vec4(origin, 1.0)).xyz;
+  ray.pos = Position( vec3<i32>(floor(pos_f32)), fract(pos_f32));
+  ray.dir = (objects[obj].inv_transform * vec4(world_dir, 0.0)).xyz;
+  ray.inv_dir = 1.0 / ray.dir;
+  let intersection = aabb_intersect(pos_f32, ray.inv_dir, objects[obj].min_cell, objects[obj].extent);
+  ray.alive = intersection.t != SENTINEL;
+  ray.local_normal = intersection.normal;
+  move_ray(&ray, max(0.0, intersection.t));
+  return ray;
+}
+
+fn dda_step(ray: ptr<function, Ray>) {
+  // Sparse marching
+  let neg_wall = (*ray).pos.cell & vec3(~0i << (*ray).voxel[1] );
+  let pos_wall = neg_wall + (1i << (*ray).voxel[1] );
+  let next_wall = select(pos_wall, neg_wall, (*ray).dir < vec3(0.0));
+  // Next position
+  let t_wall = ( vec3<f32>( next_wall - (*ray).pos.cell ) - (*ray).pos.offset ) * (*ray).inv_dir;
+  var t_step = min(min(t_wall.x, t_wall.y), t_wall.z);
+  var normal = t_wall == vec3(t_step);
+  // In a gap, everything up to the node's bounds is empty, so it goes straight there if it gets there before leaving
+  let bounds = (*ray).voxel[2];
+  if bounds != NO_GAP {
+    let step = exp2(f32((*ray).voxel[1])) / 16.0;
+    let corner = vec3<f32>(neg_wall - (*ray).pos.cell) - (*ray).pos.offset;
+    let t1 = (corner + vec3<f32>(bounds_min(bounds)) * step) * (*ray).inv_dir;
+    let t2 = (corner + vec3<f32>(bounds_max(bounds) + 1u) * step) * (*ray).inv_dir;
+    let near = min(t1, t2);
+    let t_enter = max(max(near.x, near.y), near.z);
+    let far = max(t1, t2);
+    let t_exit = min(min(far.x, far.y), far.z);
+    if t_enter <= t_exit && t_enter > 0.0 && t_enter < t_step {
+      t_step = t_enter;
+      normal = near == vec3(t_enter);
+    }
+  }
+  move_ray(ray, t_step);
+  (*ray).local_normal = normal;
+}
+
+// A node's bounds are packed as 4 bits for each of min then max, see Bounds::to_bits
+fn bounds_min(bits: u32) -> vec3<u32> { return vec3(bits, bits >> 4u, bits >> 8u) & vec3(15u); }
+fn bounds_max(bits: u32) -> vec3<u32> { return vec3(bits >> 12u, bits >> 16u, bits >> 20u) & vec3(15u); }
+fn pack_bounds(min: vec3<u32>, max: vec3<u32>) -> u32 {
+  return min.x | min.y << 4u | min.z << 8u | max.x << 12u | max.y << 16u | max.z << 20u;
+}
+
+// [leaf, height of the uniform node it's in, NO_GAP] for a cell of obj, descending no further than min_height.
+// Cells which lie outside the bounds of a node they're in (in the packed layout, which keeps them) are in a gap
+// instead: [EMPTY, height of the highest such node, its bounds], which dda_step can cross in one go
+fn vox_read(obj: u32, cell: vec3<i32>, min_height: u32) -> vec3<u32> {
+  if objects[obj].packed != 0u { return packed_read(objects[obj].base, objects[obj].head, objects[obj].height, cell, min_height); }
+  var cur_idx = objects[obj].head;
+  var cur_height = objects[obj].height;
+  while cur_height > min_height {
+    cur_height -= 1;
+    let child = cell >> vec3<u32>(cur_height) & vec3<i32>(1);
+    let next_idx = voxels[cur_idx * 8u + u32(child.z << 2 | child.y << 1 | child.x)];
+    if next_idx == cur_idx { return vec3(cur_idx, cur_height + 1, NO_GAP); }
+    cur_idx = next_idx;
+  }
+  if cur_height == 0 { return vec3<u32>(cur_idx, 0u, NO_GAP); }
+  // Stopped short, so the whole node stands in for whatever solid it holds
+  return vec3(solid_leaf(cur_idx, cur_height), cur_height, NO_GAP);
+}
+
+// The first leaf other than EMPTY found under node, EMPTY only if there's nothing else
+fn solid_leaf(node: u32, height: u32) -> u32 {
+  var cur_idx = node;
+  for (var cur_height = height; cur_height != 0; cur_height -= 1) {
+    var next_idx = cur_idx;
+    for (var i = 0u; i < 8u; i++) {
+      let child = voxels[cur_idx * 8u + i];
+      if child != 0 { next_idx = child; break; }
+    }
+    // Leaves are their own children
+    if next_idx == cur_idx { break; }
+    cur_idx = next_idx;
+  }
+  return cur_idx;
+}
+
+// vox_read over the packed layout. A node is a mask of its children which aren't EMPTY (with its bounds above)
+// followed by an entry for each of them, so the one wanted is as far along as the mask has bits set below it.
+// Entries are offsets from base, which is only past 0 when every object's tree is packed on its own
+fn packed_read(base: u32, head: u32, height: u32, cell: vec3<i32>, min_height: u32) -> vec3<u32> {
+  var entry = head;
+  var cur_height = height;
+  while (entry & PACKED_LEAF) == 0u {
+    if cur_height == min_height { return vec3(packed_solid_leaf(base, entry, cur_height), cur_height, NO_GAP); }
+    let header = voxels[base + entry];
+    // Rounded out to whole blocks of min_height, so a march using lod only ever skips whole blocks
+    let align = vec3(max((1u << min_height) << 4u >> cur_height, 1u) - 1u);
+    let bounds = header >> 8u;
+    let low = bounds_min(bounds) & ~align;
+    let high = bounds_max(bounds) | align;
+    // Which of the node's steps the cell starts in
+    let step = (bitcast<vec3<u32>>(cell) & vec3((1u << cur_height) - 1u)) << vec3(4u) >> vec3(cur_height);
+    if any(step < low) || any(step > high) { return vec3(0u, cur_height, pack_bounds(low, high)); }
+    cur_height -= 1;
+    let child = cell >> vec3<u32>(cur_height) & vec3<i32>(1);
+    let bit = 1u << u32(child.z << 2 | child.y << 1 | child.x);
+    // Left out, so EMPTY
+    if (header & bit) == 0u { return vec3(0u, cur_height, NO_GAP); }
+    entry = voxels[base + entry + 1u + countOneBits(header & (bit - 1u))];
+  }
+  return vec3(entry & ~PACKED_LEAF, cur_height, NO_GAP);
+}
+
+// solid_leaf for a packed node. Every child it stores isn't EMPTY, so the first one always leads to a solid leaf
+fn packed_solid_leaf(base: u32, node: u32, height: u32) -> u32 {
+  var entry = node;
+  for (var cur_height = height; cur_height != 0 && (entry & PACKED_LEAF) == 0u; cur_height -= 1) {
+    entry = voxels[base + entry + 1u];
+  }
+  return entry & ~PACKED_LEAF;
+}
+
+// The node a cell of obj is in at height, for finding what's stored alongside it: [address, 1] where address is
+// the node's index in the raw layout or base + entry packed, or [leaf, 0] if the cell is in a leaf by then
+fn node_at(obj: u32, cell: vec3<i32>, height: u32) -> vec2<u32> {
+  let base = objects[obj].base;
+  var entry = objects[obj].head;
+  var cur_height = objects[obj].height;
+  if objects[obj].packed != 0u {
+    while (entry & PACKED_LEAF) == 0u {
+      if cur_height <= height { return vec2(base + entry, 1u); }
+      let header = voxels[base + entry];
+      cur_height -= 1;
+      let child = cell >> vec3<u32>(cur_height) & vec3<i32>(1);
+      let bit = 1u << u32(child.z << 2 | child.y << 1 | child.x);
+      if (header & bit) == 0u { return vec2(0u, 0u); }
+      entry = voxels[base + entry + 1u + countOneBits(header & (bit - 1u))];
+    }
+    return vec2(entry & ~PACKED_LEAF, 0u);
+  }
+  while cur_height > height {
+    cur_height -= 1;
+    let child = cell >> vec3<u32>(cur_height) & vec3<i32>(1);
+    let next_idx = voxels[entry * 8u + u32(child.z << 2 | child.y << 1 | child.x)];
+    if next_idx == entry { return vec2(entry, 0u); }
+    entry = next_idx;
+  }
+  // Leaves are their own children
+  return vec2(entry, select(1u, 0u, voxels[entry * 8u] == entry));
+}
+
+struct Intersection {
+  t: f32,
+  normal: vec3<bool>
+}
+
+fn aabb_intersect(ray_origin: vec3<f32>, inv_dir: vec3<f32>, min_cell: vec3<u32>, extent: vec3<u32>) -> Intersection {
+  var intersection = Intersection(SENTINEL, vec3(false));
+  let t1 = (vec3<f32>(min_cell) - ray_origin) * inv_dir;
+  let t2 = t1 + vec3<f32>(extent) * inv_dir;
+  let min_t = min(t1, t2);
+  let max_t = max(t1, t2);
+  let t_entry = max(max(min_t.x, min_t.y), min_t.z);
+  let t_exit = min(min(max_t.x, max_t.y), max_t.z);
+  // Entry must be before exit and exit must be forward
+  if t_exit < t_entry | t_exit < 0.0 { return intersection; }
+  intersection.t = t_entry;
+  intersection.normal = vec3(t_entry) == min_t;
+  return intersection;
+}
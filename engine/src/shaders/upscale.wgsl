@@ -1,23 +1,86 @@
-const SCALE = 1.0 / 1.0;
 const VERTICIES = array<vec2<f32>, 3>(
   vec2<f32>(-1.0, -3.0),
   vec2<f32>(3.0, 1.0),
   vec2<f32>(-1.0, 1.0)
 );
 
+struct VertexOutput {
+  @builtin(position) pos: vec4<f32>,
+  // Texture rows count up from the bottom of the screen, like clip space
+  @location(0) uv: vec2<f32>,
+}
+
 @vertex
-fn vs_main(@builtin(vertex_index) idx: u32) -> @builtin(position) vec4<f32> {
-  return vec4<f32>(VERTICIES[idx], 0.0, 1.0);
+fn vs_main(@builtin(vertex_index) idx: u32) -> VertexOutput {
+  return VertexOutput(vec4<f32>(VERTICIES[idx], 0.0, 1.0), VERTICIES[idx] * 0.5 + 0.5);
 }
 
 @group(0) @binding(0)
 var my_texture: texture_2d<f32>;
+// Nearest filtering loads texels directly, some backends can't pair one texture with two samplers
 @group(0) @binding(1)
-var my_sampler: sampler;
+var linear_sampler: sampler;
+
+struct Upscale {
+  // One of the FILTER_ consts
+  mode: u32,
+  // How hard FILTER_SHARPEN pushes edges, 0 to 1
+  sharpness: f32,
+  // Resolution being drawn to, in pixels
+  size: vec2<f32>,
+  // Nonzero to draw the crosshair
+  crosshair: u32,
+}
+@group(0) @binding(2)
+var<uniform> upscale: Upscale;
+
+// Matches UpscaleFilter in wgpu_ctx.rs
+const FILTER_NEAREST = 0u;
+const FILTER_BILINEAR = 1u;
+const FILTER_SHARPEN = 2u;
+// How far the crosshair's arms reach from the center, in pixels
+const CROSSHAIR_SIZE = 8.0;
 
 @fragment
-fn fs_main(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
-  let uv = SCALE * frag_coord.xy / vec2<f32>(textureDimensions(my_texture));
-  let flipped_uv = vec2<f32>(uv.x, 1.0 - uv.y);
-  return textureSample(my_texture, my_sampler, flipped_uv);
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+  // Interpolated rather than derived from the pixel, so the texture can be any resolution
+  var color: vec4<f32>;
+  switch upscale.mode {
+    case FILTER_NEAREST: { color = nearest(in.uv); }
+    case FILTER_SHARPEN: { color = vec4(sharpen(in.uv), 1.0); }
+    default: { color = textureSample(my_texture, linear_sampler, in.uv); }
+  }
+  // Inverted, so it shows up against anything
+  if upscale.crosshair != 0 && on_crosshair(in.pos.xy) { color = vec4(1.0 - color.rgb, 1.0); }
+  return color;
+}
+
+// A plus two pixels thick at the center of the screen
+fn on_crosshair(pixel: vec2<f32>) -> bool {
+  let offset = abs(pixel - upscale.size * 0.5);
+  return (offset.x < 1.0 && offset.y <= CROSSHAIR_SIZE) || (offset.y < 1.0 && offset.x <= CROSSHAIR_SIZE);
+}
+
+fn nearest(uv: vec2<f32>) -> vec4<f32> {
+  let size = textureDimensions(my_texture);
+  let texel = min(vec2<u32>(uv * vec2<f32>(size)), size - 1);
+  return textureLoad(my_texture, texel, 0);
+}
+
+// Bilinear, then sharpened against the neighbouring texels in the style of AMD's contrast adaptive sharpening.
+// Flat areas and already high contrast edges are sharpened less, so it doesn't ring or amplify noise.
+fn sharpen(uv: vec2<f32>) -> vec3<f32> {
+  let texel = 1.0 / vec2<f32>(textureDimensions(my_texture));
+  let center = textureSample(my_texture, linear_sampler, uv).rgb;
+  let left = textureSample(my_texture, linear_sampler, uv - vec2(texel.x, 0.0)).rgb;
+  let right = textureSample(my_texture, linear_sampler, uv + vec2(texel.x, 0.0)).rgb;
+  let down = textureSample(my_texture, linear_sampler, uv - vec2(0.0, texel.y)).rgb;
+  let up = textureSample(my_texture, linear_sampler, uv + vec2(0.0, texel.y)).rgb;
+  let low = min(center, min(min(left, right), min(down, up)));
+  let high = max(center, max(max(left, right), max(down, up)));
+  // How much room there is to sharpen before clipping, per channel
+  let amount = sqrt(clamp(min(low, 1.0 - high) / max(high, vec3(1e-4)), vec3(0.0), vec3(1.0)));
+  // Negative lobe on the neighbours, -1/8 at the softest and -1/5 at the sharpest
+  let weight = amount * -mix(0.125, 0.2, upscale.sharpness);
+  return clamp((center + (left + right + down + up) * weight) / (1.0 + 4.0 * weight), vec3(0.0), vec3(1.0));
 }
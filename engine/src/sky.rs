@@ -0,0 +1,88 @@
+use glam::Vec3;
+use std::f32::consts::TAU;
+
+/// Share of the sky's light that still reaches faces in shadow or facing away from the sun, in broad daylight
+pub const AMBIENT: f32 = 0.35;
+/// Share of it that's left at night
+const NIGHT_AMBIENT: f32 = 0.15;
+/// Sunlight right at the horizon, it whitens to full strength as the sun climbs
+const SUNSET_COLOR: Vec3 = Vec3::new(1.0, 0.55, 0.3);
+/// In-game hours a second, a day every 20 minutes
+pub const DEFAULT_DAY_SPEED: f32 = 24.0 / 1200.0;
+// How far TimeOfDay::faster and slower go, from a day every 15 seconds down to days as long as real ones
+const MAX_DAY_SPEED: f32 = 24.0 / 15.0;
+const MIN_DAY_SPEED: f32 = 24.0 / 86400.0;
+
+/// What rays that escape the world see, and where the sunlight comes from
+#[derive(Debug, Clone, Copy)]
+pub struct Sky {
+  /// Points towards the sun, needn't be normalized
+  pub sun_dir: Vec3,
+  /// Haziness, around 2 is a clear day and 10 is thick haze. Washes the blue out and widens the glow around the sun
+  pub turbidity: f32,
+}
+impl Default for Sky {
+  fn default() -> Self { Self { sun_dir: Vec3::new(0.4, 1.0, 0.3), turbidity: 2.0 } }
+}
+impl Sky {
+  /// Moves the sun to where it'd be at hours past midnight. It rises along +x at 6 and sets along -x at 18,
+  /// leaning slightly towards +z so it's never quite overhead
+  pub fn set_time_of_day(&mut self, hours: f32) {
+    let angle = (hours.rem_euclid(24.0) / 24.0 - 0.25) * TAU;
+    self.sun_dir = Vec3::new(angle.cos(), angle.sin(), 0.3).normalize();
+  }
+
+  /// Inverse of set_time_of_day, ignoring the lean
+  pub fn time_of_day(&self) -> f32 {
+    let dir = self.sun_dir.normalize();
+    (dir.y.atan2(dir.x) / TAU + 0.25).rem_euclid(1.0) * 24.0
+  }
+
+  /// 0 once the sun is well below the horizon, 1 once it's well above, like daylight in ./shaders/lighting.wgsl
+  pub fn daylight(&self) -> f32 { smoothstep(-0.1, 0.2, self.sun_dir.normalize().y) }
+
+  /// How much of the sky's daytime light is left, down to NIGHT_AMBIENT once the sun's gone.
+  /// Scales the ambient light and the fog
+  pub fn brightness(&self) -> f32 { 1.0 - (1.0 - NIGHT_AMBIENT) * (1.0 - self.daylight()) }
+
+  /// Light from the sky itself, reaching everything the sun doesn't
+  pub fn ambient(&self) -> f32 { AMBIENT * self.brightness() }
+
+  /// The sunlight, reddening as the sun gets low and fading out as it sets
+  pub fn sun_color(&self) -> Vec3 {
+    let height = smoothstep(0.0, 0.35, self.sun_dir.normalize().y);
+    (Vec3::ONE - (Vec3::ONE - SUNSET_COLOR) * (1.0 - height)) * self.daylight()
+  }
+}
+
+/// The clock moving the sun through the day, advanced every tick by GameData::step
+#[derive(Debug, Clone, Copy)]
+pub struct TimeOfDay {
+  /// Hours past midnight
+  pub hours: f32,
+  /// In-game hours that go by a second
+  pub speed: f32,
+  pub paused: bool,
+}
+impl TimeOfDay {
+  /// Running from wherever sky's sun is
+  pub fn new(sky: &Sky) -> Self { Self { hours: sky.time_of_day(), speed: DEFAULT_DAY_SPEED, paused: false } }
+
+  /// Moves the clock on by dt seconds and the sun along with it
+  pub fn advance(&mut self, sky: &mut Sky, dt: f32) {
+    if self.paused || self.speed == 0.0 { return }
+    self.hours = (self.hours + self.speed * dt).rem_euclid(24.0);
+    sky.set_time_of_day(self.hours);
+  }
+
+  /// Doubles how fast time goes
+  pub fn faster(&mut self) { self.speed = (self.speed.max(MIN_DAY_SPEED) * 2.0).min(MAX_DAY_SPEED) }
+
+  /// Halves how fast time goes
+  pub fn slower(&mut self) { self.speed = (self.speed / 2.0).max(MIN_DAY_SPEED) }
+}
+
+fn smoothstep(low: f32, high: f32, x: f32) -> f32 {
+  let t = ((x - low) / (high - low)).clamp(0.0, 1.0);
+  t * t * (3.0 - 2.0 * t)
+}
@@ -0,0 +1,68 @@
+//! `--smoke-test`: runs every subsystem once without a window, to check a new machine is set up right.
+//! Each step logs what it did, the first to go wrong stops the test.
+
+use crate::objects::{DagRef, GameData, VoxelObject};
+use crate::physics::TIMESTEP;
+use crate::saves;
+use crate::templates;
+use crate::wgpu_ctx::WgpuCtx;
+use glam::{IVec3, UVec3, Vec3};
+use std::time::Instant;
+
+const FRAMES: u32 = 3;
+const TICKS: u64 = 10;
+// Deleted again at the end, unless the test fails partway
+const SLOT: &str = "smoke-test";
+
+pub fn run() -> Result<(), String> {
+  let start = Instant::now();
+  let template = templates::find("flat").unwrap();
+  let mut game_data = GameData::new(template, templates::DEFAULT_SEED);
+  // Something for physics to move, a crate dropped from above the floor
+  let head = game_data.sdg.build(2, |_| 1);
+  let crate_object = VoxelObject::new(&game_data.sdg, DagRef::new(head, 2), UVec3::ZERO, UVec3::splat(3), Vec3::new(30.0, 12.0, 30.0));
+  let dropped = game_data.add_object(crate_object, true);
+  tracing::info!("Generated {} with {} objects and {} nodes", template.name, game_data.objects.len(), game_data.sdg.nodes.len());
+
+  let mut ctx = WgpuCtx::headless(320, 180)?;
+  game_data.camera.aspect_ratio = 320.0 / 180.0;
+  ctx.update_voxels(&game_data.sdg);
+  ctx.update_materials(&game_data.materials);
+  let frames = ctx.render_frames(&game_data, FRAMES)?;
+  // The default camera looks down at the floor, a frame of nothing but clear color means nothing was drawn
+  if frames.last().unwrap().rgba.chunks_exact(4).all(|texel| texel[.. 3] == [0, 0, 0]) { return Err("The last frame came back black".into()) }
+  tracing::info!("Rendered {FRAMES} frames offscreen");
+
+  let height = game_data.objects[dropped].pos.y;
+  let first_tick = game_data.tick;
+  // A little over a step at a time, so float error in the accumulator can't leave one short
+  while game_data.tick < first_tick + TICKS { game_data.step_physics(TIMESTEP * 1.01) }
+  let fallen = height - game_data.objects[dropped].pos.y;
+  if fallen <= 0.0 { return Err(format!("The crate didn't fall in {TICKS} ticks")) }
+  tracing::info!("Stepped physics {TICKS} ticks, the crate fell {fallen:.3}");
+
+  // A cell off the negative corner of the level, which has to grow the grid to fit and keep its coordinates
+  let below = IVec3::new(-3, 0, -3);
+  let world = game_data.objects[0].transform().transform_point3(below.as_vec3() + 0.5);
+  let stone = game_data.blocks.leaf("stone").ok_or("There's no stone to place")?;
+  let placed = game_data.set_world_cell(0, world, stone).ok_or("The level couldn't grow to fit a negative cell")?;
+  let found = game_data.raycast(world + Vec3::Y * 4.0, Vec3::NEG_Y, 8.0).filter(|hit| hit.object == 0).map(|hit| hit.cell);
+  if placed != below || found != Some(below) { return Err(format!("Setting cell {below} went to {placed} and reads back as {found:?}")) }
+  tracing::info!("Set cell {below}, the level's grid now starts at {}", game_data.objects[0].origin);
+
+  saves::save(&mut game_data, SLOT).map_err(|err| format!("Saving failed: {err}"))?;
+  let loaded = saves::load(SLOT).map_err(|err| format!("Loading failed: {err}"))?;
+  saves::delete(SLOT).map_err(|err| format!("Deleting the save failed: {err}"))?;
+  if loaded.objects.len() != game_data.objects.len() { return Err("The reloaded world has a different number of objects".into()) }
+  if loaded.tick != game_data.tick { return Err("The reloaded world is on a different tick".into()) }
+  if loaded.objects[0].origin != game_data.objects[0].origin { return Err("The reloaded level starts somewhere else".into()) }
+  // Looking straight down from the same spots should find the same materials at the same depths
+  for (x, z) in [(8.0, 8.0), (31.5, 31.5), (50.0, 20.0)] {
+    let look = |world: &GameData| world.raycast(Vec3::new(x, 60.0, z), Vec3::NEG_Y, 128.0).map(|hit| (hit.cell, world.materials.get(hit.leaf)));
+    if look(&game_data) != look(&loaded) { return Err(format!("The reloaded world differs under ({x}, {z})")) }
+  }
+  tracing::info!("Saved and reloaded the world");
+
+  tracing::info!("Smoke test passed in {:.2}s", start.elapsed().as_secs_f32());
+  Ok(())
+}
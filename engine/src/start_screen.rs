@@ -0,0 +1,189 @@
+use crate::loading::{LoadState, WorldSource};
+use crate::saves::{self, SaveInfo};
+use crate::templates::{self, TEMPLATES};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Room each thumbnail takes up in the list, in points
+const THUMBNAIL_SIZE: egui::Vec2 = egui::vec2(96.0, 54.0);
+
+enum Action {
+  Play(String),
+  Duplicate(String),
+  Delete(String),
+}
+
+/// Picks the world to play before one exists: a new one from a template and seed, or an existing save
+pub struct StartScreen {
+  saves: Vec<SaveInfo>,
+  // None for saves without a readable thumbnail, so they aren't read again every frame
+  thumbnails: HashMap<String, Option<egui::TextureHandle>>,
+  name: String,
+  template: usize,
+  seed: i32,
+  // Save waiting on a second click of its delete button
+  confirm_delete: Option<String>,
+  /// Shown under Create, set by the app when a picked world fails to load
+  pub error: Option<String>,
+  /// Set once a world has been picked, the app loads it from there
+  pub started: Option<WorldSource>,
+  /// The world being loaded and how far along it is, kept up to date by the app. Nothing else can be picked meanwhile
+  pub loading: Option<(String, LoadState)>,
+}
+impl Default for StartScreen {
+  fn default() -> Self { Self::new() }
+}
+impl StartScreen {
+  pub fn new() -> Self {
+    let saves = saves::list();
+    Self {
+      name: (1 ..).map(|n| format!("World {n}")).find(|name| !saves::exists(name)).unwrap(),
+      saves,
+      thumbnails: HashMap::new(),
+      template: TEMPLATES.iter().position(|template| template.name == templates::DEFAULT).unwrap_or(0),
+      seed: templates::DEFAULT_SEED,
+      confirm_delete: None,
+      error: None,
+      started: None,
+      loading: None,
+    }
+  }
+
+  pub fn ui(&mut self, ctx: &egui::Context) {
+    egui::Window::new("Worlds")
+      .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+      .collapsible(false)
+      .resizable(false)
+      .show(ctx, |ui| {
+        if let Some((name, state)) = &self.loading {
+          ui.horizontal(|ui| {
+            ui.spinner();
+            ui.label(match state {
+              LoadState::Queued => format!("Waiting to load {name}"),
+              LoadState::Working => format!("Loading {name}"),
+            });
+          });
+          return
+        }
+        ui.heading("New world");
+        egui::Grid::new("new_world").num_columns(2).show(ui, |ui| {
+          ui.label("Name");
+          ui.text_edit_singleline(&mut self.name);
+          ui.end_row();
+          ui.label("Template");
+          egui::ComboBox::from_id_salt("template").selected_text(TEMPLATES[self.template].name).show_ui(ui, |ui| {
+            for (idx, template) in TEMPLATES.iter().enumerate() {
+              ui.selectable_value(&mut self.template, idx, template.name).on_hover_text(template.description);
+            }
+          });
+          ui.end_row();
+          ui.label("Seed");
+          ui.horizontal(|ui| {
+            ui.add(egui::DragValue::new(&mut self.seed));
+            if ui.button("Random").clicked() {
+              self.seed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().subsec_nanos() as i32;
+            }
+          });
+          ui.end_row();
+        });
+        if ui.button("Create").clicked() { self.create() }
+        if let Some(err) = &self.error { ui.colored_label(egui::Color32::LIGHT_RED, err); }
+
+        ui.separator();
+        ui.heading("Saves");
+        if self.saves.is_empty() { ui.label(format!("Nothing saved in {}/ yet", saves::SAVES_DIR)); }
+        let mut action = None;
+        egui::ScrollArea::vertical().max_height(360.0).show(ui, |ui| {
+          for save in &self.saves {
+            ui.horizontal(|ui| {
+              let thumbnail = self.thumbnails.entry(save.name.clone()).or_insert_with(|| load_thumbnail(ctx, &save.thumbnail()));
+              match thumbnail {
+                Some(texture) => { ui.add(egui::Image::new((texture.id(), texture.size_vec2())).fit_to_exact_size(THUMBNAIL_SIZE)); }
+                None => { ui.allocate_exact_size(THUMBNAIL_SIZE, egui::Sense::hover()); }
+              }
+              ui.vertical(|ui| {
+                ui.strong(&save.name);
+                ui.label(format!("{} seed {}", save.template, save.seed));
+                ui.label(format!("Saved {}, {}", ago(save.saved_at), size(save.bytes)));
+                ui.horizontal(|ui| {
+                  if ui.button("Play").clicked() { action = Some(Action::Play(save.name.clone())) }
+                  if ui.button("Duplicate").clicked() { action = Some(Action::Duplicate(save.name.clone())) }
+                  let confirming = self.confirm_delete.as_ref() == Some(&save.name);
+                  if ui.button(if confirming { "Really delete?" } else { "Delete" }).clicked() { action = Some(Action::Delete(save.name.clone())) }
+                });
+              });
+            });
+          }
+        });
+        if let Some(action) = action { self.act(action) }
+      });
+  }
+
+  fn create(&mut self) {
+    let name = self.name.trim().to_string();
+    let checked = saves::check_name(&name)
+      .and_then(|()| if saves::exists(&name) { Err(format!("There's already a save called {name}")) } else { Ok(()) });
+    if let Err(err) = checked {
+      self.error = Some(err);
+      return
+    }
+    self.error = None;
+    self.started = Some(WorldSource::New { template: &TEMPLATES[self.template], seed: self.seed, slot: Some(name) });
+  }
+
+  fn act(&mut self, action: Action) {
+    let result = match action {
+      Action::Play(name) => {
+        self.started = Some(WorldSource::Save(name));
+        Ok(())
+      }
+      Action::Duplicate(name) => saves::duplicate(&name).map(drop),
+      Action::Delete(name) if self.confirm_delete.as_ref() == Some(&name) => {
+        self.confirm_delete = None;
+        self.thumbnails.remove(&name);
+        saves::delete(&name)
+      }
+      Action::Delete(name) => {
+        self.confirm_delete = Some(name);
+        return
+      }
+    };
+    self.error = result.err().map(|err| err.to_string());
+    self.saves = saves::list();
+  }
+}
+
+/// Reads a binary PPM like the ones write_screenshot makes
+fn load_thumbnail(ctx: &egui::Context, path: &Path) -> Option<egui::TextureHandle> {
+  let bytes = std::fs::read(path).ok()?;
+  // Header is four whitespace separated fields, the last followed by exactly one byte of whitespace
+  let mut fields = Vec::new();
+  let mut at = 0;
+  while fields.len() < 4 {
+    while bytes.get(at)?.is_ascii_whitespace() { at += 1 }
+    let start = at;
+    while !bytes.get(at)?.is_ascii_whitespace() { at += 1 }
+    fields.push(std::str::from_utf8(&bytes[start .. at]).ok()?);
+  }
+  if fields[0] != "P6" || fields[3] != "255" { return None }
+  let (width, height): (usize, usize) = (fields[1].parse().ok()?, fields[2].parse().ok()?);
+  let pixels = bytes.get(at + 1 .. at + 1 + width * height * 3)?;
+  let image = egui::ColorImage::from_rgb([width, height], pixels);
+  Some(ctx.load_texture(path.to_string_lossy(), image, egui::TextureOptions::LINEAR))
+}
+
+fn ago(timestamp: u64) -> String {
+  let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+  let seconds = now.saturating_sub(timestamp);
+  match seconds {
+    0 ..= 59 => "just now".into(),
+    60 ..= 3599 => format!("{} min ago", seconds / 60),
+    3600 ..= 86399 => format!("{} h ago", seconds / 3600),
+    _ => format!("{} days ago", seconds / 86400),
+  }
+}
+
+fn size(bytes: u64) -> String {
+  if bytes < 1 << 20 { format!("{:.1} KiB", bytes as f32 / 1024.0) } else { format!("{:.1} MiB", bytes as f32 / (1 << 20) as f32) }
+}
@@ -0,0 +1,146 @@
+//! Terrain without edges. The ground is cut into cubes (chunks) of 2^height cells on a grid in x and z,
+//! generated on worker threads as the camera comes near and dropped again once it's left them behind.
+//! Each chunk is a fixed object of its own, so the renderer just sees a handful more heads and offsets.
+//! Chunks that have been edited stay loaded however far away the camera goes, regenerating them would undo the edits.
+//!
+//! The world's graph never leaves the main thread. Workers build each chunk in a graph of their own instead,
+//! which the main thread merges in with clone_from, only hashing the chunk's distinct nodes rather than every cell.
+
+use crate::debug::DebugLines;
+use crate::objects::{DagRef, GameData, VoxelObject};
+use crate::worldgen::{self, TerrainConfig, TerrainLeaves};
+use glam::{IVec2, IVec3, UVec3, Vec3, Vec3Swizzles};
+use sdg::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::sync::{mpsc, Arc, Mutex};
+
+const WORKERS: usize = 2;
+// Chunks asked for at once, few enough that the nearest still come first once the camera turns around
+const MAX_PENDING: usize = WORKERS * 2;
+// Merging a chunk's graph into the world's happens on the main thread, this many at most per tick
+const MERGES_PER_TICK: usize = 1;
+
+// A chunk built by a worker in a graph of its own, bounded to what's solid like the templates' objects
+struct BuiltChunk {
+  chunk: IVec2,
+  sdg: SparseDirectedGraph<BasicNode3d>,
+  head: Index,
+  min: UVec3,
+  max: UVec3,
+}
+
+/// Streams TerrainConfig's terrain in around the camera, see the top of this file
+pub struct WorldManager {
+  config: TerrainConfig,
+  leaves: TerrainLeaves,
+  /// Chunks whose centers are within this many chunks of the camera (in x and z) are loaded,
+  /// they're dropped again a chunk further out so walking along a border doesn't thrash
+  pub radius: f32,
+  // Chunk -> its handle in GameData::objects
+  loaded: HashMap<IVec2, usize>,
+  // Sent to the workers, not back yet
+  pending: HashSet<IVec2>,
+  jobs: mpsc::Sender<IVec2>,
+  done: mpsc::Receiver<BuiltChunk>,
+}
+impl WorldManager {
+  pub fn new(config: TerrainConfig, leaves: TerrainLeaves) -> Self {
+    let (jobs, job_queue) = mpsc::channel::<IVec2>();
+    let (finished, done) = mpsc::channel();
+    let job_queue = Arc::new(Mutex::new(job_queue));
+    for _ in 0 .. WORKERS {
+      let (job_queue, finished) = (job_queue.clone(), finished.clone());
+      // Each worker stops once the manager (and with it the sending half of the queue) is dropped
+      std::thread::spawn(move || loop {
+        let Ok(chunk) = job_queue.lock().unwrap().recv() else { return };
+        if finished.send(Self::build(&config, chunk)).is_err() { return }
+      });
+    }
+    Self { config, leaves, radius: 3.0, loaded: HashMap::new(), pending: HashSet::new(), jobs, done }
+  }
+
+  /// Cells along each side of a chunk
+  pub fn chunk_size(&self) -> u32 { 1 << self.config.height }
+
+  pub fn loaded(&self) -> usize { self.loaded.len() }
+
+  fn origin(config: &TerrainConfig, chunk: IVec2) -> IVec3 { IVec3::new(chunk.x, 0, chunk.y) << config.height }
+
+  // How many chunks from the camera chunk's center is, in x and z
+  fn distance(&self, chunk: IVec2, camera: Vec3) -> f32 {
+    let center = (chunk.as_vec2() + 0.5) * self.chunk_size() as f32;
+    center.distance(camera.xz()) / self.chunk_size() as f32
+  }
+
+  /// Asks for whatever's newly in range, merges in what the workers have finished and drops what's out of range
+  pub fn update(&mut self, game_data: &mut GameData) {
+    let camera = game_data.camera.position;
+    let reach = self.radius.ceil() as i32;
+    let center = (camera.xz() / self.chunk_size() as f32).floor().as_ivec2();
+    let mut wanted: Vec<IVec2> = (-reach ..= reach).flat_map(|z| (-reach ..= reach).map(move |x| center + IVec2::new(x, z)))
+      .filter(|&chunk| self.distance(chunk, camera) <= self.radius)
+      .filter(|chunk| !self.loaded.contains_key(chunk) && !self.pending.contains(chunk))
+      .collect();
+    wanted.sort_by(|a, b| self.distance(*a, camera).total_cmp(&self.distance(*b, camera)));
+    for chunk in wanted.into_iter().take(MAX_PENDING.saturating_sub(self.pending.len())) {
+      self.pending.insert(chunk);
+      self.jobs.send(chunk).unwrap();
+    }
+
+    for built in self.done.try_iter().take(MERGES_PER_TICK) {
+      self.pending.remove(&built.chunk);
+      // The camera may have moved on while it was generating
+      if self.distance(built.chunk, camera) > self.radius + 1.0 { continue }
+      // The worker added its leaves as empty, solid then surface, so they're 0 to 2
+      let leaves = [self.leaves.empty, self.leaves.solid, self.leaves.surface];
+      let head = match game_data.sdg.clone_from(&built.sdg, built.head, |leaf| leaves[leaf as usize]) {
+        Ok(head) => head,
+        Err(err) => { tracing::error!("Failed to merge chunk {}: {err}", built.chunk); continue }
+      };
+      let pos = Self::origin(&self.config, built.chunk).as_vec3();
+      let object = VoxelObject::new(&game_data.sdg, DagRef::new(head, self.config.height), built.min, built.max, pos);
+      self.loaded.insert(built.chunk, game_data.add_object(object, false));
+      game_data.graph_changed = true;
+    }
+
+    let far: Vec<IVec2> = self.loaded.iter()
+      .filter(|&(&chunk, &idx)| self.distance(chunk, camera) > self.radius + 1.0 && !game_data.objects[idx].edited)
+      .map(|(&chunk, _)| chunk).collect();
+    for chunk in far {
+      let idx = self.loaded.remove(&chunk).unwrap();
+      game_data.remove_object(idx);
+    }
+  }
+
+  /// Outlines every loaded chunk in orange and every one still generating in gray
+  pub fn draw_chunks(&self, lines: &mut DebugLines) {
+    let size = Vec3::splat(self.chunk_size() as f32);
+    let chunks = self.loaded.keys().map(|chunk| (chunk, Vec3::new(1.0, 0.5, 0.0)))
+      .chain(self.pending.iter().map(|chunk| (chunk, Vec3::splat(0.5))));
+    for (&chunk, color) in chunks {
+      let min = Self::origin(&self.config, chunk).as_vec3();
+      lines.aabb(min, min + size, color);
+    }
+  }
+
+  /// Forgets the object at idx if it was a chunk
+  pub fn remove_object(&mut self, idx: usize) { self.loaded.retain(|_, other| *other != idx) }
+
+  // Generates chunk into a graph of its own, on a worker
+  fn build(config: &TerrainConfig, chunk: IVec2) -> BuiltChunk {
+    let mut sdg = SparseDirectedGraph::new();
+    // Stand ins for the world's leaves, mapped back onto them by the merge
+    let leaves = TerrainLeaves { empty: sdg.add_leaf(), solid: sdg.add_leaf(), surface: sdg.add_leaf() };
+    let cells = worldgen::cells(config, leaves, Self::origin(config, chunk));
+    let size = 1 << config.height;
+    // cells always makes a whole grid of the leaves it's given
+    let head = sdg.from_dense(&cells, size).unwrap();
+    let (mut min, mut max) = (UVec3::MAX, UVec3::ZERO);
+    for (idx, _) in cells.iter().enumerate().filter(|&(_, &leaf)| leaf != leaves.empty) {
+      let cell = UVec3::new(idx as u32 % size, idx as u32 / size % size, idx as u32 / size / size);
+      (min, max) = (min.min(cell), max.max(cell));
+    }
+    if min.cmpgt(max).any() { (min, max) = (UVec3::ZERO, UVec3::splat(size - 1)) }
+    BuiltChunk { chunk, sdg, head, min, max }
+  }
+}
@@ -0,0 +1,81 @@
+use crate::objects::{DagRef, VoxelObject};
+use crate::worldgen::{self, TerrainConfig, TerrainLeaves};
+use glam::{UVec3, Vec3};
+use sdg::prelude::*;
+
+/// A named world that's only built once it's picked
+pub struct WorldTemplate {
+  pub name: &'static str,
+  pub description: &'static str,
+  /// Templates without any randomness ignore the seed
+  pub build: fn(&mut SparseDirectedGraph<BasicNode3d>, TerrainLeaves, i32) -> Vec<VoxelObject>,
+  /// Some to stream terrain from this config (given the seed) in around the camera as well, see streaming.rs
+  pub stream: Option<fn(i32) -> TerrainConfig>,
+}
+
+pub const DEFAULT: &str = "noise";
+pub const DEFAULT_SEED: i32 = 1337;
+
+pub const TEMPLATES: &[WorldTemplate] = &[
+  WorldTemplate { name: "flat", description: "A plain 64x64 floor", build: flat, stream: None },
+  WorldTemplate { name: "pyramid", description: "Terraced pyramid on a floor", build: pyramid, stream: None },
+  WorldTemplate { name: "noise", description: "Noise terrain", build: noise, stream: None },
+  WorldTemplate { name: "caves", description: "Noise terrain riddled with caves", build: caves, stream: None },
+  WorldTemplate { name: "checkerboard", description: "3d checkerboard, worst case for the ray marcher", build: checkerboard, stream: None },
+  WorldTemplate { name: "endless", description: "Noise terrain without edges, streamed in as you go", build: nothing, stream: Some(endless) },
+];
+
+pub fn find(name: &str) -> Option<&'static WorldTemplate> {
+  TEMPLATES.iter().find(|template| template.name == name)
+}
+
+/// Builds an object from a sample fn, tracking the bounds of everything non-empty
+fn object(sdg: &mut SparseDirectedGraph<BasicNode3d>, height: u32, pos: Vec3, empty: Index, mut sample: impl FnMut(UVec3) -> Index) -> VoxelObject {
+  let (mut min, mut max) = (UVec3::MAX, UVec3::ZERO);
+  let head = sdg.build(height, |cell| {
+    let leaf = sample(cell);
+    if leaf != empty { (min, max) = (min.min(cell), max.max(cell)) }
+    leaf
+  });
+  // Nothing solid, fall back to the full grid so the bounds stay valid
+  if min.cmpgt(max).any() { (min, max) = (UVec3::ZERO, UVec3::splat((1 << height) - 1)) }
+  VoxelObject::new(sdg, DagRef::new(head, height), min, max, pos)
+}
+
+fn flat(sdg: &mut SparseDirectedGraph<BasicNode3d>, leaves: TerrainLeaves, _seed: i32) -> Vec<VoxelObject> {
+  vec![object(sdg, 6, Vec3::ZERO, leaves.empty, |cell| match cell.y {
+    0 ..= 2 => leaves.solid,
+    3 => leaves.surface,
+    _ => leaves.empty,
+  })]
+}
+
+fn pyramid(sdg: &mut SparseDirectedGraph<BasicNode3d>, leaves: TerrainLeaves, _seed: i32) -> Vec<VoxelObject> {
+  const SIZE: u32 = 32;
+  vec![object(sdg, 5, Vec3::ZERO, leaves.empty, |cell| {
+    if cell.y == 0 { return leaves.solid }
+    // Each terrace is 2 cells tall and steps in by 2 on every side
+    let inset = (cell.y - 1) / 2 * 2 + 2;
+    let inside = |axis: u32| axis >= inset && axis < SIZE - inset;
+    if inside(cell.x) && inside(cell.z) { leaves.surface } else { leaves.empty }
+  })]
+}
+
+fn noise(sdg: &mut SparseDirectedGraph<BasicNode3d>, leaves: TerrainLeaves, seed: i32) -> Vec<VoxelObject> {
+  let config = TerrainConfig { seed, caves: None, ..Default::default() };
+  vec![worldgen::terrain(sdg, &config, leaves, Vec3::ZERO)]
+}
+
+fn caves(sdg: &mut SparseDirectedGraph<BasicNode3d>, leaves: TerrainLeaves, seed: i32) -> Vec<VoxelObject> {
+  vec![worldgen::terrain(sdg, &TerrainConfig { seed, ..Default::default() }, leaves, Vec3::ZERO)]
+}
+
+fn nothing(_sdg: &mut SparseDirectedGraph<BasicNode3d>, _leaves: TerrainLeaves, _seed: i32) -> Vec<VoxelObject> { Vec::new() }
+
+fn endless(seed: i32) -> TerrainConfig { TerrainConfig { seed, caves: None, ..Default::default() } }
+
+fn checkerboard(sdg: &mut SparseDirectedGraph<BasicNode3d>, leaves: TerrainLeaves, _seed: i32) -> Vec<VoxelObject> {
+  vec![object(sdg, 6, Vec3::ZERO, leaves.empty, |cell| {
+    if cell.y >= 32 || (cell.x + cell.y + cell.z) & 1 == 1 { leaves.empty } else { leaves.solid }
+  })]
+}
@@ -0,0 +1,67 @@
+//! Textures for materials. An atlas is a PNG of square tiles, numbered left to right and then top to bottom,
+//! and a material with a tile (see Material::tile) has its albedo multiplied by it. The lighting pass wraps it
+//! around cells triplanarly, a tile to a cell face, in each object's own grid so it turns with the object.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// Pixels across a tile, atlases are a grid of them
+pub const TILE_SIZE: u32 = 16;
+// The least every GPU has to support in a texture array, a layer per tile
+const MAX_TILES: u32 = 256;
+
+/// The tiles of an atlas, each one a layer of the texture array the shaders sample
+pub struct Atlas {
+  /// Pixels across (and down) each tile
+  pub tile_size: u32,
+  pub tiles: u32,
+  /// sRGB RGBA, one tile after another, each a row of pixels at a time
+  pub pixels: Vec<u8>,
+}
+impl Atlas {
+  /// Reads a PNG whose sides are multiples of TILE_SIZE. Gray and palette images are widened to RGBA
+  pub fn load(path: &Path) -> Result<Self, String> {
+    let file = File::open(path).map_err(|err| err.to_string())?;
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    // Palettes and bit depths under 8 come out as plain 8 bit samples, 16 bit ones are cut down to 8
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(|err| err.to_string())?;
+    let mut bytes = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut bytes).map_err(|err| err.to_string())?;
+    if info.width % TILE_SIZE != 0 || info.height % TILE_SIZE != 0 {
+      return Err(format!("it's {}x{}, which doesn't split into {TILE_SIZE} pixel tiles", info.width, info.height))
+    }
+    let rgba: Vec<[u8; 4]> = match info.color_type {
+      png::ColorType::Grayscale => bytes.iter().map(|&gray| [gray, gray, gray, 255]).collect(),
+      png::ColorType::GrayscaleAlpha => bytes.chunks_exact(2).map(|pair| [pair[0], pair[0], pair[0], pair[1]]).collect(),
+      png::ColorType::Rgb => bytes.chunks_exact(3).map(|rgb| [rgb[0], rgb[1], rgb[2], 255]).collect(),
+      _ => bytes.chunks_exact(4).map(|rgba| [rgba[0], rgba[1], rgba[2], rgba[3]]).collect(),
+    };
+    let (columns, rows) = (info.width / TILE_SIZE, info.height / TILE_SIZE);
+    let tiles = (columns * rows).min(MAX_TILES);
+    if columns * rows > MAX_TILES { tracing::warn!("Only the first {MAX_TILES} of the atlas' {} tiles are used", columns * rows) }
+    let mut pixels = Vec::with_capacity((tiles * TILE_SIZE * TILE_SIZE * 4) as usize);
+    for tile in 0 .. tiles {
+      let corner = ((tile % columns) * TILE_SIZE, (tile / columns) * TILE_SIZE);
+      for y in corner.1 .. corner.1 + TILE_SIZE {
+        let row = (y * info.width + corner.0) as usize;
+        for pixel in &rgba[row .. row + TILE_SIZE as usize] { pixels.extend_from_slice(pixel) }
+      }
+    }
+    Ok(Self { tile_size: TILE_SIZE, tiles, pixels })
+  }
+
+  /// Loads the atlas at path, or goes without one if there isn't a file there or it's unreadable
+  pub fn load_or_default(path: &Path) -> Self {
+    if !path.exists() { return Self::default() }
+    Self::load(path).unwrap_or_else(|err| {
+      tracing::warn!("Materials are untextured, {} is invalid: {err}", path.display());
+      Self::default()
+    })
+  }
+}
+impl Default for Atlas {
+  // A single white pixel, since the shaders need something bound. With no tiles nothing samples it
+  fn default() -> Self { Self { tile_size: 1, tiles: 0, pixels: vec![255; 4] } }
+}
@@ -1,6 +1,12 @@
-use crate::{camera::Camera, objects::DagRef};
-use glam::Mat4;
+use crate::{camera::Camera, objects::{DagRef, RayHit}};
+use glam::{Mat4, UVec2, UVec3, Vec2, Vec3};
 use crate::objects::VoxelObject;
+use crate::decals::Decal;
+use crate::materials::Material;
+use crate::lights::{Light, LightKind};
+use crate::sky::Sky;
+use crate::bounce::Average;
+use crate::wgpu_ctx::RenderSettings;
 
 #[repr(C, align(16))]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
@@ -18,22 +24,29 @@ pub struct ObjData {
   dag_ref: DagRef,
   // head: u32,
   // height: u32,
-  pad4: [u32; 2],
+  ambient: f32,
+  // Whether head is an entry in the packed layout rather than a node index, see NodeFormat
+  packed: u32,
+  // Word the packed entries are offsets from, where the object's own region starts when each is packed on its own
+  base: u32,
+  pad4: [u32; 3],
+  // The signed cell at the grid's cell 0, see VoxelObject::origin
+  origin: [i32; 3],
+  pad5: u32,
 }
 impl ObjData {
-  pub fn new(data: &VoxelObject) -> Self {
-    // I don't really understand the matrix math yet, but it works.
-    let inv_transform = 
-      Mat4::from_translation(data.pivot_offset) *
-      Mat4::from_quat(data.rot.inverse()) * 
-      Mat4::from_translation(-data.pos - data.pivot_offset);
+  /// The object alpha of the way between ticks, see VoxelObject::render_pose. packed is (base, head's entry)
+  /// if the graph was uploaded packed, None if it went up raw
+  pub fn new(data: &VoxelObject, alpha: f32, packed: Option<(u32, u32)>) -> Self {
+    let (pos, rot) = data.render_pose(alpha);
+    let inv_transform = data.inv_transform_at(pos, rot);
     let transform = inv_transform.inverse();
     Self {
-      pos: data.pos.into(),
+      pos: pos.into(),
       pad1: 0,
       min_cell: data.min_cell.into(),
       pad2: 0,
-      extent: (data.max_cell - data.min_cell).into(),
+      extent: (data.max_cell - data.min_cell + 1).into(),
       pad3: 0,
 
       transform: [
@@ -49,10 +62,15 @@ impl ObjData {
         inv_transform.col(3).into(),
       ],
 
-      dag_ref: data.dag_ref,
+      dag_ref: DagRef { head: packed.map_or(data.dag_ref.head, |(_, entry)| entry), ..data.dag_ref },
       // head: data.dag_ref.,
       // height: data.height,
-      pad4: [0; 2],
+      ambient: data.ambient,
+      packed: packed.is_some() as u32,
+      base: packed.map_or(0, |(base, _)| base),
+      pad4: [0; 3],
+      origin: data.origin.into(),
+      pad5: 0,
     }
   }
 }
@@ -72,10 +90,28 @@ pub struct CamData {
 
   aspect_ratio: f32,
   pub tan_fov: f32,
-  pad5: [f32; 2],
+  // How many of the objects are on screen, they come first
+  obj_count: u32,
+  max_distance: f32,
+  max_steps: u32,
+  detail: u32,
+  time: f32,
+  // 0 for perspective
+  ortho_height: f32,
+  debug_view: u32,
+  lod_distance: f32,
+  lod_falloff: f32,
+  all_obj_count: u32,
+  // Of the DDA's output, for the beam pass to find its tiles' rays
+  resolution: [u32; 2],
+  // Subpixel offset of every camera ray this frame, zero unless antialiasing
+  jitter: [f32; 2],
+
+  // Last frame's view_proj, for the temporal pass to find where a point was on screen
+  prev_view_proj: [ [f32; 4]; 4],
 }
 impl CamData {
-  pub fn new(camera: &Camera) -> Self {
+  pub fn new(camera: &Camera, (obj_count, all_obj_count): (u32, u32), settings: &RenderSettings, time: f32, prev_view_proj: Mat4, resolution: UVec2, jitter: Vec2) -> Self {
     Self {
       pos: camera.position.into(),
       pad1: 0.0,
@@ -89,8 +125,352 @@ impl CamData {
 
       aspect_ratio: camera.aspect_ratio,
       tan_fov: (camera.fov / 2.).tan(),
-      pad5: [0.0; 2],
+      obj_count,
+      max_distance: settings.max_distance,
+      max_steps: settings.max_steps,
+      detail: settings.detail as u32,
+      time,
+      ortho_height: camera.ortho_height().unwrap_or(0.0),
+      debug_view: settings.debug_view as u32,
+      lod_distance: settings.lod_distance,
+      lod_falloff: settings.lod_falloff,
+      all_obj_count,
+      resolution: resolution.into(),
+      jitter: jitter.into(),
+
+      prev_view_proj: prev_view_proj.to_cols_array_2d(),
+    }
+  }
+}
+
+#[repr(C, align(16))]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LightData {
+  sun_dir: [f32; 3],
+  obj_count: u32,
+  max_distance: f32,
+  max_steps: u32,
+  // Reseeds the noise every frame so the temporal pass has something to average
+  frame: u32,
+  shadows: u32,
+  ao_rays: u32,
+  turbidity: f32,
+  debug_view: u32,
+  lod_distance: f32,
+  lod_falloff: f32,
+  gi_cones: u32,
+  pad: [u32; 2],
+  // Where camera rays start from, and the way they all go while orthographic (zero while perspective)
+  camera: [f32; 3],
+  pad2: u32,
+  ortho_dir: [f32; 3],
+  pad3: u32,
+  // Both follow the time of day, see Sky
+  sun_color: [f32; 3],
+  ambient: f32,
+}
+impl LightData {
+  /// camera as the frame was drawn, for finding which way reflections bounce
+  pub fn new(sky: &Sky, camera: &Camera, obj_count: u32, settings: &RenderSettings, frame: u32) -> Self {
+    Self {
+      sun_dir: sky.sun_dir.normalize().into(),
+      obj_count,
+      max_distance: settings.max_distance,
+      max_steps: settings.max_steps,
+      frame,
+      shadows: settings.shadows as u32,
+      ao_rays: settings.ao_rays,
+      turbidity: sky.turbidity,
+      debug_view: settings.debug_view as u32,
+      lod_distance: settings.lod_distance,
+      lod_falloff: settings.lod_falloff,
+      gi_cones: settings.gi_cones,
+      pad: [0; 2],
+      camera: camera.position.into(),
+      pad2: 0,
+      ortho_dir: camera.ortho_height().map_or(Vec3::ZERO, |_| camera.forward()).into(),
+      pad3: 0,
+      sun_color: sky.sun_color().into(),
+      ambient: sky.ambient(),
+    }
+  }
+}
+
+// Sits in front of the DecalData array in ./shaders/lighting.wgsl
+#[repr(C, align(16))]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DecalHeader {
+  count: u32,
+  pad: [u32; 3],
+}
+impl DecalHeader {
+  pub fn new(count: u32) -> Self { Self { count, pad: [0; 3] } }
+}
+
+// Sits in front of the MaterialData array in ./shaders/traversal.wgsl, materials are indexed by leaf
+#[repr(C, align(16))]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialHeader {
+  count: u32,
+  pad: [u32; 3],
+}
+impl MaterialHeader {
+  pub fn new(count: u32) -> Self { Self { count, pad: [0; 3] } }
+}
+
+#[repr(C, align(16))]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialData {
+  albedo: [f32; 3],
+  roughness: f32,
+  emissive: [f32; 3],
+  flags: u32,
+  opacity: f32,
+  ior: f32,
+  // NO_TILE in ./shaders/traversal.wgsl when it's untextured
+  tile: u32,
+  pad: f32,
+}
+impl MaterialData {
+  pub fn new(material: &Material) -> Self {
+    Self {
+      albedo: material.albedo.into(),
+      roughness: material.roughness,
+      emissive: material.emissive.into(),
+      flags: material.flags,
+      opacity: material.opacity,
+      ior: material.ior,
+      tile: material.tile.unwrap_or(u32::MAX),
+      pad: 0.0,
+    }
+  }
+}
+
+#[repr(C, align(16))]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DecalData {
+  pos: [f32; 3],
+  size: f32,
+  normal: [f32; 3],
+  opacity: f32,
+  color: [f32; 3],
+  pad: f32,
+}
+impl DecalData {
+  pub fn new(decal: &Decal, tick: u64) -> Self {
+    Self {
+      pos: decal.pos.into(),
+      size: decal.size,
+      normal: decal.normal.into(),
+      opacity: decal.opacity(tick),
+      color: decal.color.truncate().into(),
+      pad: 0.0,
+    }
+  }
+}
+
+// Sits in front of the PointLightData array in ./shaders/lighting.wgsl
+#[repr(C, align(16))]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PointLightHeader {
+  count: u32,
+  pad: [u32; 3],
+}
+impl PointLightHeader {
+  pub fn new(count: u32) -> Self { Self { count, pad: [0; 3] } }
+}
+
+#[repr(C, align(16))]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PointLightData {
+  pos: [f32; 3],
+  radius: f32,
+  color: [f32; 3],
+  // 0 for a point light, 1 for a spotlight
+  kind: u32,
+  dir: [f32; 3],
+  // Cosines of the spotlight's half angle, and of where it starts to soften
+  cos_outer: f32,
+  cos_inner: f32,
+  inset: f32,
+  pad: [f32; 2],
+}
+impl PointLightData {
+  pub fn new(light: &Light) -> Self {
+    let (kind, dir, angle) = match light.kind {
+      LightKind::Point => (0, Vec3::ZERO, 0.0),
+      LightKind::Spot { dir, angle } => (1, dir, angle),
+    };
+    Self {
+      pos: light.pos.into(),
+      radius: light.radius,
+      color: light.color.into(),
+      kind,
+      dir: dir.into(),
+      cos_outer: angle.cos(),
+      cos_inner: (angle * 0.75).cos(),
+      inset: light.inset,
+      pad: [0.0; 2],
+    }
+  }
+}
+
+// An Average as the lighting pass reads it, in the same place in its buffer as the node is in the voxel buffer.
+// Albedo and coverage as 8 bit unorms, emissive the same but with a shared exponent in place of alpha since it can go past 1
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct AverageData {
+  albedo: u32,
+  emissive: u32,
+}
+impl AverageData {
+  pub fn new(average: &Average) -> Self {
+    let unorm = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u32;
+    let albedo = unorm(average.albedo.x) | unorm(average.albedo.y) << 8 | unorm(average.albedo.z) << 16 | unorm(average.coverage) << 24;
+    let brightest = average.emissive.max_element();
+    let emissive = match brightest > 1e-4 {
+      true => {
+        let exponent = brightest.log2().ceil().clamp(-128.0, 127.0);
+        let scaled = average.emissive / exponent.exp2();
+        unorm(scaled.x) | unorm(scaled.y) << 8 | unorm(scaled.z) << 16 | ((exponent + 128.0) as u32) << 24
+      }
+      false => 0,
+    };
+    Self { albedo, emissive }
+  }
+}
+
+// Everything the temporal pass does to the frame after blending, see ./shaders/temporal.wgsl
+#[repr(C, align(16))]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PostData {
+  fog_color: [f32; 3],
+  fog_density: f32,
+  fog_falloff: f32,
+  exposure: f32,
+  outline_width: u32,
+  outline_threshold: f32,
+  tonemap: u32,
+  target_object: u32,
+  pad: [u32; 2],
+  target_cell: [u32; 3],
+  pad2: u32,
+  target_transform: [[f32; 4]; 4],
+  debug_view: u32,
+  pad3: [u32; 3],
+}
+impl PostData {
+  /// target is the object (by its slot in the objects buffer) and cell to outline, along with the object's world -> grid transform
+  /// The fog dims with the sky's light
+  pub fn new(settings: &RenderSettings, sky: &Sky, target: Option<(usize, UVec3, Mat4)>) -> Self {
+    let (object, cell, transform) = target.unwrap_or((0, UVec3::ZERO, Mat4::IDENTITY));
+    Self {
+      fog_color: (settings.fog.color * sky.brightness()).into(),
+      fog_density: settings.fog.density,
+      fog_falloff: settings.fog.falloff,
+      exposure: settings.exposure,
+      outline_width: settings.outline_width,
+      outline_threshold: settings.outline_threshold,
+      tonemap: settings.tonemap as u32,
+      target_object: target.map_or(0, |_| object as u32 + 1),
+      pad: [0; 2],
+      target_cell: cell.into(),
+      pad2: 0,
+      target_transform: transform.to_cols_array_2d(),
+      debug_view: settings.debug_view as u32,
+      pad3: [0; 3],
+    }
+  }
+}
+
+// How the upscale pass filters, see ./shaders/upscale.wgsl
+#[repr(C, align(16))]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct UpscaleData {
+  mode: u32,
+  sharpness: f32,
+  size: [f32; 2],
+  crosshair: u32,
+  pad: [u32; 3],
+}
+impl UpscaleData {
+  /// size is the resolution being drawn to
+  pub fn new(settings: &RenderSettings, size: [f32; 2]) -> Self {
+    Self { mode: settings.upscale_filter as u32, sharpness: settings.sharpness, size, crosshair: settings.crosshair as u32, pad: [0; 3] }
+  }
+}
+
+// Sits in front of the per object motion matrices in ./shaders/temporal.wgsl
+#[repr(C, align(16))]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MotionHeader {
+  // Nonzero when the history is stale and shouldn't be blended in
+  reset: u32,
+  pad: [u32; 3],
+}
+impl MotionHeader {
+  pub fn new(reset: bool) -> Self { Self { reset: reset as u32, pad: [0; 3] } }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LineVertex {
+  pos: [f32; 3],
+  color: [f32; 3],
+}
+impl LineVertex {
+  pub fn new(pos: Vec3, color: Vec3) -> Self { Self { pos: pos.into(), color: color.into() } }
+
+  pub const ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3];
+}
+
+#[repr(C, align(16))]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ViewData {
+  view_proj: [ [f32; 4]; 4],
+}
+impl ViewData {
+  pub fn new(camera: &Camera) -> Self {
+    let view_proj = camera.view_proj();
+    Self {
+      view_proj: [
+        view_proj.col(0).into(),
+        view_proj.col(1).into(),
+        view_proj.col(2).into(),
+        view_proj.col(3).into(),
+      ],
     }
   }
 }
 
+// What the DDA found under the center pixel, see ./shaders/dda.wgsl
+#[repr(C, align(16))]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PickData {
+  // Signed, see VoxelObject::origin
+  cell: [i32; 3],
+  // Slot in the objects buffer + 1, 0 if nothing was hit
+  object: u32,
+  normal: [i32; 3],
+  leaf: u32,
+  pos: [f32; 3],
+  // Distance from the camera
+  t: f32,
+  uv: [f32; 2],
+  pad: [u32; 2],
+}
+impl PickData {
+  /// drawn is what was in the objects buffer when it was drawn, to find the object it hit in GameData::objects
+  pub fn hit(&self, drawn: &[usize]) -> Option<RayHit> {
+    let object = *drawn.get(self.object.checked_sub(1)? as usize)?;
+    Some(RayHit {
+      object,
+      cell: self.cell.into(),
+      normal: self.normal.into(),
+      t: self.t,
+      leaf: self.leaf,
+      pos: self.pos.into(),
+      uv: self.uv.into(),
+    })
+  }
+}
@@ -1,38 +1,117 @@
-use std::{sync::Arc, u32};
-use glam::Vec2;
-use sdg::prelude::{BasicNode3d, SparseDirectedGraph};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, Ordering};
+use glam::{Mat4, Vec2, Vec3};
+use sdg::prelude::{BasicNode3d, Index, PackedNodes, SparseDirectedGraph};
 use winit::window::Window;
-use crate::objects::GameData;
+use crate::camera::Camera;
+use crate::objects::{GameData, RayHit};
 use crate::wgpu_buffers::*;
+use crate::shaders;
+use crate::decals::MAX_DECALS;
+use crate::lights::MAX_LIGHTS;
+use crate::materials::MaterialRegistry;
+use crate::overlay::Overlay;
+use crate::start_screen::StartScreen;
+use crate::profiling::{self, count, zone};
+use crate::physics::TIMESTEP;
+use crate::bounce::NodeAverages;
+use crate::textures::Atlas;
 
-const SCALE: f32 = 1.0 / 1.0; // ./shaders/upscale.wgsl
 const WORKGROUP: u32 = 8;     // ./shaders/dda.wgsl
-const OBJECT_COUNT: u64 = 1;  // ./shaders/dda.wgsl
+const BEAM_TILE: u32 = 8;     // ./shaders/beam.wgsl
+
+// (base, head's entry) by head, for NodeFormat::Objects
+type Regions = HashMap<Index, (u32, u32)>;
+
+// How the objects' heads are found in the voxel buffer, follows Voxels::node_format
+enum VoxelLayout {
+  Raw,
+  // The packing's offsets, with the words themselves dropped once uploaded
+  Packed(PackedNodes),
+  // Where each tree went and (graph index, address) of every node packed,
+  // None until the next draw packs them since only it knows which trees the objects have
+  Objects(Option<(Regions, Vec<(Index, u32)>)>),
+}
+impl VoxelLayout {
+  // For ObjData::new, None for raw and for heads that weren't packed
+  fn locate(&self, head: Index) -> Option<(u32, u32)> {
+    match self {
+      VoxelLayout::Raw => None,
+      VoxelLayout::Packed(packed) => Some((0, packed.entry(head))),
+      VoxelLayout::Objects(regions) => regions.as_ref()?.0.get(&head).copied(),
+    }
+  }
+}
 
 // We can def turn these modules into a trait
 // I'm seconding this, turn these into a trait when I get back!!!
 struct DdaModule {
+  // Gpu::voxels' buffer as of when the bind groups were last built, see WgpuCtx::sync_voxels
   voxel_buffer: wgpu::Buffer,
   cam_buffer: wgpu::Buffer,
   objects_buffer: wgpu::Buffer,
+  // Number of ObjData slots the objects buffer can currently hold
+  objects_capacity: u64,
+  // Indices into GameData::objects of what's in the objects buffer this frame, those on screen first.
+  // Camera rays only march the first on_screen of them, everything else (shadows, reflections...) marches them all
+  drawn: Vec<usize>,
+  on_screen: usize,
+  material_buffer: wgpu::Buffer,
+  // Number of MaterialData slots the material buffer can currently hold
+  material_capacity: u64,
+  // A PickData, read back every frame
+  pick_buffer: wgpu::Buffer,
   bind_group_layout: wgpu::BindGroupLayout,
   pipeline: wgpu::ComputePipeline,
+  // (output, hit, tint, water, beam) kept around so the bind group can be rebuilt when a buffer is reallocated
+  views: Option<(wgpu::TextureView, wgpu::TextureView, wgpu::TextureView, wgpu::TextureView, wgpu::TextureView)>,
   // We can't create the bind group without an associated texture
   bind_group: Option<wgpu::BindGroup>
 } 
 impl DdaModule {
-  fn create(device: &wgpu::Device, bytes_in_voxel_buffer: u64) -> Self {
+  fn create_objects_buffer(device: &wgpu::Device, capacity: u64) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Objects Buffer"),
+      size: std::mem::size_of::<ObjData>() as u64 * capacity,
+      usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    })
+  }
+
+  fn create_material_buffer(device: &wgpu::Device, capacity: u64) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Material Buffer"),
+      size: (std::mem::size_of::<MaterialHeader>() + std::mem::size_of::<MaterialData>() * capacity as usize) as u64,
+      usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    })
+  }
+
+  fn create_voxel_buffer(device: &wgpu::Device, bytes: u64) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Voxel Buffer"),
+      size: bytes,
+      usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+      mapped_at_creation: false
+    })
+  }
+
+  fn create(device: &wgpu::Device, voxel_buffer: wgpu::Buffer) -> Self {
     let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
       label: Some("DDA BGL"),
       entries: &[
-        // (OctNorm1, OctNorm2, Time, BlockType)
+        // (OctNorm1, OctNorm2, Time, BlockType), 32 bit so every leaf index survives
         // Output buffer
         wgpu::BindGroupLayoutEntry {
           binding: 0,
           visibility: wgpu::ShaderStages::COMPUTE,
           ty: wgpu::BindingType::StorageTexture {
             access: wgpu::StorageTextureAccess::WriteOnly,
-            format: wgpu::TextureFormat::Rgba16Float,
+            format: wgpu::TextureFormat::Rgba32Float,
             view_dimension: wgpu::TextureViewDimension::D2,
           },
           count: None,
@@ -70,6 +149,75 @@ impl DdaModule {
           },
           count: None,
         },
+        // (bitcasted world hit position, object + 1 << 16 | u << 8 | v)
+        // Hit buffer
+        wgpu::BindGroupLayoutEntry {
+          binding: 4,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::StorageTexture {
+            access: wgpu::StorageTextureAccess::WriteOnly,
+            format: wgpu::TextureFormat::Rgba32Uint,
+            view_dimension: wgpu::TextureViewDimension::D2,
+          },
+          count: None,
+        },
+        // Material Buffer, translucent leaves are marched through
+        wgpu::BindGroupLayoutEntry {
+          binding: 5,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+          },
+          count: None,
+        },
+        // How much light makes it back through translucent leaves
+        // Tint buffer
+        wgpu::BindGroupLayoutEntry {
+          binding: 6,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::StorageTexture {
+            access: wgpu::StorageTextureAccess::WriteOnly,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            view_dimension: wgpu::TextureViewDimension::D2,
+          },
+          count: None,
+        },
+        // Water Texture, where reflections start
+        wgpu::BindGroupLayoutEntry {
+          binding: 7,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::StorageTexture {
+            access: wgpu::StorageTextureAccess::WriteOnly,
+            format: wgpu::TextureFormat::Rgba32Uint,
+            view_dimension: wgpu::TextureViewDimension::D2,
+          },
+          count: None,
+        },
+        // Beam Texture, where each tile's rays start
+        wgpu::BindGroupLayoutEntry {
+          binding: 8,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+          },
+          count: None,
+        },
+        // What's under the center pixel
+        // Pick Buffer
+        wgpu::BindGroupLayoutEntry {
+          binding: 9,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: false },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+          },
+          count: None,
+        },
       ],
     });
     let cam_buffer = device.create_buffer(&wgpu::BufferDescriptor {
@@ -78,43 +226,82 @@ impl DdaModule {
       usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
       mapped_at_creation: false,
     });
-    let voxel_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-      label: Some("Voxel Buffer"),
-      size: bytes_in_voxel_buffer,
-      usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-      mapped_at_creation: false
-    });
-    let objects_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-      label: Some("Objects Buffer"),
-      size: std::mem::size_of::<ObjData>() as u64 * OBJECT_COUNT,
-      usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    let objects_capacity = 1;
+    let objects_buffer = Self::create_objects_buffer(device, objects_capacity);
+    let material_capacity = 1;
+    let material_buffer = Self::create_material_buffer(device, material_capacity);
+    let pick_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Pick Buffer"),
+      size: std::mem::size_of::<PickData>() as u64,
+      usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
       mapped_at_creation: false,
     });
 
-    let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-      layout: Some(&device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-        label: Some("DDA Layout"),
-        bind_group_layouts: &[&bind_group_layout],
-        push_constant_ranges: &[]
-      })),
-      cache: None,
-      compilation_options: wgpu::PipelineCompilationOptions::default(),
-      module: &device.create_shader_module(wgpu::include_wgsl!("shaders/dda.wgsl")),
-      entry_point: Some("main"),
-      label: Some("DDA Pipeline")
-    });
+    let pipeline = Self::create_pipeline(device, &bind_group_layout, shaders::DDA);
     
     Self {
       voxel_buffer,
       cam_buffer,
       objects_buffer,
+      objects_capacity,
+      drawn: Vec::new(),
+      on_screen: 0,
+      material_buffer,
+      material_capacity,
+      pick_buffer,
       pipeline,
       bind_group_layout,
+      views: None,
       bind_group: None
     }
   }
 
-  fn set_textures(&mut self, device: &wgpu::Device, output_view: &wgpu::TextureView) {
+  fn create_pipeline(device: &wgpu::Device, bind_group_layout: &wgpu::BindGroupLayout, source: &str) -> wgpu::ComputePipeline {
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+      layout: Some(&device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("DDA Layout"),
+        bind_group_layouts: &[bind_group_layout],
+        push_constant_ranges: &[]
+      })),
+      cache: None,
+      compilation_options: wgpu::PipelineCompilationOptions::default(),
+      module: &shaders::create(device, "dda", source),
+      entry_point: Some("main"),
+      label: Some("DDA Pipeline")
+    })
+  }
+
+  /// Most bytes the voxel buffer can grow to on this device
+  fn max_voxel_bytes(device: &wgpu::Device) -> u64 {
+    let limits = device.limits();
+    limits.max_buffer_size.min(limits.max_storage_buffer_binding_size as u64)
+  }
+
+  /// Grows the objects buffer to the next power of two that fits `count` objects, returns whether it was reallocated
+  fn reserve_objects(&mut self, device: &wgpu::Device, count: u64) -> bool {
+    if count <= self.objects_capacity { return false }
+    self.objects_capacity = count.next_power_of_two();
+    self.objects_buffer = Self::create_objects_buffer(device, self.objects_capacity);
+    self.rebuild_bind_group(device);
+    true
+  }
+
+  /// Grows the material buffer to the next power of two that fits `count` materials, returns whether it was reallocated
+  fn reserve_materials(&mut self, device: &wgpu::Device, count: u64) -> bool {
+    if count <= self.material_capacity { return false }
+    self.material_capacity = count.next_power_of_two();
+    self.material_buffer = Self::create_material_buffer(device, self.material_capacity);
+    self.rebuild_bind_group(device);
+    true
+  }
+
+  fn set_textures(&mut self, device: &wgpu::Device, output_view: &wgpu::TextureView, hit_view: &wgpu::TextureView, tint_view: &wgpu::TextureView, water_view: &wgpu::TextureView, beam_view: &wgpu::TextureView) {
+    self.views = Some((output_view.clone(), hit_view.clone(), tint_view.clone(), water_view.clone(), beam_view.clone()));
+    self.rebuild_bind_group(device);
+  }
+
+  fn rebuild_bind_group(&mut self, device: &wgpu::Device) {
+    let Some((output_view, hit_view, tint_view, water_view, beam_view)) = &self.views else { return };
     self.bind_group = Some( device.create_bind_group(&wgpu::BindGroupDescriptor {
       layout: &self.bind_group_layout,
       entries: &[
@@ -122,20 +309,142 @@ impl DdaModule {
         wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::Buffer(self.cam_buffer.as_entire_buffer_binding()), },
         wgpu::BindGroupEntry { binding: 2, resource: wgpu::BindingResource::Buffer(self.voxel_buffer.as_entire_buffer_binding()), },
         wgpu::BindGroupEntry { binding: 3, resource: wgpu::BindingResource::Buffer(self.objects_buffer.as_entire_buffer_binding()), },
+        wgpu::BindGroupEntry { binding: 4, resource: wgpu::BindingResource::TextureView(hit_view), },
+        wgpu::BindGroupEntry { binding: 5, resource: wgpu::BindingResource::Buffer(self.material_buffer.as_entire_buffer_binding()), },
+        wgpu::BindGroupEntry { binding: 6, resource: wgpu::BindingResource::TextureView(tint_view), },
+        wgpu::BindGroupEntry { binding: 7, resource: wgpu::BindingResource::TextureView(water_view), },
+        wgpu::BindGroupEntry { binding: 8, resource: wgpu::BindingResource::TextureView(beam_view), },
+        wgpu::BindGroupEntry { binding: 9, resource: wgpu::BindingResource::Buffer(self.pick_buffer.as_entire_buffer_binding()), },
       ],
       label: Some("Dda BindGroup"),
     }) );
   }
 }
 
+// Marches a ray per BEAM_TILE x BEAM_TILE tile before the DDA, to find how far its pixels can skip ahead
+struct BeamModule {
+  bind_group_layout: wgpu::BindGroupLayout,
+  pipeline: wgpu::ComputePipeline,
+  // Kept around so the bind group can follow the DDA's buffers when they're reallocated
+  view: Option<wgpu::TextureView>,
+  // We can't create the bind group without an associated texture
+  bind_group: Option<wgpu::BindGroup>
+}
+impl BeamModule {
+  fn create(device: &wgpu::Device) -> Self {
+    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+      label: Some("Beam BGL"),
+      entries: &[
+        // Start t per tile
+        // Beam Texture
+        wgpu::BindGroupLayoutEntry {
+          binding: 0,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::StorageTexture {
+            access: wgpu::StorageTextureAccess::WriteOnly,
+            format: wgpu::TextureFormat::R32Float,
+            view_dimension: wgpu::TextureViewDimension::D2,
+          },
+          count: None,
+        },
+        // Cam Buffer, shared with the DDA
+        wgpu::BindGroupLayoutEntry {
+          binding: 1,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+          },
+          count: None,
+        },
+        // Voxel Buffer, shared with the DDA
+        wgpu::BindGroupLayoutEntry {
+          binding: 2,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+          },
+          count: None,
+        },
+        // Object Buffer, shared with the DDA
+        wgpu::BindGroupLayoutEntry {
+          binding: 3,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+          },
+          count: None,
+        },
+        // Material Buffer, shared with the DDA
+        wgpu::BindGroupLayoutEntry {
+          binding: 4,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+          },
+          count: None,
+        },
+      ],
+    });
+    let pipeline = Self::create_pipeline(device, &bind_group_layout, shaders::BEAM);
+    Self { bind_group_layout, pipeline, view: None, bind_group: None }
+  }
+
+  fn create_pipeline(device: &wgpu::Device, bind_group_layout: &wgpu::BindGroupLayout, source: &str) -> wgpu::ComputePipeline {
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+      layout: Some(&device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Beam Layout"),
+        bind_group_layouts: &[bind_group_layout],
+        push_constant_ranges: &[]
+      })),
+      cache: None,
+      compilation_options: wgpu::PipelineCompilationOptions::default(),
+      module: &shaders::create(device, "beam", source),
+      entry_point: Some("main"),
+      label: Some("Beam Pipeline")
+    })
+  }
+
+  fn set_textures(&mut self, device: &wgpu::Device, dda: &DdaModule, view: &wgpu::TextureView) {
+    self.view = Some(view.clone());
+    self.rebuild_bind_group(device, dda);
+  }
+
+  fn rebuild_bind_group(&mut self, device: &wgpu::Device, dda: &DdaModule) {
+    let Some(view) = &self.view else { return };
+    self.bind_group = Some( device.create_bind_group(&wgpu::BindGroupDescriptor {
+      layout: &self.bind_group_layout,
+      entries: &[
+        wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(view) },
+        wgpu::BindGroupEntry { binding: 1, resource: dda.cam_buffer.as_entire_binding() },
+        wgpu::BindGroupEntry { binding: 2, resource: dda.voxel_buffer.as_entire_binding() },
+        wgpu::BindGroupEntry { binding: 3, resource: dda.objects_buffer.as_entire_binding() },
+        wgpu::BindGroupEntry { binding: 4, resource: dda.material_buffer.as_entire_binding() },
+      ],
+      label: Some("Beam BindGroup"),
+    }) );
+  }
+}
+
 struct UpscaleModule {
+  // Surface format the pipeline renders into
+  format: wgpu::TextureFormat,
+  linear_sampler: wgpu::Sampler,
+  upscale_buffer: wgpu::Buffer,
   bind_group_layout: wgpu::BindGroupLayout,
   pipeline: wgpu::RenderPipeline,
   // We can't create the bind group without an associated texture
   bind_group: Option<wgpu::BindGroup>
 }
 impl UpscaleModule {
-  fn create(device: &wgpu::Device, adapter: &wgpu::Adapter, surface: &wgpu::Surface) -> Self {
+  fn create(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
     let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
       label: Some("Upscale BGL"),
       entries: &[
@@ -155,14 +464,42 @@ impl UpscaleModule {
           ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
           count: None,
         },
+        // Upscale Buffer, which filter to use
+        wgpu::BindGroupLayoutEntry {
+          binding: 2,
+          visibility: wgpu::ShaderStages::FRAGMENT,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+          },
+          count: None,
+        },
       ],
     });
-    let upscale_module = device.create_shader_module(wgpu::include_wgsl!("shaders/upscale.wgsl"));
-    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+    let linear_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+      label: Some("Upscale Linear Sampler"),
+      mag_filter: wgpu::FilterMode::Linear,
+      min_filter: wgpu::FilterMode::Linear,
+      ..Default::default()
+    });
+    let upscale_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Upscale Buffer"),
+      size: std::mem::size_of::<UpscaleData>() as u64,
+      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+    let pipeline = Self::create_pipeline(device, &bind_group_layout, format, shaders::UPSCALE);
+    Self { format, linear_sampler, upscale_buffer, bind_group_layout, pipeline, bind_group: None}
+  }
+
+  fn create_pipeline(device: &wgpu::Device, bind_group_layout: &wgpu::BindGroupLayout, format: wgpu::TextureFormat, source: &str) -> wgpu::RenderPipeline {
+    let upscale_module = shaders::create(device, "upscale", source);
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
       label: Some("Upscale Pipeline"),
       layout: Some(&device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
         label: Some("Upscale Layout"),
-        bind_group_layouts: &[&bind_group_layout],
+        bind_group_layouts: &[bind_group_layout],
         push_constant_ranges: &[],
       })),
       cache: None,
@@ -177,7 +514,7 @@ impl UpscaleModule {
         module: &upscale_module,
         entry_point: Some("fs_main"),
         targets: &[Some(wgpu::ColorTargetState {
-          format: surface.get_capabilities(&adapter).formats[0],
+          format,
           blend: Some(wgpu::BlendState::REPLACE),
           write_mask: wgpu::ColorWrites::ALL,
         })],
@@ -186,16 +523,16 @@ impl UpscaleModule {
       depth_stencil: None,
       multisample: wgpu::MultisampleState::default(),
       multiview: None
-    });
-    Self { bind_group_layout, pipeline, bind_group: None}
+    })
   }
 
-  fn set_textures(&mut self, device: &wgpu::Device, input: &wgpu::TextureView, sampler: &wgpu::Sampler) {
+  fn set_textures(&mut self, device: &wgpu::Device, input: &wgpu::TextureView) {
     self.bind_group = Some( device.create_bind_group(&wgpu::BindGroupDescriptor {
       layout: &self.bind_group_layout,
       entries: &[
-        wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(&input) },
-        wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::Sampler(&sampler) },
+        wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(input) },
+        wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::Sampler(&self.linear_sampler) },
+        wgpu::BindGroupEntry { binding: 2, resource: self.upscale_buffer.as_entire_binding() },
       ],
       label: Some("Upscale BindGroup"),
     }) );
@@ -203,13 +540,22 @@ impl UpscaleModule {
 }
 
 struct LightingModule {
+  decal_buffer: wgpu::Buffer,
+  point_light_buffer: wgpu::Buffer,
+  light_buffer: wgpu::Buffer,
+  // An AverageData for every node in the voxel buffer, only kept up to date while bounce lighting is on
+  average_buffer: wgpu::Buffer,
+  // A layer for each tile of the texture atlas, see textures.rs
+  atlas_view: wgpu::TextureView,
   bind_group_layout: wgpu::BindGroupLayout,
   pipeline: wgpu::ComputePipeline,
+  // (input, output, hit, tint, water) kept around so the bind group can follow the DDA's buffers when they're reallocated
+  views: Option<(wgpu::TextureView, wgpu::TextureView, wgpu::TextureView, wgpu::TextureView, wgpu::TextureView)>,
   // We can't create the bind group without an associated texture
   bind_group: Option<wgpu::BindGroup>
 }
 impl LightingModule {
-  fn create(device: &wgpu::Device) -> Self {
+  fn create(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
     let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
       label: Some("Lighting BGL"),
       entries: &[
@@ -220,7 +566,7 @@ impl LightingModule {
           ty: wgpu::BindingType::Texture {
             multisampled: false,
             view_dimension: wgpu::TextureViewDimension::D2,
-            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
           },
           count: None,
         },
@@ -235,164 +581,1833 @@ impl LightingModule {
           },
           count: None,
         },
-      ],
-    });
-    let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-      layout: Some(&device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-        label: Some("Lighting Layout"),
-        bind_group_layouts: &[&bind_group_layout],
-        push_constant_ranges: &[]
-      })),
-      cache: None,
-      compilation_options: wgpu::PipelineCompilationOptions::default(),
-      module: &device.create_shader_module(wgpu::include_wgsl!("shaders/lighting.wgsl")),
-      entry_point: Some("main"),
-      label: Some("Lighting Pipeline")
-    });
-    Self { bind_group_layout, pipeline, bind_group: None}
-  }
-
-  fn set_textures(&mut self, device: &wgpu::Device, input: &wgpu::TextureView, output: &wgpu::TextureView) {
-    self.bind_group = Some( device.create_bind_group(&wgpu::BindGroupDescriptor {
-      layout: &self.bind_group_layout,
-      entries: &[
-        wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(&input) },
-        wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::TextureView(&output) },
-      ],
-      label: Some("Upscale BindGroup"),
-    }) );
-  }
-}
-
-pub struct WgpuCtx<'window> {
-  surface: wgpu::Surface<'window>,
-  surface_config: wgpu::SurfaceConfiguration,
-  device: wgpu::Device,
-  queue: wgpu::Queue,
-  sampler: wgpu::Sampler,
-  dda_compute: DdaModule,
-  lighting_compute: LightingModule,
-  upscale_render: UpscaleModule,
-}
-impl<'window> WgpuCtx<'window> {
-  pub fn new(window: Arc<Window>) -> WgpuCtx<'window> {
-    let instance = wgpu::Instance::default();
-    let surface = instance.create_surface(Arc::clone(&window)).unwrap();
-    let adapter = pollster::block_on( instance.request_adapter(&wgpu::RequestAdapterOptions {
-      compatible_surface: Some(&surface),
-      ..Default::default()
-    })).unwrap();
-    let (device, queue) = pollster::block_on(adapter.request_device(&Default::default())).unwrap();
-
-    let size = window.inner_size();
-    let surface_config = surface.get_default_config(&adapter, size.width, size.height).unwrap();
-    surface.configure(&device, &surface_config);
-
-    let dda_compute = DdaModule::create(&device, 64_000_000);
-    let lighting_compute = LightingModule::create(&device);
-    let upscale_render = UpscaleModule::create(&device, &adapter, &surface);
-    let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
-    let mut ctx = WgpuCtx {
-      surface,
-      surface_config,
-      device,
-      queue,
-      sampler,
-      dda_compute,
-      lighting_compute,
-      upscale_render,
-    };
-    ctx.gen_textures();
-    ctx
-  }
+        // Hit Texture, for world positions
+        wgpu::BindGroupLayoutEntry {
+          binding: 2,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Uint,
+          },
+          count: None,
+        },
+        // Decal Buffer
+        wgpu::BindGroupLayoutEntry {
+          binding: 3,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+          },
+          count: None,
+        },
+        // Voxel Buffer, shared with the DDA
+        wgpu::BindGroupLayoutEntry {
+          binding: 4,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+          },
+          count: None,
+        },
+        // Object Buffer, shared with the DDA
+        wgpu::BindGroupLayoutEntry {
+          binding: 5,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+          },
+          count: None,
+        },
+        // Light Buffer
+        wgpu::BindGroupLayoutEntry {
+          binding: 6,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+          },
+          count: None,
+        },
+        // Material Buffer, shared with the DDA
+        wgpu::BindGroupLayoutEntry {
+          binding: 7,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+          },
+          count: None,
+        },
+        // Tint Texture
+        wgpu::BindGroupLayoutEntry {
+          binding: 8,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+          },
+          count: None,
+        },
+        // Point Light Buffer, emissive cells near the camera
+        wgpu::BindGroupLayoutEntry {
+          binding: 9,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+          },
+          count: None,
+        },
+        // Water Texture
+        wgpu::BindGroupLayoutEntry {
+          binding: 10,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Uint,
+          },
+          count: None,
+        },
+        // Average Buffer, what the nodes in the voxel buffer look like from afar
+        wgpu::BindGroupLayoutEntry {
+          binding: 11,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+          },
+          count: None,
+        },
+        // Atlas Texture, the tiles materials are textured with
+        wgpu::BindGroupLayoutEntry {
+          binding: 12,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            view_dimension: wgpu::TextureViewDimension::D2Array,
+            multisampled: false,
+          },
+          count: None,
+        },
+      ],
+    });
+    let decal_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Decal Buffer"),
+      size: (std::mem::size_of::<DecalHeader>() + std::mem::size_of::<DecalData>() * MAX_DECALS) as u64,
+      usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+    let point_light_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Point Light Buffer"),
+      size: (std::mem::size_of::<PointLightHeader>() + std::mem::size_of::<PointLightData>() * MAX_LIGHTS) as u64,
+      usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+    let light_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Light Buffer"),
+      size: std::mem::size_of::<LightData>() as u64,
+      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+    let average_buffer = Self::create_average_buffer(device, std::mem::size_of::<AverageData>() as u64);
+    let atlas_view = Self::create_atlas(device, queue, &Atlas::default());
+    let pipeline = Self::create_pipeline(device, &bind_group_layout, shaders::LIGHTING);
+    Self { decal_buffer, point_light_buffer, light_buffer, average_buffer, atlas_view, bind_group_layout, pipeline, views: None, bind_group: None}
+  }
+
+  fn create_atlas(device: &wgpu::Device, queue: &wgpu::Queue, atlas: &Atlas) -> wgpu::TextureView {
+    let size = wgpu::Extent3d { width: atlas.tile_size, height: atlas.tile_size, depth_or_array_layers: atlas.tiles.max(1) };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+      label: Some("Atlas Texture"),
+      size,
+      mip_level_count: 1,
+      sample_count: 1,
+      dimension: wgpu::TextureDimension::D2,
+      // Loads come out linear
+      format: wgpu::TextureFormat::Rgba8UnormSrgb,
+      usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+      view_formats: &[],
+    });
+    queue.write_texture(
+      wgpu::TexelCopyTextureInfo { texture: &texture, mip_level: 0, origin: wgpu::Origin3d::ZERO, aspect: wgpu::TextureAspect::All },
+      &atlas.pixels,
+      wgpu::TexelCopyBufferLayout { offset: 0, bytes_per_row: Some(atlas.tile_size * 4), rows_per_image: Some(atlas.tile_size) },
+      size,
+    );
+    texture.create_view(&wgpu::TextureViewDescriptor { dimension: Some(wgpu::TextureViewDimension::D2Array), ..Default::default() })
+  }
+
+  fn set_atlas(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, dda: &DdaModule, atlas: &Atlas) {
+    self.atlas_view = Self::create_atlas(device, queue, atlas);
+    self.rebuild_bind_group(device, dda);
+  }
+
+  fn create_average_buffer(device: &wgpu::Device, bytes: u64) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Average Buffer"),
+      size: bytes,
+      usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    })
+  }
+
+  /// Grows the average buffer to the next power of two that fits `bytes`, like DdaModule::reserve_voxels
+  fn reserve_averages(&mut self, device: &wgpu::Device, dda: &DdaModule, bytes: u64) {
+    if bytes <= self.average_buffer.size() { return }
+    self.average_buffer = Self::create_average_buffer(device, bytes.next_power_of_two().min(DdaModule::max_voxel_bytes(device)));
+    self.rebuild_bind_group(device, dda);
+  }
+
+  fn create_pipeline(device: &wgpu::Device, bind_group_layout: &wgpu::BindGroupLayout, source: &str) -> wgpu::ComputePipeline {
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+      layout: Some(&device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Lighting Layout"),
+        bind_group_layouts: &[bind_group_layout],
+        push_constant_ranges: &[]
+      })),
+      cache: None,
+      compilation_options: wgpu::PipelineCompilationOptions::default(),
+      module: &shaders::create(device, "lighting", source),
+      entry_point: Some("main"),
+      label: Some("Lighting Pipeline")
+    })
+  }
+
+  #[allow(clippy::too_many_arguments)]
+  fn set_textures(&mut self, device: &wgpu::Device, dda: &DdaModule, input: &wgpu::TextureView, output: &wgpu::TextureView, hit: &wgpu::TextureView, tint: &wgpu::TextureView, water: &wgpu::TextureView) {
+    self.views = Some((input.clone(), output.clone(), hit.clone(), tint.clone(), water.clone()));
+    self.rebuild_bind_group(device, dda);
+  }
+
+  fn rebuild_bind_group(&mut self, device: &wgpu::Device, dda: &DdaModule) {
+    let Some((input, output, hit, tint, water)) = &self.views else { return };
+    self.bind_group = Some( device.create_bind_group(&wgpu::BindGroupDescriptor {
+      layout: &self.bind_group_layout,
+      entries: &[
+        wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(input) },
+        wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::TextureView(output) },
+        wgpu::BindGroupEntry { binding: 2, resource: wgpu::BindingResource::TextureView(hit) },
+        wgpu::BindGroupEntry { binding: 3, resource: self.decal_buffer.as_entire_binding() },
+        wgpu::BindGroupEntry { binding: 4, resource: dda.voxel_buffer.as_entire_binding() },
+        wgpu::BindGroupEntry { binding: 5, resource: dda.objects_buffer.as_entire_binding() },
+        wgpu::BindGroupEntry { binding: 6, resource: self.light_buffer.as_entire_binding() },
+        wgpu::BindGroupEntry { binding: 7, resource: dda.material_buffer.as_entire_binding() },
+        wgpu::BindGroupEntry { binding: 8, resource: wgpu::BindingResource::TextureView(tint) },
+        wgpu::BindGroupEntry { binding: 9, resource: self.point_light_buffer.as_entire_binding() },
+        wgpu::BindGroupEntry { binding: 10, resource: wgpu::BindingResource::TextureView(water) },
+        wgpu::BindGroupEntry { binding: 11, resource: self.average_buffer.as_entire_binding() },
+        wgpu::BindGroupEntry { binding: 12, resource: wgpu::BindingResource::TextureView(&self.atlas_view) },
+      ],
+      label: Some("Lighting BindGroup"),
+    }) );
+  }
+}
+
+struct TemporalModule {
+  // MotionHeader followed by a world-to-last-frame matrix per object
+  motion_buffer: wgpu::Buffer,
+  // PostData, fog and the like
+  post_buffer: wgpu::Buffer,
+  // Number of matrices the motion buffer can currently hold
+  motion_capacity: u64,
+  // Bilinear, history is read wherever the point landed last frame
+  sampler: wgpu::Sampler,
+  bind_group_layout: wgpu::BindGroupLayout,
+  pipeline: wgpu::ComputePipeline,
+  // (lighting, hit, [history; 2], output, dda output) kept around so the bind groups can be rebuilt
+  views: Option<(wgpu::TextureView, wgpu::TextureView, [wgpu::TextureView; 2], wgpu::TextureView, wgpu::TextureView)>,
+  // Bind group n writes history n and reads the other one
+  bind_groups: Option<[wgpu::BindGroup; 2]>,
+  // Which history gets written this frame, flips every frame
+  current: usize,
+  // Last frame's camera and object transforms (by handle, None where there wasn't an object), None when there's no usable history
+  prev_view_proj: Option<Mat4>,
+  prev_transforms: Vec<Option<Mat4>>,
+}
+impl TemporalModule {
+  fn create_motion_buffer(device: &wgpu::Device, capacity: u64) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Motion Buffer"),
+      size: (std::mem::size_of::<MotionHeader>() + std::mem::size_of::<Mat4>() * capacity as usize) as u64,
+      usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    })
+  }
+
+  fn create(device: &wgpu::Device) -> Self {
+    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+      label: Some("Temporal BGL"),
+      entries: &[
+        // Lighting Output
+        wgpu::BindGroupLayoutEntry {
+          binding: 0,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+          },
+          count: None,
+        },
+        // Hit Texture, for world positions
+        wgpu::BindGroupLayoutEntry {
+          binding: 1,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Uint,
+          },
+          count: None,
+        },
+        // Last frame's History
+        wgpu::BindGroupLayoutEntry {
+          binding: 2,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+          },
+          count: None,
+        },
+        // History Sampler
+        wgpu::BindGroupLayoutEntry {
+          binding: 3,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+          count: None,
+        },
+        // This frame's History
+        wgpu::BindGroupLayoutEntry {
+          binding: 4,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::StorageTexture {
+            access: wgpu::StorageTextureAccess::WriteOnly,
+            format: wgpu::TextureFormat::Rgba16Float,
+            view_dimension: wgpu::TextureViewDimension::D2,
+          },
+          count: None,
+        },
+        // Output Texture
+        wgpu::BindGroupLayoutEntry {
+          binding: 5,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::StorageTexture {
+            access: wgpu::StorageTextureAccess::WriteOnly,
+            format: wgpu::TextureFormat::Rgba16Float,
+            view_dimension: wgpu::TextureViewDimension::D2,
+          },
+          count: None,
+        },
+        // Cam Buffer, shared with the DDA
+        wgpu::BindGroupLayoutEntry {
+          binding: 6,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+          },
+          count: None,
+        },
+        // Motion Buffer
+        wgpu::BindGroupLayoutEntry {
+          binding: 7,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+          },
+          count: None,
+        },
+        // Dda Output, normals and depth for outlines and fog
+        wgpu::BindGroupLayoutEntry {
+          binding: 8,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+          },
+          count: None,
+        },
+        // Post Buffer
+        wgpu::BindGroupLayoutEntry {
+          binding: 9,
+          visibility: wgpu::ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+          },
+          count: None,
+        },
+      ],
+    });
+    let motion_capacity = 1;
+    let motion_buffer = Self::create_motion_buffer(device, motion_capacity);
+    let post_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Post Buffer"),
+      size: std::mem::size_of::<PostData>() as u64,
+      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+      label: Some("History Sampler"),
+      mag_filter: wgpu::FilterMode::Linear,
+      min_filter: wgpu::FilterMode::Linear,
+      ..Default::default()
+    });
+    let pipeline = Self::create_pipeline(device, &bind_group_layout, shaders::TEMPORAL);
+    Self {
+      motion_buffer,
+      post_buffer,
+      motion_capacity,
+      sampler,
+      bind_group_layout,
+      pipeline,
+      views: None,
+      bind_groups: None,
+      current: 0,
+      prev_view_proj: None,
+      prev_transforms: Vec::new(),
+    }
+  }
+
+  fn create_pipeline(device: &wgpu::Device, bind_group_layout: &wgpu::BindGroupLayout, source: &str) -> wgpu::ComputePipeline {
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+      layout: Some(&device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Temporal Layout"),
+        bind_group_layouts: &[bind_group_layout],
+        push_constant_ranges: &[]
+      })),
+      cache: None,
+      compilation_options: wgpu::PipelineCompilationOptions::default(),
+      module: &shaders::create(device, "temporal", source),
+      entry_point: Some("main"),
+      label: Some("Temporal Pipeline")
+    })
+  }
+
+  /// Grows the motion buffer to the next power of two that fits `count` objects
+  fn reserve_objects(&mut self, device: &wgpu::Device, dda: &DdaModule, count: u64) {
+    if count <= self.motion_capacity { return }
+    self.motion_capacity = count.next_power_of_two();
+    self.motion_buffer = Self::create_motion_buffer(device, self.motion_capacity);
+    self.rebuild_bind_groups(device, dda);
+  }
+
+  /// New textures hold garbage, so this also throws away the history
+  #[allow(clippy::too_many_arguments)]
+  fn set_textures(&mut self, device: &wgpu::Device, dda: &DdaModule, lighting: &wgpu::TextureView, hit: &wgpu::TextureView, history: [wgpu::TextureView; 2], output: &wgpu::TextureView, gbuffer: &wgpu::TextureView) {
+    self.views = Some((lighting.clone(), hit.clone(), history, output.clone(), gbuffer.clone()));
+    self.prev_view_proj = None;
+    self.rebuild_bind_groups(device, dda);
+  }
+
+  fn rebuild_bind_groups(&mut self, device: &wgpu::Device, dda: &DdaModule) {
+    let Some((lighting, hit, history, output, gbuffer)) = &self.views else { return };
+    let bind_group = |write: usize| device.create_bind_group(&wgpu::BindGroupDescriptor {
+      layout: &self.bind_group_layout,
+      entries: &[
+        wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(lighting) },
+        wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::TextureView(hit) },
+        wgpu::BindGroupEntry { binding: 2, resource: wgpu::BindingResource::TextureView(&history[1 - write]) },
+        wgpu::BindGroupEntry { binding: 3, resource: wgpu::BindingResource::Sampler(&self.sampler) },
+        wgpu::BindGroupEntry { binding: 4, resource: wgpu::BindingResource::TextureView(&history[write]) },
+        wgpu::BindGroupEntry { binding: 5, resource: wgpu::BindingResource::TextureView(output) },
+        wgpu::BindGroupEntry { binding: 6, resource: dda.cam_buffer.as_entire_binding() },
+        wgpu::BindGroupEntry { binding: 7, resource: self.motion_buffer.as_entire_binding() },
+        wgpu::BindGroupEntry { binding: 8, resource: wgpu::BindingResource::TextureView(gbuffer) },
+        wgpu::BindGroupEntry { binding: 9, resource: self.post_buffer.as_entire_binding() },
+      ],
+      label: Some("Temporal BindGroup"),
+    });
+    self.bind_groups = Some([bind_group(0), bind_group(1)]);
+  }
+}
+
+struct LineModule {
+  view_buffer: wgpu::Buffer,
+  vertex_buffer: wgpu::Buffer,
+  // Number of LineVertex slots the vertex buffer can currently hold
+  vertex_capacity: u64,
+  vertex_count: u32,
+  pipeline: wgpu::RenderPipeline,
+  bind_group: wgpu::BindGroup,
+}
+impl LineModule {
+  fn create_vertex_buffer(device: &wgpu::Device, capacity: u64) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Line Vertex Buffer"),
+      size: std::mem::size_of::<LineVertex>() as u64 * capacity,
+      usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    })
+  }
+
+  fn create(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+      label: Some("Line BGL"),
+      entries: &[
+        // View Buffer
+        wgpu::BindGroupLayoutEntry {
+          binding: 0,
+          visibility: wgpu::ShaderStages::VERTEX,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+          },
+          count: None,
+        },
+      ],
+    });
+    let view_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Line View Buffer"),
+      size: std::mem::size_of::<ViewData>() as u64,
+      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+      layout: &bind_group_layout,
+      entries: &[
+        wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::Buffer(view_buffer.as_entire_buffer_binding()) },
+      ],
+      label: Some("Line BindGroup"),
+    });
+    let vertex_capacity = 1024;
+    let vertex_buffer = Self::create_vertex_buffer(device, vertex_capacity);
+    let line_module = shaders::create(device, "lines", shaders::LINES);
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+      label: Some("Line Pipeline"),
+      layout: Some(&device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Line Layout"),
+        bind_group_layouts: &[&bind_group_layout],
+        push_constant_ranges: &[],
+      })),
+      cache: None,
+      vertex: wgpu::VertexState {
+        compilation_options: wgpu::PipelineCompilationOptions::default(),
+        module: &line_module,
+        entry_point: Some("vs_main"),
+        buffers: &[wgpu::VertexBufferLayout {
+          array_stride: std::mem::size_of::<LineVertex>() as u64,
+          step_mode: wgpu::VertexStepMode::Vertex,
+          attributes: &LineVertex::ATTRIBUTES,
+        }],
+      },
+      fragment: Some(wgpu::FragmentState {
+        compilation_options: wgpu::PipelineCompilationOptions::default(),
+        module: &line_module,
+        entry_point: Some("fs_main"),
+        targets: &[Some(wgpu::ColorTargetState {
+          format,
+          blend: Some(wgpu::BlendState::REPLACE),
+          write_mask: wgpu::ColorWrites::ALL,
+        })],
+      }),
+      primitive: wgpu::PrimitiveState {
+        topology: wgpu::PrimitiveTopology::LineList,
+        ..Default::default()
+      },
+      depth_stencil: None,
+      multisample: wgpu::MultisampleState::default(),
+      multiview: None
+    });
+    Self { view_buffer, vertex_buffer, vertex_capacity, vertex_count: 0, pipeline, bind_group }
+  }
+
+  fn upload(&mut self, device: &wgpu::Device, uploads: &mut UploadBelt, encoder: &mut wgpu::CommandEncoder, vertices: &[LineVertex]) {
+    self.vertex_count = vertices.len() as u32;
+    if vertices.is_empty() { return }
+    if vertices.len() as u64 > self.vertex_capacity {
+      self.vertex_capacity = (vertices.len() as u64).next_power_of_two();
+      self.vertex_buffer = Self::create_vertex_buffer(device, self.vertex_capacity);
+    }
+    uploads.write(device, encoder, &self.vertex_buffer, 0, bytemuck::cast_slice(vertices));
+  }
+}
+
+/// Exponential fog over depth, thinning out with height
+#[derive(Debug, Clone, Copy)]
+pub struct Fog {
+  /// Linear rgb, rays that give up at the far plane are drawn entirely in it
+  pub color: Vec3,
+  /// Fog per unit of depth, 0 turns it off
+  pub density: f32,
+  /// How fast fog thins out going up from y = 0, 0 keeps it the same at every height
+  pub falloff: f32,
+}
+impl Default for Fog {
+  // About 90% fogged by the default max_distance
+  fn default() -> Self { Self { color: Vec3::new(0.75, 0.77, 0.8), density: 0.0045, falloff: 0.0 } }
+}
+
+/// How lighting above 1 is squeezed into what the screen can show, matches the TONEMAP_ consts in ./shaders/temporal.wgsl
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tonemap {
+  /// Anything over 1 clips to white
+  Clip = 0,
+  Reinhard = 1,
+  /// An approximation of the ACES filmic curve, with a bit more contrast than Reinhard
+  Aces = 2,
+}
+impl Tonemap {
+  pub const ALL: [Tonemap; 3] = [Tonemap::Clip, Tonemap::Reinhard, Tonemap::Aces];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Tonemap::Clip => "clip",
+      Tonemap::Reinhard => "reinhard",
+      Tonemap::Aces => "aces",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> { Self::ALL.into_iter().find(|tonemap| tonemap.name() == name) }
+}
+
+/// What the frame shows in place of the lit scene, matches the DEBUG_ consts in ./shaders/dda.wgsl
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugView {
+  /// The lit scene, no debug view
+  Lit = 0,
+  /// How many DDA steps each camera ray took, from black through blue, green, yellow and red to white at max_steps
+  Steps = 1,
+  /// Edges of the nodes camera rays step into, colored by how deep in the tree they are. Whatever's nearest wins,
+  /// so it shows the nodes a ray visits on its way to what it hits
+  Nodes = 2,
+}
+impl DebugView {
+  pub const ALL: [DebugView; 3] = [DebugView::Lit, DebugView::Steps, DebugView::Nodes];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      DebugView::Lit => "lit",
+      DebugView::Steps => "steps",
+      DebugView::Nodes => "nodes",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> { Self::ALL.into_iter().find(|view| view.name() == name) }
+}
+
+/// How the graph's nodes are laid out in the voxel buffer, see vox_read in ./shaders/traversal.wgsl
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeFormat {
+  /// The Pond's memory as is, 8 children to every slot whether it's live or not
+  Raw,
+  /// Only live nodes and only the children which aren't empty, see SparseDirectedGraph::pack
+  Packed,
+  /// Packed too, but every object's tree on its own one after another, found through each object's base offset.
+  /// Trees the objects share are stored once for each, see SparseDirectedGraph::pack_tree
+  Objects,
+}
+impl NodeFormat {
+  pub const ALL: [NodeFormat; 3] = [NodeFormat::Raw, NodeFormat::Packed, NodeFormat::Objects];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      NodeFormat::Raw => "raw",
+      NodeFormat::Packed => "packed",
+      NodeFormat::Objects => "objects",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> { Self::ALL.into_iter().find(|format| format.name() == name) }
+}
+
+/// How finished frames are handed to the display
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresentMode {
+  /// Vsync, waits for the display and never tears. The only mode every adapter has
+  Fifo,
+  /// Vsync without the wait, newer frames replace ones the display hasn't shown yet
+  Mailbox,
+  /// No vsync, frames go out as soon as they're done and may tear
+  Immediate,
+}
+impl PresentMode {
+  pub const ALL: [PresentMode; 3] = [PresentMode::Fifo, PresentMode::Mailbox, PresentMode::Immediate];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      PresentMode::Fifo => "fifo",
+      PresentMode::Mailbox => "mailbox",
+      PresentMode::Immediate => "immediate",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> { Self::ALL.into_iter().find(|mode| mode.name() == name) }
+
+  fn wgpu(self) -> wgpu::PresentMode {
+    match self {
+      PresentMode::Fifo => wgpu::PresentMode::Fifo,
+      PresentMode::Mailbox => wgpu::PresentMode::Mailbox,
+      PresentMode::Immediate => wgpu::PresentMode::Immediate,
+    }
+  }
+}
+
+/// How the rendered frame is stretched to the window, matches the FILTER_ consts in ./shaders/upscale.wgsl
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpscaleFilter {
+  /// Blocky, but every pixel is exactly what was rendered
+  Nearest = 0,
+  Bilinear = 1,
+  /// Bilinear with edges sharpened back up, for low render scales
+  Sharpen = 2,
+}
+impl UpscaleFilter {
+  pub const ALL: [UpscaleFilter; 3] = [UpscaleFilter::Nearest, UpscaleFilter::Bilinear, UpscaleFilter::Sharpen];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      UpscaleFilter::Nearest => "nearest",
+      UpscaleFilter::Bilinear => "bilinear",
+      UpscaleFilter::Sharpen => "sharpen",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> { Self::ALL.into_iter().find(|filter| filter.name() == name) }
+}
+
+/// Bundles of RenderSettings, from fastest to prettiest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quality {
+  Low,
+  Medium,
+  High,
+  Ultra,
+}
+impl Quality {
+  pub const ALL: [Quality; 4] = [Quality::Low, Quality::Medium, Quality::High, Quality::Ultra];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Quality::Low => "low",
+      Quality::Medium => "medium",
+      Quality::High => "high",
+      Quality::Ultra => "ultra",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> { Self::ALL.into_iter().find(|quality| quality.name() == name) }
+}
+
+/// Knobs on how the frame is rendered, the gameplay side owns these so they can be changed from the console.
+/// Every pass reads its knobs from here each frame, a Quality preset just sets a bunch of them at once.
+#[derive(Debug, Clone, Copy)]
+pub struct RenderSettings {
+  /// The preset last applied, the knobs may have been changed individually since
+  pub quality: Quality,
+  /// Fraction of the window's resolution the scene is rendered at before upscaling
+  pub render_scale: f32,
+  /// Some to pick the scale each frame to hold this many fps, render_scale is then the highest it goes.
+  /// Needs GPU timestamps, without them render_scale is used as is
+  pub target_fps: Option<f32>,
+  /// Rays (camera and shadow) stop marching this far out, in units of the camera's forward depth
+  pub max_distance: f32,
+  /// Most DDA steps a single ray takes across every object, guards against runaway loops on bad data
+  pub max_steps: u32,
+  /// Past this far out (in the same units as max_distance) rays stop a level above the cells, treating any node
+  /// with something solid in it as solid. 0 turns level of detail off
+  pub lod_distance: f32,
+  /// Every time the distance grows this many times over, rays stop another level up. Has to be above 1
+  pub lod_falloff: f32,
+  /// Roughens distant faces of large uniform regions with noise, off by default since it changes the apparent geometry
+  pub detail: bool,
+  /// How far in (render resolution) pixels outlines reach across depth and normal edges, 0 turns them off
+  pub outline_width: u32,
+  /// Relative depth difference between neighbouring pixels that counts as an edge
+  pub outline_threshold: f32,
+  /// Whether the sun casts shadows
+  pub shadows: bool,
+  /// Ambient occlusion rays per pixel, 0 turns it off
+  pub ao_rays: u32,
+  /// Cones per pixel gathering light bounced off whatever's nearby, 0 turns it off
+  pub gi_cones: u32,
+  /// Whether emissive cells light up their surroundings
+  pub point_lights: bool,
+  /// Whether frames are blended over time, which smooths both aliasing and the AO noise
+  pub temporal: bool,
+  /// Whether camera rays are jittered within their pixels every frame, which blending over time turns into smooth
+  /// edges. Only does anything while temporal is on
+  pub antialias: bool,
+  pub fog: Fog,
+  /// Multiplies the final color, before tonemapping
+  pub exposure: f32,
+  pub tonemap: Tonemap,
+  pub upscale_filter: UpscaleFilter,
+  /// How strongly UpscaleFilter::Sharpen sharpens, 0 to 1
+  pub sharpness: f32,
+  /// Whether a cross marks the center of the screen
+  pub crosshair: bool,
+  /// Anything but Lit skips lighting, fog, tonemapping and blending over time to show the view as is
+  pub debug_view: DebugView,
+  /// Falls back to the current mode if the adapter doesn't have it, see WgpuCtx::present_modes
+  pub present_mode: PresentMode,
+  /// Some for App to sleep between frames so there are at most this many a second
+  pub fps_limit: Option<f32>,
+  /// Switching it uploads the graph over again
+  pub node_format: NodeFormat,
+}
+impl RenderSettings {
+  /// Sets every knob the preset covers, leaving stylistic ones (outlines, detail, antialiasing, fog, exposure, tonemap, filtering, crosshair),
+  /// the debug view and frame pacing (target_fps, present_mode, fps_limit) alone
+  pub fn apply(&mut self, quality: Quality) {
+    let (render_scale, max_distance, max_steps, lod_distance, shadows, ao_rays, gi_cones, point_lights, temporal) = match quality {
+      Quality::Low => (0.5, 192.0, 384, 48.0, false, 0, 0, false, false),
+      Quality::Medium => (0.75, 256.0, 512, 96.0, true, 3, 0, true, true),
+      Quality::High => (1.0, 512.0, 1024, 0.0, true, 6, 0, true, true),
+      Quality::Ultra => (1.0, 1024.0, 2048, 0.0, true, 12, 4, true, true),
+    };
+    *self = Self { quality, render_scale, max_distance, max_steps, lod_distance, shadows, ao_rays, gi_cones, point_lights, temporal, ..*self };
+  }
+}
+impl Default for RenderSettings {
+  // Quality::High, without outlines or detail
+  fn default() -> Self {
+    Self {
+      quality: Quality::High,
+      render_scale: 1.0,
+      target_fps: None,
+      max_distance: 512.0,
+      max_steps: 1024,
+      lod_distance: 0.0,
+      lod_falloff: 2.0,
+      detail: false,
+      outline_width: 0,
+      outline_threshold: 0.1,
+      shadows: true,
+      ao_rays: 6,
+      gi_cones: 0,
+      point_lights: true,
+      temporal: true,
+      antialias: false,
+      fog: Fog::default(),
+      exposure: 1.0,
+      tonemap: Tonemap::Aces,
+      upscale_filter: UpscaleFilter::Bilinear,
+      sharpness: 0.5,
+      crosshair: true,
+      debug_view: DebugView::Lit,
+      present_mode: PresentMode::Fifo,
+      fps_limit: None,
+      node_format: NodeFormat::Packed,
+    }
+  }
+}
+
+/// Everything the renderer reports about itself each frame
+#[derive(Debug, Clone, Copy)]
+pub struct RenderStats {
+  /// None if the adapter can't timestamp passes
+  pub gpu: Option<PerfStats>,
+  pub textures: TextureStats,
+  /// What the scene is actually rendered at, which RenderSettings::target_fps moves around
+  pub render_scale: f32,
+  /// Bytes of the latest graph if it was too big for the GPU, which is then still drawing an older one
+  pub stale_graph: Option<u64>,
+}
+
+/// Where in its pixel (-0.5 to 0.5 across) a frame's camera rays go, a Halton sequence so any few frames in a row
+/// spread out evenly over it
+fn jitter(frame: u32) -> Vec2 {
+  let halton = |mut idx: u32, base: u32| {
+    let (mut fraction, mut result) = (1.0, 0.0);
+    while idx > 0 {
+      fraction /= base as f32;
+      result += fraction * (idx % base) as f32;
+      idx /= base;
+    }
+    result
+  };
+  // Starting at 1 skips the sequence's 0, which would put one frame in 8 in the corner
+  let idx = frame % 8 + 1;
+  Vec2::new(halton(idx, 2), halton(idx, 3)) - 0.5
+}
+
+/// Picks a render scale from the GPU timings that keeps them within a frame rate's budget
+#[derive(Default)]
+struct DynamicScale {
+  // Smoothed GPU ms per frame, 0 until the first reading
+  gpu_ms: f32,
+  // Frames since the scale last changed
+  settled: u32,
+}
+impl DynamicScale {
+  // Lowest it'll go, below this it's mush anyway
+  const MIN: f32 = 0.25;
+  // Scales snap to multiples of this so small wobbles don't recreate every texture
+  const STEP: f32 = 0.05;
+  // Timings lag a few frames behind and then need smoothing, so give a new scale this long before judging it
+  const SETTLE_FRAMES: u32 = 30;
+  // Share of the frame's budget the GPU aims for, the rest is for the CPU and jitter
+  const HEADROOM: f32 = 0.85;
+
+  fn pick(&mut self, perf: PerfStats, current: f32, max: f32, target_fps: f32) -> f32 {
+    let ms = perf.total_ms();
+    self.gpu_ms = if self.gpu_ms == 0.0 { ms } else { self.gpu_ms * 0.9 + ms * 0.1 };
+    self.settled += 1;
+    if self.settled < Self::SETTLE_FRAMES || self.gpu_ms <= 0.0 { return current }
+    let budget = 1000.0 / target_fps * Self::HEADROOM;
+    // Most of the work is per pixel, which goes with the square of the scale
+    let ideal = current * (budget / self.gpu_ms).sqrt();
+    // Rounding down means a scale that only just fits stays put instead of flickering up and down
+    let scale = ((ideal / Self::STEP + 0.001).floor() * Self::STEP).clamp(Self::MIN, max.max(Self::MIN));
+    if (scale - current).abs() < Self::STEP * 0.5 { return current }
+    self.settled = 0;
+    self.gpu_ms = 0.0;
+    scale
+  }
+}
+
+/// Milliseconds the GPU spent on each pass, a few frames behind
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PerfStats {
+  pub dda_ms: f32,
+  pub lighting_ms: f32,
+  pub temporal_ms: f32,
+  pub upscale_ms: f32,
+}
+impl PerfStats {
+  // Each timed pass writes a begin and end timestamp, dda at 0, lighting at 2, temporal at 4, upscale at 6
+  const PASSES: u32 = 4;
+
+  /// (pass, ms) in the order the passes run
+  pub fn passes(&self) -> [(&'static str, f32); Self::PASSES as usize] {
+    [("dda", self.dda_ms), ("lighting", self.lighting_ms), ("temporal", self.temporal_ms), ("upscale", self.upscale_ms)]
+  }
+
+  pub fn total_ms(&self) -> f32 { self.dda_ms + self.lighting_ms + self.temporal_ms + self.upscale_ms }
+}
+
+struct PassTimer {
+  query_set: wgpu::QuerySet,
+  resolve_buffer: wgpu::Buffer,
+  // Nanoseconds per timestamp tick
+  period: f32,
+  // Written by the readback
+  stats: Rc<Cell<PerfStats>>,
+}
+impl PassTimer {
+  const QUERIES: u32 = PerfStats::PASSES * 2;
+
+  /// None if the device wasn't created with TIMESTAMP_QUERY
+  fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+    if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) { return None }
+    Some(Self {
+      query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+        label: Some("Pass Timestamps"),
+        ty: wgpu::QueryType::Timestamp,
+        count: Self::QUERIES,
+      }),
+      resolve_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Timestamp Resolve Buffer"),
+        size: Self::QUERIES as u64 * 8,
+        usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+      }),
+      period: queue.get_timestamp_period(),
+      stats: Rc::new(Cell::new(PerfStats::default())),
+    })
+  }
+
+  fn compute_writes(&self, pass: usize) -> wgpu::ComputePassTimestampWrites<'_> {
+    wgpu::ComputePassTimestampWrites {
+      query_set: &self.query_set,
+      beginning_of_pass_write_index: Some(pass as u32 * 2),
+      end_of_pass_write_index: Some(pass as u32 * 2 + 1),
+    }
+  }
+
+  fn render_writes(&self, pass: usize) -> wgpu::RenderPassTimestampWrites<'_> {
+    wgpu::RenderPassTimestampWrites {
+      query_set: &self.query_set,
+      beginning_of_pass_write_index: Some(pass as u32 * 2),
+      end_of_pass_write_index: Some(pass as u32 * 2 + 1),
+    }
+  }
+
+  /// Copies this frame's timestamps out, call after every timed pass has been recorded
+  fn resolve(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, readback: &mut ReadbackRing) {
+    encoder.resolve_query_set(&self.query_set, 0 .. Self::QUERIES, &self.resolve_buffer, 0);
+    let (stats, period) = (self.stats.clone(), self.period);
+    readback.read_buffer(device, encoder, &self.resolve_buffer, 0, self.resolve_buffer.size(), move |data| {
+      let stamps: Vec<u64> = data.chunks_exact(8).map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap())).collect();
+      let ms = |pass: usize| stamps[pass * 2 + 1].wrapping_sub(stamps[pass * 2]) as f32 * period / 1_000_000.0;
+      let perf = PerfStats { dda_ms: ms(0), lighting_ms: ms(1), temporal_ms: ms(2), upscale_ms: ms(3) };
+      #[cfg(feature = "tracy")]
+      profiling::gpu_zones(&perf.passes().iter().enumerate()
+        .map(|(pass, &(name, _))| (name, stamps[pass * 2], stamps[pass * 2 + 1])).collect::<Vec<_>>(), period);
+      stats.set(perf);
+    });
+  }
+}
+
+/// How much texture memory the pool is holding
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TextureStats {
+  /// Handed out since the last release
+  pub live: usize,
+  /// Released and waiting for a request they fit
+  pub pooled: usize,
+  pub bytes: u64,
+}
+
+/// Render targets handed out by description, released ones are reused by the next request they fit
+/// so passes with compatible targets share memory and a resize never strands the old size
+#[derive(Default)]
+struct TexturePool {
+  live: Vec<wgpu::Texture>,
+  free: Vec<wgpu::Texture>,
+}
+impl TexturePool {
+  fn acquire(&mut self, device: &wgpu::Device, label: &str, size: wgpu::Extent3d, format: wgpu::TextureFormat, usage: wgpu::TextureUsages) -> wgpu::Texture {
+    let fits = |texture: &wgpu::Texture| texture.size() == size && texture.format() == format && texture.usage().contains(usage);
+    let texture = match self.free.iter().position(fits) {
+      Some(idx) => self.free.swap_remove(idx),
+      None => device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage,
+        view_formats: &[],
+      }),
+    };
+    self.live.push(texture.clone());
+    texture
+  }
+
+  /// Makes every texture available again, whoever acquired them must be done with them
+  fn release_all(&mut self) { self.free.append(&mut self.live) }
+
+  /// Frees whatever wasn't reacquired since the last release
+  fn trim(&mut self) {
+    for texture in self.free.drain(..) { texture.destroy() }
+  }
+
+  fn stats(&self) -> TextureStats {
+    let bytes = self.live.iter().chain(&self.free)
+      .map(|texture| texture.width() as u64 * texture.height() as u64 * texture.format().block_copy_size(None).unwrap_or(0) as u64)
+      .sum();
+    TextureStats { live: self.live.len(), pooled: self.free.len(), bytes }
+  }
+}
+
+// Past this many frames in flight we stall for a readback instead of letting it lag further
+const READBACK_MAX_LATENCY: u64 = 4;
+// Map states shared with the map_async callback
+const MAP_PENDING: u8 = 0;
+const MAP_DONE: u8 = 1;
+const MAP_FAILED: u8 = 2;
+
+type ReadbackCallback = Box<dyn FnOnce(&[u8])>;
+
+struct Readback {
+  buffer: wgpu::Buffer,
+  // (padded, packed) bytes per row for texture copies, the padding is stripped before delivery
+  rows: Option<(u32, u32)>,
+  callback: ReadbackCallback,
+  frame: u64,
+  // None until the copy has been submitted and the map requested
+  state: Option<Arc<AtomicU8>>,
+}
+
+/// Copies GPU data into mappable buffers and hands it to a callback a few frames later, without stalling the frame
+#[derive(Default)]
+pub struct ReadbackRing {
+  frame: u64,
+  pending: VecDeque<Readback>,
+  // Staging buffers from finished readbacks, reused when the size matches
+  free: Vec<wgpu::Buffer>,
+}
+impl ReadbackRing {
+  fn staging(&mut self, device: &wgpu::Device, size: u64) -> wgpu::Buffer {
+    if let Some(idx) = self.free.iter().position(|buffer| buffer.size() == size) { return self.free.swap_remove(idx) }
+    device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Readback Buffer"),
+      size,
+      usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    })
+  }
+
+  /// Schedules a copy of size bytes of source, it must have COPY_SRC usage
+  pub fn read_buffer(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, source: &wgpu::Buffer, offset: u64, size: u64, callback: impl FnOnce(&[u8]) + 'static) {
+    let buffer = self.staging(device, size);
+    encoder.copy_buffer_to_buffer(source, offset, &buffer, 0, size);
+    self.pending.push_back(Readback { buffer, rows: None, callback: Box::new(callback), frame: self.frame, state: None });
+  }
+
+  /// Schedules a copy of a whole 2d texture, the callback gets tightly packed rows. It must have COPY_SRC usage
+  pub fn read_texture(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, texture: &wgpu::Texture, callback: impl FnOnce(&[u8]) + 'static) {
+    let packed = texture.width() * texture.format().block_copy_size(None).unwrap();
+    let padded = packed.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT) * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let buffer = self.staging(device, padded as u64 * texture.height() as u64);
+    encoder.copy_texture_to_buffer(
+      texture.as_image_copy(),
+      wgpu::TexelCopyBufferInfo {
+        buffer: &buffer,
+        layout: wgpu::TexelCopyBufferLayout { offset: 0, bytes_per_row: Some(padded), rows_per_image: None },
+      },
+      texture.size(),
+    );
+    self.pending.push_back(Readback { buffer, rows: Some((padded, packed)), callback: Box::new(callback), frame: self.frame, state: None });
+  }
+
+  /// Requests maps for everything copied this frame, call right after the copies are submitted
+  fn submitted(&mut self) {
+    for readback in self.pending.iter_mut().filter(|readback| readback.state.is_none()) {
+      let state = Arc::new(AtomicU8::new(MAP_PENDING));
+      let signal = state.clone();
+      readback.buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+        signal.store(if result.is_ok() { MAP_DONE } else { MAP_FAILED }, Ordering::Release)
+      });
+      readback.state = Some(state);
+    }
+    self.frame += 1;
+  }
+
+  /// Delivers every finished readback, only blocking if the oldest one is overdue
+  fn poll(&mut self, device: &wgpu::Device) {
+    let overdue = self.pending.front().is_some_and(|readback| readback.frame + READBACK_MAX_LATENCY < self.frame);
+    let _ = device.poll(if overdue { wgpu::PollType::Wait } else { wgpu::PollType::Poll });
+    let mut idx = 0;
+    while idx < self.pending.len() {
+      let state = self.pending[idx].state.as_ref().map_or(MAP_PENDING, |state| state.load(Ordering::Acquire));
+      if state == MAP_PENDING { idx += 1; continue }
+      let readback = self.pending.remove(idx).unwrap();
+      if state == MAP_FAILED { continue }
+      {
+        let data = readback.buffer.slice(..).get_mapped_range();
+        match readback.rows {
+          None => (readback.callback)(&data),
+          Some((padded, packed)) => {
+            let rows: Vec<u8> = data.chunks(padded as usize).flat_map(|row| &row[.. packed as usize]).copied().collect();
+            (readback.callback)(&rows)
+          }
+        }
+      }
+      readback.buffer.unmap();
+      self.free.push(readback.buffer);
+    }
+  }
+}
+
+// Staging memory is handed out this many bytes at a time, a frame's uploads usually fit in one
+const UPLOAD_CHUNK: u64 = 1 << 18;
+
+/// Per frame data headed for the GPU, written into staging buffers and copied over by the frame's own encoder
+/// right before the passes that read it. Nothing waits on the queue, and frames still in flight keep what they were given.
+/// The voxels and materials only change now and then and can be far bigger, they still go through the queue
+struct UploadBelt {
+  belt: wgpu::util::StagingBelt,
+  // Bytes written since take_written, for profiling
+  written: u64,
+}
+impl Default for UploadBelt {
+  fn default() -> Self { Self { belt: wgpu::util::StagingBelt::new(UPLOAD_CHUNK), written: 0 } }
+}
+impl UploadBelt {
+  /// Copies bytes into target at offset ahead of whatever's recorded into encoder next, target needs COPY_DST usage
+  fn write(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, target: &wgpu::Buffer, offset: u64, bytes: &[u8]) {
+    let Some(size) = wgpu::BufferSize::new(bytes.len() as u64) else { return };
+    self.belt.write_buffer(encoder, target, offset, size, device).copy_from_slice(bytes);
+    self.written += size.get();
+  }
+
+  /// Closes off this frame's staging buffers, call right before the encoders written with are submitted
+  fn finish(&mut self) { self.belt.finish() }
+
+  /// Takes the staging buffers back for reuse once the GPU's done copying out of them, call right after submitting
+  fn recall(&mut self) { self.belt.recall() }
+
+  fn take_written(&mut self) -> u64 { std::mem::take(&mut self.written) }
+}
+
+/// A finished frame read back from a headless context, 8 bit sRGB
+pub struct Image {
+  pub width: u32,
+  pub height: u32,
+  pub rgba: Vec<u8>,
+}
+impl Image {
+  /// Writes it as a binary PPM, which has no alpha
+  pub fn write_ppm(&self, path: &Path) -> std::io::Result<()> {
+    let mut ppm = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+    for texel in self.rgba.chunks_exact(4) { ppm.extend_from_slice(&texel[.. 3]) }
+    std::fs::write(path, ppm)
+  }
+}
+
+/// Depth and normals of a frame as the DDA pass left them for the passes after it, read back by WgpuCtx::request_gbuffer.
+/// Pixels are counted from the bottom left, like the textures the passes share
+pub struct GBuffer {
+  pub width: u32,
+  pub height: u32,
+  // The DDA's output texels, see ./shaders/dda.wgsl
+  texels: Vec<[f32; 4]>,
+}
+impl GBuffer {
+  /// Linear depth (along the camera's forward axis) of what the pixel hit, None if it hit nothing
+  pub fn depth(&self, x: u32, y: u32) -> Option<f32> {
+    let texel = self.texels[(y * self.width + x) as usize];
+    (texel[3] != 0.0).then_some(texel[2])
+  }
+
+  /// World space normal of what the pixel hit, or the way its ray left on a miss
+  pub fn normal(&self, x: u32, y: u32) -> Vec3 {
+    let texel = self.texels[(y * self.width + x) as usize];
+    oct_decode(Vec2::new(texel[0], texel[1]))
+  }
+
+  /// Writes the normals (mapped from -1..1 into the colors) as a PPM at path, and the depth beside it as a PGM
+  /// that fades from white up close to black at far. Misses are black in both
+  pub fn write(&self, path: &Path, far: f32) -> std::io::Result<()> {
+    let mut normals = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+    let mut depths = format!("P5\n{} {}\n255\n", self.width, self.height).into_bytes();
+    // Images go top down
+    for y in (0 .. self.height).rev() { for x in 0 .. self.width {
+      match self.depth(x, y) {
+        Some(depth) => {
+          normals.extend((self.normal(x, y) * 0.5 + 0.5).to_array().map(|channel| (channel * 255.0).round() as u8));
+          depths.push(((1.0 - depth / far).clamp(0.0, 1.0) * 255.0).round() as u8);
+        }
+        None => {
+          normals.extend([0; 3]);
+          depths.push(0);
+        }
+      }
+    }}
+    std::fs::write(path, normals)?;
+    std::fs::write(path.with_extension("pgm"), depths)
+  }
+}
+
+/// Decodes an Rgba16Float frame and writes it as a binary PPM, keeping every step'th pixel along each axis
+fn write_screenshot(path: &PathBuf, width: u32, height: u32, step: u32, data: &[u8]) -> std::io::Result<()> {
+  let (out_width, out_height) = (width.div_ceil(step), height.div_ceil(step));
+  let mut ppm = format!("P6\n{out_width} {out_height}\n255\n").into_bytes();
+  let texels = (0 .. height).step_by(step as usize)
+    .flat_map(|y| (0 .. width).step_by(step as usize).map(move |x| (y * width + x) as usize * 8));
+  for texel in texels.map(|start| &data[start .. start + 8]) {
+    for channel in texel[.. 6].chunks_exact(2) {
+      let linear = f16_to_f32(u16::from_le_bytes([channel[0], channel[1]])).clamp(0.0, 1.0);
+      // The surface is sRGB, so the lighting output is still linear
+      let srgb = if linear <= 0.0031308 { linear * 12.92 } else { 1.055 * linear.powf(1.0 / 2.4) - 0.055 };
+      ppm.push((srgb * 255.0).round() as u8);
+    }
+  }
+  std::fs::write(path, ppm)
+}
+
+// Matches the one in ./shaders/lighting.wgsl
+fn oct_decode(encoded: Vec2) -> Vec3 {
+  let p = encoded * 2.0 - 1.0;
+  let mut n = Vec3::new(p.x, p.y, 1.0 - p.x.abs() - p.y.abs());
+  if n.z < 0.0 {
+    n.x = (1.0 - p.y.abs()) * p.x.signum();
+    n.y = (1.0 - p.x.abs()) * p.y.signum();
+  }
+  n.normalize()
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+  let sign = if bits >> 15 == 1 { -1.0 } else { 1.0 };
+  let exponent = (bits >> 10 & 0x1f) as i32;
+  let mantissa = (bits & 0x3ff) as f32;
+  sign * match exponent {
+    0 => mantissa * 2f32.powi(-24),
+    0x1f => if mantissa == 0.0 { f32::INFINITY } else { f32::NAN },
+    _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+  }
+}
+
+/// The device and what's uploaded to it once for every window, the graph. The first window's context makes it
+/// and any more share it, see WgpuCtx::share
+struct Gpu {
+  instance: wgpu::Instance,
+  adapter: wgpu::Adapter,
+  device: wgpu::Device,
+  queue: wgpu::Queue,
+  voxels: RefCell<Voxels>,
+  // Bytes written through the queue since the last frame took them, for profiling
+  uploaded: Cell<u64>,
+}
+impl Gpu {
+  fn new(instance: wgpu::Instance, adapter: wgpu::Adapter) -> Result<Self, wgpu::RequestDeviceError> {
+    let (device, queue) = Self::request_device(&adapter)?;
+    // Grown by write_voxels whenever the graph outgrows it
+    let buffer = DdaModule::create_voxel_buffer(&device, 1 << 26);
+    let voxels = Voxels { buffer, node_format: NodeFormat::Packed, layout: VoxelLayout::Raw, reallocations: 0, uploads: 0, too_big: None };
+    Ok(Self { instance, adapter, device, queue, voxels: RefCell::new(voxels), uploaded: Cell::new(0) })
+  }
+
+  fn request_device(adapter: &wgpu::Adapter) -> Result<(wgpu::Device, wgpu::Queue), wgpu::RequestDeviceError> {
+    let supported = adapter.limits();
+    // Timestamps are only for the overlay, so go without them where they're missing
+    pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+      required_features: adapter.features() & wgpu::Features::TIMESTAMP_QUERY,
+      // The voxel buffer grows with the graph, so let it get as big as the adapter manages
+      required_limits: wgpu::Limits {
+        max_buffer_size: supported.max_buffer_size,
+        max_storage_buffer_binding_size: supported.max_storage_buffer_binding_size,
+        ..Default::default()
+      },
+      ..Default::default()
+    }))
+  }
+
+  /// Replaces what's in the voxel buffer, growing it to the next power of two that fits up to what the device can hold.
+  /// Returns false if it's too big even for that
+  fn write_voxels(&self, bytes: &[u8]) -> bool {
+    let max = DdaModule::max_voxel_bytes(&self.device);
+    let mut voxels = self.voxels.borrow_mut();
+    if bytes.len() as u64 > max {
+      // Drawing what's there from a half uploaded graph would just be garbage, so keep drawing the last one
+      tracing::warn!("The graph's {} bytes don't fit in the {max} the GPU can hold, still drawing the last one that did. Try compacting it", bytes.len());
+      voxels.too_big = Some(bytes.len() as u64);
+      return false
+    }
+    voxels.too_big = None;
+    if bytes.len() as u64 > voxels.buffer.size() {
+      voxels.buffer = DdaModule::create_voxel_buffer(&self.device, (bytes.len() as u64).next_power_of_two().min(max));
+      voxels.reallocations += 1;
+    }
+    self.write_buffer(&voxels.buffer, 0, bytes);
+    voxels.uploads += 1;
+    true
+  }
+
+  /// Queues bytes to be written into buffer at offset, counting them towards the frame's uploads
+  fn write_buffer(&self, buffer: &wgpu::Buffer, offset: u64, bytes: &[u8]) {
+    self.queue.write_buffer(buffer, offset, bytes);
+    self.uploaded.set(self.uploaded.get() + bytes.len() as u64);
+  }
+}
+
+// The graph as it is on the GPU
+struct Voxels {
+  buffer: wgpu::Buffer,
+  node_format: NodeFormat,
+  // What it takes to find the objects' heads in buffer
+  layout: VoxelLayout,
+  // Counted so each context knows when to rebind buffer and when its node averages are out of date
+  reallocations: u32,
+  uploads: u32,
+  // How big the latest graph was if it didn't fit, buffer still has the one before it
+  too_big: Option<u64>,
+}
+
+pub struct WgpuCtx<'window> {
+  // None when headless, frames are drawn into offscreen instead
+  surface: Option<wgpu::Surface<'window>>,
+  // Headless contexts keep one too, for the size and format of offscreen
+  surface_config: wgpu::SurfaceConfiguration,
+  offscreen: Option<wgpu::Texture>,
+  gpu: Rc<Gpu>,
+  // Gpu::voxels' counts as of the last sync_voxels
+  voxel_reallocations: u32,
+  voxel_uploads: u32,
+  dda_compute: DdaModule,
+  beam_compute: BeamModule,
+  lighting_compute: LightingModule,
+  temporal_compute: TemporalModule,
+  upscale_render: UpscaleModule,
+  line_render: LineModule,
+  readback: ReadbackRing,
+  uploads: UploadBelt,
+  // Whether the graph or materials changed since the node averages were last worked out, see update_averages
+  averages_stale: bool,
+  textures: TexturePool,
+  // None when the adapter can't timestamp passes
+  pass_timer: Option<PassTimer>,
+  // None when headless, there's no window to take input from
+  overlay: Option<Overlay>,
+  // Some while shaders are being hot reloaded from disk
+  shader_watcher: Option<shaders::ShaderWatcher>,
+  // The finished frame before upscaling, kept so screenshots can copy out of it
+  resolved_output: Option<wgpu::Texture>,
+  // The DDA's output, normal, depth and leaf per pixel. Kept so request_gbuffer can copy out of it
+  gbuffer: Option<wgpu::Texture>,
+  // (path, step), see write_screenshot
+  screenshot: Option<(PathBuf, u32)>,
+  // Waiting on the next frame's gbuffer, see request_gbuffer
+  gbuffer_request: Option<Box<dyn FnOnce(GBuffer)>>,
+  // What the center pixel hit in the latest frame read back, see picked
+  picked: Rc<Cell<Option<Option<RayHit>>>>,
+  // Frames drawn so far, seeds the per frame noise
+  frame: u32,
+  // Scale the textures were last made at, RenderSettings::render_scale unless dynamic_scale is picking it
+  render_scale: f32,
+  // What the surface can do, just Fifo when headless
+  present_modes: Vec<PresentMode>,
+  // The last mode asked for, which the surface is only using if it's in present_modes
+  present_mode: PresentMode,
+  dynamic_scale: DynamicScale,
+}
+impl<'window> WgpuCtx<'window> {
+  pub fn new(window: Arc<Window>) -> WgpuCtx<'window> {
+    let instance = wgpu::Instance::default();
+    let surface = instance.create_surface(Arc::clone(&window)).unwrap();
+    let adapter = pollster::block_on( instance.request_adapter(&wgpu::RequestAdapterOptions {
+      compatible_surface: Some(&surface),
+      ..Default::default()
+    })).unwrap();
+    let gpu = Gpu::new(instance, adapter).unwrap();
+    Self::windowed(Rc::new(gpu), window, surface, true)
+  }
+
+  /// A context for another window, drawing from the same device and voxel buffer as this one so the graph is only
+  /// uploaded once. Materials and the atlas are per context, they need updating on both. Only the first has an overlay
+  pub fn share(&self, window: Arc<Window>) -> WgpuCtx<'window> {
+    let surface = self.gpu.instance.create_surface(Arc::clone(&window)).unwrap();
+    Self::windowed(Rc::clone(&self.gpu), window, surface, false)
+  }
+
+  fn windowed(gpu: Rc<Gpu>, window: Arc<Window>, surface: wgpu::Surface<'window>, overlay: bool) -> WgpuCtx<'window> {
+    let size = window.inner_size();
+    let surface_config = wgpu::SurfaceConfiguration {
+      present_mode: wgpu::PresentMode::Fifo,
+      ..surface.get_default_config(&gpu.adapter, size.width, size.height).unwrap()
+    };
+    surface.configure(&gpu.device, &surface_config);
+    let supported = surface.get_capabilities(&gpu.adapter).present_modes;
+    let present_modes = PresentMode::ALL.into_iter().filter(|mode| supported.contains(&mode.wgpu())).collect();
+    let overlay = overlay.then(|| Overlay::new(window, &gpu.device, surface_config.format));
+    Self::build(gpu, Some(surface), surface_config, present_modes, overlay)
+  }
+
+  /// A context without a window, drawing width x height frames into an offscreen texture.
+  /// Fails if there's no adapter to render with
+  pub fn headless(width: u32, height: u32) -> Result<WgpuCtx<'window>, String> {
+    let instance = wgpu::Instance::default();
+    let adapter = pollster::block_on(instance.request_adapter(&Default::default())).map_err(|err| format!("No adapter: {err}"))?;
+    let gpu = Gpu::new(instance, adapter).map_err(|err| format!("No device: {err}"))?;
+    let surface_config = wgpu::SurfaceConfiguration {
+      usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+      format: wgpu::TextureFormat::Rgba8UnormSrgb,
+      width,
+      height,
+      present_mode: wgpu::PresentMode::Fifo,
+      desired_maximum_frame_latency: 2,
+      alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+      view_formats: Vec::new(),
+    };
+    Ok(Self::build(Rc::new(gpu), None, surface_config, vec![PresentMode::Fifo], None))
+  }
+
+  fn build(
+    gpu: Rc<Gpu>,
+    surface: Option<wgpu::Surface<'window>>,
+    surface_config: wgpu::SurfaceConfiguration,
+    present_modes: Vec<PresentMode>,
+    overlay: Option<Overlay>,
+  ) -> Self {
+    let device = &gpu.device;
+    let voxels = gpu.voxels.borrow();
+    let (voxel_reallocations, voxel_uploads) = (voxels.reallocations, voxels.uploads);
+    let dda_compute = DdaModule::create(device, voxels.buffer.clone());
+    drop(voxels);
+    let beam_compute = BeamModule::create(device);
+    let lighting_compute = LightingModule::create(device, &gpu.queue);
+    let temporal_compute = TemporalModule::create(device);
+    let upscale_render = UpscaleModule::create(device, surface_config.format);
+    let line_render = LineModule::create(device, surface_config.format);
+    let pass_timer = PassTimer::new(device, &gpu.queue);
+    let mut ctx = WgpuCtx {
+      surface,
+      surface_config,
+      offscreen: None,
+      gpu,
+      voxel_reallocations,
+      voxel_uploads,
+      dda_compute,
+      beam_compute,
+      lighting_compute,
+      temporal_compute,
+      upscale_render,
+      line_render,
+      readback: ReadbackRing::default(),
+      uploads: UploadBelt::default(),
+      averages_stale: true,
+      textures: TexturePool::default(),
+      pass_timer,
+      overlay,
+      shader_watcher: None,
+      resolved_output: None,
+      gbuffer: None,
+      screenshot: None,
+      gbuffer_request: None,
+      picked: Rc::new(Cell::new(None)),
+      frame: 0,
+      render_scale: RenderSettings::default().render_scale,
+      present_modes,
+      present_mode: PresentMode::Fifo,
+      dynamic_scale: DynamicScale::default(),
+    };
+    ctx.gen_textures();
+    ctx
+  }
 
   fn gen_textures(&mut self) {
     let size = wgpu::Extent3d {
-      width: (self.surface_config.width as f32 * SCALE) as u32,
-      height: (self.surface_config.height as f32 * SCALE) as u32,
+      width: (self.surface_config.width as f32 * self.render_scale) as u32,
+      height: (self.surface_config.height as f32 * self.render_scale) as u32,
       depth_or_array_layers: 1
     };
 
-    // Put these into 
-    let dda_output = self.device.create_texture(&wgpu::TextureDescriptor {
-      label: Some("Dda Output Texture"),
-      size,
-      mip_level_count: 1,
-      sample_count: 1,
-      dimension: wgpu::TextureDimension::D2,
-      format: wgpu::TextureFormat::Rgba16Float,
-      usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
-      view_formats: &[],
-    }).create_view(&Default::default());
-    let lighting_output = self.device.create_texture(&wgpu::TextureDescriptor {
-      label: Some("Lighting Output Texture"),
-      size,
-      mip_level_count: 1,
-      sample_count: 1,
-      dimension: wgpu::TextureDimension::D2,
-      format: wgpu::TextureFormat::Rgba16Float,
-      usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
-      view_formats: &[],
-    }).create_view(&Default::default());
+    // Everything from the last size goes back to the pool, anything left unclaimed afterwards is freed
+    self.textures.release_all();
+    let storage = wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING;
+    let dda_texture = self.textures.acquire(&self.gpu.device, "Dda Output Texture", size, wgpu::TextureFormat::Rgba32Float, storage | wgpu::TextureUsages::COPY_SRC);
+    let dda_output = dda_texture.create_view(&Default::default());
+    self.gbuffer = Some(dda_texture);
+    let lighting_output = self.textures.acquire(&self.gpu.device, "Lighting Output Texture", size, wgpu::TextureFormat::Rgba16Float, storage)
+      .create_view(&Default::default());
+    let history = ["Temporal History Texture A", "Temporal History Texture B"]
+      .map(|label| self.textures.acquire(&self.gpu.device, label, size, wgpu::TextureFormat::Rgba16Float, storage).create_view(&Default::default()));
+    let resolved_texture = self.textures.acquire(&self.gpu.device, "Temporal Output Texture", size, wgpu::TextureFormat::Rgba16Float, storage | wgpu::TextureUsages::COPY_SRC);
+    let resolved_output = resolved_texture.create_view(&Default::default());
+    self.resolved_output = Some(resolved_texture);
+    // Exact hit position and face uv per pixel, only the shaders read it
+    let hit_output = self.textures.acquire(&self.gpu.device, "Dda Hit Texture", size, wgpu::TextureFormat::Rgba32Uint, storage)
+      .create_view(&Default::default());
+    let tint_output = self.textures.acquire(&self.gpu.device, "Dda Tint Texture", size, wgpu::TextureFormat::Rgba8Unorm, storage)
+      .create_view(&Default::default());
+    let water_output = self.textures.acquire(&self.gpu.device, "Dda Water Texture", size, wgpu::TextureFormat::Rgba32Uint, storage)
+      .create_view(&Default::default());
+    let tiles = wgpu::Extent3d { width: size.width.div_ceil(BEAM_TILE), height: size.height.div_ceil(BEAM_TILE), depth_or_array_layers: 1 };
+    let beam_output = self.textures.acquire(&self.gpu.device, "Beam Texture", tiles, wgpu::TextureFormat::R32Float, storage)
+      .create_view(&Default::default());
+    self.textures.trim();
+
+    self.dda_compute.set_textures(&self.gpu.device, &dda_output, &hit_output, &tint_output, &water_output, &beam_output);
+    self.beam_compute.set_textures(&self.gpu.device, &self.dda_compute, &beam_output);
+    self.lighting_compute.set_textures(&self.gpu.device, &self.dda_compute, &dda_output, &lighting_output, &hit_output, &tint_output, &water_output);
+    self.temporal_compute.set_textures(&self.gpu.device, &self.dda_compute, &lighting_output, &hit_output, history, &resolved_output, &dda_output);
+    self.upscale_render.set_textures(&self.gpu.device, &resolved_output);
+    if self.surface.is_none() {
+      self.offscreen = Some(self.gpu.device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Offscreen Texture"),
+        size: wgpu::Extent3d { width: self.surface_config.width, height: self.surface_config.height, depth_or_array_layers: 1 },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: self.surface_config.format,
+        usage: self.surface_config.usage,
+        view_formats: &[],
+      }));
+    }
+  }
 
+  /// Every present mode the surface supports, Fifo always among them
+  pub fn present_modes(&self) -> &[PresentMode] { &self.present_modes }
 
-    self.dda_compute.set_textures(&self.device, &dda_output);
-    self.lighting_compute.set_textures(&self.device, &dda_output, &lighting_output);
-    self.upscale_render.set_textures(&self.device, &lighting_output, &self.sampler);
+  /// Switches how frames are presented, failing if the surface doesn't support mode
+  pub fn set_present_mode(&mut self, mode: PresentMode) -> Result<(), String> {
+    if !self.present_modes.contains(&mode) {
+      let supported: Vec<_> = self.present_modes.iter().map(|mode| mode.name()).collect();
+      return Err(format!("This display can't present with {}, it has {}", mode.name(), supported.join(", ")))
+    }
+    self.surface_config.present_mode = mode.wgpu();
+    if let Some(surface) = &self.surface { surface.configure(&self.gpu.device, &self.surface_config) }
+    Ok(())
   }
 
   pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+    // Minimized windows report 0 x 0 on some platforms, which no surface can be. Keep the old size till it's restored
+    if new_size.width == 0 || new_size.height == 0 { return }
     self.surface_config.width = new_size.width;
     self.surface_config.height = new_size.height;
-    self.surface.configure(&self.device, &self.surface_config);
+    if let Some(surface) = &self.surface { surface.configure(&self.gpu.device, &self.surface_config) }
     self.gen_textures();
   }
 
-  /// Writes the raw memory of the graph into a GPU buffer
-  pub fn update_voxels(&self, sdg:&SparseDirectedGraph<BasicNode3d>) {
-    self.queue.write_buffer(
-      &self.dda_compute.voxel_buffer,
-      0,
-      bytemuck::cast_slice(& unsafe { std::slice::from_raw_parts(
-        // Pointer to the raw data, converted to a pointer of bytes
-        sdg.nodes.unsafe_data().as_ptr() as *const u8,
-        // Number of elements * bytes per element
-        sdg.nodes.len() * std::mem::size_of::<BasicNode3d>(),
-      )})
-    );
+  /// Writes the graph into a GPU buffer in the current NodeFormat, for every context sharing this one's
+  pub fn update_voxels(&mut self, sdg:&SparseDirectedGraph<BasicNode3d>) {
+    zone!("update voxels");
+    let gpu = &self.gpu;
+    let format = gpu.voxels.borrow().node_format;
+    match format {
+      NodeFormat::Raw => {
+        let bytes = unsafe { std::slice::from_raw_parts(
+          // Pointer to the raw data, converted to a pointer of bytes
+          sdg.nodes.unsafe_data().as_ptr() as *const u8,
+          // Number of elements * bytes per element
+          sdg.nodes.len() * std::mem::size_of::<BasicNode3d>(),
+        )};
+        if gpu.write_voxels(bytes) { gpu.voxels.borrow_mut().layout = VoxelLayout::Raw }
+      }
+      NodeFormat::Packed => {
+        let mut packed = sdg.pack();
+        if gpu.write_voxels(bytemuck::cast_slice(&packed.words)) {
+          // Only the offsets are needed from here on
+          packed.words = Vec::new();
+          gpu.voxels.borrow_mut().layout = VoxelLayout::Packed(packed);
+        }
+      }
+      NodeFormat::Objects => gpu.voxels.borrow_mut().layout = VoxelLayout::Objects(None),
+    }
+    self.sync_voxels();
+  }
+
+  /// Packs each distinct tree the objects have on its own, one after another, objects with the same head share it
+  fn pack_objects(&mut self, game_data: &GameData) {
+    zone!("pack objects");
+    let mut regions = HashMap::new();
+    let mut nodes = Vec::new();
+    let mut words = Vec::new();
+    for object in game_data.objects.values() {
+      let head = object.dag_ref.head;
+      if regions.contains_key(&head) { continue }
+      let tree = match game_data.sdg.pack_tree(head) {
+        Ok(tree) => tree,
+        // Left out of the regions, so it isn't drawn
+        Err(err) => { tracing::error!("Failed to pack head {head}: {err}"); continue }
+      };
+      let base = words.len() as u32;
+      regions.insert(head, (base, tree.entry));
+      nodes.extend(tree.nodes.iter().map(|&(idx, offset)| (idx, base + offset)));
+      words.extend(tree.words);
+    }
+    if self.gpu.write_voxels(bytemuck::cast_slice(&words)) { self.gpu.voxels.borrow_mut().layout = VoxelLayout::Objects(Some((regions, nodes))) }
+    self.sync_voxels();
+  }
+
+  /// Catches up with whatever any context sharing the voxel buffer did to it, rebinding it if it was reallocated
+  fn sync_voxels(&mut self) {
+    let voxels = self.gpu.voxels.borrow();
+    if voxels.uploads != self.voxel_uploads {
+      self.voxel_uploads = voxels.uploads;
+      self.averages_stale = true;
+    }
+    if voxels.reallocations == self.voxel_reallocations { return }
+    self.voxel_reallocations = voxels.reallocations;
+    self.dda_compute.voxel_buffer = voxels.buffer.clone();
+    self.dda_compute.rebuild_bind_group(&self.gpu.device);
+    self.beam_compute.rebuild_bind_group(&self.gpu.device, &self.dda_compute);
+    self.lighting_compute.rebuild_bind_group(&self.gpu.device, &self.dda_compute);
+  }
+
+  /// Swaps in the tiles materials are textured with, they start out untextured
+  pub fn update_atlas(&mut self, atlas: &Atlas) {
+    self.lighting_compute.set_atlas(&self.gpu.device, &self.gpu.queue, &self.dda_compute, atlas);
+  }
+
+  /// Uploads every leaf's material, call whenever the registry changes
+  pub fn update_materials(&mut self, materials: &MaterialRegistry) {
+    let materials: Vec<MaterialData> = materials.all().iter().map(MaterialData::new).collect();
+    if self.dda_compute.reserve_materials(&self.gpu.device, materials.len() as u64) {
+      self.beam_compute.rebuild_bind_group(&self.gpu.device, &self.dda_compute);
+      self.lighting_compute.rebuild_bind_group(&self.gpu.device, &self.dda_compute);
+    }
+    let header = MaterialHeader::new(materials.len() as u32);
+    self.gpu.write_buffer(&self.dda_compute.material_buffer, 0, bytemuck::bytes_of(&header));
+    self.averages_stale = true;
+    if !materials.is_empty() {
+      let offset = std::mem::size_of::<MaterialHeader>() as u64;
+      self.gpu.write_buffer(&self.dda_compute.material_buffer, offset, bytemuck::cast_slice(&materials));
+    }
+  }
+
+  /// Averages every node in the voxel buffer for bounce lighting, each in the same place in its own buffer as the node
+  /// is in the voxel buffer (its index in the raw layout, its word offset packed) so the cones can find it as they descend
+  fn update_averages(&mut self, game_data: &GameData) {
+    zone!("update averages");
+    let sdg = &game_data.sdg;
+    let nodes: Vec<(Index, u32)> = match &self.gpu.voxels.borrow().layout {
+      VoxelLayout::Raw => (0 .. sdg.nodes.len() as Index)
+        .filter(|&idx| sdg.nodes.get(idx as usize).is_some() && !sdg.is_leaf(idx))
+        .map(|idx| (idx, idx)).collect(),
+      VoxelLayout::Packed(packed) => packed.nodes().collect(),
+      VoxelLayout::Objects(regions) => regions.as_ref().map_or(Vec::new(), |(_, nodes)| nodes.clone()),
+    };
+    let mut averages = NodeAverages::new(sdg, &game_data.materials);
+    let len = nodes.iter().map(|&(_, address)| address as usize + 1).max().unwrap_or(0);
+    let mut data: Vec<AverageData> = vec![bytemuck::Zeroable::zeroed(); len];
+    for (idx, address) in nodes { data[address as usize] = AverageData::new(&averages.get(idx)) }
+    let bytes: &[u8] = bytemuck::cast_slice(&data);
+    if bytes.len() as u64 > DdaModule::max_voxel_bytes(&self.gpu.device) {
+      tracing::warn!("The node averages' {} bytes don't fit on the GPU, bounce lighting won't match the scene", bytes.len());
+    } else {
+      self.lighting_compute.reserve_averages(&self.gpu.device, &self.dda_compute, bytes.len() as u64);
+      self.gpu.write_buffer(&self.lighting_compute.average_buffer, 0, bytes);
+    }
+    self.averages_stale = false;
   }
 
-  fn dda(&mut self, game_data: &GameData, encoder: &mut wgpu::CommandEncoder) {
-    let cam = CamData::new(&game_data.camera);
-    self.queue.write_buffer(&self.dda_compute.cam_buffer, 0, bytemuck::bytes_of(&cam));
-    let mut objects = Vec::new();
-    for object in game_data.objects.iter() {
-      objects.push(ObjData::new(object))
+  fn dda(&mut self, game_data: &GameData, camera: &Camera, encoder: &mut wgpu::CommandEncoder) {
+    zone!("dda");
+    let alpha = game_data.physics.interpolation();
+    // Rays give up at max_distance, so anything further can't be hit either
+    let max_distance = game_data.render.max_distance;
+    if game_data.render.node_format != self.gpu.voxels.borrow().node_format {
+      self.gpu.voxels.borrow_mut().node_format = game_data.render.node_format;
+      self.update_voxels(&game_data.sdg);
     }
-    self.queue.write_buffer(&self.dda_compute.objects_buffer, 0, bytemuck::cast_slice(&objects));
+    // Objects can turn up with trees nothing had before without the graph changing, a copy of a prefab say
+    let repack = matches!(&self.gpu.voxels.borrow().layout, VoxelLayout::Objects(regions)
+      if regions.as_ref().is_none_or(|(regions, _)| game_data.objects.values().any(|object| !regions.contains_key(&object.dag_ref.head))));
+    if repack { self.pack_objects(game_data) }
+    self.sync_voxels();
+    let voxels = self.gpu.voxels.borrow();
+    let layout = &voxels.layout;
+    // A tree that didn't fit in the voxel buffer can't be drawn
+    let drawable = game_data.objects.iter()
+      .filter(|(_, object)| !matches!(layout, VoxelLayout::Objects(_)) || layout.locate(object.dag_ref.head).is_some());
+    let (on_screen, off_screen): (Vec<_>, Vec<_>) = drawable.map(|(idx, _)| idx).partition(|&idx| {
+      let (min, max) = game_data.objects[idx].render_aabb(alpha);
+      camera.sees(min, max, max_distance)
+    });
+    self.dda_compute.on_screen = on_screen.len();
+    self.dda_compute.drawn = [on_screen, off_screen].concat();
+    let objects: Vec<ObjData> = self.dda_compute.drawn.iter()
+      .map(|&idx| ObjData::new(&game_data.objects[idx], alpha, layout.locate(game_data.objects[idx].dag_ref.head)))
+      .collect();
+    drop(voxels);
+    if self.dda_compute.reserve_objects(&self.gpu.device, objects.len() as u64) {
+      self.beam_compute.rebuild_bind_group(&self.gpu.device, &self.dda_compute);
+      self.lighting_compute.rebuild_bind_group(&self.gpu.device, &self.dda_compute);
+    }
+    self.uploads.write(&self.gpu.device, encoder, &self.dda_compute.objects_buffer, 0, bytemuck::cast_slice(&objects));
+    // Without history the temporal pass ignores prev_view_proj, anything will do
+    let prev_view_proj = self.temporal_compute.prev_view_proj.unwrap_or(camera.view_proj());
+    // Wrapped hourly so it keeps its precision, the water jumps once when it does
+    let time = (game_data.tick as f64 * TIMESTEP as f64 % 3600.0) as f32;
+    let size = Vec2::new(self.surface_config.width as f32, self.surface_config.height as f32);
+    let resolution = (size * self.render_scale).as_uvec2();
+    let settings = &game_data.render;
+    let jitter = match settings.antialias && settings.temporal && settings.debug_view == DebugView::Lit {
+      true => jitter(self.frame),
+      false => Vec2::ZERO,
+    };
+    let cam = CamData::new(camera, (self.dda_compute.on_screen as u32, objects.len() as u32), settings, time, prev_view_proj, resolution, jitter);
+    self.uploads.write(&self.gpu.device, encoder, &self.dda_compute.cam_buffer, 0, bytemuck::bytes_of(&cam));
 
-    let mut compute_pass = encoder.begin_compute_pass(&Default::default());
+    // Both in one pass, so the timings count the beam as part of the DDA
+    let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+      label: Some("DDA Pass"),
+      timestamp_writes: self.timer().map(|timer| timer.compute_writes(0)),
+    });
+    compute_pass.set_pipeline(&self.beam_compute.pipeline);
+    compute_pass.set_bind_group(0, &self.beam_compute.bind_group, &[]);
+    let tiles = resolution.map(|side| side.div_ceil(BEAM_TILE));
+    compute_pass.dispatch_workgroups(tiles.x.div_ceil(WORKGROUP), tiles.y.div_ceil(WORKGROUP), 1);
     compute_pass.set_pipeline(&self.dda_compute.pipeline);
     compute_pass.set_bind_group(0, &self.dda_compute.bind_group, &[]);
-    let size = Vec2::new(self.surface_config.width as f32, self.surface_config.height as f32);
-    let scaled_size = ((size * SCALE).as_uvec2() + WORKGROUP - 1) / WORKGROUP; // Round up with int math
+    let scaled_size = (resolution + WORKGROUP - 1) / WORKGROUP; // Round up with int math
     compute_pass.dispatch_workgroups(scaled_size.x, scaled_size.y, 1);
   }
   
-  fn lighting(&mut self, encoder: &mut wgpu::CommandEncoder) {
-    let mut compute_pass = encoder.begin_compute_pass(&Default::default());
+  fn lighting(&mut self, game_data: &GameData, camera: &Camera, encoder: &mut wgpu::CommandEncoder) {
+    zone!("lighting");
+    let decals: Vec<DecalData> = game_data.decals.iter().map(|decal| DecalData::new(decal, game_data.tick)).collect();
+    if game_data.render.gi_cones > 0 && self.averages_stale { self.update_averages(game_data) }
+    // Off screen objects still cast shadows into the view
+    let light = LightData::new(&game_data.sky, camera, self.dda_compute.drawn.len() as u32, &game_data.render, self.frame);
+    self.uploads.write(&self.gpu.device, encoder, &self.lighting_compute.light_buffer, 0, bytemuck::bytes_of(&light));
+    let header = DecalHeader::new(decals.len() as u32);
+    self.uploads.write(&self.gpu.device, encoder, &self.lighting_compute.decal_buffer, 0, bytemuck::bytes_of(&header));
+    if !decals.is_empty() {
+      let offset = std::mem::size_of::<DecalHeader>() as u64;
+      self.uploads.write(&self.gpu.device, encoder, &self.lighting_compute.decal_buffer, offset, bytemuck::cast_slice(&decals));
+    }
+    let point_lights: Vec<PointLightData> = match game_data.render.point_lights {
+      true => game_data.lights.nearest(&game_data.objects, camera.position).iter().map(PointLightData::new).collect(),
+      false => Vec::new(),
+    };
+    let header = PointLightHeader::new(point_lights.len() as u32);
+    self.uploads.write(&self.gpu.device, encoder, &self.lighting_compute.point_light_buffer, 0, bytemuck::bytes_of(&header));
+    if !point_lights.is_empty() {
+      let offset = std::mem::size_of::<PointLightHeader>() as u64;
+      self.uploads.write(&self.gpu.device, encoder, &self.lighting_compute.point_light_buffer, offset, bytemuck::cast_slice(&point_lights));
+    }
+
+    let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+      label: Some("Lighting Pass"),
+      timestamp_writes: self.timer().map(|timer| timer.compute_writes(1)),
+    });
     compute_pass.set_pipeline(&self.lighting_compute.pipeline);
     compute_pass.set_bind_group(0, &self.lighting_compute.bind_group, &[]);
     let size = Vec2::new(self.surface_config.width as f32, self.surface_config.height as f32);
-    let scaled_size = ((size * SCALE).as_uvec2() + WORKGROUP - 1) / WORKGROUP; // Round up with int math
+    let scaled_size = ((size * self.render_scale).as_uvec2() + WORKGROUP - 1) / WORKGROUP; // Round up with int math
+    compute_pass.dispatch_workgroups(scaled_size.x, scaled_size.y, 1);
+  }
+
+  fn temporal(&mut self, game_data: &GameData, camera: &Camera, encoder: &mut wgpu::CommandEncoder) {
+    zone!("temporal");
+    let temporal = &mut self.temporal_compute;
+    temporal.reserve_objects(&self.gpu.device, &self.dda_compute, self.dda_compute.drawn.len() as u64);
+    // Everything as drawn, part way between ticks
+    let alpha = game_data.physics.interpolation();
+    // By handle, None for slots without an object
+    let inv_transforms: Vec<Option<Mat4>> = (0 .. game_data.objects.slots())
+      .map(|idx| Some(game_data.objects.get(idx)?.render_inv_transform(alpha))).collect();
+    let transforms: Vec<Option<Mat4>> = inv_transforms.iter().map(|inv| inv.map(|inv| inv.inverse())).collect();
+    // Maps where a point on each drawn object is now to where it was last frame, objects new this frame haven't moved
+    let motion: Vec<[[f32; 4]; 4]> = self.dda_compute.drawn.iter().map(|&idx| {
+      let (now, inv) = (transforms[idx].unwrap(), inv_transforms[idx].unwrap());
+      let prev = temporal.prev_transforms.get(idx).copied().flatten().unwrap_or(now);
+      (prev * inv).to_cols_array_2d()
+    }).collect();
+    let header = MotionHeader::new(temporal.prev_view_proj.is_none() || !game_data.render.temporal);
+    self.uploads.write(&self.gpu.device, encoder, &temporal.motion_buffer, 0, bytemuck::bytes_of(&header));
+    if !motion.is_empty() {
+      let offset = std::mem::size_of::<MotionHeader>() as u64;
+      self.uploads.write(&self.gpu.device, encoder, &temporal.motion_buffer, offset, bytemuck::cast_slice(&motion));
+    }
+    // Hits on screen carry the object's slot in the objects buffer
+    let target = game_data.targeted.and_then(|hit| {
+      let slot = self.dda_compute.drawn.iter().position(|&idx| idx == hit.object)?;
+      Some((slot, game_data.objects[hit.object].grid_cell(hit.cell)?, inv_transforms[hit.object]?))
+    });
+    self.uploads.write(&self.gpu.device, encoder, &temporal.post_buffer, 0, bytemuck::bytes_of(&PostData::new(&game_data.render, &game_data.sky, target)));
+    temporal.prev_view_proj = Some(camera.view_proj());
+    temporal.prev_transforms = transforms;
+    let current = temporal.current;
+    temporal.current = 1 - current;
+
+    let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+      label: Some("Temporal Pass"),
+      timestamp_writes: self.timer().map(|timer| timer.compute_writes(2)),
+    });
+    compute_pass.set_pipeline(&self.temporal_compute.pipeline);
+    compute_pass.set_bind_group(0, self.temporal_compute.bind_groups.as_ref().map(|groups| &groups[current]), &[]);
+    let size = Vec2::new(self.surface_config.width as f32, self.surface_config.height as f32);
+    let scaled_size = ((size * self.render_scale).as_uvec2() + WORKGROUP - 1) / WORKGROUP; // Round up with int math
     compute_pass.dispatch_workgroups(scaled_size.x, scaled_size.y, 1);
   }
 
-  fn upscale(&mut self, frame_view: &wgpu::TextureView, encoder: &mut wgpu::CommandEncoder) {
+  fn upscale(&mut self, game_data: &GameData, frame_view: &wgpu::TextureView, encoder: &mut wgpu::CommandEncoder) {
+    zone!("upscale");
+    let size = [self.surface_config.width as f32, self.surface_config.height as f32];
+    let upscale = UpscaleData::new(&game_data.render, size);
+    self.uploads.write(&self.gpu.device, encoder, &self.upscale_render.upscale_buffer, 0, bytemuck::bytes_of(&upscale));
     let mut upscale_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
       label: Some("Render Pass"),
       color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-        view: &frame_view,
+        view: frame_view,
         resolve_target: None,
         ops: wgpu::Operations {
           load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
@@ -400,25 +2415,234 @@ impl<'window> WgpuCtx<'window> {
         },
       })],
       depth_stencil_attachment: None,
-      timestamp_writes: None,
+      timestamp_writes: self.timer().map(|timer| timer.render_writes(3)),
       occlusion_query_set: None,
     });
     upscale_pass.set_pipeline(&self.upscale_render.pipeline);
     upscale_pass.set_bind_group(0, &self.upscale_render.bind_group, &[]);
     upscale_pass.draw(0..3, 0..1);
+
+    // Debug lines go straight on top of the upscaled image
+    if self.line_render.vertex_count == 0 { return }
+    upscale_pass.set_pipeline(&self.line_render.pipeline);
+    upscale_pass.set_bind_group(0, &self.line_render.bind_group, &[]);
+    upscale_pass.set_vertex_buffer(0, self.line_render.vertex_buffer.slice(..));
+    upscale_pass.draw(0..self.line_render.vertex_count, 0..1);
+  }
+
+  /// Rebuilds the pipelines whenever their shaders are saved, only works when run from the source tree
+  pub fn watch_shaders(&mut self) {
+    self.shader_watcher = Some(shaders::ShaderWatcher::new(&["dda", "beam", "lighting", "temporal", "upscale"]));
+  }
+
+  // A shader that fails to compile leaves the old pipeline in place
+  fn reload_shaders(&mut self) {
+    let Some(watcher) = &mut self.shader_watcher else { return };
+    for (label, source) in watcher.changed() {
+      let device = &self.gpu.device;
+      let result = match label {
+        "dda" => shaders::try_build(device, || DdaModule::create_pipeline(device, &self.dda_compute.bind_group_layout, &source))
+          .map(|pipeline| self.dda_compute.pipeline = pipeline),
+        "beam" => shaders::try_build(device, || BeamModule::create_pipeline(device, &self.beam_compute.bind_group_layout, &source))
+          .map(|pipeline| self.beam_compute.pipeline = pipeline),
+        "lighting" => shaders::try_build(device, || LightingModule::create_pipeline(device, &self.lighting_compute.bind_group_layout, &source))
+          .map(|pipeline| self.lighting_compute.pipeline = pipeline),
+        "temporal" => shaders::try_build(device, || TemporalModule::create_pipeline(device, &self.temporal_compute.bind_group_layout, &source))
+          .map(|pipeline| self.temporal_compute.pipeline = pipeline),
+        "upscale" => {
+          let upscale = &self.upscale_render;
+          shaders::try_build(device, || UpscaleModule::create_pipeline(device, &upscale.bind_group_layout, upscale.format, &source))
+            .map(|pipeline| self.upscale_render.pipeline = pipeline)
+        }
+        _ => unreachable!(),
+      };
+      match result {
+        Ok(()) => tracing::info!("Reloaded {label}.wgsl"),
+        Err(err) => tracing::warn!("Keeping the old {label} pipeline, {label}.wgsl failed to build:\n{err}"),
+      }
+    }
+  }
+
+  fn timer(&self) -> Option<&PassTimer> { self.pass_timer.as_ref() }
+
+  /// Per pass GPU times, None if the adapter can't timestamp passes
+  pub fn perf_stats(&self) -> Option<PerfStats> { self.timer().map(|timer| timer.stats.get()) }
+
+  pub fn texture_stats(&self) -> TextureStats { self.textures.stats() }
+
+  /// Shows or hides the debug overlay
+  pub fn toggle_overlay(&mut self) {
+    if let Some(overlay) = &mut self.overlay { overlay.visible = !overlay.visible }
+  }
+
+  /// Lets the overlay see window events, returns true if it took the event for itself
+  pub fn overlay_event(&mut self, event: &winit::event::WindowEvent, menu_open: bool) -> bool {
+    self.overlay.as_mut().is_some_and(|overlay| overlay.on_window_event(event, menu_open))
   }
 
-  pub fn draw(&mut self, game_data: &GameData) {
-    let frame = self.surface.get_current_texture().unwrap();
-    let view = frame.texture.create_view(&Default::default());
-    let mut encoder = self.device.create_command_encoder(&Default::default());
+  /// Draws game_data count times into the offscreen texture, reading back every frame as it finishes.
+  /// Frames after the first have history to blend with, so the last is the cleanest.
+  pub fn render_frames(&mut self, game_data: &GameData, count: u32) -> Result<Vec<Image>, String> {
+    if self.surface.is_some() { return Err("Only headless contexts can render frames to memory".into()) }
+    let frames = Rc::new(RefCell::new(Vec::new()));
+    for _ in 0 .. count {
+      self.draw(game_data, None);
+      let texture = self.offscreen.as_ref().unwrap();
+      let (width, height) = (texture.width(), texture.height());
+      let mut encoder = self.gpu.device.create_command_encoder(&Default::default());
+      let sink = frames.clone();
+      self.readback.read_texture(&self.gpu.device, &mut encoder, texture, move |rgba| {
+        sink.borrow_mut().push(Image { width, height, rgba: rgba.to_vec() })
+      });
+      self.gpu.queue.submit(Some(encoder.finish()));
+      self.readback.submitted();
+      // One frame at a time, so the readbacks can't pile up
+      self.finish()?;
+    }
+    let frames = frames.take();
+    if frames.len() != count as usize { return Err(format!("Only {} of {count} frames made it back from the GPU", frames.len())) }
+    Ok(frames)
+  }
+
+  /// Blocks until the GPU has done everything drawn so far, then hands out any finished readbacks
+  pub fn finish(&mut self) -> Result<(), String> {
+    self.gpu.device.poll(wgpu::PollType::Wait).map_err(|err| err.to_string())?;
+    self.readback.poll(&self.gpu.device);
+    Ok(())
+  }
+
+  /// Forgets the last frame, for when the world being drawn is swapped for another
+  pub fn reset_history(&mut self) {
+    self.temporal_compute.prev_view_proj = None;
+    self.temporal_compute.prev_transforms.clear();
+    self.picked.set(None);
+  }
+
+  /// The first voxel under the center pixel (translucent or not) in the latest frame to come back from the GPU,
+  /// exactly as it was drawn. A few frames behind like every readback, and None until the first one lands
+  pub fn picked(&self) -> Option<Option<RayHit>> { self.picked.get() }
+
+  fn read_pick(&mut self, encoder: &mut wgpu::CommandEncoder) {
+    // Slots in the objects buffer may mean other objects by the time it's back
+    let (picked, drawn) = (self.picked.clone(), self.dda_compute.drawn.clone());
+    let buffer = &self.dda_compute.pick_buffer;
+    self.readback.read_buffer(&self.gpu.device, encoder, buffer, 0, buffer.size(), move |data| {
+      picked.set(Some(bytemuck::pod_read_unaligned::<PickData>(data).hit(&drawn)))
+    });
+  }
+
+  fn upload_lines(&mut self, game_data: &GameData, camera: &Camera, encoder: &mut wgpu::CommandEncoder) {
+    zone!("upload lines");
+    // The camera as the voxels were drawn, so the lines stay put on them
+    let view = ViewData::new(camera);
+    self.uploads.write(&self.gpu.device, encoder, &self.line_render.view_buffer, 0, bytemuck::bytes_of(&view));
+    self.line_render.upload(&self.gpu.device, &mut self.uploads, encoder, game_data.debug_lines.vertices());
+  }
+
+  /// Saves the next frame to path as a PPM, without the debug lines or overlay
+  pub fn request_screenshot(&mut self, path: PathBuf) { self.screenshot = Some((path, 1)) }
+
+  /// Like request_screenshot, but shrunk to at most max_width pixels across
+  pub fn request_thumbnail(&mut self, path: PathBuf, max_width: u32) {
+    let width = (self.surface_config.width as f32 * self.render_scale) as u32;
+    self.screenshot = Some((path, width.div_ceil(max_width).max(1)));
+  }
+
+  fn capture_screenshot(&mut self, encoder: &mut wgpu::CommandEncoder) {
+    let (Some((path, step)), Some(texture)) = (self.screenshot.take(), &self.resolved_output) else { return };
+    let (width, height) = (texture.width(), texture.height());
+    self.readback.read_texture(&self.gpu.device, encoder, texture, move |data| {
+      match write_screenshot(&path, width, height, step, data) {
+        Ok(()) => tracing::info!("Saved screenshot to {}", path.display()),
+        Err(err) => tracing::error!("Failed to save screenshot to {}: {err}", path.display()),
+      }
+    });
+  }
+
+  /// Hands the next frame's depth and normals to callback, a few frames later like every readback
+  pub fn request_gbuffer(&mut self, callback: impl FnOnce(GBuffer) + 'static) { self.gbuffer_request = Some(Box::new(callback)) }
+
+  fn capture_gbuffer(&mut self, encoder: &mut wgpu::CommandEncoder) {
+    let (Some(callback), Some(texture)) = (self.gbuffer_request.take(), &self.gbuffer) else { return };
+    let (width, height) = (texture.width(), texture.height());
+    self.readback.read_texture(&self.gpu.device, encoder, texture, move |data| {
+      callback(GBuffer { width, height, texels: data.chunks_exact(16).map(bytemuck::pod_read_unaligned).collect() })
+    });
+  }
+
+  /// Draws game_data, and the start screen on top while there is one
+  pub fn draw(&mut self, game_data: &GameData, menu: Option<&mut StartScreen>) { self.draw_from(game_data, &game_data.camera, menu) }
+
+  /// Draws game_data as camera sees it rather than its own camera, for windows besides the main one
+  pub fn draw_view(&mut self, game_data: &GameData, camera: &Camera) { self.draw_from(game_data, camera, None) }
 
-    self.dda(game_data, &mut encoder);
-    self.lighting(&mut encoder);
-    self.upscale(&view, &mut encoder);
+  fn draw_from(&mut self, game_data: &GameData, camera: &Camera, menu: Option<&mut StartScreen>) {
+    zone!("draw");
+    self.reload_shaders();
+    let render = &game_data.render;
+    let scale = match (render.target_fps, self.perf_stats()) {
+      (Some(fps), Some(perf)) => self.dynamic_scale.pick(perf, self.render_scale.min(render.render_scale), render.render_scale, fps),
+      _ => render.render_scale,
+    };
+    if scale != self.render_scale {
+      self.render_scale = scale;
+      self.gen_textures();
+    }
+    // Only tried once per change, so an unsupported mode doesn't complain every frame
+    if render.present_mode != self.present_mode {
+      self.present_mode = render.present_mode;
+      match self.set_present_mode(render.present_mode) {
+        Ok(()) => tracing::info!("Presenting with {}", render.present_mode.name()),
+        Err(err) => tracing::warn!("{err}"),
+      }
+    }
+    self.readback.poll(&self.gpu.device);
+    let frame = match self.surface.as_ref().map(|surface| surface.get_current_texture()) {
+      None => None,
+      Some(Ok(frame)) => Some(frame),
+      // The window changed under the surface, it's fine again once reconfigured. Skip this frame
+      Some(Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated)) => {
+        if let Some(surface) = &self.surface { surface.configure(&self.gpu.device, &self.surface_config) }
+        return
+      }
+      Some(Err(wgpu::SurfaceError::Timeout)) => return,
+      // Out of memory or anything else, there's no frame to draw into this time
+      Some(Err(err)) => { tracing::warn!("Couldn't get a frame to draw into, skipping it: {err}"); return }
+    };
+    let view = match &frame {
+      Some(frame) => frame.texture.create_view(&Default::default()),
+      None => self.offscreen.as_ref().unwrap().create_view(&Default::default()),
+    };
+    let mut encoder = self.gpu.device.create_command_encoder(&Default::default());
+
+    let camera = camera.interpolated(game_data.physics.interpolation());
+    self.dda(game_data, &camera, &mut encoder);
+    self.read_pick(&mut encoder);
+    self.lighting(game_data, &camera, &mut encoder);
+    self.temporal(game_data, &camera, &mut encoder);
+    self.capture_screenshot(&mut encoder);
+    self.capture_gbuffer(&mut encoder);
+    self.upload_lines(game_data, &camera, &mut encoder);
+    self.upscale(game_data, &view, &mut encoder);
+    if let Some(timer) = &self.pass_timer { timer.resolve(&self.gpu.device, &mut encoder, &mut self.readback) }
+    let stale_graph = self.gpu.voxels.borrow().too_big;
+    let stats = RenderStats { gpu: self.perf_stats(), textures: self.texture_stats(), render_scale: self.render_scale, stale_graph };
+    if let Some(overlay) = &mut self.overlay { overlay.draw(&self.gpu.device, &self.gpu.queue, &mut encoder, &view, game_data, stats, menu) }
 
-    self.queue.submit(Some(encoder.finish()));
-    frame.present();
+    self.uploads.finish();
+    {
+      zone!("submit");
+      self.gpu.queue.submit(Some(encoder.finish()));
+    }
+    self.uploads.recall();
+    self.readback.submitted();
+    if let Some(frame) = frame {
+      zone!("present");
+      frame.present();
+    }
+    self.frame = self.frame.wrapping_add(1);
+    count!("bytes uploaded", self.uploads.take_written() + self.gpu.uploaded.take());
+    profiling::frame_mark();
   }
 }
 
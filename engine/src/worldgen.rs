@@ -0,0 +1,98 @@
+use crate::objects::{DagRef, VoxelObject};
+use crate::templates;
+use fastnoise_lite::{FastNoiseLite, FractalType, NoiseType};
+use glam::{IVec3, UVec3, Vec3};
+use sdg::prelude::*;
+
+/// A single fractal noise field
+#[derive(Debug, Clone, Copy)]
+pub struct NoiseLayer {
+  pub frequency: f32,
+  pub octaves: i32,
+  pub amplitude: f32,
+}
+impl NoiseLayer {
+  fn sampler(&self, seed: i32) -> FastNoiseLite {
+    let mut noise = FastNoiseLite::with_seed(seed);
+    noise.set_noise_type(Some(NoiseType::OpenSimplex2));
+    noise.set_fractal_type(Some(FractalType::FBm));
+    noise.set_fractal_octaves(Some(self.octaves));
+    noise.set_frequency(Some(self.frequency));
+    noise
+  }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TerrainConfig {
+  pub seed: i32,
+  /// The terrain is 2^height cells along each axis
+  pub height: u32,
+  /// Average ground level in cells
+  pub ground_level: f32,
+  pub heightmap: NoiseLayer,
+  /// Cells whose 3d noise exceeds the threshold are carved out
+  pub caves: Option<(NoiseLayer, f32)>,
+  /// How many cells below the surface use the surface material
+  pub surface_depth: u32,
+}
+impl Default for TerrainConfig {
+  fn default() -> Self {
+    Self {
+      seed: templates::DEFAULT_SEED,
+      height: 6,
+      ground_level: 24.0,
+      heightmap: NoiseLayer { frequency: 0.02, octaves: 4, amplitude: 12.0 },
+      caves: Some((NoiseLayer { frequency: 0.06, octaves: 2, amplitude: 1.0 }, 0.45)),
+      surface_depth: 2,
+    }
+  }
+}
+
+/// Which leaves the generator fills the terrain with
+#[derive(Debug, Clone, Copy)]
+pub struct TerrainLeaves {
+  pub empty: Index,
+  pub solid: Index,
+  pub surface: Index,
+}
+
+/// Generates a terrain head, the returned head holds a ref
+pub fn generate(sdg: &mut SparseDirectedGraph<BasicNode3d>, config: &TerrainConfig, leaves: TerrainLeaves) -> Index {
+  let cells = cells(config, leaves, IVec3::ZERO);
+  // cells always makes a whole grid of the leaves it's given
+  sdg.from_dense(&cells, 1 << config.height).unwrap()
+}
+
+/// Every leaf of the 2^height cube of terrain whose min corner is at origin, x fastest then y then z.
+/// Doesn't touch a graph, so it can run off the main thread
+pub fn cells(config: &TerrainConfig, leaves: TerrainLeaves, origin: IVec3) -> Vec<Index> {
+  let size = 1u32 << config.height;
+  let heightmap = config.heightmap.sampler(config.seed);
+  let caves = config.caves.map(|(layer, threshold)| (layer.sampler(config.seed.wrapping_add(1)), threshold));
+  // Sample each column once instead of once per cell
+  let column_heights: Vec<i32> = (0 .. size * size).map(|idx| {
+    let (x, z) = ((origin.x + (idx % size) as i32) as f32, (origin.z + (idx / size) as i32) as f32);
+    (config.ground_level + heightmap.get_noise_2d(x, z) * config.heightmap.amplitude).max(1.0) as i32
+  }).collect();
+
+  let mut cells = Vec::with_capacity((size * size * size) as usize);
+  for z in 0 .. size { for y in 0 .. size { for x in 0 .. size {
+    let cell = origin + UVec3::new(x, y, z).as_ivec3();
+    let surface = column_heights[(z * size + x) as usize];
+    cells.push(if cell.y >= surface {
+      leaves.empty
+    // Keep a floor so caves never punch through the bottom of the world
+    } else if let Some((noise, threshold)) = &caves && cell.y > 0
+    && noise.get_noise_3d(cell.x as f32, cell.y as f32, cell.z as f32) > *threshold {
+      leaves.empty
+    } else if cell.y + config.surface_depth as i32 >= surface { leaves.surface } else { leaves.solid });
+  }}}
+  cells
+}
+
+/// A terrain object with its min corner at pos
+pub fn terrain(sdg: &mut SparseDirectedGraph<BasicNode3d>, config: &TerrainConfig, leaves: TerrainLeaves, pos: Vec3) -> VoxelObject {
+  let head = generate(sdg, config, leaves);
+  let size = 1u32 << config.height;
+  VoxelObject::new(sdg, DagRef::new(head, config.height), UVec3::ZERO, UVec3::splat(size - 1), pos)
+}
@@ -1,4 +1,4 @@
-use super::sdg::{ Node, GraphNode, Childs, Path, Index, };
+use super::sdg::{ Node, GraphNode, Childs, Index, };
 use glam::UVec3;
 
 
@@ -18,27 +18,6 @@ pub enum Zorder3d {
   BackBottomLeft,   // 110
   BackBottomRight   // 111
 }
-impl Path<Zorder3d> for Zorder3d {
-  fn to_cell(path: Vec<Zorder3d>) -> UVec3 {
-    let mut cell = UVec3::ZERO;
-    for layer in 0 .. path.len() as u32 {
-      cell = cell | (path[layer as usize].to_coord() << layer);
-    }
-    cell
-  }
-
-  fn path_from(mut cell:UVec3, depth:u32) -> Vec<Self> {
-    if cell.max_element() >= 1 << depth { panic!("Cell is too large for depth {depth}") }
-    let mut path = Vec::with_capacity(depth as usize);
-    for _ in 0 .. depth {
-      path.push(Self::new(cell & 0b1));
-      cell = cell >> 1;
-    }
-    path.reverse();
-    path
-  }
-
-}
 impl Childs for Zorder3d {
   const COUNT: usize = 8;
   fn all() -> impl Iterator<Item = Self> {
@@ -68,7 +47,7 @@ impl Childs for Zorder3d {
   }
   fn to_coord(&self) -> UVec3 {
     let bits = *self as u32;
-    UVec3::new(bits & 1, bits & 0b10, bits & 0b100)
+    UVec3::new(bits & 1, bits >> 1 & 1, bits >> 2 & 1)
   }
 }
 
@@ -0,0 +1,135 @@
+use glam::UVec3;
+use crate::sdg::{SparseDirectedGraph, GraphNode, GraphError, Childs, Index, MAX_DEPTH};
+
+/// Writes to a tree queued up to be made all at once, see SparseDirectedGraph::edit.
+/// Each set_node walks from the head down to its cell and rebuilds the whole way back up, so a burst of them
+/// rebuilds the nodes near the top over and over. Committing walks the tree once, only down to where the writes are,
+/// and moves the refs from the old head to the new one a single time
+pub struct EditBatch<'a, T: GraphNode> {
+  sdg: &'a mut SparseDirectedGraph<T>,
+  head: Index,
+  height: u32,
+  // (cell, height, leaf) of each node to fill, in the order they were queued
+  writes: Vec<(UVec3, u32, Index)>,
+}
+impl<T: GraphNode> EditBatch<'_, T> {
+  /// Queues leaf to be written into cell
+  pub fn set_cell(&mut self, cell:UVec3, leaf:Index) -> &mut Self { self.fill_node(cell, 0, leaf) }
+
+  /// Queues the whole node of the given height which cell is in to be filled with leaf
+  pub fn fill_node(&mut self, cell:UVec3, height:u32, leaf:Index) -> &mut Self {
+    self.writes.push((cell, height, leaf));
+    self
+  }
+
+  pub fn len(&self) -> usize { self.writes.len() }
+
+  pub fn is_empty(&self) -> bool { self.writes.is_empty() }
+
+  /// Makes every queued write, later ones winning where they overlap. Writes outside the grid are dropped.
+  /// Like set_node this uses up head's ref and the returned head holds one, on an error nothing has changed
+  pub fn commit(self) -> Result<Index, GraphError> {
+    let Self { sdg, head, height, writes } = self;
+    let _span = tracing::debug_span!("commit edits", writes = writes.len()).entered();
+    if height as usize > MAX_DEPTH { return Err(GraphError::PathTooDeep) }
+    sdg.check_root(head)?;
+    for &(_, _, leaf) in &writes { sdg.node(leaf)?; }
+    // Down to the min corners of the nodes they fill, none of which are taller than the tree
+    let writes: Vec<_> = writes.into_iter()
+      .filter(|(cell, _, _)| cell.max_element().checked_shr(height).unwrap_or(0) == 0)
+      .map(|(cell, write_height, leaf)| {
+        let write_height = write_height.min(height);
+        (cell & UVec3::splat(u32::MAX.checked_shl(write_height).unwrap_or(0)), write_height, leaf)
+      })
+      .collect();
+    if writes.is_empty() { return Ok(head) }
+    let new_head = sdg.write_node(head, UVec3::ZERO, height, &writes);
+    if new_head == head { return Ok(head) }
+    let new_head = sdg.get_root(new_head)?;
+    sdg.drop_root(head)?;
+    Ok(new_head)
+  }
+}
+
+impl<T: GraphNode> SparseDirectedGraph<T> {
+  /// Starts a batch of writes to the tree of the given height under head, nothing changes until it's committed
+  pub fn edit(&mut self, head:Index, height:u32) -> EditBatch<'_, T> {
+    EditBatch { sdg: self, head, height, writes: Vec::new() }
+  }
+
+  // writes are the ones overlapping this node, oldest first. The new nodes hold no refs until commit roots the head
+  fn write_node(&mut self, mut idx:Index, corner:UVec3, height:u32, mut writes:&[(UVec3, u32, Index)]) -> Index {
+    // Whatever the latest write covering the whole node leaves it as, only the writes after it are left to make
+    if let Some(last) = writes.iter().rposition(|&(_, write_height, _)| write_height >= height) {
+      idx = writes[last].2;
+      writes = &writes[last + 1 ..];
+    }
+    if writes.is_empty() { return idx }
+    let half = 1 << (height - 1);
+    // Leaves are their own children, so a uniform node splits into copies of itself
+    let node = *self.nodes.get(idx as usize).unwrap();
+    let children: Vec<Index> = T::Children::all().map(|child| {
+      let child_corner = corner + child.to_coord() * half;
+      let inside: Vec<_> = writes.iter().copied().filter(|&(write_corner, _, _)| (write_corner >> (height - 1)) == (child_corner >> (height - 1))).collect();
+      self.write_node(node.get(child), child_corner, height - 1, &inside)
+    }).collect();
+    self.insert_node(T::new(&children))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::basic_node3d::BasicNode3d;
+  use crate::morton::MortonPath;
+  use crate::raycast::EMPTY;
+  use crate::testing::{check_refs, Rng};
+
+  const HEIGHT: u32 = 4;
+
+  #[test]
+  fn batches_match_set_node() {
+    let mut sdg = SparseDirectedGraph::<BasicNode3d>::new();
+    let leaves: Vec<Index> = (0 .. 4).map(|_| sdg.add_leaf()).collect();
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    let size = 1 << HEIGHT;
+    let mut head = sdg.get_root(EMPTY).unwrap();
+    for _ in 0 .. 200 {
+      let cell = UVec3::new(rng.next(size), rng.next(size), rng.next(size));
+      head = sdg.set_node(head, &MortonPath::new(cell, HEIGHT).unwrap(), leaves[rng.next(4) as usize]).unwrap();
+    }
+
+    for round in 0 .. 100 {
+      // Overlapping cells and nodes of every height (some taller than the tree), some outside the grid,
+      // and some writing back the leaf that's already there
+      let writes: Vec<(UVec3, u32, Index)> = (0 .. 1 + rng.next(40)).map(|_| {
+        let cell = UVec3::new(rng.next(size + 4), rng.next(size), rng.next(size));
+        let height = if rng.next(3) == 0 { rng.next(HEIGHT + 2) } else { 0 };
+        let inside = cell.max_element() < size;
+        let leaf = if inside && rng.next(4) == 0 { sdg.sample(head, HEIGHT, cell).unwrap().0 } else { leaves[rng.next(4) as usize] };
+        (cell, height, leaf)
+      }).collect();
+
+      let held = sdg.get_root(head).unwrap();
+      let mut batch = sdg.edit(held, HEIGHT);
+      for &(cell, height, leaf) in &writes { batch.fill_node(cell, height, leaf); }
+      let batched = batch.commit().unwrap();
+
+      let mut one_by_one = sdg.get_root(head).unwrap();
+      for &(cell, height, leaf) in &writes {
+        if cell.max_element() >= size { continue }
+        let height = height.min(HEIGHT);
+        one_by_one = sdg.set_node(one_by_one, &MortonPath::new(cell >> height, HEIGHT - height).unwrap(), leaf).unwrap();
+      }
+
+      assert_eq!(batched, one_by_one, "round {round}: {writes:?}");
+      check_refs(&sdg, &[head, batched, one_by_one]);
+      sdg.drop_root(one_by_one).unwrap();
+      sdg.drop_root(head).unwrap();
+      head = batched;
+      check_refs(&sdg, &[head]);
+      // Nothing but the tree and the leaves is left alive
+      assert_eq!(sdg.live_nodes(), leaves.len() + sdg.tree_nodes(head).len());
+    }
+  }
+}
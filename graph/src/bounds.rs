@@ -0,0 +1,44 @@
+use glam::UVec3;
+
+/// Steps each side of a node is cut into to measure its Bounds in
+pub const BOUNDS_STEPS: u32 = 16;
+// What a slot with nothing solid under it (only EMPTY) stores, to_bits never makes it
+pub(crate) const NO_BOUNDS: u32 = u32::MAX;
+
+/// The box the solid (not EMPTY) cells under a node lie within, inclusive, in BOUNDS_STEPS of the node's side
+/// along each axis. Rounded outwards, so it may reach up to a step past the cells but never misses any.
+/// The gap between it and each face of the node is empty however finely it's cut up, so a ray in there can
+/// cross it in one go instead of a node at a time. Nodes don't know their height, which is why it's measured
+/// relative to their side: a node is laid out the same at any height it's used at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bounds {
+  pub min: UVec3,
+  pub max: UVec3,
+}
+impl Bounds {
+  /// Solid all the way to the faces, as any leaf besides EMPTY is
+  pub const FULL: Self = Self { min: UVec3::ZERO, max: UVec3::splat(BOUNDS_STEPS - 1) };
+
+  /// The box around every child's bounds, with each child's corner (in child sides) beside them.
+  /// None if none of them have any
+  pub fn enclosing(children: impl Iterator<Item = (UVec3, Option<Self>)>) -> Option<Self> {
+    children.filter_map(|(corner, bounds)| {
+      // A child covers half its parent along each axis, so its steps are half as big in the parent's
+      let bounds = bounds?;
+      Some(Self { min: (bounds.min + corner * BOUNDS_STEPS) / 2, max: (bounds.max + corner * BOUNDS_STEPS) / 2 })
+    }).reduce(|a, b| Self { min: a.min.min(b.min), max: a.max.max(b.max) })
+  }
+
+  /// 4 bits for each of min then max, x then y then z, from the bottom up. 24 bits in all,
+  /// which fit above the child mask of a packed node's header (see SparseDirectedGraph::pack)
+  pub fn to_bits(self) -> u32 {
+    let [a, b, c] = self.min.to_array();
+    let [d, e, f] = self.max.to_array();
+    a | b << 4 | c << 8 | d << 12 | e << 16 | f << 20
+  }
+
+  pub fn from_bits(bits: u32) -> Self {
+    let field = |idx: u32| bits >> (idx * 4) & (BOUNDS_STEPS - 1);
+    Self { min: UVec3::new(field(0), field(1), field(2)), max: UVec3::new(field(3), field(4), field(5)) }
+  }
+}
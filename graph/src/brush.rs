@@ -0,0 +1,203 @@
+use ahash::AHashMap;
+use glam::{UVec3, Vec3};
+use crate::sdg::{SparseDirectedGraph, GraphNode, GraphError, Childs, Index};
+use crate::raycast::EMPTY;
+
+/// A shape in a tree's cell space, a cell is inside if its center is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Brush {
+  Sphere { center: Vec3, radius: f32 },
+  /// Running along axis, length long in total with center halfway
+  Cylinder { center: Vec3, radius: f32, length: f32, axis: Axis },
+  /// Everything between the min and max corners
+  Box { min: Vec3, max: Vec3 },
+}
+
+/// One of the grid's axes, what a Cylinder runs along
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis { X, Y, Z }
+impl Axis {
+  /// 0 for x up to 2 for z, for indexing vectors
+  pub fn index(self) -> usize { self as usize }
+}
+
+/// Which cells inside the brush take its leaf
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Blend {
+  /// Every one
+  Replace,
+  /// Only those which aren't empty, recoloring what's there
+  Paint,
+  /// Only empty ones, building around what's there
+  Add,
+}
+impl Blend {
+  fn takes(self, leaf: Index) -> bool {
+    match self {
+      Self::Replace => true,
+      Self::Paint => leaf != EMPTY,
+      Self::Add => leaf == EMPTY,
+    }
+  }
+}
+
+#[derive(PartialEq)]
+enum Coverage { Outside, Partial, Inside }
+
+impl Brush {
+  // How much of the box holding the cell centers min ..= max is inside
+  fn coverage(self, min: Vec3, max: Vec3) -> Coverage {
+    let nearest = |center: Vec3| center.clamp(min, max) - center;
+    let furthest = |center: Vec3| (min - center).abs().max((max - center).abs());
+    let (near, far) = match self {
+      Self::Sphere { center, radius } => (nearest(center).length() <= radius, furthest(center).length() <= radius),
+      Self::Cylinder { center, radius, length, axis } => {
+        let axis = axis.index();
+        let flat = |offset: Vec3| { let mut offset = offset; offset[axis] = 0.0; offset.length() };
+        let (near, far) = (nearest(center), furthest(center));
+        (flat(near) <= radius && near[axis].abs() <= length / 2.0, flat(far) <= radius && far[axis].abs() <= length / 2.0)
+      }
+      Self::Box { min: low, max: high } => (min.cmple(high).all() && max.cmpge(low).all(), min.cmpge(low).all() && max.cmple(high).all()),
+    };
+    match (near, far) {
+      (_, true) => Coverage::Inside,
+      (true, false) => Coverage::Partial,
+      (false, false) => Coverage::Outside,
+    }
+  }
+
+  /// The cells which could be inside, min and max inclusive. None if that's none of a grid size cells across
+  pub fn bounds(self, size: u32) -> Option<(UVec3, UVec3)> {
+    let reach = match self {
+      Self::Sphere { radius, .. } => Vec3::splat(radius),
+      Self::Cylinder { radius, length, axis, .. } => { let mut reach = Vec3::splat(radius); reach[axis.index()] = length / 2.0; reach }
+      Self::Box { min, max } => (max - min) / 2.0,
+    };
+    let center = match self {
+      Self::Sphere { center, .. } | Self::Cylinder { center, .. } => center,
+      Self::Box { min, max } => (min + max) / 2.0,
+    };
+    // Cells whose centers are within reach
+    let (min, max) = ((center - reach - 0.5).ceil(), (center + reach - 0.5).floor());
+    if min.cmpgt(max).any() || max.cmplt(Vec3::ZERO).any() || min.cmpge(Vec3::splat(size as f32)).any() { return None }
+    Some((min.max(Vec3::ZERO).as_uvec3(), max.as_uvec3().min(UVec3::splat(size - 1))))
+  }
+
+  // What the brush looks like from a node at corner, as a key two nodes share if the same cells of theirs are inside.
+  // Only boxes have one, clamped to the node a box's edge repeats all along it
+  fn local(self, corner: Vec3, size: f32) -> Option<[u32; 6]> {
+    let Self::Box { min, max } = self else { return None };
+    let (min, max) = ((min - corner).clamp(Vec3::ZERO, Vec3::splat(size)), (max - corner).clamp(Vec3::ZERO, Vec3::splat(size)));
+    Some([min.x, min.y, min.z, max.x, max.y, max.z].map(f32::to_bits))
+  }
+}
+
+// What nodes became so far, so the same node under the same bit of brush is only walked once.
+// inside holds nodes wholly inside the brush, they don't depend on where they are
+#[derive(Default)]
+struct Memo {
+  inside: AHashMap<Index, Index>,
+  edge: AHashMap<(Index, u32, [u32; 6]), Index>,
+}
+
+impl<T: GraphNode> SparseDirectedGraph<T> {
+  /// Writes leaf into every cell of the tree under head which brush covers and blend lets it take.
+  /// Nodes wholly inside or outside the brush are dealt with whole, only its edge is walked down to single cells.
+  /// Like set_node this uses up head's ref and the returned head holds one
+  pub fn apply_brush(&mut self, head:Index, height:u32, brush:Brush, leaf:Index, blend:Blend) -> Result<Index, GraphError> {
+    let _span = tracing::debug_span!("apply brush").entered();
+    self.check_root(head)?;
+    self.node(leaf)?;
+    let new_head = self.brush_node(head, UVec3::ZERO, height, brush, leaf, blend, &mut Memo::default());
+    let new_head = self.get_root(new_head)?;
+    self.drop_root(head)?;
+    Ok(new_head)
+  }
+
+  #[allow(clippy::too_many_arguments)]
+  fn brush_node(&mut self, idx:Index, corner:UVec3, height:u32, brush:Brush, leaf:Index, blend:Blend, memo:&mut Memo) -> Index {
+    let size = 1 << height;
+    let coverage = brush.coverage(corner.as_vec3() + 0.5, (corner + size).as_vec3() - 0.5);
+    if coverage == Coverage::Outside { return idx }
+    if coverage == Coverage::Inside {
+      if self.is_leaf(idx) { return if blend.takes(idx) { leaf } else { idx } }
+      if blend == Blend::Replace { return leaf }
+      if let Some(&done) = memo.inside.get(&idx) { return done }
+    }
+    let edge = if coverage == Coverage::Partial { brush.local(corner.as_vec3(), size as f32).map(|local| (idx, height, local)) } else { None };
+    if let Some(done) = edge.and_then(|key| memo.edge.get(&key)) { return *done }
+    // Only a cell's center is ever tested, so a single cell is always wholly in or out
+    let node = *self.nodes.get(idx as usize).unwrap();
+    let children: Vec<Index> = T::Children::all()
+      .map(|child| self.brush_node(node.get(child), corner + child.to_coord() * (size >> 1), height - 1, brush, leaf, blend, memo))
+      .collect();
+    let new_idx = self.insert_node(T::new(&children));
+    if coverage == Coverage::Inside { memo.inside.insert(idx, new_idx); }
+    if let Some(key) = edge { memo.edge.insert(key, new_idx); }
+    new_idx
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::basic_node3d::BasicNode3d;
+  use crate::testing::{check_refs, dense, Rng};
+
+  const HEIGHT: u32 = 4;
+
+  // Whether the cell's center is inside, worked out on its own rather than through coverage
+  fn contains(brush: Brush, cell: UVec3) -> bool {
+    let point = cell.as_vec3() + 0.5;
+    match brush {
+      Brush::Sphere { center, radius } => point.distance(center) <= radius,
+      Brush::Cylinder { center, radius, length, axis } => {
+        let offset = point - center;
+        let along = offset[axis.index()];
+        (offset.length_squared() - along * along).sqrt() <= radius && along.abs() <= length / 2.0
+      }
+      Brush::Box { min, max } => point.cmpge(min).all() && point.cmple(max).all(),
+    }
+  }
+
+  #[test]
+  fn brushes_match_a_cell_by_cell_check() {
+    let mut sdg = SparseDirectedGraph::<BasicNode3d>::new();
+    let leaves: Vec<Index> = (0 .. 3).map(|_| sdg.add_leaf()).collect();
+    let mut rng = Rng(0xdead_beef_cafe_f00d);
+    let size = 1 << HEIGHT;
+    // Solid below a bumpy floor, so brushes cross both empty and filled stretches and whole uniform nodes
+    let floor: Vec<u32> = (0 .. size * size).map(|_| 6 + rng.next(4)).collect();
+    let base = sdg.build(HEIGHT, |cell| if cell.y < floor[(cell.x + cell.z * size) as usize] { leaves[1 + (cell.x / 5) as usize % 2] } else { EMPTY });
+    let before = dense(&sdg, base, HEIGHT);
+
+    // Some poke out past the grid's edges
+    let point = |rng: &mut Rng| Vec3::new(rng.next(size + 8) as f32, rng.next(size + 8) as f32, rng.next(size + 8) as f32) - 4.0;
+    for round in 0 .. 60 {
+      let brush = match round % 5 {
+        0 => Brush::Sphere { center: point(&mut rng), radius: rng.next(90) as f32 / 10.0 },
+        1 => Brush::Box { min: point(&mut rng), max: point(&mut rng) },
+        2 => Brush::Cylinder { center: point(&mut rng), radius: rng.next(60) as f32 / 10.0, length: rng.next(20) as f32, axis: Axis::X },
+        3 => Brush::Cylinder { center: point(&mut rng), radius: rng.next(60) as f32 / 10.0, length: rng.next(20) as f32, axis: Axis::Y },
+        _ => Brush::Cylinder { center: point(&mut rng), radius: rng.next(60) as f32 / 10.0, length: rng.next(20) as f32, axis: Axis::Z },
+      };
+      for blend in [Blend::Replace, Blend::Paint, Blend::Add] {
+        let leaf = leaves[rng.next(3) as usize];
+        let head = sdg.get_root(base).unwrap();
+        let head = sdg.apply_brush(head, HEIGHT, brush, leaf, blend).unwrap();
+        let bounds = brush.bounds(size);
+        for (idx, (&was, &now)) in before.iter().zip(&dense(&sdg, head, HEIGHT)).enumerate() {
+          let cell = UVec3::new(idx as u32 % size, idx as u32 / size % size, idx as u32 / size / size);
+          let expected = if contains(brush, cell) && blend.takes(was) { leaf } else { was };
+          assert_eq!(now, expected, "{brush:?} {blend:?} with leaf {leaf} at {cell}");
+          if contains(brush, cell) {
+            let (min, max) = bounds.unwrap_or_else(|| panic!("{brush:?} has no bounds but covers {cell}"));
+            assert!(cell.cmpge(min).all() && cell.cmple(max).all(), "{brush:?} covers {cell} outside its bounds");
+          }
+        }
+        check_refs(&sdg, &[base, head]);
+        sdg.drop_root(head).unwrap();
+      }
+    }
+  }
+}
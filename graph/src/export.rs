@@ -0,0 +1,134 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+use ahash::{AHashMap, AHashSet};
+use crate::sdg::{SparseDirectedGraph, GraphNode, GraphError, Childs, Index};
+
+/// Writes the subtree under head as a graphviz digraph, stopping max_depth edges down.
+/// Every node is drawn once, so a node with several incoming edges is one that's being deduplicated.
+/// Those get a double border and are labelled with their ref count. leaf_color picks each leaf's fill.
+/// Errors if head isn't a live node
+pub fn to_dot<T: GraphNode>(sdg: &SparseDirectedGraph<T>, head: Index, max_depth: u32, leaf_color: impl Fn(Index) -> String) -> Result<String, GraphError> {
+  sdg.node(head)?;
+  let mut out = String::from("digraph sdg {\n  node [shape=box, style=filled, fillcolor=white];\n");
+  let mut depths = AHashMap::from([(head, 0)]);
+  let mut parents: AHashMap<Index, u32> = AHashMap::new();
+  let mut edges = String::new();
+  let mut queue = VecDeque::from([head]);
+  while let Some(idx) = queue.pop_front() {
+    let depth = depths[&idx];
+    if sdg.is_leaf(idx) || depth == max_depth { continue }
+    let node = sdg.nodes.get(idx as usize).unwrap();
+    for child in T::Children::all() {
+      let child_idx = node.get(child);
+      writeln!(edges, "  n{idx} -> n{child_idx} [label=\"{:?}\"];", child.to_coord().to_array()).unwrap();
+      *parents.entry(child_idx).or_default() += 1;
+      if !depths.contains_key(&child_idx) {
+        depths.insert(child_idx, depth + 1);
+        queue.push_back(child_idx);
+      }
+    }
+  }
+
+  let mut nodes: Vec<_> = depths.into_iter().collect();
+  nodes.sort_unstable();
+  for (idx, depth) in nodes {
+    let shared = parents.get(&idx).is_some_and(|&count| count > 1);
+    let border = if shared { ", peripheries=2" } else { "" };
+    if sdg.is_leaf(idx) {
+      writeln!(out, "  n{idx} [label=\"leaf {idx}\", shape=ellipse, fillcolor=\"{}\"{border}];", leaf_color(idx)).unwrap();
+    } else if depth == max_depth {
+      writeln!(out, "  n{idx} [label=\"{idx} ...\", style=dashed{border}];").unwrap();
+    } else {
+      writeln!(out, "  n{idx} [label=\"{idx}\\nrefs {}\"{border}];", sdg.refs(idx)).unwrap();
+    }
+  }
+  out.push_str(&edges);
+  out.push_str("}\n");
+  Ok(out)
+}
+
+/// Writes the subtree under head as an indented outline, each child on its own line led by its corner,
+/// stopping max_depth levels down. A node which has already been written out is only named again, so repeats are sharing.
+/// Errors if head isn't a live node
+pub fn to_tree<T: GraphNode>(sdg: &SparseDirectedGraph<T>, head: Index, max_depth: u32) -> Result<String, GraphError> {
+  sdg.node(head)?;
+  let mut out = String::new();
+  let mut written = AHashSet::new();
+  // (node, depth, which child of its parent it is)
+  let mut stack = vec![(head, 0, None)];
+  while let Some((idx, depth, child)) = stack.pop() {
+    let indent = "  ".repeat(depth as usize);
+    let corner = child.map(|child: T::Children| format!("{:?} ", child.to_coord().to_array())).unwrap_or_default();
+    if sdg.is_leaf(idx) { writeln!(out, "{indent}{corner}leaf {idx}").unwrap(); continue }
+    if depth == max_depth { writeln!(out, "{indent}{corner}{idx} ...").unwrap(); continue }
+    if !written.insert(idx) { writeln!(out, "{indent}{corner}{idx}, as above").unwrap(); continue }
+    writeln!(out, "{indent}{corner}{idx} (refs {})", sdg.refs(idx)).unwrap();
+    let node = sdg.nodes.get(idx as usize).unwrap();
+    let children: Vec<_> = T::Children::all().collect();
+    // Popped first to last
+    for &child in children.iter().rev() { stack.push((node.get(child), depth + 1, Some(child))) }
+  }
+  Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use glam::UVec3;
+  use crate::basic_node3d::BasicNode3d;
+  use crate::morton::MortonPath;
+  use crate::raycast::EMPTY;
+
+  // A tree 4 cells across holding the same 2 cell node at two of the head's corners, returned with that node
+  fn shared_tree(sdg: &mut SparseDirectedGraph<BasicNode3d>) -> (Index, Index, Index) {
+    sdg.add_leaf();
+    let leaf = sdg.add_leaf();
+    let head = sdg.build(2, |cell| if cell == UVec3::ZERO || cell == UVec3::new(2, 0, 0) { leaf } else { EMPTY });
+    let corner = sdg.descend(head, &MortonPath::new(UVec3::ZERO, 1).unwrap()).unwrap();
+    (head, corner, leaf)
+  }
+
+  #[test]
+  fn dot_draws_shared_nodes_once() {
+    let mut sdg = SparseDirectedGraph::<BasicNode3d>::new();
+    let (head, corner, leaf) = shared_tree(&mut sdg);
+    let dot = to_dot(&sdg, head, 8, |leaf| format!("color{leaf}")).unwrap();
+    assert!(dot.starts_with("digraph sdg {\n") && dot.ends_with("}\n"));
+    let declared = |idx: Index| dot.lines().filter(|line| line.starts_with(&format!("  n{idx} ["))).count();
+    let edges = |from: Index, to: Index| dot.lines().filter(|line| line.starts_with(&format!("  n{from} -> n{to} "))).count();
+    for idx in [head, corner, leaf, EMPTY] { assert_eq!(declared(idx), 1, "n{idx}") }
+    assert!(dot.contains(&format!("  n{head} [label=\"{head}\\nrefs 1\"];")));
+    assert!(dot.contains(&format!("  n{corner} [label=\"{corner}\\nrefs 2\", peripheries=2];")));
+    assert!(dot.contains(&format!("  n{leaf} [label=\"leaf {leaf}\", shape=ellipse, fillcolor=\"color{leaf}\"];")));
+    assert!(dot.contains(&format!("  n{head} -> n{corner} [label=\"[1, 0, 0]\"];")));
+    assert_eq!((edges(head, corner), edges(head, EMPTY), edges(corner, leaf), edges(corner, EMPTY)), (2, 6, 1, 7));
+    // Every edge of the corner is only written the once, however many parents it has
+    assert_eq!(dot.lines().filter(|line| line.contains(" -> ")).count(), 16);
+
+    // Stopping at the head's children leaves the corner dashed and its leaf out
+    let shallow = to_dot(&sdg, head, 1, |_| String::new()).unwrap();
+    assert!(shallow.contains(&format!("  n{corner} [label=\"{corner} ...\", style=dashed, peripheries=2];")));
+    assert!(!shallow.contains(&format!("n{leaf} ")));
+  }
+
+  #[test]
+  fn outlines_name_repeats() {
+    let mut sdg = SparseDirectedGraph::<BasicNode3d>::new();
+    let (head, corner, leaf) = shared_tree(&mut sdg);
+    let tree = to_tree(&sdg, head, 8).unwrap();
+    let lines: Vec<&str> = tree.lines().collect();
+    assert_eq!(lines.len(), 1 + 8 + 8);
+    assert_eq!(lines[0], format!("{head} (refs 1)"));
+    assert_eq!(lines[1], format!("  [0, 0, 0] {corner} (refs 2)"));
+    assert_eq!(lines[2], format!("    [0, 0, 0] leaf {leaf}"));
+    assert_eq!(lines[3], format!("    [1, 0, 0] leaf {EMPTY}"));
+    assert_eq!(lines[10], format!("  [1, 0, 0] {corner}, as above"));
+    assert_eq!(lines[16], format!("  [1, 1, 1] leaf {EMPTY}"));
+
+    // Cut off, nothing under the head is written out
+    let shallow = to_tree(&sdg, head, 1).unwrap();
+    assert_eq!(shallow.lines().filter(|line| *line == format!("  [1, 0, 0] {corner} ...")).count(), 1);
+    assert!(!shallow.contains("as above"));
+    assert_eq!(shallow.lines().count(), 9);
+  }
+}
@@ -0,0 +1,139 @@
+use std::collections::VecDeque;
+use ahash::AHashSet;
+use glam::{IVec3, UVec3};
+use crate::sdg::{SparseDirectedGraph, GraphNode, GraphError, Index, MAX_DEPTH};
+use crate::raycast::EMPTY;
+
+/// Which neighbouring cells count as touching
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connectivity {
+  /// 6 neighbours
+  Faces,
+  /// 18 neighbours
+  Edges,
+  /// 26 neighbours
+  Corners,
+}
+impl Connectivity {
+  fn offsets(self) -> impl Iterator<Item = IVec3> {
+    let max_axes = match self { Self::Faces => 1, Self::Edges => 2, Self::Corners => 3 };
+    (-1 ..= 1).flat_map(|z| (-1 ..= 1).flat_map(move |y| (-1 ..= 1).map(move |x| IVec3::new(x, y, z))))
+      .filter(move |offset| (1 ..= max_axes).contains(&offset.abs().element_sum()))
+  }
+}
+
+/// A connected group of cells which all hold leaf
+#[derive(Debug, Clone)]
+pub struct Region {
+  pub leaf: Index,
+  /// In the order the fill reached them, starting cell first
+  pub cells: Vec<UVec3>,
+  /// Corners of the cells' bounding box, inclusive
+  pub min: UVec3,
+  pub max: UVec3,
+  /// Whether the fill hit its cell limit before running out of connected cells
+  pub truncated: bool,
+}
+
+impl<T: GraphNode> SparseDirectedGraph<T> {
+  /// Every cell connected to start which holds the same leaf, stopping after max_cells.
+  /// Empty space is selected like any other leaf, so this also finds air pockets.
+  /// Errors if head isn't a live node or start is outside the tree
+  pub fn select_connected(&self, head: Index, height: u32, start: UVec3, connectivity: Connectivity, max_cells: usize) -> Result<Region, GraphError> {
+    self.node(head)?;
+    if height > MAX_DEPTH as u32 { return Err(GraphError::PathTooDeep) }
+    // A tree 32 levels tall takes up every cell a UVec3 can hold
+    let outside = |cell: UVec3| height < 32 && cell.max_element() >= 1u32 << height;
+    if outside(start) { return Err(GraphError::CellOutOfBounds(start)) }
+    let leaf = self.sample_live(head, height, start).0;
+    let mut region = Region { leaf, cells: Vec::new(), min: start, max: start, truncated: false };
+    let mut seen = AHashSet::from_iter([start]);
+    let mut queue = VecDeque::from([start]);
+    while let Some(cell) = queue.pop_front() {
+      if region.cells.len() == max_cells { region.truncated = true; break }
+      region.cells.push(cell);
+      region.min = region.min.min(cell);
+      region.max = region.max.max(cell);
+      for offset in connectivity.offsets() {
+        let Some(next) = cell.checked_add_signed(offset).filter(|&next| !outside(next)) else { continue };
+        if seen.insert(next) && self.sample_live(head, height, next).0 == leaf { queue.push_back(next) }
+      }
+    }
+    Ok(region)
+  }
+
+  /// Replaces the leaf of every cell connected to start (see select_connected) with new_leaf.
+  /// Returns the new head alongside what was filled, the old head's ref moves to the new one.
+  pub fn flood_fill(&mut self, head: Index, height: u32, start: UVec3, new_leaf: Index, connectivity: Connectivity, max_cells: usize) -> Result<(Index, Region), GraphError> {
+    let region = self.select_connected(head, height, start, connectivity, max_cells)?;
+    if region.leaf == new_leaf { return Ok((head, region)) }
+    let mut batch = self.edit(head, height);
+    for &cell in &region.cells { batch.set_cell(cell, new_leaf); }
+    Ok((batch.commit()?, region))
+  }
+
+  /// A new tree of height holding only region, everything else empty. The returned head holds a ref.
+  pub fn region_tree(&mut self, region: &Region, height: u32) -> Result<Index, GraphError> {
+    let head = self.get_root(EMPTY)?;
+    let mut batch = self.edit(head, height);
+    for &cell in &region.cells { batch.set_cell(cell, region.leaf); }
+    batch.commit()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::basic_node3d::BasicNode3d;
+  use crate::testing::{check_refs, dense};
+
+  const HEIGHT: u32 = 3;
+
+  // Two boxes of leaf 1 with a gap between them, and a cell only touching the first box at a corner
+  fn scene(sdg: &mut SparseDirectedGraph<BasicNode3d>) -> (Index, Index) {
+    let leaf = sdg.add_leaf();
+    let head = sdg.build(HEIGHT, |cell| {
+      let first = cell.cmple(UVec3::splat(2)).all();
+      let second = cell.x >= 5 && cell.y <= 1 && cell.z >= 4;
+      let corner = cell == UVec3::splat(3);
+      if first || second || corner { leaf } else { EMPTY }
+    });
+    (head, leaf)
+  }
+
+  #[test]
+  fn fills_stop_at_the_component() {
+    let mut sdg = SparseDirectedGraph::<BasicNode3d>::new();
+    sdg.add_leaf();
+    let (head, leaf) = scene(&mut sdg);
+
+    let first = sdg.select_connected(head, HEIGHT, UVec3::new(1, 2, 0), Connectivity::Faces, usize::MAX).unwrap();
+    assert_eq!((first.leaf, first.cells.len(), first.min, first.max, first.truncated), (leaf, 27, UVec3::ZERO, UVec3::splat(2), false));
+    assert_eq!(first.cells[0], UVec3::new(1, 2, 0));
+    // Only corners reach the lone cell, nothing reaches the second box
+    let corners = sdg.select_connected(head, HEIGHT, UVec3::ZERO, Connectivity::Corners, usize::MAX).unwrap();
+    assert_eq!((corners.cells.len(), corners.max), (28, UVec3::splat(3)));
+    let second = sdg.select_connected(head, HEIGHT, UVec3::new(7, 0, 7), Connectivity::Edges, usize::MAX).unwrap();
+    assert_eq!((second.cells.len(), second.min, second.max), (24, UVec3::new(5, 0, 4), UVec3::new(7, 1, 7)));
+    // The air around them is one region too
+    let air = sdg.select_connected(head, HEIGHT, UVec3::new(7, 7, 0), Connectivity::Faces, usize::MAX).unwrap();
+    assert_eq!((air.leaf, air.cells.len()), (EMPTY, 512 - 27 - 24 - 1));
+    let cut_short = sdg.select_connected(head, HEIGHT, UVec3::ZERO, Connectivity::Faces, 10).unwrap();
+    assert_eq!((cut_short.cells.len(), cut_short.truncated), (10, true));
+
+    let before = dense(&sdg, head, HEIGHT);
+    let painted = sdg.add_leaf();
+    let (filled, region) = sdg.flood_fill(head, HEIGHT, UVec3::splat(2), painted, Connectivity::Faces, usize::MAX).unwrap();
+    for (idx, (&was, &now)) in before.iter().zip(&dense(&sdg, filled, HEIGHT)).enumerate() {
+      let cell = UVec3::new(idx as u32 % 8, idx as u32 / 8 % 8, idx as u32 / 64);
+      assert_eq!(now, if region.cells.contains(&cell) { painted } else { was }, "{cell}");
+    }
+    check_refs(&sdg, &[filled]);
+
+    assert_eq!(sdg.select_connected(filled, HEIGHT, UVec3::new(8, 0, 0), Connectivity::Faces, 1).unwrap_err(), GraphError::CellOutOfBounds(UVec3::new(8, 0, 0)));
+    // Right up against the far corner of the tallest tree there is
+    let everywhere = sdg.select_connected(EMPTY, 32, UVec3::MAX, Connectivity::Corners, 8).unwrap();
+    assert!(everywhere.cells.iter().all(|cell| cell.cmpge(UVec3::MAX - 1).all()) && everywhere.truncated);
+    assert_eq!(sdg.select_connected(filled, 33, UVec3::ZERO, Connectivity::Faces, 1).unwrap_err(), GraphError::PathTooDeep);
+  }
+}
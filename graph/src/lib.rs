@@ -1,7 +1,31 @@
 pub mod sdg;
 pub mod basic_node3d;
+pub mod export;
+pub mod raycast;
+pub mod flood;
+pub mod stats;
+pub mod orientation;
+pub mod brush;
+pub mod mesh;
+pub mod region;
+pub mod packed;
+pub mod bounds;
+pub mod batch;
+pub mod morton;
+#[cfg(test)]
+mod testing;
 
 pub mod prelude {
-  pub use super::sdg::{SparseDirectedGraph, Index, Path, Node};
+  pub use super::sdg::{SparseDirectedGraph, GraphError, Index, Path, Node, Childs, MAX_DEPTH};
   pub use super::basic_node3d::{BasicNode3d, Zorder3d};
+  pub use super::raycast::{Hit, EMPTY};
+  pub use super::flood::{Connectivity, Region};
+  pub use super::stats::GraphStats;
+  pub use super::orientation::Orientation;
+  pub use super::brush::{Brush, Blend, Axis};
+  pub use super::region::RegionIter;
+  pub use super::packed::{PackedNodes, PackedTree, PACKED_LEAF};
+  pub use super::bounds::{Bounds, BOUNDS_STEPS};
+  pub use super::batch::EditBatch;
+  pub use super::morton::MortonPath;
 }
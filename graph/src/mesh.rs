@@ -0,0 +1,224 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use glam::{IVec3, UVec3, Vec3};
+use serde_json::json;
+use crate::sdg::{SparseDirectedGraph, GraphNode, GraphError, Index};
+use crate::raycast::EMPTY;
+
+/// One merged rectangle of exposed faces, corners counterclockwise seen from the side normal points out of
+#[derive(Debug, Clone, Copy)]
+pub struct Quad {
+  pub corners: [Vec3; 4],
+  pub normal: IVec3,
+}
+
+/// The surface of a tree in its cell space, one cell to a unit. Every face between a solid cell and an empty one
+/// (or the edge of the grid) is covered, merged into as few quads as possible per leaf
+#[derive(Debug, Clone, Default)]
+pub struct Mesh {
+  /// Leaf -> its quads, in ascending order of leaf
+  pub groups: BTreeMap<Index, Vec<Quad>>,
+}
+impl Mesh {
+  pub fn quads(&self) -> usize { self.groups.values().map(Vec::len).sum() }
+
+  /// Wavefront OBJ, a group and material per leaf named leaf_<n>. The materials are in mtl_name, see to_mtl
+  pub fn to_obj(&self, mtl_name: &str) -> String {
+    let mut out = format!("mtllib {mtl_name}\n");
+    let normals = [IVec3::X, IVec3::NEG_X, IVec3::Y, IVec3::NEG_Y, IVec3::Z, IVec3::NEG_Z];
+    for normal in normals { writeln!(out, "vn {} {} {}", normal.x, normal.y, normal.z).unwrap() }
+    // OBJ counts from 1
+    let mut vertex = 1;
+    for (leaf, quads) in &self.groups {
+      writeln!(out, "g leaf_{leaf}\nusemtl leaf_{leaf}").unwrap();
+      for quad in quads {
+        for corner in quad.corners { writeln!(out, "v {} {} {}", corner.x, corner.y, corner.z).unwrap() }
+        let normal = normals.iter().position(|&normal| normal == quad.normal).unwrap() + 1;
+        writeln!(out, "f {}//{normal} {}//{normal} {}//{normal} {}//{normal}", vertex, vertex + 1, vertex + 2, vertex + 3).unwrap();
+        vertex += 4;
+      }
+    }
+    out
+  }
+
+  /// The materials to_obj refers to, leaf_color gives each leaf's linear rgba
+  pub fn to_mtl(&self, leaf_color: impl Fn(Index) -> [f32; 4]) -> String {
+    let mut out = String::new();
+    for &leaf in self.groups.keys() {
+      let [r, g, b, a] = leaf_color(leaf);
+      writeln!(out, "newmtl leaf_{leaf}\nKd {r} {g} {b}\nd {a}\n").unwrap();
+    }
+    out
+  }
+
+  /// glTF 2.0 as (the json, the binary buffer it expects to find at bin_uri). A primitive and material per leaf,
+  /// leaf_color gives each leaf's linear rgba
+  pub fn to_gltf(&self, bin_uri: &str, leaf_color: impl Fn(Index) -> [f32; 4]) -> (String, Vec<u8>) {
+    let mut bin = Vec::new();
+    let (mut views, mut accessors, mut primitives, mut materials) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    let mut push_view = |bin: &mut Vec<u8>, bytes: &[u8], target: u32| {
+      views.push(json!({ "buffer": 0, "byteOffset": bin.len(), "byteLength": bytes.len(), "target": target }));
+      bin.extend_from_slice(bytes);
+      views.len() - 1
+    };
+    for (&leaf, quads) in &self.groups {
+      let positions: Vec<Vec3> = quads.iter().flat_map(|quad| quad.corners).collect();
+      let normals: Vec<Vec3> = quads.iter().flat_map(|quad| [quad.normal.as_vec3(); 4]).collect();
+      let indices: Vec<u32> = (0 .. quads.len() as u32).flat_map(|quad| [0, 1, 2, 0, 2, 3].map(|corner| quad * 4 + corner)).collect();
+      let floats = |vecs: &[Vec3]| vecs.iter().flat_map(|vec| vec.to_array()).flat_map(f32::to_le_bytes).collect::<Vec<u8>>();
+      let (min, max) = positions.iter().fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), &pos| (min.min(pos), max.max(pos)));
+      // 34962 is ARRAY_BUFFER, 34963 ELEMENT_ARRAY_BUFFER, 5126 FLOAT and 5125 UNSIGNED_INT
+      let position_view = push_view(&mut bin, &floats(&positions), 34962);
+      let normal_view = push_view(&mut bin, &floats(&normals), 34962);
+      let index_view = push_view(&mut bin, &indices.iter().flat_map(|index| index.to_le_bytes()).collect::<Vec<u8>>(), 34963);
+      accessors.push(json!({ "bufferView": position_view, "componentType": 5126, "count": positions.len(), "type": "VEC3", "min": min.to_array(), "max": max.to_array() }));
+      accessors.push(json!({ "bufferView": normal_view, "componentType": 5126, "count": normals.len(), "type": "VEC3" }));
+      accessors.push(json!({ "bufferView": index_view, "componentType": 5125, "count": indices.len(), "type": "SCALAR" }));
+      let color = leaf_color(leaf);
+      materials.push(json!({
+        "name": format!("leaf_{leaf}"),
+        "pbrMetallicRoughness": { "baseColorFactor": color, "metallicFactor": 0.0 },
+        "alphaMode": if color[3] < 1.0 { "BLEND" } else { "OPAQUE" },
+      }));
+      let first = accessors.len() - 3;
+      primitives.push(json!({ "attributes": { "POSITION": first, "NORMAL": first + 1 }, "indices": first + 2, "material": materials.len() - 1 }));
+    }
+    let gltf = json!({
+      "asset": { "version": "2.0", "generator": "sdg" },
+      "scene": 0,
+      "scenes": [{ "nodes": [0] }],
+      "nodes": [{ "mesh": 0 }],
+      "meshes": [{ "primitives": primitives }],
+      "materials": materials,
+      "accessors": accessors,
+      "bufferViews": views,
+      "buffers": [{ "uri": bin_uri, "byteLength": bin.len() }],
+    });
+    (serde_json::to_string_pretty(&gltf).unwrap(), bin)
+  }
+}
+
+impl<T: GraphNode> SparseDirectedGraph<T> {
+  /// Every cell of the tree under head, x fastest then y then z. 8^height of them, so keep it to smallish trees.
+  /// Errors if head isn't a live node
+  pub fn dense(&self, head:Index, height:u32) -> Result<Vec<Index>, GraphError> {
+    let size = 1usize << height;
+    let mut cells = vec![EMPTY; size * size * size];
+    // Uniform regions are written in one go rather than split all the way down
+    for (corner, leaf, side) in self.iter_region(head, height, UVec3::ZERO, UVec3::splat(size as u32 - 1))? {
+      let (corner, side) = (corner.as_usizevec3(), side as usize);
+      for z in corner.z .. corner.z + side { for y in corner.y .. corner.y + side {
+        let row = (z * size + y) * size;
+        cells[row + corner.x .. row + corner.x + side].fill(leaf);
+      }}
+    }
+    Ok(cells)
+  }
+
+  /// The surface of the tree under head, see Mesh. Works from dense, so it's as costly as that
+  pub fn greedy_mesh(&self, head:Index, height:u32) -> Result<Mesh, GraphError> {
+    let size = 1usize << height;
+    let cells = self.dense(head, height)?;
+    let at = |cell: [usize; 3]| cells[(cell[2] * size + cell[1]) * size + cell[0]];
+    let mut mesh = Mesh::default();
+    for axis in 0 .. 3 {
+      let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+      // (leaf, whether the face looks down +axis) for every face on the plane between layers - 1 and layer
+      let mut mask: Vec<Option<(Index, bool)>> = vec![None; size * size];
+      for layer in 0 ..= size {
+        for j in 0 .. size { for i in 0 .. size {
+          let mut cell = [0; 3];
+          (cell[u], cell[v]) = (i, j);
+          let mut layer_at = |layer: usize| if layer < size { cell[axis] = layer; at(cell) } else { EMPTY };
+          // Past the edge of the grid (including below 0, which wraps) is empty
+          let (behind, front) = (layer_at(layer.wrapping_sub(1)), layer_at(layer));
+          mask[j * size + i] = match (behind != EMPTY, front != EMPTY) {
+            (true, false) => Some((behind, true)),
+            (false, true) => Some((front, false)),
+            _ => None,
+          };
+        }}
+
+        // Grow each face as wide as it'll go along u, then as tall along v as the whole width allows
+        for j in 0 .. size {
+          let mut i = 0;
+          while i < size {
+            let Some(face) = mask[j * size + i] else { i += 1; continue };
+            let width = (i .. size).take_while(|&x| mask[j * size + x] == Some(face)).count();
+            let rows = (j .. size).take_while(|&y| (i .. i + width).all(|x| mask[y * size + x] == Some(face))).count();
+            for y in j .. j + rows { mask[y * size + i .. y * size + i + width].fill(None) }
+            let corner = |du: usize, dv: usize| {
+              let mut pos = Vec3::ZERO;
+              (pos[axis], pos[u], pos[v]) = (layer as f32, (i + du) as f32, (j + dv) as f32);
+              pos
+            };
+            let (leaf, positive) = face;
+            // u then v turns counterclockwise around +axis
+            let mut corners = [corner(0, 0), corner(width, 0), corner(width, rows), corner(0, rows)];
+            if !positive { corners.reverse() }
+            let mut normal = IVec3::ZERO;
+            normal[axis] = if positive { 1 } else { -1 };
+            mesh.groups.entry(leaf).or_default().push(Quad { corners, normal });
+            i += width;
+          }
+        }
+      }
+    }
+    Ok(mesh)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::basic_node3d::BasicNode3d;
+
+  // A 2x1x1 bar of leaf along x, in a tree 4 cells across
+  fn bar() -> (Mesh, Index) {
+    let mut sdg = SparseDirectedGraph::<BasicNode3d>::new();
+    sdg.add_leaf();
+    let leaf = sdg.add_leaf();
+    let head = sdg.build(2, |cell| if cell.x >= 1 && cell.x <= 2 && cell.y == 1 && cell.z == 1 { leaf } else { EMPTY });
+    (sdg.greedy_mesh(head, 2).unwrap(), leaf)
+  }
+
+  #[test]
+  fn bars_mesh_to_a_box() {
+    let (mesh, leaf) = bar();
+    assert_eq!(mesh.groups.keys().copied().collect::<Vec<_>>(), [leaf]);
+    // The long sides each merge into one quad, and nothing's left of the faces the two cells share
+    assert_eq!(mesh.quads(), 6);
+    let quads = &mesh.groups[&leaf];
+    let x_faces: Vec<f32> = quads.iter().filter(|quad| quad.normal.x != 0).map(|quad| quad.corners[0].x).collect();
+    assert_eq!(x_faces, [1.0, 3.0]);
+    for quad in quads {
+      let [a, b, _, d] = quad.corners;
+      let (area, normal) = ((b - a).cross(d - a).length(), (b - a).cross(d - a).normalize());
+      assert_eq!(area, if quad.normal.x != 0 { 1.0 } else { 2.0 }, "{quad:?}");
+      // Counterclockwise seen from outside
+      assert_eq!(normal, quad.normal.as_vec3(), "{quad:?}");
+    }
+  }
+
+  #[test]
+  fn exports_hold_every_quad() {
+    let (mesh, leaf) = bar();
+    let obj = mesh.to_obj("bar.mtl");
+    assert!(obj.starts_with("mtllib bar.mtl\n"));
+    assert!(obj.contains(&format!("usemtl leaf_{leaf}\n")));
+    assert_eq!(obj.lines().filter(|line| line.starts_with("v ")).count(), 24);
+    assert_eq!(obj.lines().filter(|line| line.starts_with("f ")).count(), 6);
+    assert!(obj.contains("f 21//"));
+    assert_eq!(mesh.to_mtl(|_| [1.0, 0.5, 0.0, 1.0]), format!("newmtl leaf_{leaf}\nKd 1 0.5 0\nd 1\n\n"));
+
+    let (gltf, bin) = mesh.to_gltf("bar.bin", |_| [1.0, 0.5, 0.0, 1.0]);
+    let gltf: serde_json::Value = serde_json::from_str(&gltf).unwrap();
+    assert_eq!(gltf["buffers"][0]["byteLength"], bin.len());
+    // 24 positions and normals of 12 bytes, then 36 u32 indices
+    assert_eq!(bin.len(), 24 * 12 * 2 + 36 * 4);
+    let counts: Vec<_> = gltf["accessors"].as_array().unwrap().iter().map(|accessor| accessor["count"].as_u64().unwrap()).collect();
+    assert_eq!(counts, [24, 24, 36]);
+    assert_eq!(gltf["accessors"][0]["min"], serde_json::json!([1.0, 1.0, 1.0]));
+    assert_eq!(gltf["accessors"][0]["max"], serde_json::json!([3.0, 2.0, 2.0]));
+  }
+}
@@ -0,0 +1,145 @@
+use glam::UVec3;
+use crate::sdg::{Childs, GraphError, Path};
+
+/// A path packed into a u64 as the Morton code of the cell it ends at, three bits a level with the head's child
+/// in the highest ones. Copy and allocation free, so it's cheap to make per cell and easy to keep around,
+/// and sorting by it puts cells in z-order. Only works for children with 8 to a node, like Zorder3d
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct MortonPath {
+  code: u64,
+  depth: u32,
+}
+impl MortonPath {
+  /// As deep as a path fits in 64 bits
+  pub const MAX_DEPTH: u32 = 21;
+
+  /// The path to cell in a tree depth levels tall. Errors if depth is past MAX_DEPTH or cell is outside the tree
+  pub fn new(cell: UVec3, depth: u32) -> Result<Self, GraphError> {
+    if depth > Self::MAX_DEPTH { return Err(GraphError::PathTooDeep) }
+    if cell.max_element() >> depth != 0 { return Err(GraphError::CellOutOfBounds(cell)) }
+    Ok(Self { code: spread(cell.x) | spread(cell.y) << 1 | spread(cell.z) << 2, depth })
+  }
+
+  /// The cell the path ends at
+  pub fn cell(&self) -> UVec3 { UVec3::new(compact(self.code), compact(self.code >> 1), compact(self.code >> 2)) }
+
+  pub fn depth(&self) -> u32 { self.depth }
+
+  /// The x, y and z bits of each level interleaved, x lowest
+  pub fn code(&self) -> u64 { self.code }
+
+  /// The path one level up, to the node this one ends in
+  pub fn parent(&self) -> Option<Self> {
+    (self.depth > 0).then(|| Self { code: self.code >> 3, depth: self.depth - 1 })
+  }
+
+  /// The path one level further down, into child. Errors if that's past MAX_DEPTH
+  pub fn child(&self, child: impl Childs) -> Result<Self, GraphError> {
+    if self.depth >= Self::MAX_DEPTH { return Err(GraphError::PathTooDeep) }
+    let coord = child.to_coord();
+    Ok(Self { code: self.code << 3 | (coord.x | coord.y << 1 | coord.z << 2) as u64, depth: self.depth + 1 })
+  }
+}
+impl<T: Childs> Path<T> for MortonPath {
+  fn to_cell(&self) -> UVec3 { self.cell() }
+
+  fn path_from(cell:UVec3, depth:u32) -> Result<Self, GraphError> { Self::new(cell, depth) }
+
+  fn depth(&self) -> u32 { self.depth }
+
+  fn step(&self, level:u32) -> T {
+    let bits = (self.code >> (3 * (self.depth - 1 - level))) as u32;
+    T::new(UVec3::new(bits & 1, bits >> 1 & 1, bits >> 2 & 1))
+  }
+}
+
+// The low 21 bits of value moved 3 apart
+fn spread(value: u32) -> u64 {
+  let mut bits = value as u64 & 0x1f_ffff;
+  bits = (bits | bits << 32) & 0x1f_0000_0000_ffff;
+  bits = (bits | bits << 16) & 0x1f_0000_ff00_00ff;
+  bits = (bits | bits << 8) & 0x100f_00f0_0f00_f00f;
+  bits = (bits | bits << 4) & 0x10c3_0c30_c30c_30c3;
+  bits = (bits | bits << 2) & 0x1249_2492_4924_9249;
+  bits
+}
+
+// Undoes spread, gathering every third bit starting from the lowest
+fn compact(code: u64) -> u32 {
+  let mut bits = code & 0x1249_2492_4924_9249;
+  bits = (bits ^ bits >> 2) & 0x10c3_0c30_c30c_30c3;
+  bits = (bits ^ bits >> 4) & 0x100f_00f0_0f00_f00f;
+  bits = (bits ^ bits >> 8) & 0x1f_0000_ff00_00ff;
+  bits = (bits ^ bits >> 16) & 0x1f_0000_0000_ffff;
+  bits = (bits ^ bits >> 32) & 0x1f_ffff;
+  bits as u32
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::basic_node3d::Zorder3d;
+
+  // Cells spread over every bit a path of each depth can reach, xorshift so runs are repeatable
+  fn cells(depth: u32) -> impl Iterator<Item = UVec3> {
+    let mut state = 0x9e37_79b9_7f4a_7c15_u64 ^ depth as u64;
+    let mask = ((1_u64 << depth) - 1) as u32;
+    let corners = [UVec3::ZERO, UVec3::splat(mask), UVec3::new(mask, 0, mask)];
+    corners.into_iter().chain(std::iter::repeat_with(move || {
+      state ^= state << 13;
+      state ^= state >> 7;
+      state ^= state << 17;
+      UVec3::new(state as u32, (state >> 21) as u32, (state >> 42) as u32) & mask
+    }).take(200))
+  }
+
+  #[test]
+  fn cells_round_trip() {
+    for depth in 0 ..= MortonPath::MAX_DEPTH {
+      for cell in cells(depth) {
+        let path = MortonPath::new(cell, depth).unwrap();
+        assert_eq!(path.cell(), cell, "depth {depth}");
+        assert_eq!(<MortonPath as Path<Zorder3d>>::to_cell(&path), cell);
+      }
+    }
+  }
+
+  #[test]
+  fn steps_match_vec_paths() {
+    for depth in 0 ..= MortonPath::MAX_DEPTH {
+      for cell in cells(depth) {
+        let morton = MortonPath::new(cell, depth).unwrap();
+        let vec: Vec<Zorder3d> = Path::path_from(cell, depth).unwrap();
+        for level in 0 .. depth {
+          let step: Zorder3d = morton.step(level);
+          assert_eq!(step as u8, vec[level as usize] as u8, "cell {cell} depth {depth} level {level}");
+        }
+        // Walking down one child at a time builds the same code
+        let walked = vec.iter().try_fold(MortonPath::default(), |path, &child| path.child(child)).unwrap();
+        assert_eq!(walked, morton);
+        assert_eq!(morton.parent().map(|parent| parent.cell()), (depth > 0).then(|| cell >> 1));
+      }
+    }
+  }
+
+  #[test]
+  fn children_are_unit_offsets() {
+    // x is the low bit of a child's number, then y, then z
+    let coords = [(0, 0, 0), (1, 0, 0), (0, 1, 0), (1, 1, 0), (0, 0, 1), (1, 0, 1), (0, 1, 1), (1, 1, 1)];
+    for (child, (x, y, z)) in Zorder3d::all().zip(coords) {
+      assert_eq!(child.to_coord(), UVec3::new(x, y, z), "{child:?}");
+      assert_eq!(Zorder3d::new(child.to_coord()) as u8, child as u8);
+    }
+    // The first step is the top bit of the cell
+    let path = vec![Zorder3d::BackBottomRight, Zorder3d::FrontBottomLeft, Zorder3d::BackTopRight];
+    assert_eq!(path.to_cell(), UVec3::new(0b101, 0b110, 0b101));
+  }
+
+  #[test]
+  fn bad_paths_are_errors() {
+    assert_eq!(MortonPath::new(UVec3::ZERO, MortonPath::MAX_DEPTH + 1), Err(GraphError::PathTooDeep));
+    assert_eq!(MortonPath::new(UVec3::new(0, 4, 0), 2), Err(GraphError::CellOutOfBounds(UVec3::new(0, 4, 0))));
+    let deepest = MortonPath::new(UVec3::ZERO, MortonPath::MAX_DEPTH).unwrap();
+    assert_eq!(deepest.child(Zorder3d::FrontTopLeft), Err(GraphError::PathTooDeep));
+  }
+}
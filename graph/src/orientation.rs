@@ -0,0 +1,164 @@
+use ahash::AHashMap;
+use glam::{BVec3, UVec3};
+use crate::sdg::{SparseDirectedGraph, GraphNode, GraphError, Childs, Index};
+
+/// One of the 48 ways to lay a cube back onto itself by swapping and flipping its axes.
+/// 24 of them are rotations, the rest mirror it as well
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Orientation {
+  // Axis i of the result is axis axes[i] of the original,
+  axes: [usize; 3],
+  // then flipped if flip[i] is set
+  flip: BVec3,
+}
+impl Orientation {
+  pub const IDENTITY: Self = Self { axes: [0, 1, 2], flip: BVec3::FALSE };
+
+  /// A quarter turn around axis (0 is x) for each of turns, counterclockwise looking down the axis towards the origin
+  pub fn rotation(axis: usize, turns: u32) -> Self {
+    // The other two axes in the order the turn carries one onto the other
+    let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+    let mut quarter = Self::IDENTITY;
+    quarter.axes[u] = v;
+    quarter.axes[v] = u;
+    quarter.flip.set(u, true);
+    (0 .. turns % 4).fold(Self::IDENTITY, |orientation, _| orientation.then(quarter))
+  }
+
+  /// Flipped across the plane axis is normal to
+  pub fn mirror(axis: usize) -> Self {
+    let mut mirror = Self::IDENTITY;
+    mirror.flip.set(axis, true);
+    mirror
+  }
+
+  /// self followed by next
+  pub fn then(self, next: Self) -> Self {
+    Self {
+      axes: next.axes.map(|axis| self.axes[axis]),
+      flip: BVec3::from(std::array::from_fn(|i| next.flip.test(i) != self.flip.test(next.axes[i]))),
+    }
+  }
+
+  /// What undoes this, self.then(self.inverse()) is the identity
+  pub fn inverse(self) -> Self {
+    let mut inverse = Self::IDENTITY;
+    for i in 0 .. 3 {
+      inverse.axes[self.axes[i]] = i;
+      inverse.flip.set(self.axes[i], self.flip.test(i));
+    }
+    inverse
+  }
+
+  /// Whether this turns without mirroring
+  pub fn is_rotation(self) -> bool {
+    // Every swap of two axes mirrors, so does every flip
+    let swaps = (0 .. 3).filter(|&i| self.axes[i] != i).count().saturating_sub(1);
+    (swaps + self.flip.bitmask().count_ones() as usize).is_multiple_of(2)
+  }
+
+  /// All 24 rotations, the identity first
+  pub fn rotations() -> Vec<Self> {
+    let mut all = vec![Self::IDENTITY];
+    // Keep turning everything found so far until nothing new turns up
+    let mut idx = 0;
+    while idx < all.len() {
+      for axis in 0 .. 3 {
+        let turned = all[idx].then(Self::rotation(axis, 1));
+        if !all.contains(&turned) { all.push(turned) }
+      }
+      idx += 1;
+    }
+    all
+  }
+
+  /// Where cell lands in a grid size cells across
+  pub fn apply(self, cell: UVec3, size: u32) -> UVec3 {
+    UVec3::from_array(std::array::from_fn(|i| {
+      let coord = cell[self.axes[i]];
+      if self.flip.test(i) { size - 1 - coord } else { coord }
+    }))
+  }
+}
+
+impl<T: GraphNode> SparseDirectedGraph<T> {
+  /// The tree under head laid out in orientation within its grid. Each distinct node is only turned once,
+  /// so this costs about as much as the tree has distinct nodes. The returned head holds a ref.
+  /// Errors if head isn't a live node
+  pub fn transformed(&mut self, head:Index, orientation:Orientation) -> Result<Index, GraphError> {
+    self.node(head)?;
+    let mut turned = AHashMap::new();
+    let new_head = self.transform_node(head, orientation, &mut turned);
+    self.get_root(new_head)
+  }
+
+  fn transform_node(&mut self, idx:Index, orientation:Orientation, turned:&mut AHashMap<Index, Index>) -> Index {
+    // Leaves look the same any way round
+    if self.is_leaf(idx) { return idx }
+    if let Some(&done) = turned.get(&idx) { return done }
+    let node = *self.nodes.get(idx as usize).unwrap();
+    let mut new_node = node;
+    for child in T::Children::all() {
+      let moved = T::Children::new(orientation.apply(child.to_coord(), 2));
+      new_node.set(moved, self.transform_node(node.get(child), orientation, turned));
+    }
+    let new_idx = self.insert_node(new_node);
+    turned.insert(idx, new_idx);
+    new_idx
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::basic_node3d::BasicNode3d;
+  use crate::testing::{check_refs, dense, Rng};
+
+  // The rotations and their mirror images
+  fn all() -> Vec<Orientation> {
+    let rotations = Orientation::rotations();
+    rotations.iter().copied().chain(rotations.iter().map(|rotation| rotation.then(Orientation::mirror(0)))).collect()
+  }
+
+  #[test]
+  fn orientations_undo() {
+    let rotations = Orientation::rotations();
+    assert_eq!(rotations.len(), 24);
+    assert!(rotations.iter().all(|rotation| rotation.is_rotation()));
+    let all = all();
+    assert!(all[24 ..].iter().all(|mirrored| !mirrored.is_rotation() && !rotations.contains(mirrored)));
+    for orientation in all {
+      assert_eq!(orientation.then(orientation.inverse()), Orientation::IDENTITY, "{orientation:?}");
+      assert_eq!(orientation.inverse().then(orientation), Orientation::IDENTITY, "{orientation:?}");
+      for idx in 0 .. 64 {
+        let cell = UVec3::new(idx % 4, idx / 4 % 4, idx / 16);
+        assert_eq!(orientation.inverse().apply(orientation.apply(cell, 4), 4), cell, "{orientation:?}");
+      }
+    }
+  }
+
+  #[test]
+  fn transformed_trees_match_moving_each_cell() {
+    const HEIGHT: u32 = 3;
+    let size = 1 << HEIGHT;
+    let mut sdg = SparseDirectedGraph::<BasicNode3d>::new();
+    let leaves: Vec<Index> = (0 .. 4).map(|_| sdg.add_leaf()).collect();
+    let mut rng = Rng(0x0123_4567_89ab_cdef);
+    // Mostly noise, with a couple of uniform stretches for whole nodes to move
+    let head = sdg.build(HEIGHT, |cell| if cell.x < 4 && cell.z >= 4 { leaves[1] } else if cell.y == 0 { leaves[2] } else { leaves[rng.next(4) as usize] });
+    let before = dense(&sdg, head, HEIGHT);
+    for orientation in all() {
+      let turned = sdg.transformed(head, orientation).unwrap();
+      for (idx, &leaf) in before.iter().enumerate() {
+        let cell = UVec3::new(idx as u32 % size, idx as u32 / size % size, idx as u32 / size / size);
+        assert_eq!(sdg.sample(turned, HEIGHT, orientation.apply(cell, size)).unwrap().0, leaf, "{orientation:?} at {cell}");
+      }
+      // Turning back lands on the very same nodes
+      let back = sdg.transformed(turned, orientation.inverse()).unwrap();
+      assert_eq!(back, head, "{orientation:?}");
+      check_refs(&sdg, &[head, turned, back]);
+      sdg.drop_root(back).unwrap();
+      sdg.drop_root(turned).unwrap();
+    }
+  }
+}
@@ -0,0 +1,156 @@
+use crate::sdg::{SparseDirectedGraph, GraphNode, GraphError, Childs, Index};
+use crate::raycast::EMPTY;
+use ahash::AHashMap;
+
+/// Set on packed entries which are a leaf rather than the offset of a packed node
+pub const PACKED_LEAF: u32 = 1 << 31;
+const UNPACKED: u32 = u32::MAX;
+
+/// The graph laid out for the GPU with empty children left out, see SparseDirectedGraph::pack.
+/// Each node is a header word with a bit set for every child that isn't EMPTY, in child order, and the node's
+/// Bounds (as bits) above those, followed by an entry for each of those children: a leaf flagged with PACKED_LEAF
+/// or the word offset of a node. Finding a child is a popcount of the mask bits below it,
+/// and a node with one solid child takes 2 words instead of 8
+pub struct PackedNodes {
+  pub words: Vec<u32>,
+  // Graph index -> word offset of the node, UNPACKED for leaves and free slots
+  offsets: Vec<u32>,
+}
+impl PackedNodes {
+  /// What a live index (a head, say) becomes in the packed layout
+  pub fn entry(&self, idx: Index) -> u32 {
+    match self.offsets.get(idx as usize) {
+      Some(&offset) if offset != UNPACKED => offset,
+      _ => PACKED_LEAF | idx,
+    }
+  }
+
+  /// (graph index, word offset) of every node packed
+  pub fn nodes(&self) -> impl Iterator<Item = (Index, u32)> + '_ {
+    self.offsets.iter().enumerate().filter(|&(_, &offset)| offset != UNPACKED).map(|(idx, &offset)| (idx as Index, offset))
+  }
+}
+
+/// A single tree packed on its own, see SparseDirectedGraph::pack_tree
+pub struct PackedTree {
+  /// What the head becomes, 0 (its node comes first) unless it's a leaf, which packs to nothing
+  pub entry: u32,
+  pub words: Vec<u32>,
+  /// (graph index, word offset) of every node in the tree, parents before their children
+  pub nodes: Vec<(Index, u32)>,
+}
+
+impl<T: GraphNode> SparseDirectedGraph<T> {
+  /// Packs every node in the graph, in slot order so nodes near each other in the graph stay near each other
+  pub fn pack(&self) -> PackedNodes {
+    // Every node's offset first, so children can be written as they're reached whichever slot they're in
+    let mut offsets = vec![UNPACKED; self.nodes.len()];
+    let mut len = 0;
+    for (idx, offset) in offsets.iter_mut().enumerate() {
+      if self.nodes.get(idx).is_none() || self.is_leaf(idx as Index) { continue }
+      *offset = len;
+      len += self.packed_len(idx as Index);
+    }
+    let mut words = Vec::with_capacity(len as usize);
+    let entry = |child: Index| match offsets[child as usize] { UNPACKED => PACKED_LEAF | child, offset => offset };
+    for (idx, &offset) in offsets.iter().enumerate() {
+      if offset != UNPACKED { self.pack_node(idx as Index, &mut words, entry) }
+    }
+    PackedNodes { words, offsets }
+  }
+
+  /// Packs just the tree under head, on its own: entries are offsets from the start of the returned words,
+  /// so they can go anywhere in a buffer as long as they're read from there. Errors if head isn't a live node
+  pub fn pack_tree(&self, head: Index) -> Result<PackedTree, GraphError> {
+    self.node(head)?;
+    if self.is_leaf(head) { return Ok(PackedTree { entry: PACKED_LEAF | head, words: Vec::new(), nodes: Vec::new() }) }
+    // Parents before their children, which puts head first
+    let mut order = self.tree_nodes(head);
+    order.reverse();
+    let mut offsets = AHashMap::with_capacity(order.len());
+    let mut nodes = Vec::with_capacity(order.len());
+    let mut len = 0;
+    for &idx in &order {
+      offsets.insert(idx, len);
+      nodes.push((idx, len));
+      len += self.packed_len(idx);
+    }
+    let mut words = Vec::with_capacity(len as usize);
+    for &idx in &order {
+      self.pack_node(idx, &mut words, |child| offsets.get(&child).copied().unwrap_or(PACKED_LEAF | child));
+    }
+    Ok(PackedTree { entry: 0, words, nodes })
+  }
+
+  // Words the node at idx takes up packed
+  fn packed_len(&self, idx: Index) -> u32 {
+    let node = self.nodes.get(idx as usize).unwrap();
+    1 + T::Children::all().filter(|&child| node.get(child) != EMPTY).count() as u32
+  }
+
+  // Appends the node at idx packed, with entry giving what each of its children becomes
+  fn pack_node(&self, idx: Index, words: &mut Vec<u32>, entry: impl Fn(Index) -> u32) {
+    let node = self.nodes.get(idx as usize).unwrap();
+    let header = words.len();
+    // Every node besides EMPTY has some, and EMPTY is a leaf
+    words.push(self.bounds(idx).unwrap().to_bits() << T::Children::COUNT);
+    for (bit, child) in T::Children::all().enumerate() {
+      let child = node.get(child);
+      if child == EMPTY { continue }
+      words[header] |= 1 << bit;
+      words.push(entry(child));
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use glam::UVec3;
+  use crate::basic_node3d::BasicNode3d;
+  use crate::testing::{dense, Rng};
+
+  const HEIGHT: u32 = 5;
+
+  // The shader's packed_read on the CPU, down to single cells. Cells outside a node's bounds are checked to be EMPTY
+  // rather than skipped, so a wrong bound shows up as a wrong leaf
+  fn decode(words: &[u32], entry: u32, cell: UVec3) -> Index {
+    let (mut entry, mut height) = (entry, HEIGHT);
+    while entry & PACKED_LEAF == 0 {
+      let header = words[entry as usize];
+      let bounds = header >> 8;
+      let (low, high) = (UVec3::new(bounds, bounds >> 4, bounds >> 8) & 15, UVec3::new(bounds >> 12, bounds >> 16, bounds >> 20) & 15);
+      let step: UVec3 = (cell & UVec3::splat((1 << height) - 1)) << 4u32 >> height;
+      if step.cmplt(low).any() || step.cmpgt(high).any() { return EMPTY }
+      height -= 1;
+      let child = cell >> height & 1;
+      let bit = 1 << (child.z << 2 | child.y << 1 | child.x);
+      if header & bit == 0 { return EMPTY }
+      entry = words[(entry + 1 + (header & (bit - 1)).count_ones()) as usize];
+    }
+    entry & !PACKED_LEAF
+  }
+
+  #[test]
+  fn packed_words_decode_to_the_same_cells() {
+    let mut sdg = SparseDirectedGraph::<BasicNode3d>::new();
+    let leaves: Vec<Index> = (0 .. 3).map(|_| sdg.add_leaf()).collect();
+    let mut rng = Rng(0x0f1e_2d3c_4b5a_6978);
+    let size = 1 << HEIGHT;
+    // A lump off in one corner, so bounds cut nodes down, and a mostly empty scatter
+    let lump = sdg.build(HEIGHT, |cell| if cell.cmplt(UVec3::new(11, 7, 20)).all() && cell.x > 2 { leaves[1 + rng.next(2) as usize] } else { EMPTY });
+    let scatter = sdg.build(HEIGHT, |_| if rng.next(6) == 0 { leaves[1 + rng.next(2) as usize] } else { EMPTY });
+    let solid = sdg.build(HEIGHT, |_| leaves[2]);
+
+    let packed = sdg.pack();
+    for head in [lump, scatter, solid] {
+      let cells = dense(&sdg, head, HEIGHT);
+      let tree = sdg.pack_tree(head).unwrap();
+      for (idx, &leaf) in cells.iter().enumerate() {
+        let cell = UVec3::new(idx as u32 % size, idx as u32 / size % size, idx as u32 / size / size);
+        assert_eq!(decode(&packed.words, packed.entry(head), cell), leaf, "pack, head {head} at {cell}");
+        assert_eq!(decode(&tree.words, tree.entry, cell), leaf, "pack_tree, head {head} at {cell}");
+      }
+    }
+  }
+}
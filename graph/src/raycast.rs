@@ -0,0 +1,208 @@
+use glam::{IVec3, UVec3, Vec2, Vec3};
+use crate::sdg::{SparseDirectedGraph, GraphNode, GraphError, Childs, Index};
+
+/// The leaf every tree reserves for empty space, rays pass straight through it
+pub const EMPTY: Index = 0;
+
+/// Where a ray first met a non-empty leaf, in the tree's cell space
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit {
+  pub cell: UVec3,
+  /// Points out of the face that was hit, zero if the ray started inside a solid cell
+  pub normal: IVec3,
+  /// Measured in units of dir
+  pub t: f32,
+  pub leaf: Index,
+  /// Exact point the ray entered the cell, in cell space
+  pub pos: Vec3,
+  /// Where on the face the ray struck, [0, 1] across the two axes the face spans
+  pub uv: Vec2,
+}
+impl Hit {
+  /// x faces span (z, y), z faces span (x, y), y faces and hits from inside span (x, z). Matches face_uv in dda.wgsl
+  pub fn face_uv(offset: Vec3, normal: IVec3) -> Vec2 {
+    let uv = if normal.x != 0 { Vec2::new(offset.z, offset.y) }
+      else if normal.z != 0 { Vec2::new(offset.x, offset.y) }
+      else { Vec2::new(offset.x, offset.z) };
+    uv.clamp(Vec2::ZERO, Vec2::ONE)
+  }
+}
+
+impl<T: GraphNode> SparseDirectedGraph<T> {
+  /// Returns the leaf at cell and the height of the uniform node containing it, mirroring vox_read in dda.wgsl.
+  /// Errors if head isn't a live node
+  pub fn sample(&self, head: Index, height: u32, cell: UVec3) -> Result<(Index, u32), GraphError> {
+    self.node(head)?;
+    Ok(self.sample_live(head, height, cell))
+  }
+
+  // sample for heads already checked, everything under a live node is live
+  pub(crate) fn sample_live(&self, head: Index, height: u32, cell: UVec3) -> (Index, u32) {
+    let mut idx = head;
+    for height in (0 .. height).rev() {
+      let next = self.nodes.get(idx as usize).unwrap().get(T::Children::new(cell >> height & 1));
+      if next == idx { return (idx, height + 1) }
+      idx = next;
+    }
+    (idx, 0)
+  }
+
+  /// Marches a ray through the tree under head, skipping uniform nodes the same way dda.wgsl does.
+  /// origin and dir are in cell space, the tree covers [0, 2^height) on every axis.
+  /// Cells are u32s, so trees taller than 32 levels are never hit. Errors if head isn't a live node
+  pub fn raycast(&self, head: Index, height: u32, origin: Vec3, dir: Vec3, max_t: f32) -> Result<Option<Hit>, GraphError> {
+    if height > 32 { return self.node(head).map(|_| None) }
+    self.raycast_within(head, height, origin, dir, max_t, UVec3::ZERO, UVec3::splat(((1u64 << height) - 1) as u32))
+  }
+
+  /// raycast, only considering the cells between min and max (inclusive)
+  #[allow(clippy::too_many_arguments)]
+  pub fn raycast_within(&self, head: Index, height: u32, origin: Vec3, dir: Vec3, max_t: f32, min: UVec3, max: UVec3) -> Result<Option<Hit>, GraphError> {
+    self.node(head)?;
+    Ok(self.march(head, height, origin, dir, max_t, min, max))
+  }
+
+  // raycast_within once head is known to be live
+  #[allow(clippy::too_many_arguments)]
+  fn march(&self, head: Index, height: u32, origin: Vec3, dir: Vec3, max_t: f32, min: UVec3, max: UVec3) -> Option<Hit> {
+    let inv_dir = 1.0 / dir;
+    let (low, high) = (min.as_vec3(), max.as_vec3() + 1.0);
+
+    // Clip the ray to the bounds
+    let t1 = (low - origin) * inv_dir;
+    let t2 = (high - origin) * inv_dir;
+    let t_entry = t1.min(t2).max_element();
+    let t_exit = t1.max(t2).min_element().min(max_t);
+    if t_exit < t_entry.max(0.0) { return None }
+    let mut t = t_entry.max(0.0);
+    let mut normal = if t_entry > 0.0 {
+      IVec3::from(t1.min(t2).cmpeq(Vec3::splat(t_entry))) * -dir.signum().as_ivec3()
+    } else { IVec3::ZERO };
+
+    // Nudge forward so we start in the cell we're entering. After that cells are stepped through by whole nodes,
+    // far enough out a nudge is lost to rounding and the ray would never leave the cell it's in
+    let mut cell = (origin + dir * t + dir.signum() * 1e-4).floor().clamp(low, high - 1.0).as_uvec3().clamp(min, max);
+    loop {
+      let (leaf, node_height) = self.sample_live(head, height, cell);
+      if leaf != EMPTY {
+        let pos = origin + dir * t;
+        let uv = Hit::face_uv(pos - cell.as_vec3(), normal);
+        return Some(Hit { cell, normal, t, leaf, pos, uv })
+      }
+      // A uniform root 32 levels tall is a node 2^32 across, past what a u32 shift reaches
+      let mask = UVec3::splat(u32::MAX.checked_shl(node_height).unwrap_or(0));
+      let (node_min, node_max) = (cell & mask, cell | !mask);
+      let next_wall = Vec3::select(dir.cmplt(Vec3::ZERO), node_min.as_vec3(), node_max.as_vec3() + 1.0);
+      let t_wall = (next_wall - origin) * inv_dir;
+      let axis = (0 .. 3).find(|&axis| t_wall[axis] == t_wall.min_element())?;
+      // Rounding can put the wall just behind where the ray's got to, it never goes back
+      t = t_wall[axis].max(t);
+      if t >= t_exit { return None }
+
+      // Out of the node through the wall, and along the wall as far as the ray's got on the other axes.
+      // Every axis only moves the way the ray's going, so the march always ends
+      let along = (origin + dir * t).floor().clamp(node_min.max(min).as_vec3(), node_max.min(max).as_vec3()).as_uvec3();
+      for other in (0 .. 3).filter(|&other| other != axis) {
+        cell[other] = if dir[other] < 0.0 { cell[other].min(along[other]) } else { cell[other].max(along[other]) };
+      }
+      if dir[axis] < 0.0 {
+        if node_min[axis] <= min[axis] { return None }
+        cell[axis] = node_min[axis] - 1;
+      } else {
+        if node_max[axis] >= max[axis] { return None }
+        cell[axis] = node_max[axis] + 1;
+      }
+      normal = IVec3::ZERO;
+      normal[axis] = -dir[axis].signum() as i32;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::basic_node3d::BasicNode3d;
+
+  // 8 cells across: one cell of a at (5, 2, 3) and a uniform node of b filling the 4 cells across from (4, 4, 4)
+  fn scene() -> (SparseDirectedGraph<BasicNode3d>, Index, Index, Index) {
+    let mut sdg = SparseDirectedGraph::new();
+    let _empty = sdg.add_leaf();
+    let (a, b) = (sdg.add_leaf(), sdg.add_leaf());
+    let head = sdg.build(3, |cell| {
+      if cell == UVec3::new(5, 2, 3) { a } else if cell.cmpge(UVec3::splat(4)).all() { b } else { EMPTY }
+    });
+    (sdg, head, a, b)
+  }
+
+  #[test]
+  fn samples_find_leaves_and_uniform_nodes() {
+    let (sdg, head, a, b) = scene();
+    assert_eq!(sdg.sample(head, 3, UVec3::new(5, 2, 3)), Ok((a, 0)));
+    assert_eq!(sdg.sample(head, 3, UVec3::new(4, 2, 3)), Ok((EMPTY, 0)));
+    assert_eq!(sdg.sample(head, 3, UVec3::new(6, 7, 5)), Ok((b, 2)));
+    assert_eq!(sdg.sample(head, 3, UVec3::new(1, 0, 2)), Ok((EMPTY, 2)));
+  }
+
+  #[test]
+  fn axis_aligned_rays_hit_the_near_face() {
+    let (sdg, head, a, b) = scene();
+    let hit = sdg.raycast(head, 3, Vec3::new(-1.0, 2.5, 3.25), Vec3::X, 100.0).unwrap().unwrap();
+    assert_eq!(hit, Hit { cell: UVec3::new(5, 2, 3), normal: IVec3::NEG_X, t: 6.0, leaf: a, pos: Vec3::new(5.0, 2.5, 3.25), uv: Vec2::new(0.25, 0.5) });
+    // t is in units of dir
+    let hit = sdg.raycast(head, 3, Vec3::new(5.5, 20.0, 3.5), Vec3::new(0.0, -2.0, 0.0), 100.0).unwrap().unwrap();
+    assert_eq!((hit.cell, hit.normal, hit.t, hit.leaf), (UVec3::new(5, 2, 3), IVec3::Y, 8.5, a));
+    // Straight through the empty half into the uniform node
+    let hit = sdg.raycast(head, 3, Vec3::new(6.5, 6.5, -3.0), Vec3::Z, 100.0).unwrap().unwrap();
+    assert_eq!((hit.cell, hit.normal, hit.t, hit.leaf), (UVec3::new(6, 6, 4), IVec3::NEG_Z, 7.0, b));
+    let hit = sdg.raycast(head, 3, Vec3::new(9.0, 5.5, 7.5), Vec3::NEG_X, 100.0).unwrap().unwrap();
+    assert_eq!((hit.cell, hit.normal, hit.t, hit.leaf), (UVec3::new(7, 5, 7), IVec3::X, 1.0, b));
+  }
+
+  #[test]
+  fn rays_that_miss() {
+    let (sdg, head, ..) = scene();
+    // Along an empty row
+    assert_eq!(sdg.raycast(head, 3, Vec3::new(-1.0, 0.5, 0.5), Vec3::X, 100.0), Ok(None));
+    // Pointing away from the tree
+    assert_eq!(sdg.raycast(head, 3, Vec3::new(-1.0, 2.5, 3.5), Vec3::NEG_X, 100.0), Ok(None));
+    // Stopping short of the cell
+    assert_eq!(sdg.raycast(head, 3, Vec3::new(-1.0, 2.5, 3.5), Vec3::X, 5.5), Ok(None));
+    // Past it on a diagonal
+    assert_eq!(sdg.raycast(head, 3, Vec3::new(0.5, 0.5, 0.5), Vec3::new(1.0, 0.3, 0.0), 100.0), Ok(None));
+  }
+
+  #[test]
+  fn rays_starting_inside_solid_cells() {
+    let (sdg, head, a, b) = scene();
+    let hit = sdg.raycast(head, 3, Vec3::new(5.5, 2.5, 3.5), Vec3::X, 100.0).unwrap().unwrap();
+    assert_eq!((hit.cell, hit.normal, hit.t, hit.leaf), (UVec3::new(5, 2, 3), IVec3::ZERO, 0.0, a));
+    let hit = sdg.raycast(head, 3, Vec3::new(6.2, 4.7, 5.1), Vec3::new(-1.0, 1.0, 0.5), 100.0).unwrap().unwrap();
+    assert_eq!((hit.cell, hit.normal, hit.t, hit.leaf), (UVec3::new(6, 4, 5), IVec3::ZERO, 0.0, b));
+  }
+
+  #[test]
+  fn trees_32_levels_tall() {
+    let (mut sdg, _, a, _) = scene();
+    let head = sdg.get_root(a).unwrap();
+    let hit = sdg.raycast(head, 32, Vec3::new(-1.0, 0.5, 0.5), Vec3::X, 100.0).unwrap().unwrap();
+    assert_eq!((hit.cell, hit.normal, hit.t), (UVec3::ZERO, IVec3::NEG_X, 1.0));
+    assert_eq!(sdg.raycast(EMPTY, 32, Vec3::new(-1.0, 0.5, 0.5), Vec3::X, 100.0), Ok(None));
+    assert_eq!(sdg.raycast(head, 33, Vec3::new(-1.0, 0.5, 0.5), Vec3::X, 100.0), Ok(None));
+  }
+
+  #[test]
+  fn rays_far_from_the_origin() {
+    // Around 2^20 a cell is only 8 f32 steps across, too coarse for any nudge to carry the ray over a wall
+    let (mut sdg, _, a, _) = scene();
+    let far = 1 << 20;
+    let empty = sdg.get_root(EMPTY).unwrap();
+    let mut batch = sdg.edit(empty, 21);
+    batch.set_cell(UVec3::new(far - 7, far, far), a);
+    let head = batch.commit().unwrap();
+    let origin = Vec3::new(far as f32 + 0.5, far as f32 + 0.5, far as f32 + 0.5);
+    let hit = sdg.raycast(head, 21, origin, Vec3::NEG_X, 100.0).unwrap().unwrap();
+    assert_eq!((hit.cell, hit.normal, hit.t, hit.leaf), (UVec3::new(far - 7, far, far), IVec3::X, 6.5, a));
+    // Skimming past it the whole way out of the tree
+    assert_eq!(sdg.raycast(head, 21, origin + Vec3::Y, Vec3::new(-1.0, 0.0, -0.25), 1e7), Ok(None));
+  }
+}
@@ -0,0 +1,90 @@
+use ahash::AHashSet;
+use glam::UVec3;
+use crate::sdg::{SparseDirectedGraph, GraphNode, GraphError, Childs, Index};
+
+/// Walks the uniform nodes of a tree which overlap a box, see SparseDirectedGraph::iter_region
+pub struct RegionIter<'a, T: GraphNode> {
+  sdg: &'a SparseDirectedGraph<T>,
+  min: UVec3,
+  max: UVec3,
+  // (node, min corner, height) still to be visited, the next one on top
+  stack: Vec<(Index, UVec3, u32)>,
+  // If set, nodes outside it have nothing worth finding under them
+  holding: Option<AHashSet<Index>>,
+}
+impl<T: GraphNode> RegionIter<'_, T> {
+  fn overlaps(&self, corner: UVec3, height: u32) -> bool {
+    corner.cmple(self.max).all() && (corner + ((1 << height) - 1)).cmpge(self.min).all()
+  }
+
+  fn visits(&self, idx: Index, corner: UVec3, height: u32) -> bool {
+    self.overlaps(corner, height) && self.holding.as_ref().is_none_or(|holding| holding.contains(&idx))
+  }
+}
+impl<T: GraphNode> Iterator for RegionIter<'_, T> {
+  type Item = (UVec3, Index, u32);
+
+  fn next(&mut self) -> Option<Self::Item> {
+    while let Some((idx, corner, height)) = self.stack.pop() {
+      if self.sdg.is_leaf(idx) { return Some((corner, idx, 1 << height)) }
+      let node = self.sdg.nodes.get(idx as usize).unwrap();
+      let start = self.stack.len();
+      for child in T::Children::all() {
+        let child_corner = corner + child.to_coord() * (1 << height >> 1);
+        if self.visits(node.get(child), child_corner, height - 1) { self.stack.push((node.get(child), child_corner, height - 1)) }
+      }
+      // So they come back off in z-order
+      self.stack[start ..].reverse();
+    }
+    None
+  }
+}
+
+impl<T: GraphNode> SparseDirectedGraph<T> {
+  /// Every uniform node of the tree under head overlapping the cells between min and max (inclusive),
+  /// as (min corner, leaf, cells across) in z-order. Whole nodes come back without being split up,
+  /// so they can reach past the box, and nodes lying wholly outside it are never visited. Errors if head isn't a live node
+  pub fn iter_region(&self, head:Index, height:u32, min:UVec3, max:UVec3) -> Result<RegionIter<'_, T>, GraphError> {
+    self.node(head)?;
+    let mut iter = RegionIter { sdg: self, min, max, stack: Vec::new(), holding: None };
+    if iter.overlaps(UVec3::ZERO, height) { iter.stack.push((head, UVec3::ZERO, height)) }
+    Ok(iter)
+  }
+
+  /// Like iter_region, but only the uniform nodes whose leaf is wanted. Parts of the tree without any are skipped
+  /// whole, so finding a few cells costs about as much as the tree has distinct nodes rather than uniform regions
+  pub fn iter_region_holding(&self, head:Index, height:u32, min:UVec3, max:UVec3, wanted: impl Fn(Index) -> bool) -> Result<RegionIter<'_, T>, GraphError> {
+    self.node(head)?;
+    let mut holding: AHashSet<Index> = self.leaves().iter().copied().filter(|&leaf| wanted(leaf)).collect();
+    // Children come first, so whether they hold one is known by the time their parents are reached
+    for idx in self.tree_nodes(head) {
+      let node = self.nodes.get(idx as usize).unwrap();
+      if T::Children::all().any(|child| holding.contains(&node.get(child))) { holding.insert(idx); }
+    }
+    let mut iter = RegionIter { sdg: self, min, max, stack: Vec::new(), holding: Some(holding) };
+    if iter.visits(head, UVec3::ZERO, height) { iter.stack.push((head, UVec3::ZERO, height)) }
+    Ok(iter)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::basic_node3d::BasicNode3d;
+  use crate::testing::Rng;
+
+  #[test]
+  fn holding_skips_only_whats_unwanted() {
+    let mut sdg = SparseDirectedGraph::<BasicNode3d>::new();
+    let leaves: Vec<Index> = (0 .. 3).map(|_| sdg.add_leaf()).collect();
+    let mut rng = Rng(11);
+    // Mostly the first leaf, so plenty of the tree has none of the others
+    let head = sdg.build(5, |_| leaves[if rng.next(16) == 0 { 1 + rng.next(2) as usize } else { 0 }]);
+    for (min, max) in [(UVec3::ZERO, UVec3::splat(31)), (UVec3::new(3, 9, 0), UVec3::new(20, 12, 30))] {
+      let all: Vec<_> = sdg.iter_region(head, 5, min, max).unwrap().filter(|&(_, leaf, _)| leaf == leaves[2]).collect();
+      let held: Vec<_> = sdg.iter_region_holding(head, 5, min, max, |leaf| leaf == leaves[2]).unwrap().collect();
+      assert!(!all.is_empty());
+      assert_eq!(all, held);
+    }
+  }
+}
@@ -1,13 +1,65 @@
 use std::collections::VecDeque;
-use ahash::AHashMap;
-use glam::UVec3;
+use ahash::{AHashMap, AHashSet};
+use glam::{IVec3, UVec3};
 use lilypads::Pond;
+use rayon::prelude::*;
+use crate::bounds::{Bounds, NO_BOUNDS};
+use crate::raycast::EMPTY;
 
 pub type Index = u32;
-#[allow(unused)]
-pub trait Path<T : Childs> {
-  fn to_cell(path: Vec<T>) -> UVec3;
-  fn path_from(cell:UVec3, depth:u32) -> Vec<T>;
+/// Deepest a path can go, cells are addressed with a u32 per axis so no tree is taller than this
+pub const MAX_DEPTH: usize = 32;
+
+/// What the graph's fallible methods give back instead of panicking, so a bad head or a stale index
+/// from the engine or editor doesn't take everything down with it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphError {
+  /// Nothing lives at the index, or it's a head nothing holds a ref on
+  InvalidIndex(Index),
+  NotALeaf(Index),
+  /// The leaf can't be removed while cells still use it
+  LeafStillReferenced(Index),
+  /// The path is longer than MAX_DEPTH, or than the kind of path can hold
+  PathTooDeep,
+  /// The cell is past the far side of a tree as tall as the path is deep
+  CellOutOfBounds(UVec3),
+  /// from_dense was given a number of cells which isn't size cubed, or a size which isn't a power of 2
+  NotAGrid { cells: usize, size: u32 },
+}
+impl std::fmt::Display for GraphError {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match self {
+      Self::InvalidIndex(idx) => write!(f, "index {idx} isn't a live node"),
+      Self::NotALeaf(idx) => write!(f, "index {idx} isn't a leaf"),
+      Self::LeafStillReferenced(idx) => write!(f, "the graph still needs leaf {idx}"),
+      Self::PathTooDeep => write!(f, "paths can't be more than {MAX_DEPTH} steps long"),
+      Self::CellOutOfBounds(cell) => write!(f, "cell {cell} doesn't fit in the tree"),
+      Self::NotAGrid { cells, size } => write!(f, "{cells} cells aren't a grid {size} across"),
+    }
+  }
+}
+impl std::error::Error for GraphError {}
+/// A way down from a head, one child a level. Vecs of children are paths, as is the packed MortonPath
+pub trait Path<T : Childs> : Sized {
+  /// The cell the path ends at, in a tree of its depth
+  fn to_cell(&self) -> UVec3;
+  fn path_from(cell:UVec3, depth:u32) -> Result<Self, GraphError>;
+  fn depth(&self) -> u32;
+  /// The child taken at level, 0 being the head's
+  fn step(&self, level:u32) -> T;
+}
+impl<T : Childs> Path<T> for Vec<T> {
+  fn to_cell(&self) -> UVec3 { self.iter().fold(UVec3::ZERO, |cell, step| cell << 1 | step.to_coord()) }
+
+  fn path_from(cell:UVec3, depth:u32) -> Result<Self, GraphError> {
+    if depth as usize > MAX_DEPTH { return Err(GraphError::PathTooDeep) }
+    if cell.max_element().checked_shr(depth).unwrap_or(0) != 0 { return Err(GraphError::CellOutOfBounds(cell)) }
+    Ok((0 .. depth).rev().map(|layer| T::new(cell >> layer & 1)).collect())
+  }
+
+  fn depth(&self) -> u32 { self.len() as u32 }
+
+  fn step(&self, level:u32) -> T { self[level as usize] }
 }
 pub trait Childs: std::fmt::Debug + Clone + Copy {
   fn all() -> impl Iterator<Item = Self>;
@@ -31,52 +83,70 @@ pub trait GraphNode : Node + std::hash::Hash + Eq {}
 pub struct SparseDirectedGraph<T: GraphNode> {
   pub nodes : Pond<T>,
   ref_count: Vec<u32>,
+  // Each slot's Bounds as bits, kept up to date as nodes are added
+  bounds: Vec<u32>,
   index_lookup : AHashMap<T, Index>,
   leaves: Vec<Index>,
+  // Nodes added or freed since take_touched, a rough measure of what edits are costing
+  touched: usize,
 }
 impl<T: GraphNode> SparseDirectedGraph<T> {
   pub fn new() -> Self {
     Self {
       nodes : Pond::new(),
       ref_count : Vec::new(),
+      bounds : Vec::new(),
       index_lookup : AHashMap::new(),
       leaves : Vec::new(),
+      touched : 0,
     }
   }
 
-  /// Returns a trail with length path.len() + 1. trail.first() is the head of the trail and trail.last() is the node the path leads to.
-  fn get_trail(&self, head:Index, path:&[T::Children]) -> Vec<Index>  {
-    let mut trail = Vec::with_capacity(path.len() + 1);
-    trail.push(head);
-    for step in 0 .. path.len() { trail.push(self.child(trail[step], path[step])) }
-    trail 
+  /// Every node along path, trail[0] is head and trail[path.depth()] the node the path leads to.
+  /// Kept on the stack rather than in a Vec since lookups happen per cell, anything past the path's depth is junk
+  fn get_trail(&self, head:Index, path:&impl Path<T::Children>) -> Result<[Index; MAX_DEPTH + 1], GraphError> {
+    if path.depth() as usize > MAX_DEPTH { return Err(GraphError::PathTooDeep) }
+    self.node(head)?;
+    // Everything under a live node is live, so only the head needs checking
+    let mut trail = [head; MAX_DEPTH + 1];
+    for level in 0 .. path.depth() as usize { trail[level + 1] = self.child(trail[level], path.step(level as u32)) }
+    Ok(trail)
   }
-  
-  fn get_ref(&self, idx: Index) -> u32 { self.ref_count[idx as usize] }
 
-  fn add_ref(&mut self, idx: Index) {
-    if idx as usize >= self.ref_count.len() { self.ref_count.resize(idx as usize + 1, 0) }
-    self.ref_count[idx as usize] += 1;
+  // Heads the caller is about to give up a ref on have to be live and hold one
+  pub(crate) fn check_root(&self, idx:Index) -> Result<(), GraphError> {
+    self.node(idx)?;
+    if self.refs(idx) == 0 { return Err(GraphError::InvalidIndex(idx)) }
+    Ok(())
   }
 
-  fn decrement_ref(&mut self, idx: Index) {
+  fn get_ref(&self, idx: Index) -> u32 { self.refs(idx) }
+
+  // Only for nodes known to be live, every slot gets a count when it's first allocated
+  fn add_ref(&mut self, idx: Index) { self.ref_count[idx as usize] += 1 }
+
+  fn decrement_ref(&mut self, idx: Index) -> Result<(), GraphError> {
     let mut queue = vec![idx];
     while let Some(cur_idx) = queue.pop() {
-      self.ref_count[cur_idx as usize] -= 1;
-      if self.get_ref(cur_idx) == 0 && !self.is_leaf(cur_idx) {
-        let old_node = self.nodes.free(cur_idx as usize).unwrap();
+      let count = self.ref_count.get_mut(cur_idx as usize).filter(|count| **count > 0).ok_or(GraphError::InvalidIndex(cur_idx))?;
+      *count -= 1;
+      if *count == 0 && !self.is_leaf(cur_idx) {
+        let old_node = self.nodes.free(cur_idx as usize).ok_or(GraphError::InvalidIndex(cur_idx))?;
         self.index_lookup.remove(&old_node);
+        self.touched += 1;
         for child in T::Children::all() {
           queue.push(old_node.get(child));
         }
       }
     }
+    Ok(())
   }
 
   pub fn add_leaf(&mut self) -> Index {
     let idx = self.nodes.next_allocated() as Index;
     let leaf = T::new(&vec![idx; T::Children::COUNT][..]);
     self.nodes.write(idx as usize, leaf);
+    self.set_bounds(idx, (idx != EMPTY).then_some(Bounds::FULL));
     self.leaves.insert(
       self.leaves.iter().position(|leaf| idx < *leaf ).unwrap_or(self.leaves.len()),
       idx
@@ -85,55 +155,291 @@ impl<T: GraphNode> SparseDirectedGraph<T> {
     idx
   }
 
-  pub fn _remove_leaf(&mut self, leaf:Index) {
-    let leaf_list_idx = self.leaves.binary_search(&leaf).expect(format!("Index {leaf} isn't a leaf!!").as_str());
-    if self.get_ref(leaf) > 0 { panic!("The graph still needs leaf {leaf}") } else {
-      let leaf_node = self.nodes.free(leaf as usize).unwrap();
-      self.index_lookup.remove(&leaf_node);
-      self.leaves.remove(leaf_list_idx);
-    }
+  pub fn remove_leaf(&mut self, leaf:Index) -> Result<(), GraphError> {
+    let leaf_list_idx = self.leaves.binary_search(&leaf).map_err(|_| GraphError::NotALeaf(leaf))?;
+    if self.get_ref(leaf) > 0 { return Err(GraphError::LeafStillReferenced(leaf)) }
+    let leaf_node = self.nodes.free(leaf as usize).ok_or(GraphError::InvalidIndex(leaf))?;
+    self.index_lookup.remove(&leaf_node);
+    self.leaves.remove(leaf_list_idx);
+    Ok(())
   }
 
   fn add_node(&mut self, node:T) -> Index {
-    let idx = self.nodes.alloc(node.clone()) as Index;
+    // Nodes never change once they're in, so this is the only time they need measuring
+    let bounds = Bounds::enclosing(T::Children::all().map(|child| (child.to_coord(), self.bounds(node.get(child)))));
+    let idx = self.nodes.alloc(node) as Index;
+    self.set_bounds(idx, bounds);
     for child in T::Children::all() { self.add_ref(node.get(child)); }
     self.index_lookup.insert(node, idx);
+    self.touched += 1;
     idx
   }
 
-  fn propagate_change(&mut self, path: &[T::Children], trail: &[Index], mut new_child: Index,) -> Index {
-    for cur_depth in (0 .. path.len()).rev() {
-      let new_node = self.node(trail[cur_depth]).with_child(path[cur_depth], new_child);
+  // Called as a slot is allocated, which is also when it gets room for a ref count
+  fn set_bounds(&mut self, idx:Index, bounds:Option<Bounds>) {
+    if idx as usize >= self.bounds.len() { self.bounds.resize(idx as usize + 1, NO_BOUNDS) }
+    if idx as usize >= self.ref_count.len() { self.ref_count.resize(idx as usize + 1, 0) }
+    self.bounds[idx as usize] = bounds.map_or(NO_BOUNDS, Bounds::to_bits);
+  }
+
+  /// Where the solid cells under a node lie, None for EMPTY
+  pub fn bounds(&self, idx:Index) -> Option<Bounds> {
+    self.bounds.get(idx as usize).copied().filter(|&bits| bits != NO_BOUNDS).map(Bounds::from_bits)
+  }
+
+  fn propagate_change(&mut self, path: &impl Path<T::Children>, trail: &[Index], mut new_child: Index,) -> Index {
+    for cur_depth in (0 .. path.depth()).rev() {
+      let new_node = self.live_node(trail[cur_depth as usize]).with_child(path.step(cur_depth), new_child);
       new_child = if let Some(idx) = self.find_index(&new_node) { idx } else { self.add_node(new_node) };
     };
     new_child
   }
 
-  pub fn set_node(&mut self, head:Index, path:&[T::Children], new_idx:Index) -> Index {
-    let trail = self.get_trail(head, path);
-    if *trail.last().unwrap() == new_idx { return head }
+  /// Points the end of path under head at new_idx. Uses up head's ref and the returned head holds one,
+  /// on an error nothing has changed and head still holds its ref
+  pub fn set_node(&mut self, head:Index, path:&impl Path<T::Children>, new_idx:Index) -> Result<Index, GraphError> {
+    self.check_root(head)?;
+    self.node(new_idx)?;
+    let trail = self.get_trail(head, path)?;
+    if trail[path.depth() as usize] == new_idx { return Ok(head) }
     let new_head = self.propagate_change(path, &trail, new_idx);
     self.add_ref(new_head);
-    self.decrement_ref(head);
-    new_head
+    self.decrement_ref(head)?;
+    Ok(new_head)
+  }
+
+  /// A root one level taller with head as one of its children and empty space around it, so a tree can grow when
+  /// something needs to go past its edge. Along each axis the new space goes on the side direction points to,
+  /// the positive one where it's 0. Like set_node this uses up head's ref and the returned head holds one
+  pub fn grow_root(&mut self, head:Index, direction:IVec3) -> Result<Index, GraphError> {
+    self.check_root(head)?;
+    // Growing towards the negative side leaves the old root in the high half
+    let corner = UVec3::from(direction.cmplt(IVec3::ZERO));
+    let children: Vec<Index> = T::Children::all().map(|child| if child.to_coord() == corner { head } else { EMPTY }).collect();
+    let root = self.insert_node(T::new(&children));
+    self.add_ref(root);
+    // The new root holds its own ref to the old one now
+    self.drop_root(head)?;
+    Ok(root)
+  }
+
+  /// Carries what changed from one head to another over onto head: every cell where from and to differ takes to's leaf,
+  /// unless head has since written something else there. Subtrees from and to share are skipped whole.
+  /// Like set_node this uses up head's ref and the returned head holds one, from and to are left as they were
+  pub fn apply_change(&mut self, head:Index, from:Index, to:Index) -> Result<Index, GraphError> {
+    self.check_root(head)?;
+    self.node(from)?;
+    self.node(to)?;
+    let new_head = self.merge(head, from, to);
+    self.add_ref(new_head);
+    self.decrement_ref(head)?;
+    Ok(new_head)
+  }
+
+  // The new nodes hold no refs of their own until something above them (or apply_change) takes one
+  fn merge(&mut self, head:Index, from:Index, to:Index) -> Index {
+    if from == to { return head }
+    if head == from { return to }
+    // Down to a single cell which has been written over again, that write wins
+    if self.is_leaf(head) && self.is_leaf(from) && self.is_leaf(to) { return head }
+    // Leaves are their own children, so a uniform region splits into copies of itself
+    let children: Vec<Index> = T::Children::all()
+      .map(|child| self.merge(self.child(head, child), self.child(from, child), self.child(to, child)))
+      .collect();
+    self.insert_node(T::new(&children))
+  }
+
+  /// Builds a tree of the given height bottom-up, sampling every cell exactly once. 
+  /// Each node is only hashed once, so this is far cheaper than a set_node per cell. The returned head holds a ref.
+  pub fn build(&mut self, height:u32, mut sample: impl FnMut(UVec3) -> Index) -> Index {
+    self.build_regions(height, |corner, height| (height == 0).then(|| sample(corner)))
+  }
+
+  /// Like build, but sample is asked about every node (by its min corner and height) on the way down and
+  /// answers Some(leaf) for ones it already knows are all that leaf, which are never split any further.
+  /// Single cells always need an answer
+  pub fn build_regions(&mut self, height:u32, mut sample: impl FnMut(UVec3, u32) -> Option<Index>) -> Index {
+    let head = self.build_node(UVec3::ZERO, height, &mut sample);
+    self.add_ref(head);
+    head
+  }
+
+  fn build_node(&mut self, corner:UVec3, height:u32, sample: &mut impl FnMut(UVec3, u32) -> Option<Index>) -> Index {
+    if let Some(leaf) = sample(corner, height) { return leaf }
+    let half = 1 << (height - 1);
+    let children: Vec<Index> = T::Children::all()
+      .map(|child| self.build_node(corner + child.to_coord() * half, height - 1, sample))
+      .collect();
+    // Uniform children hash to the leaf itself, so they collapse for free
+    let node = T::new(&children);
+    if let Some(idx) = self.find_index(&node) { idx } else { self.add_node(node) }
+  }
+
+  /// Builds a tree out of a grid of cells size across (a power of 2), x fastest then y then z, like dense returns them.
+  /// Works up a level at a time, gathering each level's nodes and looking up the ones the graph already has
+  /// (uniform ones are found as their leaf) across every core. Only nodes new to the graph are added one by one.
+  /// The returned head holds a ref. Errors if the cells aren't a whole grid or any of them isn't a live node
+  pub fn from_dense(&mut self, cells:&[Index], size:u32) -> Result<Index, GraphError> where T: Send + Sync {
+    if !size.is_power_of_two() || cells.len() != (size as usize).pow(3) { return Err(GraphError::NotAGrid { cells: cells.len(), size }) }
+    if let Some(&cell) = cells.par_iter().find_any(|&&cell| self.nodes.get(cell as usize).is_none()) { return Err(GraphError::InvalidIndex(cell)) }
+    let mut level = cells.to_vec();
+    let mut side = size as usize;
+    while side > 1 {
+      let half = side / 2;
+      let (lower, this) = (&level, &*self);
+      // Ok for nodes the graph has, Err for ones it doesn't yet
+      let nodes: Vec<Result<Index, T>> = (0 .. half * half * half).into_par_iter().map(|idx| {
+        let corner = UVec3::new((idx % half) as u32, (idx / half % half) as u32, (idx / half / half) as u32) * 2;
+        // Saves allocating for every node, no node has more than 8 children
+        let mut buffer = [0; 8];
+        for (slot, child) in buffer.iter_mut().zip(T::Children::all()) {
+          let cell = (corner + child.to_coord()).as_usizevec3();
+          *slot = lower[(cell.z * side + cell.y) * side + cell.x];
+        }
+        let children = &buffer[.. T::Children::COUNT];
+        // Most of a grid is usually uniform, which needs no hashing to know it's the leaf
+        if children.iter().all(|&child| child == children[0]) && this.is_leaf(children[0]) { return Ok(children[0]) }
+        let node = T::new(children);
+        this.find_index(&node).ok_or(node)
+      }).collect();
+      level = nodes.into_iter().map(|found| found.unwrap_or_else(|node| self.insert_node(node))).collect();
+      side = half;
+    }
+    self.add_ref(level[0]);
+    Ok(level[0])
   }
 
   fn find_index(&self, node:&T) -> Option<Index> { self.index_lookup.get(node).copied() }
   
-  fn is_leaf(&self, idx:Index) -> bool { self.leaves.binary_search(&idx).is_ok() }
+  pub fn is_leaf(&self, idx:Index) -> bool { self.leaves.binary_search(&idx).is_ok() }
+
+  /// The node at idx, if there's one there
+  pub fn node(&self, idx:Index) -> Result<&T, GraphError> { self.nodes.get(idx as usize).ok_or(GraphError::InvalidIndex(idx)) }
 
-  fn node(&self, idx:Index) -> &T { self.nodes.get(idx as usize).unwrap() }
+  // For walking trees the graph is known to hold, every index under a live node is live
+  fn live_node(&self, idx:Index) -> &T { self.nodes.get(idx as usize).unwrap() }
 
-  fn child(&self, idx:Index, child:T::Children) -> Index { self.node(idx).get(child) }
+  fn child(&self, idx:Index, child:T::Children) -> Index { self.live_node(idx).get(child) }
 
-  pub fn descend(&self, head:Index, path:&[T::Children]) -> Index { *self.get_trail(head, path).last().unwrap() }
+  pub fn descend(&self, head:Index, path:&impl Path<T::Children>) -> Result<Index, GraphError> { Ok(self.get_trail(head, path)?[path.depth() as usize]) }
 
-  pub fn get_root(&mut self, idx:Index) -> Index { self.add_ref(idx); idx }
+  /// Takes a ref on idx so it can be held as a head. Errors if nothing lives there
+  pub fn get_root(&mut self, idx:Index) -> Result<Index, GraphError> {
+    self.node(idx)?;
+    self.add_ref(idx);
+    Ok(idx)
+  }
+
+  /// Gives back a ref taken by get_root (or held by a returned head), freeing whatever nothing else needs.
+  /// Errors if idx isn't a head anything holds
+  pub fn drop_root(&mut self, idx:Index) -> Result<(), GraphError> {
+    self.check_root(idx)?;
+    self.decrement_ref(idx)
+  }
+
+  pub fn refs(&self, idx:Index) -> u32 { self.ref_count.get(idx as usize).copied().unwrap_or(0) }
+
+  /// Every leaf, in ascending order
+  pub fn leaves(&self) -> &[Index] { &self.leaves }
+
+  /// The distinct nodes under head (head included) which aren't leaves, children before their parents.
+  /// Rebuilding them in this order with insert_node recreates the tree.
+  pub fn tree_nodes(&self, head:Index) -> Vec<Index> {
+    let mut seen = AHashSet::new();
+    let mut order = Vec::new();
+    // (node, whether its children have been pushed already)
+    let mut stack = vec![(head, false)];
+    while let Some((idx, expanded)) = stack.pop() {
+      if self.is_leaf(idx) { continue }
+      if expanded { order.push(idx); continue }
+      if !seen.insert(idx) { continue }
+      stack.push((idx, true));
+      for child in T::Children::all() { stack.push((self.child(idx, child), false)) }
+    }
+    order
+  }
+
+  /// The index of node, adding it if the graph doesn't have it yet. Its children have to be in the graph already.
+  /// Like set_node's intermediate nodes it holds no ref of its own, get_root whatever ends up as a head
+  pub fn insert_node(&mut self, node:T) -> Index {
+    if let Some(idx) = self.find_index(&node) { idx } else { self.add_node(node) }
+  }
+
+  /// Copies the tree under head out of another graph, with leaf_map picking which of this graph's leaves stands in
+  /// for each of other's. Anything this graph already has is shared rather than copied. The returned head holds a ref.
+  /// Graphs aren't shared between threads, so this is also how work done on another thread gets in: build the tree
+  /// in a graph of the worker's own, then clone it across, which only costs as much as it has distinct nodes.
+  /// Errors if head isn't live in other
+  pub fn clone_from(&mut self, other:&Self, head:Index, leaf_map: impl Fn(Index) -> Index) -> Result<Index, GraphError> {
+    other.node(head)?;
+    // other's index -> ours
+    let mut copied = AHashMap::new();
+    for &leaf in other.leaves() { copied.insert(leaf, leaf_map(leaf)); }
+    for idx in other.tree_nodes(head) {
+      let node = other.live_node(idx);
+      let children: Vec<Index> = T::Children::all().map(|child| copied[&node.get(child)]).collect();
+      copied.insert(idx, self.insert_node(T::new(&children)));
+    }
+    self.get_root(copied[&head])
+  }
+
+  /// Live nodes, leaves included. nodes.len() is the most there have ever been at once, holes and all
+  pub fn live_nodes(&self) -> usize { self.index_lookup.len() }
+
+  /// How many nodes have been added or freed since this was last called, for profiling
+  pub fn take_touched(&mut self) -> usize { std::mem::take(&mut self.touched) }
+
+  /// Moves nodes from the back into the holes edits have left, then drops the empty tail so nodes.len() is back down
+  /// near live_nodes. Leaves stay where they are, everything outside the graph refers to them directly.
+  /// Nodes nothing refers to (insert_node's that never made it into a tree) are freed first.
+  /// Returns old -> new for every node which moved, heads kept anywhere else have to be fixed up with it
+  pub fn compact(&mut self) -> AHashMap<Index, Index> {
+    let len = self.nodes.len() as Index;
+    let live = |sdg: &Self, idx: Index| sdg.nodes.get(idx as usize).is_some();
+    // ref_count only reaches as far as the furthest slot ever allocated, anything past its end has no refs
+    self.ref_count.resize(len as usize, 0);
+    let orphans: Vec<Index> = (0 .. len).filter(|&idx| live(self, idx) && !self.is_leaf(idx) && self.refs(idx) == 0).collect();
+    for idx in orphans {
+      // Freeing an earlier orphan can't have freed this one, nothing referred to it
+      let node = self.nodes.free(idx as usize).unwrap();
+      self.index_lookup.remove(&node);
+      self.touched += 1;
+      for child in T::Children::all() { self.decrement_ref(node.get(child)).unwrap() }
+    }
+    let holes: Vec<Index> = (0 .. len).filter(|&idx| !live(self, idx)).collect();
+    let movable: Vec<Index> = (0 .. len).rev().filter(|&idx| live(self, idx) && !self.is_leaf(idx)).collect();
+    // The lowest hole takes the highest node until they meet in the middle
+    let remap: AHashMap<Index, Index> = movable.into_iter().zip(holes).take_while(|(old, new)| new < old).collect();
+    for (&old, &new) in &remap {
+      let node = self.nodes.free(old as usize).unwrap();
+      self.nodes.write(new as usize, node);
+      self.ref_count[new as usize] = std::mem::take(&mut self.ref_count[old as usize]);
+      self.bounds[new as usize] = self.bounds[old as usize];
+    }
+
+    // Every parent (moved or not) may point at a node which moved, so the lookup is rebuilt from scratch
+    self.index_lookup.clear();
+    for idx in 0 .. len {
+      let Some(node) = self.nodes.get_mut(idx as usize) else { continue };
+      for child in T::Children::all() {
+        if let Some(&new) = remap.get(&node.get(child)) { node.set(child, new) }
+      }
+      self.index_lookup.insert(*node, idx);
+    }
+    let end = (0 .. len).rev().find(|&idx| live(self, idx)).map_or(0, |idx| idx + 1) as usize;
+    self.nodes.resize(end);
+    self.ref_count.truncate(end);
+    self.bounds.truncate(end);
+    remap
+  }
 
 }
+impl<T: GraphNode> Default for SparseDirectedGraph<T> {
+  fn default() -> Self { Self::new() }
+}
 
 // Utility function
-pub fn bfs_nodes<N: Node>(nodes:&Vec<N>, head:Index, leaves:&Vec<Index>) -> Vec<Index> {
+pub fn bfs_nodes<N: Node>(nodes:&[N], head:Index, leaves:&[Index]) -> Vec<Index> {
   let mut queue = VecDeque::from([head]);
   let mut bfs_indexes = Vec::new();
   while let Some(index) = queue.pop_front() {
@@ -148,3 +454,106 @@ pub fn bfs_nodes<N: Node>(nodes:&Vec<N>, head:Index, leaves:&Vec<Index>) -> Vec<
   bfs_indexes
 }
 
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::basic_node3d::{BasicNode3d, Zorder3d};
+  use crate::morton::MortonPath;
+  use crate::testing::{check_refs, dense, Rng};
+
+  const HEIGHT: u32 = 3;
+
+  #[test]
+  fn compact_keeps_trees_and_frees_orphans() {
+    let mut sdg = SparseDirectedGraph::<BasicNode3d>::new();
+    let leaves: Vec<Index> = (0 .. 3).map(|_| sdg.add_leaf()).collect();
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    let mut heads: Vec<Index> = (0 .. 3).map(|_| sdg.build(HEIGHT, |_| leaves[rng.next(3) as usize])).collect();
+    // Edits free nodes all over, leaving holes for compact to fill
+    for _ in 0 .. 100 {
+      let which = rng.next(3) as usize;
+      let path = MortonPath::new(rng.cell(1 << HEIGHT), HEIGHT).unwrap();
+      heads[which] = sdg.set_node(heads[which], &path, leaves[rng.next(3) as usize]).unwrap();
+    }
+    // Nodes added but never taken as a head, one of them over a node nothing else uses
+    let loose = sdg.insert_node(BasicNode3d::new(&[leaves[1], leaves[2], leaves[1], leaves[2], leaves[2], leaves[1], leaves[1], leaves[0]]));
+    sdg.insert_node(BasicNode3d::new(&[loose; 8]));
+    sdg.insert_node(BasicNode3d::new(&[leaves[2], leaves[0], leaves[0], leaves[0], leaves[0], leaves[0], leaves[0], leaves[1]]));
+    let before: Vec<Vec<Index>> = heads.iter().map(|&head| dense(&sdg, head, HEIGHT)).collect();
+    let slots = sdg.nodes.len();
+
+    let remap = sdg.compact();
+    for head in &mut heads { *head = remap.get(head).copied().unwrap_or(*head) }
+    for (head, cells) in heads.iter().zip(&before) { assert_eq!(&dense(&sdg, *head, HEIGHT), cells) }
+    check_refs(&sdg, &heads);
+    // Leaves stay put and still point at themselves
+    for &leaf in &leaves {
+      assert!(!remap.contains_key(&leaf));
+      assert!(sdg.node(leaf).unwrap().iter().all(|&child| child == leaf));
+    }
+    // The orphans are gone and what's left is packed in without holes
+    let trees: AHashSet<Index> = heads.iter().flat_map(|&head| sdg.tree_nodes(head)).collect();
+    assert_eq!(sdg.live_nodes(), leaves.len() + trees.len());
+    assert_eq!(sdg.nodes.len(), sdg.live_nodes());
+    assert!(sdg.nodes.len() < slots);
+  }
+
+  #[test]
+  fn from_dense_round_trips() {
+    let mut sdg = SparseDirectedGraph::<BasicNode3d>::new();
+    let leaves: Vec<Index> = (0 .. 3).map(|_| sdg.add_leaf()).collect();
+    let mut rng = Rng(0x1357_9bdf_2468_ace0);
+    let size = 1 << HEIGHT;
+    // A uniform half, a striped quarter and noise, so there's something to share
+    let cells: Vec<Index> = (0 .. size * size * size).map(|idx| {
+      let cell = UVec3::new(idx % size, idx / size % size, idx / size / size);
+      if cell.z >= 4 { leaves[1] } else if cell.y >= 4 { leaves[(cell.x + cell.y) as usize % 2 * 2] } else { leaves[rng.next(3) as usize] }
+    }).collect();
+
+    let head = sdg.from_dense(&cells, size).unwrap();
+    assert_eq!(dense(&sdg, head, HEIGHT), cells);
+    check_refs(&sdg, &[head]);
+    // The uniform half is the leaf itself and each repeated node is stored once
+    assert_eq!(sdg.descend(head, &vec![Zorder3d::new(UVec3::Z)]).unwrap(), leaves[1]);
+    let live = sdg.live_nodes();
+    assert_eq!(live, leaves.len() + sdg.tree_nodes(head).len());
+    // Building the same cells again, one at a time or all at once, finds every node already there
+    let built = sdg.build(HEIGHT, |cell| cells[(cell.x + (cell.y + cell.z * size) * size) as usize]);
+    let again = sdg.from_dense(&cells, size).unwrap();
+    assert_eq!((built, again), (head, head));
+    assert_eq!(sdg.live_nodes(), live);
+    check_refs(&sdg, &[head, built, again]);
+
+    assert_eq!(sdg.from_dense(&cells[1 ..], size), Err(GraphError::NotAGrid { cells: cells.len() - 1, size }));
+    assert_eq!(sdg.from_dense(&[leaves[0]; 27], 3), Err(GraphError::NotAGrid { cells: 27, size: 3 }));
+    assert_eq!(sdg.from_dense(&[leaves[0], 99, leaves[1], leaves[2], 0, 0, 0, 0], 2), Err(GraphError::InvalidIndex(99)));
+    check_refs(&sdg, &[head, built, again]);
+  }
+
+  #[test]
+  fn dead_heads_are_errors() {
+    let mut sdg = SparseDirectedGraph::<BasicNode3d>::new();
+    let leaves: Vec<Index> = (0 .. 2).map(|_| sdg.add_leaf()).collect();
+    let head = sdg.build(HEIGHT, |cell| leaves[(cell.x & 1) as usize]);
+    // One slot freed inside the pond and one past its end
+    let freed = sdg.build(1, |cell| leaves[(cell.y & 1) as usize]);
+    sdg.drop_root(freed).unwrap();
+    for dead in [freed, 1000] {
+      let err = Some(GraphError::InvalidIndex(dead));
+      assert_eq!(sdg.get_root(dead).err(), err);
+      assert_eq!(sdg.sample(dead, HEIGHT, UVec3::ZERO).err(), err);
+      assert_eq!(sdg.raycast(dead, HEIGHT, glam::Vec3::NEG_ONE, glam::Vec3::ONE, 100.0).err(), err);
+      assert_eq!(sdg.transformed(dead, crate::orientation::Orientation::IDENTITY).err(), err);
+      assert_eq!(SparseDirectedGraph::new().clone_from(&sdg, dead, |leaf| leaf).err(), err);
+      assert_eq!(sdg.stats(&[head, dead]).err(), err);
+      assert_eq!(crate::export::to_dot(&sdg, dead, 1, |_| String::new()).err(), err);
+      assert_eq!(crate::export::to_tree(&sdg, dead, 1).err(), err);
+      assert_eq!(sdg.iter_region(dead, HEIGHT, UVec3::ZERO, UVec3::ONE).err(), err);
+      assert_eq!(sdg.pack_tree(dead).err(), err);
+    }
+    // None of that took a ref on anything
+    assert_eq!((sdg.refs(freed), sdg.refs(1000)), (0, 0));
+    check_refs(&sdg, &[head]);
+  }
+}
@@ -0,0 +1,110 @@
+use std::fmt;
+use ahash::{AHashMap, AHashSet};
+use crate::sdg::{SparseDirectedGraph, GraphNode, GraphError, Childs, Index};
+
+/// How big the graph is and how well it's sharing, see SparseDirectedGraph::stats
+#[derive(Debug, Clone, Default)]
+pub struct GraphStats {
+  /// Live nodes, leaves included
+  pub nodes: usize,
+  pub leaves: usize,
+  /// Slots in the Pond, live or freed. Every one of them is uploaded
+  pub slots: usize,
+  pub freed: usize,
+  /// Distinct nodes which aren't leaves at each depth under the heads asked about, [0] being the heads themselves.
+  /// A node reached at several depths (from heads of different heights) is counted at each
+  pub per_depth: Vec<usize>,
+  /// Distinct nodes which aren't leaves under the heads
+  pub shared: usize,
+  /// Nodes the heads' trees would need if nothing were shared between or within them, uniform regions still collapsing
+  pub unshared: u64,
+  /// Size of the voxel buffer upload, as the raw nodes and packed (see SparseDirectedGraph::pack)
+  pub gpu_bytes: usize,
+  pub packed_bytes: usize,
+}
+impl GraphStats {
+  /// How many tree nodes each stored node stands in for, 1 is no deduplication at all
+  pub fn dedup_ratio(&self) -> f64 {
+    if self.shared == 0 { 1.0 } else { self.unshared as f64 / self.shared as f64 }
+  }
+}
+impl fmt::Display for GraphStats {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(f, "{} nodes live ({} leaves) in {} slots, {} freed", self.nodes, self.leaves, self.slots, self.freed)?;
+    writeln!(f, "{} nodes under the heads stand in for {} ({:.1}x deduplicated)", self.shared, self.unshared, self.dedup_ratio())?;
+    writeln!(f, "Per depth: {:?}", self.per_depth)?;
+    write!(f, "{:.2} MB uploaded raw, {:.2} MB packed", self.gpu_bytes as f64 / 1_000_000.0, self.packed_bytes as f64 / 1_000_000.0)
+  }
+}
+
+impl<T: GraphNode> SparseDirectedGraph<T> {
+  /// Counts the whole graph, and how the trees under heads are laid out and shared. Errors if any head isn't a live node
+  pub fn stats(&self, heads: &[Index]) -> Result<GraphStats, GraphError> {
+    // Everything under a live node is live, so the heads are all that need checking
+    for &head in heads { self.node(head)?; }
+    let slots = self.nodes.len();
+    let mut per_depth = Vec::new();
+    let mut level: AHashSet<Index> = heads.iter().copied().filter(|&head| !self.is_leaf(head)).collect();
+    while !level.is_empty() {
+      per_depth.push(level.len());
+      level = level.iter()
+        .flat_map(|&idx| T::Children::all().map(move |child| self.nodes.get(idx as usize).unwrap().get(child)))
+        .filter(|&idx| !self.is_leaf(idx))
+        .collect();
+    }
+
+    // Tree nodes under each node, filled in children first so every child is already known
+    let mut expanded: AHashMap<Index, u64> = AHashMap::new();
+    for &head in heads {
+      for idx in self.tree_nodes(head) {
+        if expanded.contains_key(&idx) { continue }
+        let node = self.nodes.get(idx as usize).unwrap();
+        let count = 1 + T::Children::all().map(|child| expanded.get(&node.get(child)).copied().unwrap_or(0)).sum::<u64>();
+        expanded.insert(idx, count);
+      }
+    }
+
+    Ok(GraphStats {
+      nodes: self.live_nodes(),
+      leaves: self.leaves().len(),
+      slots,
+      freed: slots - self.live_nodes(),
+      per_depth,
+      shared: expanded.len(),
+      unshared: heads.iter().map(|head| expanded.get(head).copied().unwrap_or(0)).sum(),
+      gpu_bytes: slots * std::mem::size_of::<T>(),
+      packed_bytes: self.pack().words.len() * std::mem::size_of::<u32>(),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use glam::UVec3;
+  use crate::basic_node3d::BasicNode3d;
+  use crate::morton::MortonPath;
+  use crate::raycast::EMPTY;
+
+  #[test]
+  fn counts_nodes_and_sharing() {
+    let mut sdg = SparseDirectedGraph::<BasicNode3d>::new();
+    sdg.add_leaf();
+    let leaf = sdg.add_leaf();
+    // The same 2 cell node twice over under the head
+    let head = sdg.build(2, |cell| if cell == UVec3::ZERO || cell == UVec3::new(2, 0, 0) { leaf } else { EMPTY });
+    let stats = sdg.stats(&[head]).unwrap();
+    assert_eq!((stats.nodes, stats.leaves, stats.freed), (4, 2, stats.slots - 4));
+    assert_eq!(stats.per_depth, [1, 1]);
+    assert_eq!((stats.shared, stats.unshared), (2, 3));
+    assert_eq!(stats.dedup_ratio(), 1.5);
+    let corner = sdg.descend(head, &MortonPath::new(UVec3::ZERO, 1).unwrap()).unwrap();
+    assert_eq!((sdg.refs(head), sdg.refs(corner)), (1, 2));
+    assert!(stats.to_string().starts_with("4 nodes live (2 leaves)"));
+
+    // Only the graph's own counts without any heads
+    let empty = sdg.stats(&[]).unwrap();
+    assert_eq!((empty.nodes, empty.shared, empty.unshared, empty.dedup_ratio()), (4, 0, 0, 1.0));
+    assert!(empty.per_depth.is_empty());
+  }
+}
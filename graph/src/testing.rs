@@ -0,0 +1,39 @@
+//! Helpers the tests share
+
+use glam::UVec3;
+use crate::basic_node3d::BasicNode3d;
+use crate::sdg::{Index, SparseDirectedGraph};
+
+/// xorshift, so test cases are the same every run without pulling in a crate for it
+pub struct Rng(pub u64);
+impl Rng {
+  pub fn next(&mut self, below: u32) -> u32 {
+    self.0 ^= self.0 << 13;
+    self.0 ^= self.0 >> 7;
+    self.0 ^= self.0 << 17;
+    (self.0 >> 32) as u32 % below
+  }
+
+  pub fn cell(&mut self, size: u32) -> UVec3 { UVec3::new(self.next(size), self.next(size), self.next(size)) }
+}
+
+/// Every live node's refs should be what its parents' children and the heads held outside add up to
+pub fn check_refs(sdg: &SparseDirectedGraph<BasicNode3d>, held: &[Index]) {
+  let mut expected = vec![0; sdg.nodes.len()];
+  for &head in held { expected[head as usize] += 1 }
+  for idx in 0 .. sdg.nodes.len() as Index {
+    let Some(node) = sdg.nodes.get(idx as usize) else { continue };
+    if sdg.is_leaf(idx) { continue }
+    for &child in node { expected[child as usize] += 1 }
+  }
+  for idx in 0 .. sdg.nodes.len() as Index {
+    if sdg.nodes.get(idx as usize).is_none() { continue }
+    assert_eq!(sdg.refs(idx), expected[idx as usize], "refs of node {idx}");
+  }
+}
+
+/// Every cell of a tree, in x then y then z order like from_dense takes them
+pub fn dense(sdg: &SparseDirectedGraph<BasicNode3d>, head: Index, height: u32) -> Vec<Index> {
+  let size = 1 << height;
+  (0 .. size * size * size).map(|idx| sdg.sample(head, height, UVec3::new(idx % size, idx / size % size, idx / size / size)).unwrap().0).collect()
+}